@@ -1,7 +1,9 @@
 use super::{Cache, CacheStats};
+use super::sync_lru_cache::SyncLruCache;
 use crate::error::PlexError;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 
 #[derive(Clone)]
@@ -16,6 +18,8 @@ pub struct Block {
 pub struct BlockCache {
     cache: Arc<dyn Cache<u64, Block> + Send + Sync>,
     block_size: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl BlockCache {
@@ -23,46 +27,166 @@ impl BlockCache {
         cache: Arc<dyn Cache<u64, Block> + Send + Sync>,
         block_size: usize,
     ) -> Self {
-        Self { cache, block_size}
+        Self {
+            cache,
+            block_size,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
     }
 
     pub async fn get_block(&self, offset: u64) -> Option<Block> {
         let block_offset = self.align_to_block(offset);
-        self.cache.get(&block_offset).await;
-
+        let block = self.cache.get(&block_offset).await;
+        if block.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        block
     }
 
     pub async fn set_block(&self, block: Block) {
-        let block_offset = self.align_to_block(offset);
-        self.cache.set(&block_offset, block).await;
+        let block_offset = self.align_to_block(block.offset);
+        self.cache.set(block_offset, block).await;
+    }
 
+    /// Returns `size` bytes starting at `offset` if the block holding them is
+    /// cached. Ranges that run past the end of the block return `None`.
+    pub async fn get_data(&self, offset: u64, size: usize) -> Option<Vec<u8>> {
+        let block = self.get_block(offset).await?;
+        Self::slice_block(&block, offset, size)
     }
 
-    pub async get_data(&self, offset: u64, size: usize) -> Option<Vec<u8>> {
-        let block_offset = self.align_to_block(offset);
-        let block = self.cache.get(&block_offset).await;
+    /// Like `get_data`, but loads and caches the block with `loader` on a miss.
+    /// `loader` is called with the aligned block offset.
+    pub async fn read_through<F, Fut>(
+        &self,
+        offset: u64,
+        size: usize,
+        loader: F,
+    ) -> Result<Option<Vec<u8>>, PlexError>
+    where
+        F: FnOnce(u64) -> Fut,
+        Fut: Future<Output = Result<Block, PlexError>>,
+    {
+        let block = match self.get_block(offset).await {
+            Some(block) => block,
+            None => {
+                let block = loader(self.align_to_block(offset)).await?;
+                self.set_block(block.clone()).await;
+                block
+            }
+        };
 
-        let start = (offset - block_offset) as usize;
-        let end = std::cmp::min(start + size, block.data.len());
+        Ok(Self::slice_block(&block, offset, size))
+    }
 
-        if start < block.data.len() && end <= block.data.len() {
-            Some(block.data[start..end].to_vec())
-        } else {
-            None
-        }
+    fn slice_block(block: &Block, offset: u64, size: usize) -> Option<Vec<u8>> {
+        let start = offset.checked_sub(block.offset)? as usize;
+        let end = start.checked_add(size)?;
 
+        block.data.get(start..end).map(|data| data.to_vec())
     }
 
-    fn align_to_block(&self, offset) -> u64 {
+    pub fn align_to_block(&self, offset: u64) -> u64 {
         (offset / self.block_size as u64)  * self.block_size as u64
     }
 
+    /// Block lookups that found or missed a cached block. Evictions are
+    /// tracked by the underlying cache, not here.
+    pub async fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: 0,
+            size: self.cache.size().await,
+            capacity: self.cache.capacity().await,
+        }
+    }
+
+}
+
+/// A block cache for `FileManager`, whose reads are synchronous and so
+/// cannot await a `Cache`. Blocks sit in a `SyncLruCache` behind a plain
+/// mutex; a poisoned cache misses every lookup and drops every insert.
+pub struct SyncBlockCache {
+    blocks: Mutex<SyncLruCache<u64, Block>>,
+    block_size: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SyncBlockCache {
+    pub fn new(capacity: usize, block_size: usize) -> Self {
+        Self {
+            blocks: Mutex::new(SyncLruCache::new(capacity)),
+            block_size,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn align_to_block(&self, offset: u64) -> u64 {
+        (offset / self.block_size as u64) * self.block_size as u64
+    }
+
+    /// Returns `size` bytes starting at `offset` if the block holding them is
+    /// cached. Ranges that run past the end of the block return `None`.
+    pub fn get_data(&self, offset: u64, size: usize) -> Option<Vec<u8>> {
+        let data = self.blocks.lock().ok().and_then(|mut blocks| {
+            let block = blocks.get(&self.align_to_block(offset))?;
+            Some(BlockCache::slice_block(block, offset, size))
+        });
+        match data {
+            Some(data) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                data
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn set_block(&self, block: Block) {
+        if let Ok(mut blocks) = self.blocks.lock() {
+            blocks.insert(self.align_to_block(block.offset), block);
+        }
+    }
+
+    /// Block lookups that found or missed a cached block.
+    pub fn stats(&self) -> CacheStats {
+        let (size, capacity) = self
+            .blocks
+            .lock()
+            .map_or((0, 0), |blocks| (blocks.len(), blocks.capacity()));
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: 0,
+            size,
+            capacity,
+        }
+    }
 }
 
 pub struct CacheLayer<K, V> {
     l1_cache: Arc<dyn Cache<K, V> + Send + Sync>,
     l2_cache: Arc<dyn Cache<K, V> + Send + Sync>,
     l3_cache: Option<Arc<BlockCache>>,
+    l1_hits: AtomicU64,
+    l2_hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl <K, V> CacheLayer<K, V>
@@ -80,21 +204,31 @@ where
             l1_cache,
             l2_cache,
             l3_cache,
+            l1_hits: AtomicU64::new(0),
+            l2_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
 
+    /// The block cache sitting under the two key-value layers, if any.
+    pub fn block_cache(&self) -> Option<&Arc<BlockCache>> {
+        self.l3_cache.as_ref()
+    }
+
     pub async fn get(&self, key: &K) -> Option<V> {
 
         if let Some(value) = self.l1_cache.get(key).await {
+            self.l1_hits.fetch_add(1, Ordering::Relaxed);
             return Some(value);
         }
 
         if let Some(value) = self.l2_cache.get(key).await {
-
+            self.l2_hits.fetch_add(1, Ordering::Relaxed);
             self.l1_cache.set(key.clone(), value.clone()).await;
             return Some(value);
         }
 
+        self.misses.fetch_add(1, Ordering::Relaxed);
         None
     }
 
@@ -106,32 +240,122 @@ where
         let l1_result = self.l1_cache.remove(key).await;
         let l2_result = self.l2_cache.remove(key).await;
 
-
-        l1_result.or(l2_result);
+        l1_result.or(l2_result)
     }
 
-    pub async fn clear(&self) -> {
+    pub async fn clear(&self) {
         self.l1_cache.clear().await;
         self.l2_cache.clear().await;
     }
 
+    /// Hit and miss counts as seen through this layer. Every L1 miss is
+    /// looked up in L2, so L1 misses are L2 hits plus total misses. Evictions
+    /// are tracked by the underlying caches, not here.
     pub async fn stats(&self) -> (CacheStats, CacheStats) {
+        let l1_hits = self.l1_hits.load(Ordering::Relaxed);
+        let l2_hits = self.l2_hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+
         let l1_stats = CacheStats {
-            hits: 0,
-            misses: 0,
+            hits: l1_hits,
+            misses: l2_hits + misses,
             evictions: 0,
-            size: self.l1_cache.size().await;
-            capacity: self.l1_cache.capacity().await;
+            size: self.l1_cache.size().await,
+            capacity: self.l1_cache.capacity().await,
         };
 
         let l2_stats = CacheStats {
-            hits: 0,
-            misses: 0,
+            hits: l2_hits,
+            misses,
             evictions: 0,
-            size: self.l1_cache.size().await,
-            capacity: self.l1_cache.capacity().await;
+            size: self.l2_cache.size().await,
+            capacity: self.l2_cache.capacity().await,
         };
 
         (l1_stats, l2_stats)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::lru_cache::AsyncLruCache;
+
+    const BLOCK_SIZE: usize = 4096;
+
+    fn block_cache() -> BlockCache {
+        BlockCache::new(Arc::new(AsyncLruCache::new(8)), BLOCK_SIZE)
+    }
+
+    fn block_at(offset: u64) -> Block {
+        let data: Vec<u8> = (0..BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        Block { checksum: 0, size: data.len(), data, offset }
+    }
+
+    #[tokio::test]
+    async fn reads_a_range_from_the_middle_of_a_block() {
+        let cache = block_cache();
+        let block = block_at(8192);
+        let expected = block.data[1000..3000].to_vec();
+        cache.set_block(block).await;
+
+        assert_eq!(cache.get_data(8192 + 1000, 2000).await, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn uncached_and_overlong_ranges_return_none() {
+        let cache = block_cache();
+        cache.set_block(block_at(0)).await;
+
+        assert_eq!(cache.get_data(BLOCK_SIZE as u64, 10).await, None);
+        assert_eq!(cache.get_data(4000, 200).await, None);
+    }
+
+    #[tokio::test]
+    async fn read_through_loads_once_then_hits() {
+        let cache = block_cache();
+
+        let first = cache
+            .read_through(4096 + 10, 5, |offset| async move { Ok(block_at(offset)) })
+            .await
+            .unwrap();
+        let second = cache
+            .read_through(4096 + 10, 5, |_| async { panic!("loaded twice") })
+            .await
+            .unwrap();
+
+        assert_eq!(first, Some(block_at(4096).data[10..15].to_vec()));
+        assert_eq!(first, second);
+    }
+
+    fn cache_layer() -> (CacheLayer<String, u32>, Arc<AsyncLruCache<String, u32>>) {
+        let l1 = Arc::new(AsyncLruCache::new(8));
+        let l2 = Arc::new(AsyncLruCache::new(8));
+        (CacheLayer::new(l1, l2.clone(), None), l2)
+    }
+
+    #[tokio::test]
+    async fn counts_l2_hits_and_promotes_into_l1() {
+        let (layer, l2) = cache_layer();
+        l2.set("key".to_string(), 7).await;
+
+        assert_eq!(layer.get(&"key".to_string()).await, Some(7));
+        assert_eq!(layer.get(&"key".to_string()).await, Some(7));
+
+        let (l1_stats, l2_stats) = layer.stats().await;
+        assert_eq!((l1_stats.hits, l1_stats.misses), (1, 1));
+        assert_eq!((l2_stats.hits, l2_stats.misses), (1, 0));
+        assert_eq!(l1_stats.size, 1);
+    }
+
+    #[tokio::test]
+    async fn counts_a_miss_in_both_layers() {
+        let (layer, _l2) = cache_layer();
+
+        assert_eq!(layer.get(&"missing".to_string()).await, None);
+
+        let (l1_stats, l2_stats) = layer.stats().await;
+        assert_eq!((l1_stats.hits, l1_stats.misses), (0, 1));
+        assert_eq!((l2_stats.hits, l2_stats.misses), (0, 1));
+    }
+}
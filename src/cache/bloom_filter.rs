@@ -1,8 +1,8 @@
 use crate::error::{PlexError, PlexResult};
+use crate::utils::hash::HashFamily;
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasher, Hash};
 use std::io::{BufReader, BufWriter};
 use std::path::Path;
 
@@ -13,10 +13,19 @@ pub struct BloomFilter {
     hash_functions: u32,
     inserted_elements: u64,
     false_positive_rate: f64,
+    hash_family: HashFamily,
 }
 
 impl BloomFilter {
     pub fn new(expected_elements: usize, false_positive: f64) -> PlexResult<Self> {
+        Self::with_hash_family(expected_elements, false_positive, HashFamily::default())
+    }
+
+    pub fn with_hash_family(
+        expected_elements: usize,
+        false_positive: f64,
+        hash_family: HashFamily,
+    ) -> PlexResult<Self> {
         if false_positive <= 0.0 || false_positive >= 1.0 {
             return Err(PlexError::BloomFilter(
                 "False positive must be between 0 and 1".to_string(),
@@ -27,11 +36,12 @@ impl BloomFilter {
         let hash_functions = Self::optimal_hash_functions(size, expected_elements);
 
         Ok(Self {
-            bit_array: vec![0; (size + 7) / 8],
+            bit_array: vec![0; size.div_ceil(8)],
             size,
             hash_functions,
             inserted_elements: 0,
             false_positive_rate: false_positive,
+            hash_family,
         })
     }
 
@@ -41,6 +51,7 @@ impl BloomFilter {
         hash_functions: u32,
         inserted_elements: u64,
         false_positive_rate: f64,
+        hash_family: HashFamily,
     ) -> Self {
         Self {
             bit_array,
@@ -48,9 +59,14 @@ impl BloomFilter {
             hash_functions,
             inserted_elements,
             false_positive_rate,
+            hash_family,
         }
     }
 
+    pub fn hash_family(&self) -> HashFamily {
+        self.hash_family
+    }
+
     fn optimal_size(expected_elements: usize, false_positive_rate: f64) -> usize {
         let ln2 = std::f64::consts::LN_2;
         let size = -(expected_elements as f64 * false_positive_rate.ln()) / (ln2 * ln2);
@@ -87,23 +103,7 @@ impl BloomFilter {
     }
 
     fn hash_element<T: Hash>(&self, element: &T) -> Vec<u64> {
-        let mut hashes = Vec::with_capacity(self.hash_functions as usize);
-
-        let mut hasher1 = DefaultHasher::new();
-        element.hash(&mut hasher1);
-        let hash1 = hasher1.finish();
-
-        let mut hasher2 = DefaultHasher::new();
-        hash1.hash(&mut hasher2);
-        element.hash(&mut hasher2);
-        let hash2 = hasher2.finish();
-
-        for i in 0..self.hash_functions {
-            let hash = hash1.wrapping_add((i as u64).wrapping_mul(hash2));
-            hashes.push(hash);
-        }
-
-        hashes
+        double_hash(element, self.hash_functions, self.hash_family)
     }
 
     fn set_bit(&mut self, index: usize) {
@@ -139,11 +139,8 @@ impl BloomFilter {
             return 0.0;
         }
 
-        let set_bits = self.count_set_bits();
-        let ratio = set_bits as f64 / self.size as f64;
-
         let exponent = -(self.hash_functions as f64 * self.inserted_elements as f64) / self.size as f64;
-        let base = 1.0 - (-exponent).exp();
+        let base = 1.0 - exponent.exp();
         base.powf(self.hash_functions as f64)
     }
 
@@ -165,6 +162,7 @@ impl BloomFilter {
             current_false_positive_rate: self.current_false_positive_rate(),
             target_false_positive_rate: self.false_positive_rate,
             memory_usage: self.bit_array.len(),
+            observed_false_positive_rate: None,
         }
     }
 
@@ -186,6 +184,9 @@ impl BloomFilter {
         Ok(())
     }
 
+    /// The hash family is stored with the bits, so a filter is always probed
+    /// with the hash it was built with. Files written without one fail to
+    /// deserialize instead of being read with the wrong hash.
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> PlexResult<Self> {
         let file = File::open(&path).map_err(|e| {
             Self::create_bloom_filter_error("open bloom filter file", e)
@@ -200,7 +201,10 @@ impl BloomFilter {
     }
 
     pub fn merge(&mut self, other: &BloomFilter) -> PlexResult<()> {
-        if self.size != other.size || self.hash_functions != other.hash_functions {
+        if self.size != other.size
+            || self.hash_functions != other.hash_functions
+            || self.hash_family != other.hash_family
+        {
             return Err(PlexError::BloomFilter(
                 "Cannot merge bloom filters with different parameters".to_string(),
             ));
@@ -219,6 +223,314 @@ impl BloomFilter {
     pub fn should_resize(&self) -> bool {
         self.current_false_positive_rate() > self.false_positive_rate * 2.0
     }
+
+    /// Replaces the filter with one sized for `expected` elements, keeping
+    /// the target rate and hash family, and inserts `keys` into it.
+    pub fn rebuild_from_keys<T: Hash>(
+        &mut self,
+        keys: impl Iterator<Item = T>,
+        expected: usize,
+    ) -> PlexResult<()> {
+        let mut rebuilt = Self::with_hash_family(expected.max(1), self.false_positive_rate, self.hash_family)?;
+        for key in keys {
+            rebuilt.insert(&key);
+        }
+
+        *self = rebuilt;
+        Ok(())
+    }
+}
+
+fn double_hash<T: Hash>(element: &T, hash_functions: u32, hash_family: HashFamily) -> Vec<u64> {
+    let mut hashes = Vec::with_capacity(hash_functions as usize);
+
+    let hash1 = hash_family.hash_one(element);
+    let hash2 = hash_family.hash_one((hash1, element));
+
+    for i in 0..hash_functions {
+        let hash = hash1.wrapping_add((i as u64).wrapping_mul(hash2));
+        hashes.push(hash);
+    }
+
+    hashes
+}
+
+const COUNTER_MAX: u8 = 0x0F;
+
+/// A bloom filter that keeps a 4-bit counter per slot instead of a single bit,
+/// so elements can be removed again. Counters saturate at 15 and are never
+/// decremented once saturated, so removal cannot introduce false negatives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountingBloomFilter {
+    counters: Vec<u8>,
+    size: usize,
+    hash_functions: u32,
+    inserted_elements: u64,
+    false_positive_rate: f64,
+    hash_family: HashFamily,
+}
+
+impl CountingBloomFilter {
+    pub fn new(expected_elements: usize, false_positive: f64) -> PlexResult<Self> {
+        Self::with_hash_family(expected_elements, false_positive, HashFamily::default())
+    }
+
+    pub fn with_hash_family(
+        expected_elements: usize,
+        false_positive: f64,
+        hash_family: HashFamily,
+    ) -> PlexResult<Self> {
+        if false_positive <= 0.0 || false_positive >= 1.0 {
+            return Err(PlexError::BloomFilter(
+                "False positive must be between 0 and 1".to_string(),
+            ));
+        }
+
+        let size = BloomFilter::optimal_size(expected_elements, false_positive);
+        let hash_functions = BloomFilter::optimal_hash_functions(size, expected_elements);
+
+        Ok(Self {
+            counters: vec![0; size.div_ceil(2)],
+            size,
+            hash_functions,
+            inserted_elements: 0,
+            false_positive_rate: false_positive,
+            hash_family,
+        })
+    }
+
+    pub fn insert<T: Hash>(&mut self, element: &T) {
+        for hash in double_hash(element, self.hash_functions, self.hash_family) {
+            let index = (hash % self.size as u64) as usize;
+            let counter = self.get_counter(index);
+            if counter < COUNTER_MAX {
+                self.set_counter(index, counter + 1);
+            }
+        }
+
+        self.inserted_elements += 1;
+    }
+
+    pub fn remove<T: Hash>(&mut self, element: &T) {
+        if !self.contains(element) {
+            return;
+        }
+
+        for hash in double_hash(element, self.hash_functions, self.hash_family) {
+            let index = (hash % self.size as u64) as usize;
+            let counter = self.get_counter(index);
+            if counter > 0 && counter < COUNTER_MAX {
+                self.set_counter(index, counter - 1);
+            }
+        }
+
+        self.inserted_elements = self.inserted_elements.saturating_sub(1);
+    }
+
+    pub fn contains<T: Hash>(&self, element: &T) -> bool {
+        double_hash(element, self.hash_functions, self.hash_family)
+            .into_iter()
+            .all(|hash| self.get_counter((hash % self.size as u64) as usize) > 0)
+    }
+
+    fn get_counter(&self, index: usize) -> u8 {
+        let byte = self.counters[index / 2];
+        if index.is_multiple_of(2) { byte & 0x0F } else { byte >> 4 }
+    }
+
+    fn set_counter(&mut self, index: usize, value: u8) {
+        let byte = &mut self.counters[index / 2];
+        if index.is_multiple_of(2) {
+            *byte = (*byte & 0xF0) | value;
+        } else {
+            *byte = (*byte & 0x0F) | (value << 4);
+        }
+    }
+
+    fn count_set_counters(&self) -> usize {
+        (0..self.size).filter(|&index| self.get_counter(index) > 0).count()
+    }
+
+    pub fn current_false_positive_rate(&self) -> f64 {
+        if self.inserted_elements == 0 {
+            return 0.0;
+        }
+
+        let exponent = -(self.hash_functions as f64 * self.inserted_elements as f64) / self.size as f64;
+        let base = 1.0 - exponent.exp();
+        base.powf(self.hash_functions as f64)
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.fill(0);
+        self.inserted_elements = 0;
+    }
+
+    pub fn stats(&self) -> BloomFilterStats {
+        BloomFilterStats {
+            size: self.size,
+            hash_functions: self.hash_functions,
+            inserted_elements: self.inserted_elements,
+            set_bits: self.count_set_counters(),
+            current_false_positive_rate: self.current_false_positive_rate(),
+            target_false_positive_rate: self.false_positive_rate,
+            memory_usage: self.counters.len(),
+            observed_false_positive_rate: None,
+        }
+    }
+
+    pub fn should_resize(&self) -> bool {
+        self.current_false_positive_rate() > self.false_positive_rate * 2.0
+    }
+
+    /// Replaces the filter with one sized for `expected` elements, keeping
+    /// the target rate and hash family, and inserts `keys` into it.
+    pub fn rebuild_from_keys<T: Hash>(
+        &mut self,
+        keys: impl Iterator<Item = T>,
+        expected: usize,
+    ) -> PlexResult<()> {
+        let mut rebuilt = Self::with_hash_family(expected.max(1), self.false_positive_rate, self.hash_family)?;
+        for key in keys {
+            rebuilt.insert(&key);
+        }
+
+        *self = rebuilt;
+        Ok(())
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> PlexResult<()> {
+        let file = File::create(&path).map_err(|e| {
+            BloomFilter::create_bloom_filter_error("create bloom filter file", e)
+        })?;
+
+        let writer = BufWriter::new(file);
+        bincode::serialize_into(writer, self).map_err(|e| {
+            BloomFilter::create_bloom_filter_error("serialize bloom filter", e)
+        })?;
+
+        Ok(())
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> PlexResult<Self> {
+        let file = File::open(&path).map_err(|e| {
+            BloomFilter::create_bloom_filter_error("open bloom filter file", e)
+        })?;
+
+        let reader = BufReader::new(file);
+        let filter = bincode::deserialize_from(reader).map_err(|e| {
+            BloomFilter::create_bloom_filter_error("deserialize bloom filter", e)
+        })?;
+
+        Ok(filter)
+    }
+}
+
+const SCALABLE_GROWTH_FACTOR: usize = 2;
+const SCALABLE_TIGHTENING_RATIO: f64 = 0.5;
+
+/// A bloom filter that grows by adding sub-filters instead of being rebuilt.
+/// Each new sub-filter is twice as large with a tighter false-positive rate,
+/// so the compound rate stays close to the configured target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScalableBloomFilter {
+    filters: Vec<BloomFilter>,
+    initial_capacity: usize,
+    false_positive_rate: f64,
+}
+
+impl ScalableBloomFilter {
+    pub fn new(initial_capacity: usize, false_positive: f64) -> PlexResult<Self> {
+        let filter = BloomFilter::new(initial_capacity, false_positive * SCALABLE_TIGHTENING_RATIO)?;
+
+        Ok(Self {
+            filters: vec![filter],
+            initial_capacity,
+            false_positive_rate: false_positive,
+        })
+    }
+
+    pub fn insert<T: Hash>(&mut self, element: &T) -> PlexResult<()> {
+        if self.newest_is_full() {
+            self.grow()?;
+        }
+
+        if let Some(filter) = self.filters.last_mut() {
+            filter.insert(element);
+        }
+        Ok(())
+    }
+
+    pub fn contains<T: Hash>(&self, element: &T) -> bool {
+        self.filters.iter().any(|filter| filter.contains(element))
+    }
+
+    fn capacity_of(&self, generation: usize) -> usize {
+        self.initial_capacity * SCALABLE_GROWTH_FACTOR.pow(generation as u32)
+    }
+
+    fn false_positive_rate_of(&self, generation: usize) -> f64 {
+        self.false_positive_rate * SCALABLE_TIGHTENING_RATIO.powi(generation as i32 + 1)
+    }
+
+    fn newest_is_full(&self) -> bool {
+        match self.filters.last() {
+            Some(filter) => {
+                let capacity = self.capacity_of(self.filters.len() - 1);
+                filter.inserted_elements as usize >= capacity || filter.should_resize()
+            }
+            None => true,
+        }
+    }
+
+    fn grow(&mut self) -> PlexResult<()> {
+        let generation = self.filters.len();
+        let filter = BloomFilter::new(
+            self.capacity_of(generation),
+            self.false_positive_rate_of(generation),
+        )?;
+
+        self.filters.push(filter);
+        Ok(())
+    }
+
+    pub fn sub_filter_count(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn clear(&mut self) -> PlexResult<()> {
+        *self = Self::new(self.initial_capacity, self.false_positive_rate)?;
+        Ok(())
+    }
+
+    /// Aggregate stats across all sub-filters. The current false-positive
+    /// rate is the chance that at least one sub-filter reports a false hit.
+    pub fn stats(&self) -> BloomFilterStats {
+        let mut stats = BloomFilterStats {
+            size: 0,
+            hash_functions: 0,
+            inserted_elements: 0,
+            set_bits: 0,
+            current_false_positive_rate: 0.0,
+            target_false_positive_rate: self.false_positive_rate,
+            memory_usage: 0,
+            observed_false_positive_rate: None,
+        };
+        let mut miss_probability = 1.0;
+
+        for filter in &self.filters {
+            let filter_stats = filter.stats();
+            stats.size += filter_stats.size;
+            stats.hash_functions = stats.hash_functions.max(filter_stats.hash_functions);
+            stats.inserted_elements += filter_stats.inserted_elements;
+            stats.set_bits += filter_stats.set_bits;
+            stats.memory_usage += filter_stats.memory_usage;
+            miss_probability *= 1.0 - filter_stats.current_false_positive_rate;
+        }
+
+        stats.current_false_positive_rate = 1.0 - miss_probability;
+        stats
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -230,11 +542,17 @@ pub struct BloomFilterStats {
     pub current_false_positive_rate: f64,
     pub target_false_positive_rate: f64,
     pub memory_usage: usize,
+    /// Share of absent-key lookups the filter let through, measured over the
+    /// last full window by whoever owns the filter. `None` until measured.
+    pub observed_false_positive_rate: Option<f64>,
 }
 
 impl BloomFilterStats {
     pub fn is_healthy(&self) -> bool {
         self.current_false_positive_rate <= self.target_false_positive_rate * 1.5
+            && self
+                .observed_false_positive_rate
+                .is_none_or(|observed| observed <= self.target_false_positive_rate * 2.0)
     }
 }
 
@@ -242,7 +560,6 @@ impl BloomFilterStats {
 pub struct BloomFilterCollection {
     filters: Vec<BloomFilter>,
     default_capacity: usize,
-    default_fp_rate: f64,
 }
 
 impl BloomFilterCollection {
@@ -257,7 +574,6 @@ impl BloomFilterCollection {
         Ok(Self {
             filters,
             default_capacity,
-            default_fp_rate,
         })
     }
 
@@ -295,16 +611,36 @@ impl BloomFilterCollection {
         self.filters.iter().map(|f| f.stats()).collect()
     }
 
-    pub fn rebuild_degraded_filters(&mut self) -> PlexResult<()> {
-        for filter in &mut self.filters {
-            if filter.should_resize() {
-                let new_filter = BloomFilter::new(self.default_capacity, self.default_fp_rate)?;
-                *filter = new_filter;
+    /// Rebuilds every filter past its target false positive rate from the
+    /// live keys of its partition, `partition_keys[partition_id]`, so the
+    /// rebuilt filter still contains everything the old one did.
+    pub fn rebuild_degraded_filters<S: AsRef<str>>(&mut self, partition_keys: &[Vec<S>]) -> PlexResult<()> {
+        for partition_id in 0..self.filters.len() {
+            if !self.filters[partition_id].should_resize() {
+                continue;
             }
+
+            let keys = partition_keys.get(partition_id).ok_or_else(|| {
+                PlexError::Config(format!("No keys given for partition ID: {}", partition_id))
+            })?;
+            self.rebuild_from_keys(partition_id, keys.iter().map(AsRef::as_ref), keys.len())?;
         }
         Ok(())
     }
 
+    /// Replaces a partition's filter with a fresh one holding `keys`, sized
+    /// for `expected` keys but never below the collection's default.
+    pub fn rebuild_from_keys<T: Hash>(
+        &mut self,
+        partition_id: usize,
+        keys: impl Iterator<Item = T>,
+        expected: usize,
+    ) -> PlexResult<()> {
+        self.validate_partition_id(partition_id)?;
+
+        self.filters[partition_id].rebuild_from_keys(keys, expected.max(self.default_capacity))
+    }
+
     pub fn save_to_directory<P: AsRef<Path>>(&self, dir_path: P) -> PlexResult<()> {
         let dir = dir_path.as_ref();
         std::fs::create_dir_all(dir).map_err(|e| {
@@ -328,7 +664,7 @@ impl BloomFilterCollection {
             .filter_map(|entry| entry.ok())
             .filter(|entry| {
                 entry.path().extension()
-                    .map_or(false, |ext| ext == "bf")
+                    .is_some_and(|ext| ext == "bf")
             })
             .collect();
 
@@ -344,12 +680,172 @@ impl BloomFilterCollection {
         }
 
         let default_capacity = filters[0].inserted_elements.max(1000) as usize;
-        let default_fp_rate = filters[0].false_positive_rate;
 
         Ok(Self {
             filters,
             default_capacity,
-            default_fp_rate,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn counting_filter_forgets_removed_elements() {
+        let mut filter = CountingBloomFilter::new(1_000, 0.01).unwrap();
+        filter.insert(&"a");
+        filter.insert(&"b");
+
+        filter.remove(&"a");
+
+        assert!(!filter.contains(&"a"));
+        assert!(filter.contains(&"b"));
+        assert_eq!(filter.stats().inserted_elements, 1);
+    }
+
+    #[test]
+    fn counting_filter_stays_near_its_false_positive_rate() {
+        let mut filter = CountingBloomFilter::new(1_000, 0.01).unwrap();
+        for i in 0..1_000 {
+            filter.insert(&format!("in{}", i));
+        }
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(&format!("out{}", i)))
+            .count();
+
+        // 1% target; allow some slack for an unlucky hash spread.
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn removing_an_absent_element_changes_nothing() {
+        let mut filter = CountingBloomFilter::new(1_000, 0.01).unwrap();
+        filter.insert(&"a");
+
+        filter.remove(&"never-inserted");
+
+        assert!(filter.contains(&"a"));
+        assert_eq!(filter.stats().inserted_elements, 1);
+    }
+
+    #[test]
+    fn scalable_filter_adds_sub_filters_as_it_fills() {
+        let mut filter = ScalableBloomFilter::new(100, 0.01).unwrap();
+        assert_eq!(filter.sub_filter_count(), 1);
+
+        for i in 0..1_000 {
+            filter.insert(&i).unwrap();
+        }
+
+        assert!(filter.sub_filter_count() > 1);
+        assert!((0..1_000).all(|i| filter.contains(&i)));
+    }
+
+    #[test]
+    fn scalable_filter_keeps_its_target_rate() {
+        let mut filter = ScalableBloomFilter::new(100, 0.01).unwrap();
+        for i in 0..1_000 {
+            filter.insert(&i).unwrap();
+        }
+
+        let false_positives = (1_000..11_000).filter(|i| filter.contains(i)).count();
+
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert!(filter.stats().current_false_positive_rate <= 0.02);
+    }
+
+    #[test]
+    fn clearing_drops_back_to_one_sub_filter() {
+        let mut filter = ScalableBloomFilter::new(10, 0.01).unwrap();
+        for i in 0..100 {
+            filter.insert(&i).unwrap();
+        }
+
+        filter.clear().unwrap();
+
+        assert_eq!(filter.sub_filter_count(), 1);
+        assert!(!filter.contains(&1));
+    }
+
+    #[test]
+    fn reloaded_filter_answers_membership_the_same() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("bloom.bin");
+
+        let mut filter = BloomFilter::with_hash_family(1_000, 0.01, HashFamily::Fnv1a).unwrap();
+        for i in 0..500 {
+            filter.insert(&format!("key{}", i));
+        }
+        filter.save_to_file(&path).unwrap();
+
+        let loaded = BloomFilter::load_from_file(&path).unwrap();
+        assert_eq!(loaded.hash_family(), HashFamily::Fnv1a);
+        for i in 0..500 {
+            assert!(loaded.contains(&format!("key{}", i)));
+        }
+        for i in 0..1_000 {
+            let key = format!("other{}", i);
+            assert_eq!(loaded.contains(&key), filter.contains(&key));
+        }
+    }
+
+    #[test]
+    fn reloaded_counting_filter_keeps_its_hash_family() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("counting.bin");
+
+        let mut filter =
+            CountingBloomFilter::with_hash_family(1_000, 0.01, HashFamily::XxHash64).unwrap();
+        filter.insert(&"a");
+        filter.insert(&"b");
+        filter.save_to_file(&path).unwrap();
+
+        let mut loaded = CountingBloomFilter::load_from_file(&path).unwrap();
+        assert!(loaded.contains(&"a"));
+        loaded.remove(&"a");
+        assert!(!loaded.contains(&"a"));
+        assert!(loaded.contains(&"b"));
+    }
+
+    #[test]
+    fn filters_with_different_hash_families_do_not_merge() {
+        let mut xx = BloomFilter::with_hash_family(1_000, 0.01, HashFamily::XxHash64).unwrap();
+        let fnv = BloomFilter::with_hash_family(1_000, 0.01, HashFamily::Fnv1a).unwrap();
+
+        assert!(xx.merge(&fnv).is_err());
+    }
+
+    #[test]
+    fn rebuilding_a_degraded_filter_keeps_every_key() {
+        let mut collection = BloomFilterCollection::new(2, 100, 0.01).unwrap();
+        let keys: Vec<String> = (0..5_000).map(|i| format!("in{}", i)).collect();
+        for key in &keys {
+            collection.insert(0, key).unwrap();
+        }
+        assert!(collection.get_filter(0).unwrap().should_resize());
+
+        collection.rebuild_degraded_filters(&[keys.clone(), Vec::new()]).unwrap();
+
+        let filter = collection.get_filter(0).unwrap();
+        assert!(!filter.should_resize());
+        assert!(keys.iter().all(|key| filter.contains(key)));
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(&format!("out{}", i)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn rebuild_needs_keys_for_every_degraded_partition() {
+        let mut collection = BloomFilterCollection::new(2, 10, 0.01).unwrap();
+        for i in 0..1_000 {
+            collection.insert(1, &i).unwrap();
+        }
+
+        assert!(collection.rebuild_degraded_filters::<String>(&[Vec::new()]).is_err());
+    }
+}
@@ -1,70 +1,63 @@
-use super::{Cache, CacheStats};
-use crate::utils::comprssion::Compressor;
+use super::Cache;
+use crate::utils::compression::Compressor;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 
+/// Stores values serialized with bincode and compressed in an inner byte
+/// cache, trading CPU on every access for a smaller footprint.
 pub struct CompressedCache<K, V> {
     inner: Arc<dyn Cache<K, Vec<u8>> + Send + Sync>,
     compressor: Arc<dyn Compressor + Send + Sync>,
-
+    _value: PhantomData<fn() -> V>,
 }
 
 impl<K, V> CompressedCache<K, V>
 where
-    K: Clone + Send + Sync 'static,
-    V: Clone + Send + Sync serde::Serialize + serde::de::DeserializedOwned + 'static,
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
 {
-
     pub fn new(
-        inner: Arc<dyn Cache<K, Vec<<u8>> + Send + Sync>,
-        compressore: Arc<dyn Compressor + Send + Sync>,
+        inner: Arc<dyn Cache<K, Vec<u8>> + Send + Sync>,
+        compressor: Arc<dyn Compressor + Send + Sync>,
     ) -> Self {
-        Self { inner, compressor }
+        Self {
+            inner,
+            compressor,
+            _value: PhantomData,
+        }
     }
 
+    fn decode(&self, compressed_data: &[u8]) -> Option<V> {
+        let data = self.compressor.decompress(compressed_data).ok()?;
+        bincode::deserialize(&data).ok()
+    }
 }
+
+#[async_trait]
 impl<K, V> Cache<K, V> for CompressedCache<K, V>
 where
-
     K: Clone + Send + Sync + 'static,
-    V: Clone + Send + Sync + serde::de::Serialize + serde::de::DeserializOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
 {
-
-    async fn get(&self, key: &k) -> Option<V> {
-        if let Some(compressed_data) = self.inner.get(key).await {
-            match self.compressor.decompress(&compressed_data) {
-                Ok(data) => match bincode::deserialize(&data) {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
-
-                },
-                Err(_) => None,
-            }
-        } else {
-            None
-        }
+    async fn get(&self, key: &K) -> Option<V> {
+        let compressed_data = self.inner.get(key).await?;
+        self.decode(&compressed_data)
     }
-    async fn set(&self, key: K, value: V) ->  Option<V> {
-        if let Ok(serialized) = bincode::serialize(&value) {
-            if let Ok(compressed) = self.compressor.compress(&serialized) {
-                self.inner.set(key, compressed).await;
-            }
+
+    async fn set(&self, key: K, value: V) {
+        if let Ok(serialized) = bincode::serialize(&value)
+            && let Ok(compressed) = self.compressor.compress(&serialized)
+        {
+            self.inner.set(key, compressed).await;
         }
     }
 
-    async fn remove(&self, key: &k) -> Option<V> {
-        if let Some(compressed_data) = self.inner.remove(key).await {
-            match self.compressor.decompress(&compressed_data) {
-                Ok(data) => match bincode::deserialize(&data) {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
-                },
-                Err(_) => None,
-            }
-        
-        } else {
-            None
-        }
+    async fn remove(&self, key: &K) -> Option<V> {
+        let compressed_data = self.inner.remove(key).await?;
+        self.decode(&compressed_data)
     }
 
     async fn clear(&self) {
@@ -79,4 +72,3 @@ where
         self.inner.capacity().await
     }
 }
-
@@ -0,0 +1,149 @@
+use super::{Cache, CacheStats};
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+struct FifoState<K, V> {
+    entries: HashMap<K, V>,
+    // Insertion order, oldest first. Reads never reorder it.
+    order: VecDeque<K>,
+}
+
+pub struct AsyncFifoCache<K, V> {
+    state: Arc<RwLock<FifoState<K, V>>>,
+    capacity: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+
+}
+
+impl<K, V> AsyncFifoCache<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(FifoState {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+            capacity,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            evictions: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size: self.state.read().await.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for AsyncFifoCache<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static
+{
+
+    async fn get(&self, key: &K) -> Option<V> {
+        let state = self.state.read().await;
+
+        if let Some(value) = state.entries.get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            Some(value.clone())
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    async fn set(&self, key: K, value: V) {
+        let mut state = self.state.write().await;
+
+        // Overwriting keeps the key's original position in the queue.
+        if let Some(existing) = state.entries.get_mut(&key) {
+            *existing = value;
+            return;
+        }
+
+        if state.entries.len() >= self.capacity && let Some(oldest) = state.order.pop_front() {
+            state.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        state.order.push_back(key.clone());
+        state.entries.insert(key, value);
+    }
+
+    async fn remove(&self, key: &K) -> Option<V> {
+        let mut state = self.state.write().await;
+
+        let value = state.entries.remove(key)?;
+        state.order.retain(|queued| queued != key);
+        Some(value)
+    }
+
+    async fn clear(&self) {
+        let mut state = self.state.write().await;
+        state.entries.clear();
+        state.order.clear();
+    }
+
+    async fn size(&self) -> usize {
+        self.state.read().await.entries.len()
+    }
+
+    async fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_do_not_save_the_oldest_entry() {
+        let cache = AsyncFifoCache::new(2);
+        cache.set("a", 1).await;
+        cache.set("b", 2).await;
+
+        for _ in 0..10 {
+            assert_eq!(cache.get(&"a").await, Some(1));
+        }
+        cache.set("c", 3).await;
+
+        assert_eq!(cache.get(&"a").await, None);
+        assert_eq!(cache.get(&"b").await, Some(2));
+        assert_eq!(cache.get(&"c").await, Some(3));
+        assert_eq!(cache.stats().await.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn removed_entries_free_their_slot() {
+        let cache = AsyncFifoCache::new(2);
+        cache.set("a", 1).await;
+        cache.set("b", 2).await;
+
+        assert_eq!(cache.remove(&"a").await, Some(1));
+        cache.set("c", 3).await;
+
+        assert_eq!(cache.size().await, 2);
+        assert_eq!(cache.get(&"b").await, Some(2));
+        assert_eq!(cache.stats().await.evictions, 0);
+    }
+}
@@ -0,0 +1,198 @@
+use super::{Cache, CacheStats};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+struct LfuEntry<V> {
+    value: V,
+    frequency: u64,
+    last_access: u64,
+}
+
+struct LfuState<K, V> {
+    entries: HashMap<K, LfuEntry<V>>,
+    // Ordered by (frequency, last_access) so the first element is always the
+    // least frequently used entry, ties broken by the least recent access.
+    order: BTreeMap<(u64, u64), K>,
+    tick: u64,
+}
+
+impl<K, V> LfuState<K, V>
+where
+    K: Clone + Eq + Hash,
+{
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn touch(&mut self, key: &K) -> Option<&mut LfuEntry<V>> {
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+
+        self.order.remove(&(entry.frequency, entry.last_access));
+        entry.frequency += 1;
+        entry.last_access = tick;
+        self.order.insert((entry.frequency, entry.last_access), key.clone());
+
+        Some(entry)
+    }
+
+    fn evict_least_frequent(&mut self) -> Option<(K, V)> {
+        let (_, key) = self.order.pop_first()?;
+        let entry = self.entries.remove(&key)?;
+        Some((key, entry.value))
+    }
+}
+
+pub struct AsyncLfuCache<K, V> {
+    state: Arc<RwLock<LfuState<K, V>>>,
+    capacity: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+
+}
+
+impl<K, V> AsyncLfuCache<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(LfuState {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+            })),
+            capacity,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            evictions: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size: self.state.read().await.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for AsyncLfuCache<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static
+{
+
+    async fn get(&self, key: &K) -> Option<V> {
+        let mut state = self.state.write().await;
+
+        if let Some(entry) = state.touch(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            Some(entry.value.clone())
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    async fn set(&self, key: K, value: V) {
+        let mut state = self.state.write().await;
+
+        if let Some(entry) = state.touch(&key) {
+            entry.value = value;
+            return;
+        }
+
+        if state.entries.len() >= self.capacity && state.evict_least_frequent().is_some() {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let tick = state.next_tick();
+        state.order.insert((1, tick), key.clone());
+        state.entries.insert(key, LfuEntry {
+            value,
+            frequency: 1,
+            last_access: tick,
+        });
+    }
+
+    async fn remove(&self, key: &K) -> Option<V> {
+        let mut state = self.state.write().await;
+
+        let entry = state.entries.remove(key)?;
+        state.order.remove(&(entry.frequency, entry.last_access));
+        Some(entry.value)
+    }
+
+    async fn clear(&self) {
+        let mut state = self.state.write().await;
+        state.entries.clear();
+        state.order.clear();
+    }
+
+    async fn size(&self) -> usize {
+        self.state.read().await.entries.len()
+    }
+
+    async fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn evicts_the_least_frequently_used_entry() {
+        let cache = AsyncLfuCache::new(2);
+        cache.set("a", 1).await;
+        cache.set("b", 2).await;
+
+        cache.get(&"a").await;
+        cache.get(&"a").await;
+        cache.get(&"b").await;
+
+        cache.set("c", 3).await;
+
+        assert_eq!(cache.get(&"b").await, None);
+        assert_eq!(cache.get(&"a").await, Some(1));
+        assert_eq!(cache.get(&"c").await, Some(3));
+        assert_eq!(cache.stats().await.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn ties_evict_the_least_recently_used() {
+        let cache = AsyncLfuCache::new(2);
+        cache.set("a", 1).await;
+        cache.set("b", 2).await;
+
+        cache.set("c", 3).await;
+
+        assert_eq!(cache.get(&"a").await, None);
+        assert_eq!(cache.get(&"b").await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn overwriting_keeps_the_size() {
+        let cache = AsyncLfuCache::new(2);
+        cache.set("a", 1).await;
+        cache.set("a", 2).await;
+
+        assert_eq!(cache.size().await, 1);
+        assert_eq!(cache.get(&"a").await, Some(2));
+    }
+}
@@ -1,28 +1,33 @@
 use super::{Cache, CacheStats};
-use crate::error::PlexError;
-use std::collections::HashMaps;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::future::Future;
 use std::hash::Hash;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
+
+type NodeRef<K, V> = Arc<RwLock<LruNode<K, V>>>;
 
 struct LruNode<K, V> {
     key: K,
     value: V,
-    prev: Option<Arc<RwLock<LruNode<K, V>>>>,
-    next: Option<Arc<RwLock<LruNode<K, V>>>>,
+    prev: Option<NodeRef<K, V>>,
+    next: Option<NodeRef<K, V>>,
 
 }
 
 pub struct AsyncLruCache<K, V> {
-    map: Arc<RwLock<HashMap<K, Arc<RwLock<LruNode<K, V>>>>>>,
-    head: Arc<RwLock<Option<Arc<RwLock<LruNode<K, V>>>>>>,
-    tail: Arc<RwLock<Option<Arc<RwLock<LruNode<K, V>>>>>>,
+    map: Arc<RwLock<HashMap<K, NodeRef<K, V>>>>,
+    head: Arc<RwLock<Option<NodeRef<K, V>>>>,
+    tail: Arc<RwLock<Option<NodeRef<K, V>>>>,
     capacity: usize,
     size: Arc<AtomicU64>,
     hits: Arc<AtomicU64>,
     misses: Arc<AtomicU64>,
     evictions: Arc<AtomicU64>,
+    in_flight: Arc<Mutex<HashMap<K, Arc<Mutex<()>>>>>,
+    on_evict: Option<Box<dyn Fn(K, V) + Send + Sync>>,
 
 }
 
@@ -42,9 +47,57 @@ where
             hits: Arc::new(AtomicU64::new(0)),
             misses: Arc::new(AtomicU64::new(0)),
             evictions: Arc::new(AtomicU64::new(0)),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            on_evict: None,
         }
     }
 
+    /// Like `new`, but `on_evict` is called with every entry pushed out by a
+    /// capacity eviction, before it is dropped. Entries removed by `remove`
+    /// or `clear` are not passed to it.
+    pub fn with_on_evict<F>(capacity: usize, on_evict: F) -> Self
+    where
+        F: Fn(K, V) + Send + Sync + 'static,
+    {
+        Self {
+            on_evict: Some(Box::new(on_evict)),
+            ..Self::new(capacity)
+        }
+    }
+
+    /// Returns the cached value for `key`, computing it with `producer` on a
+    /// miss. Concurrent callers missing on the same key wait for the first
+    /// one's result instead of running `producer` themselves.
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, producer: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        if let Some(value) = self.get(&key).await {
+            return value;
+        }
+
+        let key_lock = {
+            let mut in_flight = self.in_flight.lock().await;
+            in_flight
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(Mutex::new(())))
+                .clone()
+        };
+        let _guard = key_lock.lock().await;
+
+        // Whoever held the lock before us has most likely filled the entry.
+        if let Some(value) = self.get(&key).await {
+            return value;
+        }
+
+        let value = producer().await;
+        self.set(key.clone(), value.clone()).await;
+        self.in_flight.lock().await.remove(&key);
+
+        value
+    }
+
 
     pub async fn stats(&self) -> CacheStats {
         CacheStats {
@@ -56,12 +109,20 @@ where
         }
     }
 
-    async fn move_head(&self, node: Arc<RwLock<LruNode<K, V>>>) {
+    /// Zeroes the hit, miss and eviction counters. The cached entries are
+    /// left alone.
+    pub async fn reset_stats(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+    }
+
+    async fn move_head(&self, node: NodeRef<K, V>) {
         self.remove_node(node.clone()).await;
         self.add_to_head(node).await;
     }
 
-    async fn remove_node(&self, node: Arc<RwLock<LruNode<K, V>>>) {
+    async fn remove_node(&self, node: NodeRef<K, V>) {
         let node_guard = node.read().await;
         
         if let Some(prev) = &node_guard.prev {
@@ -73,14 +134,14 @@ where
         if let Some(next) = &node_guard.next {
             next.write().await.prev = node_guard.prev.clone();
         } else {
-            *self.tail.write().await( = node_guard.prev.clone();
+            *self.tail.write().await = node_guard.prev.clone();
         }
     }
 
-    async fn add_to_head(&self, node: Arc<RwLock<LruNode<K, V>>>) {
+    async fn add_to_head(&self, node: NodeRef<K, V>) {
         let mut head_guard = self.head.write().await;
 
-        if let Some(old_head) = &*head_guard) {
+        if let Some(old_head) = &*head_guard {
             old_head.write().await.prev = Some(node.clone());
             node.write().await.next = Some(old_head.clone());
         } else {
@@ -91,8 +152,8 @@ where
         *head_guard = Some(node);
     }
 
-    async fn remove_tail(&self) -> Option<Arc<RwLock<LruNode<K, V>>>> {
-        let tail = self.tail.read().await;
+    async fn remove_tail(&self) -> Option<NodeRef<K, V>> {
+        let tail_guard = self.tail.read().await;
         if let Some(tail) = &*tail_guard {
             let tail_clone = tail.clone();
             drop(tail_guard);
@@ -105,19 +166,20 @@ where
 
 }
 
+#[async_trait]
 impl<K, V> Cache<K, V> for AsyncLruCache<K, V>
 where
     K: Clone + Eq + Hash + Send + Sync + 'static,
     V: Clone + Send + Sync + 'static
 {
 
-    async fn get(&self, key: &k) -> Option<V> {
+    async fn get(&self, key: &K) -> Option<V> {
         let map_guard = self.map.read().await;
-        if let Some(node) = map_get_key(key) {
+        if let Some(node) = map_guard.get(key) {
             let node_clone = node.clone();
             drop(map_guard);
 
-            self.move_to_head(node_clone.clone()).await;
+            self.move_head(node_clone.clone()).await;
             self.hits.fetch_add(1, Ordering::Relaxed);
 
             Some(node_clone.read().await.value.clone())
@@ -130,15 +192,15 @@ where
     async fn set(&self, key: K, value: V) {
         let mut map_guard = self.map.write().await;
         
-        if let Some(exiting_node) = map_guard.get(&key) {
+        if let Some(existing_node) = map_guard.get(&key) {
             let node_clone = existing_node.clone();
             drop(map_guard);
 
             node_clone.write().await.value = value;
-            self.move_to_head(node_clone).await;
+            self.move_head(node_clone).await;
         } else {
             let new_node = Arc::new(RwLock::new(LruNode {
-                key: key.clone,
+                key: key.clone(),
                 value,
                 prev: None,
                 next: None,
@@ -150,14 +212,20 @@ where
             self.add_to_head(new_node).await;
             self.size.fetch_add(1, Ordering::Relaxed);
 
-            if self.size.load(Ordering::Relaxed) as usize > self.capacity {
-                if let Some(tail) = self.remove_tail.key.await {
-                    let tail_key = tail.read().await.key.clone();
+            if self.size.load(Ordering::Relaxed) as usize > self.capacity
+                && let Some(tail) = self.remove_tail().await {
+                    let (tail_key, tail_value) = {
+                        let tail = tail.read().await;
+                        (tail.key.clone(), tail.value.clone())
+                    };
                     self.map.write().await.remove(&tail_key);
                     self.size.fetch_sub(1, Ordering::Relaxed);
                     self.evictions.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(on_evict) = &self.on_evict {
+                        on_evict(tail_key, tail_value);
+                    }
                 }
-            }
         }
     }
 
@@ -195,3 +263,69 @@ where
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_misses_run_the_producer_once() {
+        let cache: Arc<AsyncLruCache<String, u64>> = Arc::new(AsyncLruCache::new(16));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let cache = cache.clone();
+                let calls = calls.clone();
+                tokio::spawn(async move {
+                    cache
+                        .get_or_insert_with("key".to_string(), || async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            42
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cached_values_skip_the_producer() {
+        let cache: AsyncLruCache<&str, u64> = AsyncLruCache::new(16);
+        cache.set("key", 1).await;
+
+        let value = cache.get_or_insert_with("key", || async { panic!("producer ran") }).await;
+
+        assert_eq!(value, 1);
+    }
+
+    #[tokio::test]
+    async fn on_evict_receives_evicted_entries_in_order() {
+        let evicted = Arc::new(StdMutex::new(Vec::new()));
+        let sink = Arc::clone(&evicted);
+        let cache = AsyncLruCache::with_on_evict(2, move |key: u32, value: String| {
+            sink.lock().unwrap().push((key, value));
+        });
+
+        cache.set(1, "one".to_string()).await;
+        cache.set(2, "two".to_string()).await;
+        cache.get(&1).await;
+        cache.set(3, "three".to_string()).await;
+        cache.set(4, "four".to_string()).await;
+        cache.remove(&4).await;
+
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(2, "two".to_string()), (1, "one".to_string())]
+        );
+    }
+}
@@ -1,15 +1,25 @@
+pub mod bloom_filter;
 pub mod lru_cache;
+pub mod lfu_cache;
+pub mod fifo_cache;
 pub mod block_cache;
 pub mod compressed_cache;
+pub mod ttl_cache;
+pub mod sized_lru_cache;
+pub mod sync_lru_cache;
 
-use crate::error::PlexError;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use async_trait::async_trait;
+use std::ops::Sub;
 
+/// A key-value cache shared across tasks. Implementations go through
+/// `#[async_trait]`, since caches are stacked as `Arc<dyn Cache<K, V>>` and
+/// native `async fn` in traits cannot be called through `dyn`.
+#[async_trait]
 pub trait Cache<K, V> {
     async fn get(&self, key: &K) -> Option<V>;
-    async gn set(&self, key: K, value: V);
-    async fn remove(&self, key: &K) > Option<V>;
+    async fn set(&self, key: K, value: V);
+    /// Removes `key`, returning the value it held.
+    async fn remove(&self, key: &K) -> Option<V>;
     async fn clear(&self);
     async fn size(&self) -> usize;
     async fn capacity(&self) -> usize;
@@ -20,7 +30,7 @@ pub struct CacheStats {
     pub hits: u64,
     pub misses: u64,
     pub evictions: u64,
-    pub size: usize;
+    pub size: usize,
     pub capacity: usize,
 
 }
@@ -35,4 +45,114 @@ impl CacheStats {
         }
 
     }
+
+    /// Hit rate over the interval since `prev`, an earlier snapshot of the
+    /// same cache. Lets a poller report recent behaviour rather than the
+    /// lifetime average.
+    pub fn rate_since(&self, prev: &CacheStats) -> f64 {
+        (self - prev).hit_rates()
+    }
+}
+
+/// The counters accumulated between two snapshots, `self` being the later
+/// one. `size` and `capacity` are not counters, so they are taken from
+/// `self`. Counters saturate at zero in case the cache's stats were reset
+/// in between.
+impl Sub for &CacheStats {
+    type Output = CacheStats;
+
+    fn sub(self, prev: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits.saturating_sub(prev.hits),
+            misses: self.misses.saturating_sub(prev.misses),
+            evictions: self.evictions.saturating_sub(prev.evictions),
+            size: self.size,
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl Sub for CacheStats {
+    type Output = CacheStats;
+
+    fn sub(self, prev: CacheStats) -> CacheStats {
+        &self - &prev
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::compressed_cache::CompressedCache;
+    use crate::cache::fifo_cache::AsyncFifoCache;
+    use crate::cache::lfu_cache::AsyncLfuCache;
+    use crate::cache::lru_cache::AsyncLruCache;
+    use crate::cache::sized_lru_cache::SizedLruCache;
+    use crate::cache::ttl_cache::TtlCache;
+    use crate::utils::compression::ZstdCompressor;
+    use std::sync::Arc;
+
+    // Fails to compile if `Cache` stops being object safe.
+    fn _assert_object_safe(_: &dyn Cache<String, String>) {}
+
+    #[tokio::test]
+    async fn every_cache_works_as_a_trait_object() {
+        let caches: Vec<Arc<dyn Cache<String, String> + Send + Sync>> = vec![
+            Arc::new(AsyncLruCache::new(4)),
+            Arc::new(AsyncLfuCache::new(4)),
+            Arc::new(AsyncFifoCache::new(4)),
+            Arc::new(SizedLruCache::new(4096)),
+            Arc::new(TtlCache::new(Arc::new(AsyncLruCache::new(4)), 60)),
+            Arc::new(CompressedCache::new(
+                Arc::new(AsyncLruCache::new(4)),
+                Arc::new(ZstdCompressor::new(3)),
+            )),
+        ];
+
+        for cache in caches {
+            _assert_object_safe(cache.as_ref());
+
+            cache.set("key".to_string(), "value".to_string()).await;
+            assert_eq!(cache.get(&"key".to_string()).await.as_deref(), Some("value"));
+            // `SizedLruCache` counts bytes rather than entries.
+            assert!(cache.size().await > 0);
+
+            assert_eq!(cache.remove(&"key".to_string()).await.as_deref(), Some("value"));
+            assert_eq!(cache.get(&"key".to_string()).await, None);
+
+            cache.set("key".to_string(), "value".to_string()).await;
+            cache.clear().await;
+            assert_eq!(cache.size().await, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn interval_hit_rate_only_counts_lookups_since_the_snapshot() {
+        let cache = AsyncLruCache::new(16);
+        for i in 0..8 {
+            cache.set(i, i).await;
+        }
+        // A lifetime of misses that the interval rate must not see.
+        for i in 100..120 {
+            cache.get(&i).await;
+        }
+        let before = cache.stats().await;
+
+        // Three hits for every miss.
+        for i in 0..6 {
+            cache.get(&i).await;
+        }
+        cache.get(&200).await;
+        cache.get(&201).await;
+        let after = cache.stats().await;
+
+        assert_eq!(after.rate_since(&before), 0.75);
+        let delta = &after - &before;
+        assert_eq!((delta.hits, delta.misses, delta.size), (6, 2, 8));
+
+        cache.reset_stats().await;
+        let reset = cache.stats().await;
+        assert_eq!((reset.hits, reset.misses, reset.evictions, reset.size), (0, 0, 0, 8));
+        assert_eq!((reset - after).hits, 0);
+    }
 }
@@ -0,0 +1,239 @@
+use super::{Cache, CacheStats};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Bytes a value owns on the heap, not counting its inline `size_of`.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for Vec<u8> {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for u64 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+struct SizedEntry<V> {
+    value: V,
+    bytes: usize,
+    last_used: u64,
+}
+
+struct SizedLruState<K, V> {
+    entries: HashMap<K, SizedEntry<V>>,
+    // Keys by the tick of their last use; the first entry is the least
+    // recently used.
+    recency: BTreeMap<u64, K>,
+    next_tick: u64,
+    bytes: usize,
+}
+
+impl<K: Clone + Eq + Hash, V> SizedLruState<K, V> {
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    fn remove(&mut self, key: &K) -> Option<SizedEntry<V>> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        self.bytes -= entry.bytes;
+        Some(entry)
+    }
+}
+
+/// An LRU cache bounded by the estimated memory of its entries instead of
+/// their count. `size` and `capacity` are reported in bytes.
+pub struct SizedLruCache<K, V> {
+    state: Arc<RwLock<SizedLruState<K, V>>>,
+    byte_budget: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+}
+
+impl<K, V> SizedLruCache<K, V>
+where
+    K: Clone + Eq + Hash + HeapSize + Send + Sync + 'static,
+    V: Clone + HeapSize + Send + Sync + 'static,
+{
+    pub fn new(byte_budget: usize) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(SizedLruState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_tick: 0,
+                bytes: 0,
+            })),
+            byte_budget,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            evictions: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size: self.state.read().await.bytes,
+            capacity: self.byte_budget,
+        }
+    }
+
+    fn entry_size(key: &K, value: &V) -> usize {
+        mem::size_of::<K>() + mem::size_of::<V>() + key.heap_size() + value.heap_size()
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for SizedLruCache<K, V>
+where
+    K: Clone + Eq + Hash + HeapSize + Send + Sync + 'static,
+    V: Clone + HeapSize + Send + Sync + 'static,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        let mut state = self.state.write().await;
+        let tick = state.tick();
+
+        let Some(entry) = state.entries.get_mut(key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let previous = mem::replace(&mut entry.last_used, tick);
+        let value = entry.value.clone();
+        state.recency.remove(&previous);
+        state.recency.insert(tick, key.clone());
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    /// Inserts the entry, then evicts least recently used entries until the
+    /// total fits the budget. An entry larger than the whole budget is not
+    /// cached, since it would evict everything else and then itself.
+    async fn set(&self, key: K, value: V) {
+        let bytes = Self::entry_size(&key, &value);
+        let mut state = self.state.write().await;
+        state.remove(&key);
+
+        if bytes > self.byte_budget {
+            return;
+        }
+
+        let tick = state.tick();
+        state.recency.insert(tick, key.clone());
+        state.entries.insert(key, SizedEntry { value, bytes, last_used: tick });
+        state.bytes += bytes;
+
+        while state.bytes > self.byte_budget {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&oldest) {
+                state.bytes -= entry.bytes;
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    async fn remove(&self, key: &K) -> Option<V> {
+        let mut state = self.state.write().await;
+        state.remove(key).map(|entry| entry.value)
+    }
+
+    async fn clear(&self) {
+        let mut state = self.state.write().await;
+        state.entries.clear();
+        state.recency.clear();
+        state.bytes = 0;
+    }
+
+    async fn size(&self) -> usize {
+        self.state.read().await.bytes
+    }
+
+    async fn capacity(&self) -> usize {
+        self.byte_budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_bytes(value: &str) -> usize {
+        SizedLruCache::<String, String>::entry_size(&"k0".to_string(), &value.to_string())
+    }
+
+    #[tokio::test]
+    async fn evicts_on_bytes_not_entry_count() {
+        let small = "s".repeat(16);
+        let large = "l".repeat(1024);
+        let cache = SizedLruCache::new(entry_bytes(&large) + 2 * entry_bytes(&small));
+
+        cache.set("k0".to_string(), small.clone()).await;
+        cache.set("k1".to_string(), small.clone()).await;
+        assert_eq!(cache.stats().await.evictions, 0);
+
+        // A third small entry would fit any count-based bound, but the large
+        // one pushes the total over the byte budget.
+        cache.set("k2".to_string(), large.clone()).await;
+        cache.set("k3".to_string(), small.clone()).await;
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.evictions, 1);
+        assert!(stats.size <= stats.capacity);
+        assert_eq!(cache.get(&"k0".to_string()).await, None);
+        assert_eq!(cache.get(&"k2".to_string()).await, Some(large));
+    }
+
+    #[tokio::test]
+    async fn skips_entries_larger_than_the_budget() {
+        let cache = SizedLruCache::new(64);
+
+        cache.set("key".to_string(), "v".repeat(1024)).await;
+
+        assert_eq!(cache.size().await, 0);
+        assert_eq!(cache.get(&"key".to_string()).await, None);
+    }
+
+    #[tokio::test]
+    async fn get_refreshes_recency() {
+        let value = "v".repeat(32);
+        let cache = SizedLruCache::new(2 * entry_bytes(&value));
+
+        cache.set("k0".to_string(), value.clone()).await;
+        cache.set("k1".to_string(), value.clone()).await;
+        cache.get(&"k0".to_string()).await;
+        cache.set("k2".to_string(), value.clone()).await;
+
+        assert!(cache.get(&"k0".to_string()).await.is_some());
+        assert_eq!(cache.get(&"k1".to_string()).await, None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::mem;
+
+struct SyncLruEntry<V> {
+    value: V,
+    last_used: u64,
+}
+
+/// An LRU cache bounded by entry count, for the synchronous read paths that
+/// cannot await the `Cache` implementations. It is not shared on its own;
+/// callers put it behind a `std::sync::Mutex`.
+pub struct SyncLruCache<K, V> {
+    entries: HashMap<K, SyncLruEntry<V>>,
+    // Keys by the tick of their last use; the first entry is the least
+    // recently used.
+    recency: BTreeMap<u64, K>,
+    next_tick: u64,
+    capacity: usize,
+}
+
+impl<K: Clone + Eq + Hash, V> SyncLruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+            capacity,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    /// The value of `key`, marking it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+
+        let previous = mem::replace(&mut entry.last_used, tick);
+        self.recency.remove(&previous);
+        self.recency.insert(tick, key.clone());
+        Some(&entry.value)
+    }
+
+    /// Inserts the entry, evicting the least recently used one if the cache
+    /// is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.remove(&key);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity
+            && let Some((_, oldest)) = self.recency.pop_first()
+        {
+            self.entries.remove(&oldest);
+        }
+
+        let tick = self.tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(key, SyncLruEntry { value, last_used: tick });
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        Some(entry.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let mut cache = SyncLruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(&1));
+
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.get(&"c"), Some(&3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn replacing_a_key_does_not_evict_another() {
+        let mut cache = SyncLruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        cache.insert("a", 10);
+
+        assert_eq!(cache.get(&"a"), Some(&10));
+        assert_eq!(cache.get(&"b"), Some(&2));
+        assert_eq!(cache.remove(&"a"), Some(10));
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,123 @@
+use super::Cache;
+use crate::utils::time;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Gives any cache per-entry expiration by storing each value next to its
+/// absolute expiry timestamp (seconds since the epoch).
+pub struct TtlCache<K, V> {
+    inner: Arc<dyn Cache<K, (V, u64)> + Send + Sync>,
+    default_ttl_secs: u64,
+    clock: fn() -> u64,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(inner: Arc<dyn Cache<K, (V, u64)> + Send + Sync>, default_ttl_secs: u64) -> Self {
+        Self::with_clock(inner, default_ttl_secs, time::current_timestamp)
+    }
+
+    /// Same as `new`, but reads the current time from `clock`.
+    pub fn with_clock(
+        inner: Arc<dyn Cache<K, (V, u64)> + Send + Sync>,
+        default_ttl_secs: u64,
+        clock: fn() -> u64,
+    ) -> Self {
+        Self {
+            inner,
+            default_ttl_secs,
+            clock,
+        }
+    }
+
+    pub async fn set_with_ttl(&self, key: K, value: V, ttl_secs: u64) {
+        let expires_at = (self.clock)().saturating_add(ttl_secs);
+        self.inner.set(key, (value, expires_at)).await;
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache<K, V> for TtlCache<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    async fn get(&self, key: &K) -> Option<V> {
+        let (value, expires_at) = self.inner.get(key).await?;
+
+        if expires_at <= (self.clock)() {
+            self.inner.remove(key).await;
+            return None;
+        }
+
+        Some(value)
+    }
+
+    async fn set(&self, key: K, value: V) {
+        self.set_with_ttl(key, value, self.default_ttl_secs).await;
+    }
+
+    async fn remove(&self, key: &K) -> Option<V> {
+        let (value, expires_at) = self.inner.remove(key).await?;
+
+        if expires_at <= (self.clock)() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    async fn clear(&self) {
+        self.inner.clear().await;
+    }
+
+    async fn size(&self) -> usize {
+        self.inner.size().await
+    }
+
+    async fn capacity(&self) -> usize {
+        self.inner.capacity().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::lru_cache::AsyncLruCache;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static NOW: AtomicU64 = AtomicU64::new(1_000);
+
+    fn mock_clock() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn entries_expire_once_the_clock_passes_their_ttl() {
+        let inner: Arc<AsyncLruCache<&str, (u32, u64)>> = Arc::new(AsyncLruCache::new(16));
+        let cache = TtlCache::with_clock(inner, 1, mock_clock);
+
+        cache.set("key", 7).await;
+        assert_eq!(cache.get(&"key").await, Some(7));
+
+        NOW.fetch_add(2, Ordering::SeqCst);
+
+        assert_eq!(cache.get(&"key").await, None);
+        assert_eq!(cache.size().await, 0);
+    }
+
+    #[tokio::test]
+    async fn set_with_ttl_overrides_the_default() {
+        let inner: Arc<AsyncLruCache<&str, (u32, u64)>> = Arc::new(AsyncLruCache::new(16));
+        let cache = TtlCache::new(inner, 0);
+
+        cache.set("short", 1).await;
+        cache.set_with_ttl("long", 2, 3600).await;
+
+        assert_eq!(cache.get(&"short").await, None);
+        assert_eq!(cache.get(&"long").await, Some(2));
+    }
+}
@@ -5,16 +5,66 @@ use serde::{Deserialize, Serialize};
 #[derive(Parser)]
 #[command(name = "plexdb")]
 #[command(about = "A Rust based key-value store", long_about = None)]
-
 pub struct CliArgs {
     #[arg(short, long, default_value = "./data")]
     pub data_dir: PathBuf,
 
+    /// Open the data directory without write access; writes fail
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// TOML or JSON file with settings to open the store with; the flags
+    /// below override it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Number of partitions; must match the count the data directory was created with
+    #[arg(long)]
+    pub partitions: Option<u32>,
+
+    /// Target false positive rate of each partition's bloom filter, in (0, 1)
+    #[arg(long)]
+    pub bloom_fp_rate: Option<f64>,
+
+    /// Size in bytes past which a partition is compacted
+    #[arg(long)]
+    pub max_partition_size: Option<u64>,
+
+    /// Compact a partition once its oldest tombstone is this many seconds old
+    #[arg(long)]
+    pub tombstone_ttl_secs: Option<u64>,
+
+    /// Check for partitions to compact in the background every this many seconds
+    #[arg(long)]
+    pub compaction_interval_secs: Option<u64>,
+
+    /// Most partitions compacted at once by background compaction
+    #[arg(long)]
+    pub max_concurrent_compactions: Option<usize>,
+
+    /// Skip the CRC check on each read; for storage that is already trusted
+    #[arg(long)]
+    pub no_verify_crc: bool,
+
+    /// Tombstone expired keys in the background every this many seconds
+    #[arg(long)]
+    pub expiry_sweep_interval_secs: Option<u64>,
+
+    /// Compression for new entries: none, lz4, snappy, zstd or adaptive
+    #[arg(long)]
+    pub compression: Option<String>,
+
+    /// Compression level; only valid with lz4 or zstd
+    #[arg(long)]
+    pub compression_level: Option<i32>,
+
     #[command(subcommand)]
     pub command: Command,
 }
 
-#[derive(Subcommand, Deserialize, Serialize)]
+/// Baseline commands keep their original positions and new ones go at the
+/// end, so serialized commands keep their discriminants.
+#[derive(Debug, Clone, Subcommand, Deserialize, Serialize)]
 pub enum Command {
     Set {
         key: String,
@@ -25,10 +75,91 @@ pub enum Command {
         key: String,
     },
 
-
     Delete {
         key: String
     },
 
-    Compact,
+    Compact {
+        /// Compact up to this many partitions at a time
+        #[arg(long, default_value_t = 1)]
+        parallel: usize,
+    },
+
+    SetEx {
+        key: String,
+        value: String,
+        ttl_secs: u64,
+    },
+
+    BatchSet {
+        path: PathBuf,
+    },
+
+    /// Get every key read from stdin, one per line
+    MGet,
+
+    DeleteRange {
+        start: String,
+        end: String,
+    },
+
+    Exists {
+        key: String,
+    },
+
+    Cas {
+        key: String,
+
+        /// Value the key must currently hold; omit to require that it does not exist
+        #[arg(long)]
+        expected: Option<String>,
+
+        value: String,
+    },
+
+    /// Set a key and print the value it replaced
+    Swap {
+        key: String,
+        value: String,
+    },
+
+    Incr {
+        key: String,
+
+        /// Amount to add; negative values decrement
+        #[arg(long, default_value_t = 1, allow_hyphen_values = true)]
+        by: i64,
+    },
+
+    /// Show how much compacting each partition would reclaim, without compacting
+    CompactEstimate,
+
+    /// Compact only the partitions past the tombstone ratio, tombstone age or size limits
+    Compactif,
+
+    /// Drop entries that fail their CRC and rebuild the indexes and bloom filters
+    Repair,
+
+    Stats,
+
+    Count,
+
+    Rebalance,
+
+    /// Delete every key in the store
+    Clear {
+        /// Confirm that every key should be deleted
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Write every live key to a newline-delimited JSON file
+    Export {
+        path: PathBuf,
+    },
+
+    /// Load keys from a newline-delimited JSON file written by `export`
+    Import {
+        path: PathBuf,
+    },
 }
@@ -0,0 +1,131 @@
+use crate::engine::partition_manager::PartitionConfig;
+use crate::error::PlexError;
+use crate::storage::wal::WALConfig;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Every tunable in one place, as read from a config file. Omitted fields
+/// keep their defaults, so a file only needs the settings it changes:
+///
+/// ```toml
+/// [partition]
+/// partition_count = 16
+/// bloom_filter_fp_rate = 0.001
+/// compression = "zstd"
+/// block_cache_blocks = 4096
+///
+/// [wal]
+/// fsync = "fsync"
+/// max_file_size = 67108864
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlexConfig {
+    pub partition: PartitionConfig,
+    pub wal: WALConfig,
+}
+
+impl PlexConfig {
+    /// Reads a config file, as JSON if its extension is `.json` and as TOML
+    /// otherwise, and validates it. Errors name the offending field.
+    pub fn from_file(path: &Path) -> Result<Self, PlexError> {
+        let contents = std::fs::read_to_string(path)?;
+
+        let is_json = path.extension().is_some_and(|extension| extension == "json");
+        let config: PlexConfig = if is_json {
+            serde_json::from_str(&contents)
+                .map_err(|e| PlexError::Config(format!("Invalid config in {:?}: {}", path, e)))?
+        } else {
+            toml::from_str(&contents)
+                .map_err(|e| PlexError::Config(format!("Invalid config in {:?}: {}", path, e)))?
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), PlexError> {
+        self.partition.validate().map_err(|e| match e {
+            PlexError::Config(message) => PlexError::Config(format!("[partition] {}", message)),
+            other => other,
+        })?;
+        self.wal.validate().map_err(|e| match e {
+            PlexError::Config(message) => PlexError::Config(format!("[wal] {}", message)),
+            other => other,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::partition_manager::Partitioning;
+    use crate::storage::wal::FsyncMode;
+    use crate::utils::compression::CompressionAlgorithm;
+    use tempfile::TempDir;
+
+    fn load(file_name: &str, contents: &str) -> Result<PlexConfig, PlexError> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(file_name);
+        std::fs::write(&path, contents).unwrap();
+        PlexConfig::from_file(&path)
+    }
+
+    #[test]
+    fn full_config_sets_every_section() {
+        let config = load(
+            "plex.toml",
+            r#"
+            [partition]
+            partition_count = 16
+            bloom_filter_fp_rate = 0.001
+            compression = "zstd"
+            block_cache_blocks = 4096
+
+            [wal]
+            fsync = "fsync"
+            max_file_size = 1048576
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.partition.partition_count, 16);
+        assert_eq!(config.partition.bloom_filter_fp_rate, 0.001);
+        assert_eq!(config.partition.compression, CompressionAlgorithm::Zstd);
+        assert_eq!(config.partition.block_cache_blocks, 4096);
+        assert_eq!(config.wal.fsync, FsyncMode::Fsync);
+        assert_eq!(config.wal.max_file_size, 1048576);
+    }
+
+    #[test]
+    fn partial_config_keeps_defaults_for_omitted_fields() {
+        let config = load("plex.json", r#"{ "partition": { "partition_count": 8 } }"#).unwrap();
+        let defaults = PlexConfig::default();
+
+        assert_eq!(config.partition.partition_count, 8);
+        assert_eq!(config.partition.block_size, defaults.partition.block_size);
+        assert_eq!(config.partition.max_value_size, defaults.partition.max_value_size);
+        assert_eq!(config.wal.max_file_size, defaults.wal.max_file_size);
+        assert_eq!(config.wal.fsync, defaults.wal.fsync);
+    }
+
+    #[test]
+    fn partitioning_is_set_as_an_inline_table() {
+        let config = load(
+            "plex.toml",
+            "[partition]\npartitioning = { ConsistentHash = { virtual_nodes = 128 } }\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.partition.partitioning, Partitioning::ConsistentHash { virtual_nodes: 128 });
+    }
+
+    #[test]
+    fn invalid_config_names_the_offending_field() {
+        let wrong_type = load("plex.toml", "[partition]\npartition_count = \"many\"\n");
+        assert!(matches!(wrong_type, Err(PlexError::Config(message)) if message.contains("partition_count")));
+
+        let out_of_range = load("plex.toml", "[wal]\nmax_file_size = 0\n");
+        assert!(matches!(out_of_range, Err(PlexError::Config(message)) if message.contains("[wal] max_file_size")));
+    }
+}
@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+pub mod cli;
+pub mod config;
+
+pub use cli::{CliArgs, Command};
+pub use config::PlexConfig;
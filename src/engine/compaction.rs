@@ -0,0 +1,204 @@
+use crate::engine::partition_manager::{Partition, PartitionConfig, PartitionManager};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often the background scheduler looks for partitions to compact, and
+/// how many it compacts at once.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CompactionSchedule {
+    pub interval: Duration,
+    pub max_concurrent: usize,
+}
+
+impl Default for CompactionSchedule {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            max_concurrent: 1,
+        }
+    }
+}
+
+/// How a partition is compacted once it needs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CompactionStrategy {
+    /// Rewrite every live entry of the partition into fresh segments.
+    #[default]
+    Full,
+    /// Merge a tier of similarly sized segments, leaving the rest alone,
+    /// so large segments are only rewritten once enough peers of their
+    /// size have accumulated. See `size_tier`.
+    SizeTiered,
+}
+
+/// Fewest segments a size tier needs before it is merged.
+pub const SIZE_TIER_MIN_SEGMENTS: usize = 4;
+/// Most segments merged at once.
+pub const SIZE_TIER_MAX_SEGMENTS: usize = 32;
+/// A segment joins a tier if it is at most this many times the average
+/// size of the segments already in it.
+const SIZE_TIER_SPREAD: f64 = 1.5;
+
+/// Picks the segments a size-tiered compaction merges next. Segments are
+/// grouped into tiers from smallest to largest, and the tier of the
+/// smallest segments that has at least `SIZE_TIER_MIN_SEGMENTS` is
+/// returned, oldest first and capped at `SIZE_TIER_MAX_SEGMENTS`.
+pub fn size_tier(segments: &[(u32, u64)]) -> Option<Vec<u32>> {
+    let mut by_size = segments.to_vec();
+    by_size.sort_by_key(|&(_, size)| size);
+
+    let mut tiers: Vec<(u64, Vec<u32>)> = Vec::new();
+    for (id, size) in by_size {
+        match tiers.last_mut() {
+            Some((total, ids)) if size as f64 <= *total as f64 / ids.len() as f64 * SIZE_TIER_SPREAD => {
+                *total += size;
+                ids.push(id);
+            }
+            _ => tiers.push((size, vec![id])),
+        }
+    }
+
+    let (_, mut ids) = tiers.into_iter().find(|(_, ids)| ids.len() >= SIZE_TIER_MIN_SEGMENTS)?;
+    ids.truncate(SIZE_TIER_MAX_SEGMENTS);
+    ids.sort_unstable();
+    Some(ids)
+}
+
+/// Compacts partitions that pass `PartitionManager::partition_needs_compaction`
+/// on a background thread. Each compaction only locks its own partition, so
+/// foreground reads and writes to the others carry on. Dropping the
+/// scheduler stops it, waiting for a pass in progress to finish.
+pub struct CompactionScheduler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CompactionScheduler {
+    /// Starts compacting the manager's current partitions. The scheduler
+    /// keeps its own handles on them, so it has to be dropped and spawned
+    /// again whenever the manager replaces its partitions.
+    pub fn spawn(manager: &PartitionManager, schedule: CompactionSchedule) -> Self {
+        let partitions = manager.partition_handles();
+        let config = manager.config().clone();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    thread::park_timeout(schedule.interval);
+                    if stop.load(Ordering::Acquire) {
+                        break;
+                    }
+
+                    run_pass(&partitions, &config, schedule.max_concurrent);
+                }
+            })
+        };
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for CompactionScheduler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_pass(partitions: &[Partition], config: &PartitionConfig, max_concurrent: usize) {
+    let due: Vec<&Partition> = partitions
+        .iter()
+        .filter(|partition| PartitionManager::partition_needs_compaction(partition, config))
+        .collect();
+
+    for batch in due.chunks(max_concurrent.max(1)) {
+        thread::scope(|scope| {
+            for &partition in batch {
+                scope.spawn(move || match PartitionManager::compact_partition_in(partition, config) {
+                    Ok(report) => info!(
+                        "Background compaction of partition {} finished, dropping {} superseded versions",
+                        partition.id, report.superseded_versions
+                    ),
+                    Err(e) => warn!("Background compaction of partition {} failed: {}", partition.id, e),
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::wal::{WALConfig, WAL};
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    #[test]
+    fn scheduler_compacts_a_partition_past_the_tombstone_threshold() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 2,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        for i in 0..50 {
+            manager.set(&format!("key{}", i), "value").unwrap();
+        }
+        for i in 1..50 {
+            manager.delete(&format!("key{}", i)).unwrap();
+        }
+
+        let partitions = manager.partition_handles();
+        let generation = |partition: &Partition| partition.metadata.read().unwrap().generation;
+        let before: Vec<u64> = partitions.iter().map(generation).collect();
+
+        let schedule = CompactionSchedule {
+            interval: Duration::from_millis(20),
+            max_concurrent: 2,
+        };
+        let scheduler = CompactionScheduler::spawn(&manager, schedule);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while partitions.iter().any(|partition| generation(partition) == before[partition.id as usize]) {
+            assert!(Instant::now() < deadline, "not every partition was compacted");
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(scheduler);
+
+        for partition in &partitions {
+            assert_eq!(partition.metadata.read().unwrap().tombstone_count, 0);
+        }
+        assert_eq!(manager.get("key0").unwrap().as_deref(), Some("value"));
+        assert_eq!(manager.get("key1").unwrap(), None);
+    }
+
+    #[test]
+    fn size_tier_picks_the_smallest_tier_with_enough_segments() {
+        let small = [(1, 100), (2, 110), (3, 95), (4, 105)];
+        let mut segments = small.to_vec();
+        segments.extend([(5, 10_000), (6, 10_200)]);
+        assert_eq!(size_tier(&segments), Some(vec![1, 2, 3, 4]));
+
+        // Three small segments are not a tier yet, and neither are two large.
+        assert_eq!(size_tier(&segments[1..]), None);
+
+        segments.extend([(7, 9_900), (8, 10_100)]);
+        assert_eq!(size_tier(&segments[1..]), Some(vec![5, 6, 7, 8]));
+    }
+}
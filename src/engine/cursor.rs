@@ -0,0 +1,223 @@
+use crate::engine::partition_manager::{KeyIter, PartitionManager};
+use crate::error::PlexError;
+use crate::storage::wal::WALCommand;
+use std::collections::VecDeque;
+
+/// One item of a `ChangeCursor`: a key as it stood while the cursor took
+/// its snapshot, or a write read back from the WAL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeEvent {
+    Snapshot { key: String, value: String },
+    Set { sequence: u64, key: String, value: String },
+    Delete { sequence: u64, key: String },
+    /// Every key was deleted by `clear`.
+    Cleared { sequence: u64 },
+}
+
+/// Yields every live key, then every write logged after the cursor's
+/// starting sequence, in sequence order.
+///
+/// The starting sequence is capped at the WAL's latest sequence before the
+/// snapshot begins, so a write landing while the snapshot is taken is
+/// tailed even if the snapshot already saw it. Each logged write is yielded
+/// exactly once; applying events in order converges on the store's state.
+///
+/// Once it has caught up with the WAL, `next` returns `None`. Calling it
+/// again later picks up whatever was written since, so the iterator is not
+/// fused. Writes inside a transaction are held back until its commit marker
+/// is read. Pairs that are not UTF-8 are skipped, as in `iter_keys`, and so
+/// is `bulk_load`, which does not go through the WAL.
+pub struct ChangeCursor<'a> {
+    manager: &'a PartitionManager,
+    snapshot: Option<KeyIter<'a>>,
+    pending: VecDeque<ChangeEvent>,
+    /// Begin sequence and buffered writes of a transaction whose commit
+    /// marker has not been read yet.
+    open_txn: Option<(u64, Vec<ChangeEvent>)>,
+    next_sequence: u64,
+    polled: bool,
+}
+
+impl<'a> ChangeCursor<'a> {
+    pub(crate) fn new(manager: &'a PartitionManager, from_sequence: u64) -> Self {
+        let snapshot_start = manager.wal().get_lastest_sequence();
+
+        Self {
+            manager,
+            snapshot: Some(manager.iter_keys()),
+            pending: VecDeque::new(),
+            open_txn: None,
+            next_sequence: from_sequence.min(snapshot_start) + 1,
+            polled: false,
+        }
+    }
+
+    /// Sequence of the last WAL entry read. Passing it to
+    /// `PartitionManager::cursor_from` resumes after it.
+    pub fn sequence(&self) -> u64 {
+        self.next_sequence - 1
+    }
+
+    fn poll(&mut self) -> Result<(), PlexError> {
+        let wal = self.manager.wal();
+        // Entries still in the WAL's write buffer are not in the file yet.
+        wal.flush()?;
+        let entries = wal.read_from_sequence(self.next_sequence)?;
+
+        if !self.polled
+            && let Some(first) = entries.first()
+            && first.sequence_number > self.next_sequence
+        {
+            return Err(PlexError::WAL(format!(
+                "entries from sequence {} have been truncated from the WAL",
+                self.next_sequence
+            )));
+        }
+        self.polled = true;
+
+        for entry in entries {
+            let sequence = entry.sequence_number;
+            self.next_sequence = sequence + 1;
+
+            let command = match (entry.command, self.manager.namespace()) {
+                (WALCommand::Namespaced { namespace, command }, Some(own)) if namespace == own => *command,
+                (WALCommand::Namespaced { .. }, _) | (_, Some(_)) => continue,
+                (command, None) => command,
+            };
+
+            let event = match command {
+                WALCommand::Set { key, value } | WALCommand::SetEx { key, value, .. } => {
+                    ChangeEvent::Set { sequence, key, value }
+                }
+                WALCommand::Delete { key } => ChangeEvent::Delete { sequence, key },
+                WALCommand::Truncate => ChangeEvent::Cleared { sequence },
+                WALCommand::SetBytes { key, value } | WALCommand::SetAt { key, value, .. } => match (String::from_utf8(key), String::from_utf8(value)) {
+                    (Ok(key), Ok(value)) => ChangeEvent::Set { sequence, key, value },
+                    _ => continue,
+                },
+                WALCommand::DeleteBytes { key } => match String::from_utf8(key) {
+                    Ok(key) => ChangeEvent::Delete { sequence, key },
+                    Err(_) => continue,
+                },
+                WALCommand::TxnBegin => {
+                    self.open_txn = Some((sequence, Vec::new()));
+                    continue;
+                }
+                WALCommand::TxnCommit { begin_sequence } => {
+                    if let Some((begin, buffered)) = self.open_txn.take()
+                        && begin == begin_sequence
+                    {
+                        self.pending.extend(buffered);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match self.open_txn.as_mut() {
+                Some((_, buffered)) => buffered.push(event),
+                None => self.pending.push_back(event),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for ChangeCursor<'_> {
+    type Item = Result<ChangeEvent, PlexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(snapshot) = self.snapshot.as_mut() {
+            match snapshot.next() {
+                Some(Ok((key, value))) => return Some(Ok(ChangeEvent::Snapshot { key, value })),
+                Some(Err(e)) => return Some(Err(e)),
+                None => self.snapshot = None,
+            }
+        }
+
+        if self.pending.is_empty()
+            && let Err(e) = self.poll()
+        {
+            return Some(Err(e));
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::partition_manager::PartitionConfig;
+    use crate::storage::wal::{WALConfig, WAL};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn open_manager(dir: &std::path::Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let mut manager = PartitionManager::new(dir.join("partitions"), PartitionConfig::default(), wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    #[test]
+    fn writes_during_iteration_are_observed_exactly_once() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..10 {
+            manager.set(&format!("old-{}", i), "v").unwrap();
+        }
+
+        let mut cursor = manager.cursor_from(u64::MAX);
+        let mut snapshot = Vec::new();
+        let mut changes: HashMap<String, u32> = HashMap::new();
+        let mut written = 0;
+        for event in cursor.by_ref() {
+            match event.unwrap() {
+                ChangeEvent::Snapshot { key, .. } => snapshot.push(key),
+                ChangeEvent::Set { key, .. } | ChangeEvent::Delete { key, .. } => {
+                    *changes.entry(key).or_default() += 1;
+                }
+                ChangeEvent::Cleared { .. } => panic!("nothing was cleared"),
+            }
+            if written < 5 {
+                manager.set(&format!("new-{}", written), "v").unwrap();
+                written += 1;
+            }
+        }
+        manager.delete("old-0").unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap(), ChangeEvent::Delete {
+            sequence: cursor.sequence(),
+            key: "old-0".to_string(),
+        });
+        assert!(cursor.next().is_none());
+
+        assert_eq!(snapshot.iter().filter(|key| key.starts_with("old-")).count(), 10);
+        assert_eq!(changes.len(), 5);
+        for i in 0..5 {
+            assert_eq!(changes[&format!("new-{}", i)], 1);
+        }
+    }
+
+    #[test]
+    fn cursor_resumes_after_the_sequence_it_stopped_at() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("a", "1").unwrap();
+
+        let mut cursor = manager.cursor_from(0);
+        let events: Vec<_> = cursor.by_ref().map(Result::unwrap).collect();
+        assert_eq!(events.len(), 2);
+        let resume_at = cursor.sequence();
+        drop(cursor);
+
+        manager.set("b", "2").unwrap();
+        let tail: Vec<_> = manager
+            .cursor_from(resume_at)
+            .map(Result::unwrap)
+            .filter(|event| !matches!(event, ChangeEvent::Snapshot { .. }))
+            .collect();
+        assert_eq!(tail, [ChangeEvent::Set { sequence: resume_at + 1, key: "b".to_string(), value: "2".to_string() }]);
+    }
+}
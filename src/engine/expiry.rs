@@ -0,0 +1,112 @@
+use crate::engine::partition_manager::{Partition, PartitionManager};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Tombstones expired keys on a background thread, so they stop taking up
+/// index slots and disk without waiting for a read to find them. The
+/// tombstones then count towards normal compaction. Dropping the sweeper
+/// stops it, waiting for a sweep in progress to finish.
+pub struct ExpirySweeper {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ExpirySweeper {
+    /// Starts sweeping the manager's current partitions every `interval`.
+    /// Like `CompactionScheduler`, it keeps its own handles on them and has
+    /// to be spawned again whenever the manager replaces its partitions.
+    pub fn spawn(manager: &PartitionManager, interval: Duration) -> Self {
+        let partitions = manager.partition_handles();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    thread::park_timeout(interval);
+                    if stop.load(Ordering::Acquire) {
+                        break;
+                    }
+
+                    run_sweep(&partitions, &stop);
+                }
+            })
+        };
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for ExpirySweeper {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_sweep(partitions: &[Partition], stop: &AtomicBool) {
+    for partition in partitions {
+        if stop.load(Ordering::Acquire) {
+            return;
+        }
+
+        match PartitionManager::sweep_expired_in(partition) {
+            Ok(0) => {}
+            Ok(swept) => info!("Tombstoned {} expired keys in partition {}", swept, partition.id),
+            Err(e) => warn!("Expiry sweep of partition {} failed: {}", partition.id, e),
+        }
+
+        // Each key takes the index lock on its own, but give foreground work
+        // a clear turn before moving on to the next partition.
+        thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::partition_manager::PartitionConfig;
+    use crate::storage::wal::{WALConfig, WAL};
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    #[test]
+    fn sweeper_tombstones_expired_keys_without_a_read() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 2,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        for i in 0..20 {
+            manager.set_with_ttl(&format!("short{}", i), "value", 1).unwrap();
+        }
+        manager.set("kept", "value").unwrap();
+        assert_eq!(manager.stats().unwrap().total_keys, 21);
+
+        let sweeper = ExpirySweeper::spawn(&manager, Duration::from_millis(20));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while manager.stats().unwrap().total_keys > 1 {
+            assert!(Instant::now() < deadline, "expired keys were not swept");
+            thread::sleep(Duration::from_millis(20));
+        }
+        drop(sweeper);
+
+        let stats = manager.stats().unwrap();
+        assert_eq!(stats.total_tombstones, 20);
+        assert_eq!(manager.get("kept").unwrap().as_deref(), Some("value"));
+    }
+}
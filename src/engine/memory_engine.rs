@@ -0,0 +1,94 @@
+use crate::error::PlexError;
+use crate::storage::storage_engine::StorageEngine;
+use std::collections::HashMap;
+
+/// A `StorageEngine` kept entirely in a `HashMap`, with no WAL and no disk
+/// IO. Validates keys and values the same way `PlexEngine` does, so tests
+/// written against the trait can run on either.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryEngine {
+    entries: HashMap<String, String>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, key: &str) -> Result<Option<String>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError> {
+        if key.is_empty() || value.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.entries.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        Ok(self.entries.remove(key).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::plex_engine::PlexEngine;
+    use tempfile::TempDir;
+
+    /// Behavior every `StorageEngine` has to share.
+    fn conformance(engine: &mut impl StorageEngine) {
+        assert_eq!(engine.get("missing").unwrap(), None);
+
+        engine.set("key", "value").unwrap();
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("value"));
+
+        engine.set("key", "updated").unwrap();
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("updated"));
+
+        assert!(engine.delete("key").unwrap());
+        assert_eq!(engine.get("key").unwrap(), None);
+        assert!(!engine.delete("key").unwrap());
+
+        assert!(matches!(engine.get(""), Err(PlexError::KeyIsEmpty)));
+        assert!(matches!(engine.set("", "value"), Err(PlexError::KeyIsEmpty)));
+        assert!(matches!(engine.set("key", ""), Err(PlexError::KeyIsEmpty)));
+        assert!(matches!(engine.delete(""), Err(PlexError::KeyIsEmpty)));
+        assert_eq!(engine.get("key").unwrap(), None);
+    }
+
+    #[test]
+    fn memory_engine_conforms() {
+        let mut engine = MemoryEngine::new();
+        conformance(&mut engine);
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn plex_engine_conforms() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        conformance(&mut engine);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::engine::partition_manager::FileOffset;
+use crate::utils::time;
+use std::collections::BTreeMap;
+
+/// `FileOffset::file_id` of keys whose value is still in the memtable.
+pub const MEMTABLE_FILE_ID: u32 = u32::MAX;
+
+/// Sets buffered in memory for one partition until they are flushed to a
+/// segment of their own, in key order. The partition index maps each
+/// buffered key to an offset with `MEMTABLE_FILE_ID`, so lookups know to
+/// read it from here; the WAL keeps it durable until the flush.
+#[derive(Debug, Default)]
+pub struct Memtable {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    size: usize,
+}
+
+impl Memtable {
+    /// Buffers `value` under `key` and returns the offset the index should
+    /// hold for it.
+    pub fn insert(&mut self, partition_id: u32, key: &[u8], value: &[u8], expires_at: Option<u64>) -> FileOffset {
+        if let Some(previous) = self.entries.insert(key.to_vec(), value.to_vec()) {
+            self.size -= key.len() + previous.len();
+        }
+        self.size += key.len() + value.len();
+
+        FileOffset {
+            partition_id,
+            file_id: MEMTABLE_FILE_ID,
+            offset: 0,
+            size: (key.len() + value.len()) as u32,
+            timestamp: time::current_timestamp(),
+            expires_at,
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(Vec::as_slice)
+    }
+
+    /// Buffered pairs in key order. Keys overwritten on disk or deleted
+    /// since they were buffered are still here; the index tells them apart.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries.iter().map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+
+    /// Bytes of keys and values buffered.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+}
@@ -1 +1,10 @@
+pub mod compaction;
+pub mod cursor;
+pub mod expiry;
+pub mod memtable;
+pub mod memory_engine;
+pub mod partition_manager;
 pub mod plex_engine;
+pub mod transaction;
+pub mod typed_store;
+pub mod value_cache;
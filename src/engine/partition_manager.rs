@@ -1,41 +1,391 @@
+use crate::engine::cursor::ChangeCursor;
+use crate::engine::memtable::{Memtable, MEMTABLE_FILE_ID};
+use crate::engine::transaction::Transaction;
+use crate::engine::value_cache::ValueCache;
 use crate::error::PlexError;
-use crate::storage::file_manager::FileManager;
-use crate::storage::wal::WriteAheadLog;
-use crate::cache::bloom_filter::BloomFilter;
+use crate::storage::file_manager::{is_dictionary_file, segment_file_name, Durability, EntryHeader, FileManager, ScannedEntry, VALUE_LOG_PREFIX};
+use crate::storage::wal::{self, WALCommand, WALEntry, WAL};
+use crate::utils::compression::{CompressionAlgorithm, DictionaryCompressor};
+use crate::utils::encryption::{seal, unseal, AesGcmEncryptor, EncryptionKey};
+use crate::cache::block_cache::SyncBlockCache;
+use crate::cache::bloom_filter::{BloomFilterStats, CountingBloomFilter};
+use crate::engine::compaction::{self, CompactionSchedule, CompactionStrategy};
+use crate::utils::hash::{HashFamily, KeyBytes};
+use crate::utils::retry::RetryPolicy;
+use crate::utils::time;
+use log::debug;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hsher};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+use std::hash::BuildHasher;
+use std::time::Duration;
+use tracing::{info, warn};
 
 pub const DEFAULT_PARTITION_COUNT: u32 = 16;
 pub const DEFAULT_MAX_PARTITION_SIZE: u64 = 1024 * 1024 * 1024;
 pub const DEFAULT_BLOOM_FILTER_SIZE: usize = 10_000;
-pub const DEFAULT_BLOOOM_FILTER_FP_RATE: f64 = 0.0.1;
+pub const DEFAULT_BLOOM_FILTER_FP_RATE: f64 = 0.01;
+pub const DEFAULT_COMPACTION_THRESHOLD: f64 = 0.7;
+pub const DEFAULT_VIRTUAL_NODES: u32 = 128;
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+pub const DEFAULT_MAX_KEY_SIZE: usize = 64 * 1024;
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+pub const DICTIONARY_SAMPLE_SIZE: usize = 1000;
+const SNAPSHOT_MANIFEST_FILE: &str = "manifest.bin";
+const SNAPSHOT_INDEX_FILE: &str = "index.bin";
+const SNAPSHOT_BLOOM_FILE: &str = "bloom.bin";
+const IMPORT_BATCH_SIZE: usize = 1000;
+const BULK_LOAD_CHUNK_SIZE: usize = 10_000;
+/// Absent-key lookups per bloom filter false positive measurement.
+const BLOOM_FP_WINDOW: u64 = 1000;
+const PERSISTED_INDEX_FILE: &str = "index.bin";
+const CONFIG_FILE: &str = "config.json";
+const BACKUP_MAGIC: [u8; 4] = *b"PXBK";
+const BACKUP_VERSION: u32 = 1;
+const RESTORE_STAGING_DIR: &str = "restore.tmp";
+
+/// Which bloom filters a partition keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BloomGranularity {
+    /// One filter over the partition's live keys, checked before the index.
+    #[default]
+    Partition,
+    /// The partition filter, plus one saved next to each sealed segment
+    /// over every key it holds. A size-tiered merge uses them to drop
+    /// tombstones that no segment outside the merge can need.
+    Segment,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartitionConfig {
+    /// Defaults to `DEFAULT_PARTITION_COUNT`.
+    #[serde(default = "default_partition_count")]
     pub partition_count: u32,
+    /// Defaults to `DEFAULT_MAX_PARTITION_SIZE`.
+    #[serde(default = "default_max_partition_size")]
     pub max_partition_size: u64,
+    /// Defaults to `DEFAULT_BLOOM_FILTER_SIZE`.
+    #[serde(default = "default_bloom_filter_size")]
     pub bloom_filter_size: usize,
+    /// Defaults to `DEFAULT_BLOOM_FILTER_FP_RATE`.
+    #[serde(default = "default_bloom_filter_fp_rate")]
     pub bloom_filter_fp_rate: f64,
-    pub enable_compression: bool,
+    /// Per-segment filters use `bloom_filter_fp_rate` too. May change
+    /// between opens; missing segment filters are rebuilt on open.
+    #[serde(default)]
+    pub bloom_granularity: BloomGranularity,
+    /// Compressor for new entries. Once entries have been compressed, the
+    /// directory has to keep using the same algorithm.
+    #[serde(default)]
+    pub compression: CompressionAlgorithm,
+    /// Level passed to the compressor; only lz4 and zstd take one. Not
+    /// needed to decompress, so it may change between opens.
+    #[serde(default)]
+    pub compression_level: Option<i32>,
+    /// Tombstone ratio past which a partition needs compacting. Defaults to
+    /// `DEFAULT_COMPACTION_THRESHOLD`.
+    #[serde(default = "default_compaction_threshold")]
     pub compaction_threshold: f64,
+    /// Fixed once a data directory has been written to, since it decides
+    /// which partition every stored key lives in.
+    #[serde(default)]
+    pub partitioning: Partitioning,
+    /// Weight of each partition under `Partitioning::Weighted`, in partition
+    /// order; partitions without an entry weigh 1. Fixed once data has been
+    /// written, like `partitioning`.
+    #[serde(default)]
+    pub partition_weights: Vec<u32>,
+    /// Hashes keys for partitioning and for the bloom filters. Like
+    /// `partitioning`, it cannot change once data has been written.
+    #[serde(default)]
+    pub hash_family: HashFamily,
+    /// When partition data files are fsync'd. The WAL covers anything a
+    /// relaxed mode leaves unsynced.
+    #[serde(default)]
+    pub durability: Durability,
+    /// Values longer than this many bytes are stored out of line in a value
+    /// log. `None` keeps every value inline.
+    #[serde(default)]
+    pub value_log_threshold: Option<usize>,
+    /// Buffer sets in a per-partition memtable until its keys and values
+    /// take up this many bytes, then write them to a new segment in key
+    /// order. The WAL keeps buffered sets durable meanwhile. `None` writes
+    /// every set straight to disk.
+    #[serde(default)]
+    pub memtable_size: Option<usize>,
+    /// Writes with a longer key, in bytes, are rejected before they reach
+    /// the WAL.
+    #[serde(default = "default_max_key_size")]
+    pub max_key_size: usize,
+    /// Writes with a longer value, in bytes, are rejected before they reach
+    /// the WAL.
+    #[serde(default = "default_max_value_size")]
+    pub max_value_size: usize,
+    /// Compact a partition once its oldest tombstone is older than this,
+    /// whatever its tombstone ratio, so deleted values are purged from disk
+    /// in bounded time. `None` disables the age trigger.
+    #[serde(default)]
+    pub tombstone_ttl: Option<Duration>,
+    /// How a partition is rewritten when it is compacted. `repair` always
+    /// rewrites every segment.
+    #[serde(default)]
+    pub compaction_strategy: CompactionStrategy,
+    /// Compact partitions that need it on a background thread. `None`
+    /// leaves compaction to explicit calls.
+    #[serde(default)]
+    pub compaction_schedule: Option<CompactionSchedule>,
+    /// Tombstone expired keys on a background thread this often. `None`
+    /// leaves them in place until a read or compaction finds them.
+    #[serde(default)]
+    pub expiry_sweep_interval: Option<Duration>,
+    /// Blocks of segment data each partition keeps in an LRU cache for
+    /// reads. 0 disables the cache.
+    #[serde(default)]
+    pub block_cache_blocks: usize,
+    /// Size in bytes of a cached block.
+    #[serde(default = "default_block_size")]
+    pub block_size: usize,
+    /// Values each partition keeps in an LRU cache for `get`. 0 disables
+    /// the cache.
+    #[serde(default)]
+    pub value_cache_entries: usize,
+    /// Check the CRC of every entry read. Segment scans check regardless.
+    #[serde(default = "default_verify_crc")]
+    pub verify_crc: bool,
+    /// How `get`, `set` and `delete` retry taking a partition lock that is
+    /// held or poisoned. Only the lock is retried, never the I/O after it.
+    #[serde(default)]
+    pub lock_retry: RetryPolicy,
+    /// Open segments without write access; every write fails. Not saved
+    /// with the rest of the config, since it is a property of one process.
+    #[serde(skip)]
+    pub read_only: bool,
+    /// Encrypt entries on disk with AES-GCM under this key, along with the
+    /// persisted indexes, snapshots, backups and exports that hold keys or
+    /// values. Compression dictionaries are not trained while it is set,
+    /// since they are built from values. Never saved; it has to be supplied
+    /// on every open of an encrypted directory.
+    #[serde(skip)]
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+fn default_partition_count() -> u32 {
+    DEFAULT_PARTITION_COUNT
+}
+
+fn default_max_partition_size() -> u64 {
+    DEFAULT_MAX_PARTITION_SIZE
+}
+
+fn default_bloom_filter_size() -> usize {
+    DEFAULT_BLOOM_FILTER_SIZE
+}
+
+fn default_bloom_filter_fp_rate() -> f64 {
+    DEFAULT_BLOOM_FILTER_FP_RATE
+}
+
+fn default_compaction_threshold() -> f64 {
+    DEFAULT_COMPACTION_THRESHOLD
+}
+
+fn default_max_key_size() -> usize {
+    DEFAULT_MAX_KEY_SIZE
+}
+
+fn default_max_value_size() -> usize {
+    DEFAULT_MAX_VALUE_SIZE
+}
+
+fn default_block_size() -> usize {
+    DEFAULT_BLOCK_SIZE
+}
 
+fn default_verify_crc() -> bool {
+    true
 }
 
 impl Default for PartitionConfig {
     fn default() -> Self {
-
         Self {
-
             partition_count: DEFAULT_PARTITION_COUNT,
             max_partition_size: DEFAULT_MAX_PARTITION_SIZE,
+            bloom_filter_size: DEFAULT_BLOOM_FILTER_SIZE,
             bloom_filter_fp_rate: DEFAULT_BLOOM_FILTER_FP_RATE,
-            enable_compression: false,
-            compaction_threshold: 0.7,
+            bloom_granularity: BloomGranularity::Partition,
+            compression: CompressionAlgorithm::None,
+            compression_level: None,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            partitioning: Partitioning::default(),
+            partition_weights: Vec::new(),
+            hash_family: HashFamily::default(),
+            durability: Durability::Sync,
+            value_log_threshold: None,
+            memtable_size: None,
+            max_key_size: DEFAULT_MAX_KEY_SIZE,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            tombstone_ttl: None,
+            compaction_strategy: CompactionStrategy::Full,
+            compaction_schedule: None,
+            expiry_sweep_interval: None,
+            block_cache_blocks: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            value_cache_entries: 0,
+            verify_crc: true,
+            lock_retry: RetryPolicy::default(),
+            read_only: false,
+            encryption_key: None,
+        }
+    }
+}
+
+/// How keys are assigned to partitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Partitioning {
+    /// `hash % partition_count`. Changing the partition count remaps almost
+    /// every key.
+    #[default]
+    Modulo,
+    /// A hash ring with `virtual_nodes` points per partition. Adding a
+    /// partition only remaps the keys on the arcs it takes over.
+    ConsistentHash { virtual_nodes: u32 },
+    /// A hash ring with `virtual_nodes` points per unit of partition weight,
+    /// taken from `PartitionConfig::partition_weights`.
+    Weighted { virtual_nodes: u32 },
+}
+
+impl Partitioning {
+    /// Partitions without an entry in `weights` weigh 1; only `Weighted`
+    /// looks at them.
+    fn build(
+        self,
+        partition_count: u32,
+        hash_family: HashFamily,
+        weights: &[u32],
+    ) -> Result<Box<dyn Partitioner>, PlexError> {
+        let partitioner: Box<dyn Partitioner> = match self {
+            Partitioning::Modulo => {
+                Box::new(HashPartitioner::with_hasher(partition_count, hash_family))
+            }
+            Partitioning::ConsistentHash { virtual_nodes } => Box::new(
+                ConsistentHashPartitioner::with_hasher(partition_count, virtual_nodes, hash_family),
+            ),
+            Partitioning::Weighted { virtual_nodes } => {
+                let mut weights = weights.to_vec();
+                weights.resize(partition_count as usize, 1);
+                Box::new(WeightedPartitioner::with_hasher(weights, virtual_nodes, hash_family)?)
+            }
+        };
+        Ok(partitioner)
+    }
+}
+
+impl PartitionConfig {
+    pub fn validate(&self) -> Result<(), PlexError> {
+        if self.partition_count == 0 {
+            return Err(PlexError::Config("partition count must be at least 1".to_string()));
+        }
+        if !(self.bloom_filter_fp_rate > 0.0 && self.bloom_filter_fp_rate < 1.0) {
+            return Err(PlexError::Config(format!(
+                "bloom filter false positive rate must be between 0 and 1, got {}",
+                self.bloom_filter_fp_rate
+            )));
+        }
+        if self.max_partition_size == 0 {
+            return Err(PlexError::Config("max partition size must be greater than 0".to_string()));
+        }
+        if self.partition_weights.contains(&0) {
+            return Err(PlexError::Config("partition weights must all be at least 1".to_string()));
+        }
+        if self.partition_weights.len() > self.partition_count as usize {
+            return Err(PlexError::Config(format!(
+                "{} partition weights given for {} partitions",
+                self.partition_weights.len(),
+                self.partition_count
+            )));
+        }
+        if self.max_key_size == 0 || self.max_value_size == 0 {
+            return Err(PlexError::Config("max key and value sizes must be greater than 0".to_string()));
+        }
+        if self.block_cache_blocks > 0 && self.block_size == 0 {
+            return Err(PlexError::Config("block_size must be greater than 0 when the block cache is enabled".to_string()));
+        }
+        if self.lock_retry.max_attempts == 0 {
+            return Err(PlexError::Config("lock_retry.max_attempts must be at least 1".to_string()));
+        }
+        if self.durability == Durability::Interval(Duration::ZERO) {
+            return Err(PlexError::Config("durability interval must be greater than 0".to_string()));
+        }
+        self.compression.validate_level(self.compression_level)?;
+        Ok(())
+    }
+
+    /// Reads the config saved in `data_dir`, if the directory has one.
+    pub fn load(data_dir: &Path) -> Result<Option<Self>, PlexError> {
+        let path = data_dir.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let config = serde_json::from_slice(&std::fs::read(&path)?)
+            .map_err(|e| PlexError::Config(format!("Invalid config in {:?}: {}", path, e)))?;
+        Ok(Some(config))
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), PlexError> {
+        std::fs::create_dir_all(data_dir)?;
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| PlexError::Config(format!("Failed to serialize config: {}", e)))?;
+
+        let tmp_path = data_dir.join(format!("{}.tmp", CONFIG_FILE));
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(tmp_path, data_dir.join(CONFIG_FILE))?;
+        Ok(())
+    }
+
+    /// Rejects settings that would send existing keys to different
+    /// partitions than the ones they were written to.
+    pub fn check_compatible(&self, saved: &PartitionConfig) -> Result<(), PlexError> {
+        if self.partition_count != saved.partition_count {
+            return Err(PlexError::Config(format!(
+                "data directory was created with {} partitions but {} were requested",
+                saved.partition_count, self.partition_count
+            )));
+        }
+        if self.partitioning != saved.partitioning {
+            return Err(PlexError::Config(format!(
+                "data directory was created with {:?} partitioning but {:?} was requested",
+                saved.partitioning, self.partitioning
+            )));
+        }
+        if self.partition_weights != saved.partition_weights {
+            return Err(PlexError::Config(format!(
+                "data directory was created with partition weights {:?} but {:?} were requested",
+                saved.partition_weights, self.partition_weights
+            )));
+        }
+        if self.hash_family != saved.hash_family {
+            return Err(PlexError::Config(format!(
+                "data directory was created with the {:?} hash family but {:?} was requested",
+                saved.hash_family, self.hash_family
+            )));
         }
+        // Starting to compress an uncompressed directory is fine, since old
+        // entries are flagged as uncompressed; anything else is unreadable.
+        if saved.compression != CompressionAlgorithm::None && self.compression != saved.compression {
+            return Err(PlexError::Config(format!(
+                "data directory was written with {} compression but {} was requested",
+                saved.compression, self.compression
+            )));
+        }
+        Ok(())
     }
 }
 
@@ -43,92 +393,565 @@ impl Default for PartitionConfig {
 pub struct PartitionMetadata {
     pub id: u32,
     pub generation: u64,
-    pu size: u64,
+    pub size: u64,
     pub key_count: u64,
     pub created_at: u64,
     pub last_compaction: u64,
     pub tombstone_count: u64,
+    /// Timestamp of the oldest tombstone written since the last compaction.
+    #[serde(default)]
+    pub oldest_tombstone: Option<u64>,
 }
 
-#[derive(Debug)]
+/// Read and write volume of one partition since it was opened. Counters
+/// are not persisted.
+#[derive(Debug, Default)]
+pub struct PartitionIoStats {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    bloom_false_positives: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    window_lookups: AtomicU64,
+    window_false_positives: AtomicU64,
+    observed_false_positive_rate: Mutex<Option<f64>>,
+}
+
+impl PartitionIoStats {
+    fn record_read(&self, bytes: u64) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn record_write(&self, bytes: u64) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a lookup the bloom filter let through that the index then
+    /// had no live entry for. Returns the observed false positive rate if
+    /// this lookup completed a window.
+    fn record_bloom_false_positive(&self) -> Option<f64> {
+        self.bloom_false_positives.fetch_add(1, Ordering::Relaxed);
+        self.window_false_positives.fetch_add(1, Ordering::Relaxed);
+        self.advance_bloom_window()
+    }
+
+    /// Counts a lookup the bloom filter rejected. Returns the observed false
+    /// positive rate if this lookup completed a window.
+    fn record_bloom_negative(&self) -> Option<f64> {
+        self.advance_bloom_window()
+    }
+
+    fn advance_bloom_window(&self) -> Option<f64> {
+        let lookups = self.window_lookups.fetch_add(1, Ordering::Relaxed) + 1;
+        if lookups < BLOOM_FP_WINDOW {
+            return None;
+        }
+        // Only the lookup that resets the count closes the window, so
+        // concurrent lookups never close it twice.
+        if self
+            .window_lookups
+            .compare_exchange(lookups, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+
+        let false_positives = self.window_false_positives.swap(0, Ordering::Relaxed);
+        let rate = false_positives as f64 / lookups as f64;
+        if let Ok(mut observed) = self.observed_false_positive_rate.lock() {
+            *observed = Some(rate);
+        }
+        Some(rate)
+    }
+
+    /// Drops the current window and the last measurement, once the filter
+    /// they describe has been replaced.
+    fn reset_bloom_window(&self) {
+        self.window_lookups.store(0, Ordering::Relaxed);
+        self.window_false_positives.store(0, Ordering::Relaxed);
+        if let Ok(mut observed) = self.observed_false_positive_rate.lock() {
+            *observed = None;
+        }
+    }
+
+    /// False positive rate over the last full window of absent-key lookups.
+    pub fn observed_false_positive_rate(&self) -> Option<f64> {
+        self.observed_false_positive_rate.lock().ok().and_then(|observed| *observed)
+    }
+
+    pub fn snapshot(&self, id: u32) -> PartitionIoSnapshot {
+        PartitionIoSnapshot {
+            id,
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            bloom_false_positives: self.bloom_false_positives.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A partition's index, write-locked.
+type IndexGuard<'a> = RwLockWriteGuard<'a, HashMap<Vec<u8>, FileOffset>>;
+
+#[derive(Debug, Clone)]
 pub struct Partition {
     pub id: u32,
-    pub metadata: Arc<RwLock<PartitonMetadata>>,
-    pub file_manager: Arc<FileManager>,
-    pub bloom_filter: Arc<RwLock<BloomFilter>>,
-    pub index: Arc<RwLock<HasMap<String, FileOffset>>>,
+    pub metadata: Arc<RwLock<PartitionMetadata>>,
+    pub io_stats: Arc<PartitionIoStats>,
+    pub file_manager: Arc<Mutex<FileManager>>,
+    pub bloom_filter: Arc<RwLock<CountingBloomFilter>>,
+    pub index: Arc<RwLock<HashMap<Vec<u8>, FileOffset>>>,
+    pub memtable: Arc<RwLock<Memtable>>,
+    pub value_cache: Option<Arc<ValueCache>>,
+}
+
+impl Partition {
+    /// Whether a thread panicked while holding one of the partition's
+    /// locks. Poisoning is permanent, so every later access fails with
+    /// `LockError` until the store is reopened.
+    fn is_poisoned(&self) -> bool {
+        self.index.is_poisoned()
+            || self.memtable.is_poisoned()
+            || self.file_manager.is_poisoned()
+            || self.bloom_filter.is_poisoned()
+            || self.metadata.is_poisoned()
+    }
+
+    fn file_manager(&self) -> Result<MutexGuard<'_, FileManager>, PlexError> {
+        self.file_manager.lock().map_err(|_| PlexError::LockError("file manager".to_string()))
+    }
+
+    /// Reads the value of `key`, which the index maps to `offset`. A flush
+    /// can move a value out of the memtable once the index lock is released,
+    /// in which case this returns `None` and the caller has to look the key
+    /// up again.
+    fn read_value(&self, key: &[u8], offset: &FileOffset) -> Result<Option<Vec<u8>>, PlexError> {
+        if offset.in_memtable() {
+            let memtable = self.memtable.read().map_err(|_| PlexError::LockError("memtable".to_string()))?;
+            return Ok(memtable.get(key).map(<[u8]>::to_vec));
+        }
+        self.file_manager()?.read_value(offset)
+    }
+
+    /// Like `read_value`, but serves the value from the value cache when it
+    /// was cached from `offset`, and caches it otherwise. Buffered values
+    /// are already in memory and bypass the cache.
+    fn read_value_cached(&self, key: &[u8], offset: &FileOffset) -> Result<Option<Vec<u8>>, PlexError> {
+        let cache = self
+            .value_cache
+            .as_ref()
+            .filter(|_| !offset.in_memtable());
+        let Some(cache) = cache else {
+            self.io_stats.record_read(offset.size as u64);
+            return self.read_value(key, offset);
+        };
+
+        if let Some(value) = cache.get(key, offset) {
+            self.io_stats.record_cache_hit();
+            return Ok(Some(value));
+        }
+        self.io_stats.record_cache_miss();
+        self.io_stats.record_read(offset.size as u64);
+
+        let value = self.file_manager()?.read_value(offset)?;
+        if let Some(value) = &value {
+            cache.insert(key, offset, value);
+        }
+        Ok(value)
+    }
+
+    /// Drops `key` from the value cache after a write. Cached values are
+    /// checked against the index on every hit, so this only frees memory
+    /// early.
+    fn forget_cached(&self, key: &[u8]) {
+        if let Some(cache) = &self.value_cache {
+            cache.remove(key);
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileOffset {
     pub partition_id: u32,
     pub file_id: u32,
     pub offset: u64,
     pub size: u32,
     pub timestamp: u64,
+    pub expires_at: Option<u64>,
+}
+
+impl FileOffset {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether the value is buffered in the partition's memtable rather
+    /// than written to a segment.
+    pub fn in_memtable(&self) -> bool {
+        self.file_id == MEMTABLE_FILE_ID
+    }
 }
 
 pub trait Partitioner: Send + Sync {
-    fn partition_for_key(&self, key: &str) -> u32;
-    fn rebalance_needed(&self, partition: &[Partiton]) -> bool;
+    fn partition_for_key(&self, key: &[u8]) -> u32;
+    fn rebalance_needed(&self, partitions: &[Partition]) -> bool;
+    fn add_partition(&mut self, partition_id: u32);
 }
 
 #[derive(Debug)]
-pub struct HashPartitioner {
+pub struct HashPartitioner<S = HashFamily> {
     partition_count: u32,
+    hash_builder: S,
 }
 
-impl HashPartitoner {
+impl HashPartitioner {
     pub fn new(partition_count: u32) -> Self {
-        Self { partition_count }
+        Self::with_hasher(partition_count, HashFamily::default())
+    }
+}
+
+impl<S: BuildHasher> HashPartitioner<S> {
+    /// Routes keys with `hash_builder`, which must hash identically across
+    /// restarts or keys will be looked up in the wrong partition.
+    pub fn with_hasher(partition_count: u32, hash_builder: S) -> Self {
+        Self {
+            partition_count,
+            hash_builder,
+        }
     }
 }
 
-impl Partitioner for HashPartitioner {
-    fn partition_for_key(&self, key: &str) -> u32 {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        (hasher.finish() % self.partition_count as u64) as u32
+impl<S: BuildHasher + Send + Sync> Partitioner for HashPartitioner<S> {
+    fn partition_for_key(&self, key: &[u8]) -> u32 {
+        (self.hash_builder.hash_one(KeyBytes(key)) % self.partition_count as u64) as u32
     }
 
 
     fn rebalance_needed(&self, partitions: &[Partition]) -> bool {
-        if partitions.is_empty() {
-            return false;
+        partition_sizes_skewed(partitions)
+    }
+
+    fn add_partition(&mut self, partition_id: u32) {
+        self.partition_count = self.partition_count.max(partition_id + 1);
+    }
+}
+
+/// True when any partition holds more than three times the average size.
+fn partition_sizes_skewed(partitions: &[Partition]) -> bool {
+    partition_sizes_skewed_by_weight(partitions, &[])
+}
+
+/// True when any partition holds more than three times its share of the
+/// total size, where each partition's share is proportional to its weight.
+/// Partitions past the end of `weights` weigh 1.
+fn partition_sizes_skewed_by_weight(partitions: &[Partition], weights: &[u32]) -> bool {
+    if partitions.is_empty() {
+        return false;
+    }
+
+    let sizes: Vec<u64> = partitions
+        .iter()
+        .map(|p| p.metadata.read().unwrap().size)
+        .collect();
+    let weight_of = |i: usize| weights.get(i).copied().unwrap_or(1) as u128;
+
+    let total_size: u128 = sizes.iter().map(|&size| size as u128).sum();
+    let total_weight: u128 = (0..sizes.len()).map(weight_of).sum();
+
+    // size / total_size > 3 * weight / total_weight, without dividing.
+    sizes
+        .iter()
+        .enumerate()
+        .any(|(i, &size)| size as u128 * total_weight > 3 * total_size * weight_of(i))
+}
+
+/// Places every partition on a hash ring through `virtual_nodes` points and
+/// routes each key to the first point clockwise from its hash. Adding or
+/// removing a partition only remaps the keys on the arcs it gains or loses.
+#[derive(Debug)]
+pub struct ConsistentHashPartitioner<S = HashFamily> {
+    ring: BTreeMap<u64, u32>,
+    virtual_nodes: u32,
+    hash_builder: S,
+}
+
+impl ConsistentHashPartitioner {
+    pub fn new(partition_count: u32, virtual_nodes: u32) -> Self {
+        Self::with_hasher(partition_count, virtual_nodes, HashFamily::default())
+    }
+}
+
+impl<S: BuildHasher> ConsistentHashPartitioner<S> {
+    /// Places points and keys on the ring with `hash_builder`, which must
+    /// hash identically across restarts or keys will be looked up in the
+    /// wrong partition.
+    pub fn with_hasher(partition_count: u32, virtual_nodes: u32, hash_builder: S) -> Self {
+        let mut partitioner = Self {
+            ring: BTreeMap::new(),
+            virtual_nodes,
+            hash_builder,
+        };
+
+        for partition_id in 0..partition_count {
+            partitioner.insert_points(partition_id);
         }
 
-        let size: Vec<u64> = partitions
-            .iter()
-            .map(|p| p.metadata.read().unwrap().size)
-            .collect();
+        partitioner
+    }
+
+    fn insert_points(&mut self, partition_id: u32) {
+        for replica in 0..self.virtual_nodes {
+            let point = self.hash_builder.hash_one((partition_id, replica));
+            self.ring.insert(point, partition_id);
+        }
+    }
+
+    pub fn remove_partition(&mut self, partition_id: u32) {
+        self.ring.retain(|_, id| *id != partition_id);
+    }
+}
+
+impl<S: BuildHasher + Send + Sync> Partitioner for ConsistentHashPartitioner<S> {
+    fn partition_for_key(&self, key: &[u8]) -> u32 {
+        let hash = self.hash_builder.hash_one(KeyBytes(key));
 
-        let tota_size: u64 = sizes.iter().sum();
-        let avg_size = total_size / partitions.len() as u64;
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, &partition_id)| partition_id)
+            .unwrap_or(0)
+    }
+
+    fn rebalance_needed(&self, partitions: &[Partition]) -> bool {
+        partition_sizes_skewed(partitions)
+    }
 
-        sizes.iter().any(|&size| size > avg_size * 3)
+    fn add_partition(&mut self, partition_id: u32) {
+        self.insert_points(partition_id);
     }
 }
 
+/// A hash ring like `ConsistentHashPartitioner`, but each partition gets
+/// `virtual_nodes` points per unit of weight, so a partition of weight 2
+/// receives about twice the keys of one of weight 1.
 #[derive(Debug)]
+pub struct WeightedPartitioner<S = HashFamily> {
+    ring: BTreeMap<u64, u32>,
+    weights: Vec<u32>,
+    virtual_nodes: u32,
+    hash_builder: S,
+}
+
+impl WeightedPartitioner {
+    pub fn new(weights: Vec<u32>, virtual_nodes: u32) -> Result<Self, PlexError> {
+        Self::with_hasher(weights, virtual_nodes, HashFamily::default())
+    }
+}
+
+impl<S: BuildHasher> WeightedPartitioner<S> {
+    /// `weights[i]` is the weight of partition `i`, and must be at least 1.
+    /// Like `ConsistentHashPartitioner::with_hasher`, `hash_builder` must
+    /// hash identically across restarts.
+    pub fn with_hasher(weights: Vec<u32>, virtual_nodes: u32, hash_builder: S) -> Result<Self, PlexError> {
+        if weights.is_empty() || weights.contains(&0) {
+            return Err(PlexError::Config("partition weights must all be at least 1".to_string()));
+        }
+
+        let mut partitioner = Self {
+            ring: BTreeMap::new(),
+            weights,
+            virtual_nodes,
+            hash_builder,
+        };
+        for partition_id in 0..partitioner.weights.len() as u32 {
+            partitioner.insert_points(partition_id);
+        }
+
+        Ok(partitioner)
+    }
+
+    pub fn weights(&self) -> &[u32] {
+        &self.weights
+    }
+
+    fn insert_points(&mut self, partition_id: u32) {
+        let weight = self.weights[partition_id as usize];
+        for replica in 0..self.virtual_nodes * weight {
+            let point = self.hash_builder.hash_one((partition_id, replica));
+            self.ring.insert(point, partition_id);
+        }
+    }
+}
+
+impl<S: BuildHasher + Send + Sync> Partitioner for WeightedPartitioner<S> {
+    fn partition_for_key(&self, key: &[u8]) -> u32 {
+        let hash = self.hash_builder.hash_one(KeyBytes(key));
+
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, &partition_id)| partition_id)
+            .unwrap_or(0)
+    }
+
+    fn rebalance_needed(&self, partitions: &[Partition]) -> bool {
+        partition_sizes_skewed_by_weight(partitions, &self.weights)
+    }
+
+    /// Partitions added this way weigh 1.
+    fn add_partition(&mut self, partition_id: u32) {
+        let placed = self.weights.len() as u32;
+        if partition_id < placed {
+            return;
+        }
+        self.weights.resize(partition_id as usize + 1, 1);
+        for new_id in placed..=partition_id {
+            self.insert_points(new_id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RebalanceReport {
+    pub new_partitions: Vec<u32>,
+    pub keys_moved: u64,
+}
+
+/// What `merge_from` did with each live key of the other store.
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    /// Keys this store did not hold.
+    pub inserted: u64,
+    /// Keys this store held with an older timestamp.
+    pub overwritten: u64,
+    /// Keys this store held with a timestamp at least as new, and keys
+    /// that had already expired.
+    pub skipped: u64,
+}
+
+/// One line of an NDJSON export.
+#[derive(Debug, Serialize, Deserialize)]
+struct NdjsonRecord {
+    key: String,
+    value: String,
+}
+
+/// Associated data every sealed line of an encrypted export is bound to.
+const EXPORT_AAD: &[u8] = b"export";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A partition's index and bloom filter, with the segment sizes they were
+/// captured at. Anything past those sizes is read from the log on load.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedIndex {
+    watermark: Vec<(u32, u64)>,
+    metadata: PartitionMetadata,
+    index: HashMap<Vec<u8>, FileOffset>,
+    bloom_filter: CountingBloomFilter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionSnapshot {
+    pub id: u32,
+    /// `(segment id, length)` of every segment captured for the partition.
+    pub segments: Vec<(u32, u64)>,
+    pub key_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub created_at: u64,
+    pub wal_sequence: u64,
+    pub partitions: Vec<PartitionSnapshot>,
+}
+
+/// One record of a `backup_stream` archive. The archive is a header, a
+/// manifest, then each partition's index and files, then `End`.
+#[derive(Debug, Serialize, Deserialize)]
+enum BackupRecord {
+    Manifest(SnapshotManifest),
+    Index {
+        partition_id: u32,
+        index: HashMap<Vec<u8>, FileOffset>,
+        bloom_filter: CountingBloomFilter,
+    },
+    /// Followed by `length` raw bytes of the file.
+    File {
+        partition_id: u32,
+        name: String,
+        length: u64,
+    },
+    End,
+    /// An `Index` of an encrypted store: the index and bloom filter,
+    /// sealed with its key.
+    SealedIndex {
+        partition_id: u32,
+        sealed: Vec<u8>,
+    },
+}
+
 pub struct PartitionManager {
     partitions: Vec<Partition>,
     partitioner: Box<dyn Partitioner>,
-    config: ParttionConfig,
+    config: PartitionConfig,
     data_dir: PathBuf,
-    wal: Arc<WriteAheadLog>,
+    wal: Arc<WAL>,
+    /// Last sequence number handed out per event stream, filled in from
+    /// disk the first time a stream is appended to.
+    stream_sequences: HashMap<String, u64>,
+    /// Set on managers holding a namespace rather than the default
+    /// keyspace. Their WAL entries are tagged with it so replay can route
+    /// them back.
+    namespace: Option<String>,
 }
 
-implPartitionManager {
+impl PartitionManager {
     pub fn new(
         data_dir: PathBuf,
         config: PartitionConfig,
-        wal: Arc<WriteAheadLog>,
+        wal: Arc<WAL>,
     ) -> Result<Self, PlexError> {
-        let partitioner = Box::new(Hashpartitioner::new(config.partition_count));
+        let mut config = config;
+        config.partition_count = Self::validate_partition_dirs(&data_dir, &config)?;
+        let partitioner =
+            config
+                .partitioning
+                .build(config.partition_count, config.hash_family, &config.partition_weights)?;
 
         let mut partitions = Vec::new();
 
         for i in 0..config.partition_count {
-            let prtition = Self::create_partition(i, &data_dir, &config)?;
+            let partition = Self::create_partition(i, &data_dir, &config)?;
             partitions.push(partition);
         }
 
@@ -138,209 +961,4939 @@ implPartitionManager {
             config,
             data_dir,
             wal,
+            stream_sequences: HashMap::new(),
+            namespace: None,
+        })
+    }
+
+    /// Like `new`, but for the keyspace `namespace`, which shares `wal` with
+    /// the default keyspace and any other namespaces.
+    pub fn for_namespace(
+        data_dir: PathBuf,
+        config: PartitionConfig,
+        wal: Arc<WAL>,
+        namespace: &str,
+    ) -> Result<Self, PlexError> {
+        let mut manager = Self::new(data_dir, config, wal)?;
+        manager.namespace = Some(namespace.to_string());
+        Ok(manager)
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Appends `command` to the WAL, tagged with this manager's namespace if
+    /// it has one, and returns its sequence number.
+    pub(crate) fn log(&self, command: WALCommand) -> Result<u64, PlexError> {
+        self.wal.append(self.tag(command))
+    }
+
+    /// Logs `commands` as one batch between `BatchStart` and `BatchEnd`
+    /// markers, so replay applies all of them or none.
+    fn log_batch(&self, commands: Vec<WALCommand>) -> Result<u64, PlexError> {
+        let checksum = wal::batch_checksum(&commands)?;
+
+        let mut framed = Vec::with_capacity(commands.len() + 2);
+        framed.push(self.tag(WALCommand::BatchStart { count: commands.len() as u32 }));
+        framed.extend(commands.into_iter().map(|command| self.tag(command)));
+        framed.push(self.tag(WALCommand::BatchEnd { checksum }));
+
+        self.wal.append_batch(framed)
+    }
+
+    /// Wraps `command` in this manager's namespace, if it has one.
+    fn tag(&self, command: WALCommand) -> WALCommand {
+        match &self.namespace {
+            Some(namespace) => WALCommand::Namespaced {
+                namespace: namespace.clone(),
+                command: Box::new(command),
+            },
+            None => command,
+        }
+    }
+
+    fn partition_dir(data_dir: &Path, id: u32) -> PathBuf {
+        data_dir.join(format!("partition_{:03}", id))
+    }
+
+    /// Checks the partition directories on disk against the config and
+    /// returns the partition count to open, failing instead of letting keys
+    /// be routed to partitions that do not hold them. An empty data directory
+    /// is a fresh store and passes. Consistent-hash stores may have more
+    /// partitions than configured, added by `rebalance`.
+    fn validate_partition_dirs(data_dir: &Path, config: &PartitionConfig) -> Result<u32, PlexError> {
+        let mut ids = Self::partition_ids_on_disk(data_dir)?;
+        if ids.is_empty() {
+            return Ok(config.partition_count);
+        }
+
+        ids.sort_unstable();
+        let on_disk = ids.len() as u32;
+        for (expected, &id) in (0u32..).zip(&ids) {
+            if id != expected {
+                return Err(PlexError::Partition {
+                    id: expected,
+                    message: format!("Missing on disk, though partition {} exists", id),
+                });
+            }
+        }
+
+        let grown_by_rebalance = config.partitioning != Partitioning::Modulo
+            && on_disk > config.partition_count;
+        if on_disk != config.partition_count && !grown_by_rebalance {
+            return Err(PlexError::Config(format!(
+                "Config has {} partitions but {} exist on disk",
+                config.partition_count, on_disk
+            )));
+        }
+
+        Ok(on_disk)
+    }
+
+    /// Ids of the partition directories in `data_dir`, in no particular
+    /// order. A missing directory has none.
+    fn partition_ids_on_disk(data_dir: &Path) -> Result<Vec<u32>, PlexError> {
+        let mut ids = Vec::new();
+        if !data_dir.exists() {
+            return Ok(ids);
+        }
+
+        for entry in std::fs::read_dir(data_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("partition_"))
+                .and_then(|id| id.parse::<u32>().ok())
+            {
+                ids.push(id);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn partition(&self, partition_id: u32) -> Result<&Partition, PlexError> {
+        self.partitions.get(partition_id as usize).ok_or_else(|| PlexError::Partition {
+            id: partition_id,
+            message: "No such partition".to_string(),
         })
     }
 
     fn create_partition(
         id: u32,
-        data_dir: &PathBuf,
+        data_dir: &Path,
         config: &PartitionConfig,
     ) -> Result<Partition, PlexError> {
-        let partition_dir = data_dir.join(format!("partition_{:03}", id));
-        std::fs::create_dir_all(&partition_dir)?;
+        let partition_dir = Self::partition_dir(data_dir, id);
+        if !config.read_only {
+            std::fs::create_dir_all(&partition_dir)?;
+        }
 
         let metadata = PartitionMetadata {
             id,
             generation: 0,
             size: 0,
             key_count: 0,
-            created_at: time::current_timesamp(),
+            created_at: time::current_timestamp(),
             last_compaction: 0,
             tombstone_count: 0,
+            oldest_tombstone: None,
         };
 
-        let file_manager = Arc::new(FileManager::new(partition_dir.clone())?);
-        let bloom_filter = Arc::new(RwLock::new(BloomFilter::new(
-                    config.bloom_filter_size,
-                    config.bloom_filter_fp_rate,
+        let compressor = config.compression.compressor(config.compression_level)?;
+        let mut file_manager = if config.read_only {
+            FileManager::open_read_only(partition_dir.clone(), compressor)?
+        } else if let Some(compressor) = compressor {
+            FileManager::with_compressor(partition_dir.clone(), compressor)?
+        } else {
+            FileManager::new(partition_dir.clone())?
+        };
+        file_manager.set_durability(config.durability)?;
+        file_manager.set_value_log_threshold(config.value_log_threshold)?;
+        file_manager.set_verify_crc(config.verify_crc);
+        file_manager.load_dictionaries(config.compression_level.unwrap_or(DEFAULT_COMPRESSION_LEVEL))?;
+        if let Some(key) = &config.encryption_key {
+            file_manager.set_encryptor(id, Some(Box::new(AesGcmEncryptor::new(key))));
+        }
+        // Segments are scanned for missing filters, so decryption comes first.
+        if config.bloom_granularity == BloomGranularity::Segment {
+            file_manager.set_segment_filters(Some(config.bloom_filter_fp_rate))?;
+        }
+        if config.block_cache_blocks > 0 {
+            file_manager.set_block_cache(Some(Arc::new(SyncBlockCache::new(config.block_cache_blocks, config.block_size))));
+        }
+        let file_manager = Arc::new(Mutex::new(file_manager));
+        let bloom_filter = Arc::new(RwLock::new(CountingBloomFilter::with_hash_family(
+            config.bloom_filter_size,
+            config.bloom_filter_fp_rate,
+            config.hash_family,
         )?));
 
         Ok(Partition {
             id,
             metadata: Arc::new(RwLock::new(metadata)),
+            io_stats: Arc::new(PartitionIoStats::default()),
             file_manager,
             bloom_filter,
             index: Arc::new(RwLock::new(HashMap::new())),
+            memtable: Arc::new(RwLock::new(Memtable::default())),
+            value_cache: (config.value_cache_entries > 0).then(|| Arc::new(ValueCache::new(config.value_cache_entries))),
         })
     }
 
     pub fn get(&self, key: &str) -> Result<Option<String>, PlexError> {
-        let partition_id = self.partitioner.partition_for_key(key);
-        let partition = &self.partitions[partition_id as usize];
+        self.get_bytes(key.as_bytes())?.map(Self::utf8).transpose()
+    }
 
-        {
-            let bloom_filter = partition.bloom_filter.read().map_err(|_| PlexError::LockError)?;
-            if !bloom_filter.contains(key) {
+    /// Like `get`, but never reads from or fills the value cache, so a full
+    /// pass over the keys (a scrubber, say) does not push hot keys out.
+    pub fn get_no_cache(&self, key: &str) -> Result<Option<String>, PlexError> {
+        self.read_bytes(key.as_bytes(), false)?.map(Self::utf8).transpose()
+    }
+
+    /// The value of `key` if `pred` accepts it. Reads bypass the value
+    /// cache, as in `get_no_cache`.
+    pub fn get_if(&self, key: &str, pred: impl Fn(&str) -> bool) -> Result<Option<String>, PlexError> {
+        Ok(self.get_no_cache(key)?.filter(|value| pred(value)))
+    }
+
+    /// Like `get`, for keys and values that need not be UTF-8.
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>, PlexError> {
+        self.read_bytes(key, true)
+    }
+
+    fn read_bytes(&self, key: &[u8], use_cache: bool) -> Result<Option<Vec<u8>>, PlexError> {
+        loop {
+            let Some((partition, offset)) = self.live_offset(key)? else {
+                return Ok(None);
+            };
+
+            let value = if use_cache {
+                partition.read_value_cached(key, &offset)?
+            } else {
+                partition.io_stats.record_read(offset.size as u64);
+                partition.read_value(key, &offset)?
+            };
+            match value {
+                // Flushed since the lookup; the index now points at disk.
+                None if offset.in_memtable() => continue,
+                value => return Ok(value),
+            }
+        }
+    }
+
+    /// Reads `len` bytes of the value of `key` from byte `start`, clamped to
+    /// the end of the value. For entries that are neither compressed nor
+    /// encrypted only that range is read from disk; see
+    /// `FileManager::read_value_range`. Fails with `PlexError::NotUtf8` if
+    /// the range splits a character.
+    pub fn get_range(&self, key: &str, start: usize, len: usize) -> Result<Option<String>, PlexError> {
+        let (partition, range) = loop {
+            let Some((partition, offset)) = self.live_offset(key.as_bytes())? else {
                 return Ok(None);
+            };
+
+            if !offset.in_memtable() {
+                break (partition, partition.file_manager()?.read_value_range(&offset, start, len)?);
+            }
+            if let Some(value) = partition.read_value(key.as_bytes(), &offset)? {
+                let start = start.min(value.len());
+                break (partition, Some(value[start..start + len.min(value.len() - start)].to_vec()));
             }
+        };
+
+        partition.io_stats.record_read(range.as_ref().map_or(0, |range| range.len() as u64));
+        range.map(Self::utf8).transpose()
+    }
+
+    /// Finds the partition and offset holding `key`, dropping it from the
+    /// index if it has expired.
+    fn live_offset(&self, key: &[u8]) -> Result<Option<(&Partition, FileOffset)>, PlexError> {
+        let partition_id = self.partitioner.partition_for_key(key);
+        let partition = self.partition(partition_id)?;
+
+        let maybe_present = self.config.lock_retry.read(&partition.bloom_filter, "bloom filter")?.contains(&KeyBytes(key));
+        if !maybe_present {
+            self.retune_bloom_filter(partition, partition.io_stats.record_bloom_negative())?;
+            return Ok(None);
         }
 
-        let index = partition.index.read().map_err(|_| PlexError::LockError)?;
-        if let Some(offset) = index.get(key) {
-            return partition.file_manager.read_value(offset);
+        let offset = self.config.lock_retry.read(&partition.index, "partition index")?.get(key).cloned();
+        let Some(offset) = offset else {
+            self.retune_bloom_filter(partition, partition.io_stats.record_bloom_false_positive())?;
+            return Ok(None);
+        };
+
+        if offset.is_expired(time::current_timestamp()) {
+            // Another writer may have replaced the key since the read lock
+            // was released; only drop the entry that was seen to expire.
+            let mut index = self.config.lock_retry.write(&partition.index, "partition index")?;
+            if index.get(key) == Some(&offset) {
+                index.remove(key);
+
+                let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+                bloom_filter.remove(&KeyBytes(key));
+
+                let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+                metadata.key_count = metadata.key_count.saturating_sub(1);
+            }
+            return Ok(None);
         }
 
-        Ok(None);
+        Ok(Some((partition, offset)))
     }
 
-    pub fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError> {
-        if key.is_empty() {
+    fn utf8(bytes: Vec<u8>) -> Result<String, PlexError> {
+        String::from_utf8(bytes).map_err(|_| PlexError::NotUtf8)
+    }
+
+    /// Looks up every key, taking each partition's locks once and reading its
+    /// values in file order. The result lines up with `keys`. Unlike `get`,
+    /// expired keys are reported missing but left in the index.
+    pub fn multi_get(&self, keys: &[&str]) -> Result<Vec<Option<String>>, PlexError> {
+        if keys.iter().any(|key| key.is_empty()) {
             return Err(PlexError::KeyIsEmpty);
         }
 
+        let mut by_partition: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (position, key) in keys.iter().enumerate() {
+            let partition_id = self.partitioner.partition_for_key(key.as_bytes());
+            by_partition.entry(partition_id).or_default().push(position);
+        }
+
+        let mut values = vec![None; keys.len()];
+        let now = time::current_timestamp();
+
+        for (partition_id, positions) in by_partition {
+            let partition = self.partition(partition_id)?;
+
+            let mut observed_rate = None;
+            let mut offsets = {
+                let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+                // Buffered values are read while the index is locked, so no
+                // flush can move them out from under their offsets.
+                let memtable = partition.memtable.read().map_err(|_| PlexError::LockError("memtable".to_string()))?;
+                let bloom_filter = partition.bloom_filter.read().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+
+                let mut offsets = Vec::with_capacity(positions.len());
+                for position in positions {
+                    let key = keys[position];
+                    if !bloom_filter.contains(&KeyBytes(key.as_bytes())) {
+                        observed_rate = partition.io_stats.record_bloom_negative().or(observed_rate);
+                        continue;
+                    }
+                    match index.get(key.as_bytes()) {
+                        Some(offset) if offset.in_memtable() && !offset.is_expired(now) => {
+                            partition.io_stats.record_read(offset.size as u64);
+                            values[position] = memtable.get(key.as_bytes()).map(|value| Self::utf8(value.to_vec())).transpose()?;
+                        }
+                        Some(offset) if !offset.is_expired(now) => offsets.push((position, offset.clone())),
+                        Some(_) => {}
+                        None => {
+                            observed_rate = partition.io_stats.record_bloom_false_positive().or(observed_rate);
+                        }
+                    }
+                }
+                offsets
+            };
+            self.retune_bloom_filter(partition, observed_rate)?;
+            offsets.sort_by_key(|(_, offset)| (offset.file_id, offset.offset));
+
+            let file_manager = partition.file_manager()?;
+            for (position, offset) in offsets {
+                partition.io_stats.record_read(offset.size as u64);
+                values[position] = file_manager.read_value(&offset)?.map(Self::utf8).transpose()?;
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Reports whether `key` is live without reading its value from disk.
+    /// The bloom filter only rules keys out; the index has the final say.
+    pub fn exists(&self, key: &str) -> Result<bool, PlexError> {
+        self.exists_bytes(key.as_bytes())
+    }
+
+    pub fn exists_bytes(&self, key: &[u8]) -> Result<bool, PlexError> {
         let partition_id = self.partitioner.partition_for_key(key);
-        let partition = &mut self.partitions[partition_id as usize];
+        let partition = self.partition(partition_id)?;
 
-        self.wal.log_set(key, value)?;
+        let maybe_present = partition.bloom_filter.read().map_err(|_| PlexError::LockError("bloom filter".to_string()))?.contains(&KeyBytes(key));
+        if !maybe_present {
+            self.retune_bloom_filter(partition, partition.io_stats.record_bloom_negative())?;
+            return Ok(false);
+        }
 
-        let offset = partition.file_manager.write_entry(key, value)?;
+        let offset = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?.get(key).cloned();
+        let Some(offset) = offset else {
+            self.retune_bloom_filter(partition, partition.io_stats.record_bloom_false_positive())?;
+            return Ok(false);
+        };
+        Ok(!offset.is_expired(time::current_timestamp()))
+    }
 
-        {
+    pub fn set(&self, key: &str, value: &str) -> Result<(), PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+        self.check_entry_size(key.as_bytes(), value.as_bytes())?;
+
+        let (partition, mut index) = self.lock_index_for(key.as_bytes())?;
+        self.log(WALCommand::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+        })?;
+
+        self.apply_set_locked(partition, &mut index, key.as_bytes(), value.as_bytes(), None)
+    }
+
+    /// Write-locks the index of the partition `key` belongs to. Writers log
+    /// to the WAL while holding it, so the WAL orders writes to a key the
+    /// way they were applied. Only taking the lock is retried; retrying a
+    /// whole write could log it twice.
+    fn lock_index_for(&self, key: &[u8]) -> Result<(&Partition, IndexGuard<'_>), PlexError> {
+        let partition = self.partition(self.partitioner.partition_for_key(key))?;
+        let index = self.config.lock_retry.write(&partition.index, "partition index")?;
+        Ok((partition, index))
+    }
 
-            let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LocError)?;
-            bloom_filter.insert(key);
+    /// Like `set`, for keys and values that need not be UTF-8. Such keys are
+    /// only visible through the `_bytes` methods: the string API skips them
+    /// when listing and fails with `PlexError::NotUtf8` when reading a value
+    /// that is not UTF-8.
+    pub fn set_bytes(&self, key: &[u8], value: &[u8]) -> Result<(), PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
         }
+        self.check_entry_size(key, value)?;
 
-        {
-            let mut index = partition.index.write().map_err(|_| PlexError::LockError)?;
-            index.insert(key.to_string(), offset);
+        let (partition, mut index) = self.lock_index_for(key)?;
+        self.log(WALCommand::SetBytes {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        })?;
+
+        self.apply_set_locked(partition, &mut index, key, value, None)
+    }
+
+    /// Writes all pairs with one WAL sync and one data file sync per touched
+    /// partition. Every WAL entry is made durable before any data is written,
+    /// so a batch that fails midway can still be recovered from the WAL. The
+    /// entries are framed as one batch, so a crash while logging them
+    /// replays none of them. The touched partitions' indexes are locked, in
+    /// partition order, before the batch is logged and until it is applied.
+    pub fn set_batch(&self, pairs: &[(String, String)]) -> Result<(), PlexError> {
+        if pairs.iter().any(|(key, _)| key.is_empty()) {
+            return Err(PlexError::KeyIsEmpty);
         }
-        
-        {
-            let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError)?;
-            metadata.key_count -= 1;
-            metadata.tombstone_count += 1;
+        for (key, value) in pairs {
+            self.check_entry_size(key.as_bytes(), value.as_bytes())?;
+        }
+
+        let mut by_partition: BTreeMap<u32, Vec<_>> = BTreeMap::new();
+        for (key, value) in pairs {
+            let partition_id = self.partitioner.partition_for_key(key.as_bytes());
+            by_partition
+                .entry(partition_id)
+                .or_default()
+                .push((key.as_bytes(), value.as_bytes()));
+        }
+
+        let mut locked = Vec::with_capacity(by_partition.len());
+        for (partition_id, partition_pairs) in by_partition {
+            let partition = self.partition(partition_id)?;
+            let index = self.config.lock_retry.write(&partition.index, "partition index")?;
+            locked.push((partition_id, partition, index, partition_pairs));
+        }
+
+        self.log_batch(
+            pairs
+                .iter()
+                .map(|(key, value)| WALCommand::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        )?;
+        self.wal.sync()?;
+
+        for (partition_id, partition, mut index, partition_pairs) in locked {
+            let offsets = partition.file_manager()?.write_entries(&partition_pairs)?;
+
+            let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+            let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+            for ((key, _), offset) in partition_pairs.iter().zip(offsets) {
+                partition.io_stats.record_write(offset.size as u64);
+                metadata.size += offset.size as u64;
+                if index.insert(key.to_vec(), offset).is_none() {
+                    bloom_filter.insert(&KeyBytes(key));
+                    metadata.key_count += 1;
+                }
+                partition.forget_cached(key);
+            }
+
+            if bloom_filter.should_resize() {
+                self.rebuild_bloom_filter(partition_id, &index, &mut bloom_filter)?;
+            }
         }
 
         Ok(())
     }
 
-    fn should_compact_partition(&self, partition_id: u32) -> bool {
-        let partition = &self.prtitions[partition_id as usize];
-        let metadata = partition.metadata.read().unwrap();
+    /// Loads pairs straight into fresh segments, without going through the
+    /// WAL: the source of the pairs is expected to be the durable record
+    /// until this returns. Pairs are buffered per partition and written a
+    /// chunk at a time, and every segment is synced once at the end. Input
+    /// does not have to be sorted, though sorted input keeps each segment in
+    /// key order. Returns how many pairs were loaded.
+    ///
+    /// Refused while the WAL holds entries past its checkpoint, since
+    /// replaying an older write of a loaded key would overwrite it; the WAL
+    /// is shared with other keyspaces, so it has to be checkpointed first.
+    pub fn bulk_load(&mut self, sorted_pairs: impl Iterator<Item = (String, String)>) -> Result<u64, PlexError> {
+        let logged = self.wal.get_lastest_sequence();
+        if logged > self.wal.durable_sequence()? {
+            return Err(PlexError::WAL(format!(
+                "WAL entries up to sequence {} are not checkpointed; checkpoint before a bulk load",
+                logged
+            )));
+        }
 
-        if metadata.key_count > 0 {
-            let tombstone_ratio = metadata.tombstone_count as f64 /
-                                (metadata.key_count + metadata.tombstone_count) as f64;
-            if tombstone_ratio > self.config.compaction_threshold {
-                return true;
+        for partition in &self.partitions {
+            partition.file_manager()?.start_new_segment()?;
+        }
+
+        let mut pending: Vec<Vec<(Vec<u8>, Vec<u8>)>> = vec![Vec::new(); self.partitions.len()];
+        let mut count = 0;
+
+        for (key, value) in sorted_pairs {
+            if key.is_empty() {
+                return Err(PlexError::KeyIsEmpty);
+            }
+            self.check_entry_size(key.as_bytes(), value.as_bytes())?;
+
+            let partition_id = self.partitioner.partition_for_key(key.as_bytes());
+            let chunk = &mut pending[partition_id as usize];
+            chunk.push((key.into_bytes(), value.into_bytes()));
+            count += 1;
+
+            if chunk.len() == BULK_LOAD_CHUNK_SIZE {
+                let chunk = std::mem::take(chunk);
+                self.bulk_load_chunk(partition_id, &chunk)?;
             }
         }
 
-        metadata.size > self.config.max_partition_size
+        for (partition_id, chunk) in pending.into_iter().enumerate() {
+            self.bulk_load_chunk(partition_id as u32, &chunk)?;
+        }
+
+        for partition in &self.partitions {
+            partition.file_manager()?.flush()?;
+            self.rebuild_bloom_filter_if_degraded(partition)?;
+        }
+
+        Ok(count)
     }
 
-    fn compact_partition(&mut self, partition_id: u32) -> Result<(), PlexError> {
-        let partition = &mut self.partitions[partition_id as usize];
+    fn bulk_load_chunk(&self, partition_id: u32, pairs: &[(Vec<u8>, Vec<u8>)]) -> Result<(), PlexError> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
 
+        let partition = self.partition(partition_id)?;
+        let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        let offsets = partition.file_manager()?.bulk_write(pairs)?;
 
-        let new_generation = {
-            let mut metadata = partition.metadata.write).map_err(|_| PlexError::LockError)?;
-            metadata.generation += 1;
-            metadata.generation
+        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+        for ((key, _), offset) in pairs.iter().zip(offsets) {
+            metadata.size += offset.size as u64;
+            partition.io_stats.record_write(offset.size as u64);
+            if index.insert(key.clone(), offset).is_none() {
+                bloom_filter.insert(&KeyBytes(key));
+                metadata.key_count += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends `payload` to `stream` under the key `stream:seq` and returns
+    /// the sequence number it was given. Sequences start at 1 and increase by
+    /// one per event, so deleting an event from the middle of a stream makes
+    /// it look shorter the next time it is opened.
+    pub fn append_event(&mut self, stream: &str, payload: &str) -> Result<u64, PlexError> {
+        if stream.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        let last = match self.stream_sequences.get(stream) {
+            Some(&last) => last,
+            None => self.find_last_sequence(stream)?,
         };
+        let seq = last + 1;
+
+        self.set(&Self::event_key(stream, seq), payload)?;
+        self.stream_sequences.insert(stream.to_string(), seq);
+        Ok(seq)
+    }
 
-        let compacted_data = self.collect_live_data(partition_id)?;
-        let new_file_manager = partition.file_manager.compact(new_generation, compacted_data)?;
+    /// Up to `limit` events of `stream` as `(seq, payload)`, in sequence
+    /// order, starting at `from_seq`.
+    pub fn read_stream(&self, stream: &str, from_seq: u64, limit: usize) -> Result<Vec<(u64, String)>, PlexError> {
+        if stream.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
 
+        let from_seq = from_seq.max(1);
+        let last = match self.stream_sequences.get(stream) {
+            Some(&last) => last,
+            None => self.find_last_sequence(stream)?,
+        };
+        if from_seq > last || limit == 0 {
+            return Ok(Vec::new());
+        }
 
-        partition.file_manager = Arc::new(new_file_manager);
+        let to_seq = last.min(from_seq.saturating_add(limit as u64 - 1));
+        let keys: Vec<String> = (from_seq..=to_seq).map(|seq| Self::event_key(stream, seq)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
 
-        {
-            let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError)?;
-            for key in index.keys() {
-                bloom_filter.insert(key);
+        let events = (from_seq..=to_seq)
+            .zip(self.multi_get(&keys)?)
+            .filter_map(|(seq, payload)| payload.map(|payload| (seq, payload)))
+            .collect();
+        Ok(events)
+    }
+
+    /// Zero-padded so a stream's keys sort in sequence order.
+    fn event_key(stream: &str, seq: u64) -> String {
+        format!("{}:{:020}", stream, seq)
+    }
+
+    /// Finds the highest sequence stored for `stream`, relying on sequences
+    /// being contiguous from 1: doubles a probe until it misses, then binary
+    /// searches the last gap. Returns 0 for a stream with no events.
+    fn find_last_sequence(&self, stream: &str) -> Result<u64, PlexError> {
+        let mut present = 0;
+        let mut probe = 1;
+        while self.exists(&Self::event_key(stream, probe))? {
+            present = probe;
+            probe = probe.saturating_mul(2);
+        }
+
+        // `present` exists and `probe` does not.
+        while probe - present > 1 {
+            let mid = present + (probe - present) / 2;
+            if self.exists(&Self::event_key(stream, mid))? {
+                present = mid;
+            } else {
+                probe = mid;
             }
         }
+        Ok(present)
+    }
+
+    /// Rejects keys and values over the configured size limits.
+    pub(crate) fn check_entry_size(&self, key: &[u8], value: &[u8]) -> Result<(), PlexError> {
+        if key.len() > self.config.max_key_size {
+            return Err(PlexError::KeyTooLarge {
+                size: key.len(),
+                limit: self.config.max_key_size,
+            });
+        }
+        if value.len() > self.config.max_value_size {
+            return Err(PlexError::ValueTooLarge {
+                size: value.len(),
+                limit: self.config.max_value_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Stores `value` under `key` and lets it expire `ttl_secs` seconds from now.
+    /// Expired keys are dropped lazily, the next time they are read, or by
+    /// the expiry sweeper when `expiry_sweep_interval` is set.
+    pub fn set_with_ttl(&self, key: &str, value: &str, ttl_secs: u64) -> Result<(), PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+        self.check_entry_size(key.as_bytes(), value.as_bytes())?;
+
+        let (partition, mut index) = self.lock_index_for(key.as_bytes())?;
+        self.log(WALCommand::SetEx {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl_secs,
+        })?;
+
+        let expires_at = time::current_timestamp() + ttl_secs;
+        self.apply_set_locked(partition, &mut index, key.as_bytes(), value.as_bytes(), Some(expires_at))
+    }
+
+    pub(crate) fn apply_set(&self, key: &[u8], value: &[u8], expires_at: Option<u64>) -> Result<(), PlexError> {
+        let partition_id = self.partitioner.partition_for_key(key);
+        let partition = self.partition(partition_id)?;
+
+        let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        self.apply_set_locked(partition, &mut index, key, value, expires_at)
+    }
 
+    /// Writes `key` to the partition's memtable, or straight to disk when
+    /// there is none, and updates the index, bloom filter and metadata. The
+    /// caller holds the index lock across all of it, so concurrent writers
+    /// to the same key update all three in the order their entries hit the
+    /// log.
+    fn apply_set_locked(
+        &self,
+        partition: &Partition,
+        index: &mut HashMap<Vec<u8>, FileOffset>,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<(), PlexError> {
+        let (offset, size) = match self.config.memtable_size {
+            // Buffered sets count towards the partition's size once flushed.
+            Some(_) => {
+                let mut memtable = partition.memtable.write().map_err(|_| PlexError::LockError("memtable".to_string()))?;
+                (memtable.insert(partition.id, key, value, expires_at), 0)
+            }
+            None => {
+                let offset = partition.file_manager()?.write_entry_with_expiry(key, value, expires_at)?;
+                partition.io_stats.record_write(offset.size as u64);
+                let size = offset.size as u64;
+                (offset, size)
+            }
+        };
+        self.index_set_locked(partition, index, key, offset, size)
+    }
+
+    /// Sets `key` like `apply_set_locked`, stamping its entry with
+    /// `timestamp`. The entry goes straight to disk, since the memtable
+    /// stamps the entries it flushes with the time of the flush.
+    fn apply_set_at_locked(
+        &self,
+        partition: &Partition,
+        index: &mut HashMap<Vec<u8>, FileOffset>,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+        timestamp: u64,
+    ) -> Result<(), PlexError> {
+        let offset = partition.file_manager()?.write_entry_at(key, value, expires_at, timestamp)?;
+        partition.io_stats.record_write(offset.size as u64);
+        let size = offset.size as u64;
+        self.index_set_locked(partition, index, key, offset, size)
+    }
+
+    /// Points the index at a just-written `offset` for `key` and updates the
+    /// bloom filter and metadata to match. `size` is what the write added
+    /// to the partition on disk.
+    fn index_set_locked(
+        &self,
+        partition: &Partition,
+        index: &mut HashMap<Vec<u8>, FileOffset>,
+        key: &[u8],
+        offset: FileOffset,
+        size: u64,
+    ) -> Result<(), PlexError> {
         {
-            let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError)?;
-            metadata.last_compaction = time::current_timestamp();
-            metadata.tombstone_count = 0;
+            let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+            let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+            // Only count a key once in the bloom filter, so that a single
+            // delete brings its counters back down.
+            if index.insert(key.to_vec(), offset).is_none() {
+                bloom_filter.insert(&KeyBytes(key));
+                metadata.key_count += 1;
+
+                if bloom_filter.should_resize() {
+                    self.rebuild_bloom_filter(partition.id, index, &mut bloom_filter)?;
+                }
+            }
+            metadata.size += size;
+        }
+        partition.forget_cached(key);
+
+        if let Some(memtable_size) = self.config.memtable_size {
+            let full = partition.memtable.read().map_err(|_| PlexError::LockError("memtable".to_string()))?.size() >= memtable_size;
+            if full {
+                Self::flush_memtable_in(partition, index)?;
+            }
         }
 
         Ok(())
     }
 
-    fn collect_live_data(&self, partition_id: u32) -> Result<Vec<(String, String)>, PlexError> {
-        let partition = &self.partitions[partition_id as usize];
-        let index = partition.index.read().map_err(|_| PlexError::LockError)?;
+    /// Sets `key` to `value` and returns the value it replaced, `None` if
+    /// the key was missing or expired. The read and the write happen under
+    /// one hold of the partition's index lock, so no other write to the key
+    /// can land in between.
+    pub fn set_and_get(&self, key: &str, value: &str) -> Result<Option<String>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+        self.check_entry_size(key.as_bytes(), value.as_bytes())?;
+
+        let (partition, mut index) = self.lock_index_for(key.as_bytes())?;
 
-        let mut live_data = Vec::new();
-        for (key, offset) in index.iter() {
-            if let Some(value) = partition.file_manager.read_value(offset)? {
-                live_data.push((key.clone(), value);
+        let now = time::current_timestamp();
+        let previous = match index.get(key.as_bytes()) {
+            Some(offset) if !offset.is_expired(now) => {
+                partition.io_stats.record_read(offset.size as u64);
+                partition.read_value(key.as_bytes(), offset)?.map(Self::utf8).transpose()?
             }
-        }
+            _ => None,
+        };
+
+        self.log(WALCommand::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+        })?;
 
-        Ok(live_data)
+        self.apply_set_locked(partition, &mut index, key.as_bytes(), value.as_bytes(), None)?;
+
+        Ok(previous)
     }
 
-    pub fn load_from_disk(&mut self) -> Result<(), PlexError> {
-        for partition in &mut self.partitions {
-            self.load_partition(partition)?;
+    /// Writes `new` only if the current value equals `expected`, where `None`
+    /// means the key must not exist. The partition index stays write-locked
+    /// from the read through the write, so no other writer can interleave.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
         }
-        Ok(())
+        self.check_entry_size(key.as_bytes(), new.as_bytes())?;
+
+        let (partition, mut index) = self.lock_index_for(key.as_bytes())?;
+
+        let now = time::current_timestamp();
+        let current = match index.get(key.as_bytes()) {
+            Some(offset) if !offset.is_expired(now) => {
+                partition.io_stats.record_read(offset.size as u64);
+                partition.read_value(key.as_bytes(), offset)?
+            }
+            _ => None,
+        };
+
+        if current.as_deref() != expected.map(str::as_bytes) {
+            return Ok(false);
+        }
+
+        self.log(WALCommand::Set {
+            key: key.to_string(),
+            value: new.to_string(),
+        })?;
+
+        self.apply_set_locked(partition, &mut index, key.as_bytes(), new.as_bytes(), None)?;
+
+        Ok(true)
     }
 
-    fn load_partitions(&self, partition: &mut Partition) -> Result<(), PlexError> {
-        let entries = partition.file_manager.read_all_entries()?;
+    /// Adds `delta` to the integer stored under `key`, treating a missing key
+    /// as 0, and returns the new total. The WAL records the resulting value
+    /// rather than the delta, so replaying it is idempotent.
+    pub fn merge(&self, key: &str, delta: i64) -> Result<i64, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
 
-        let mut index = partition.index.write().map_err(|_| PlexError::LockError)?;
-        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError)?;
-        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError)?;
+        let (partition, mut index) = self.lock_index_for(key.as_bytes())?;
+        let partition_id = partition.id;
 
-        for (key, offset, is_tombstone) in entries {
-            if is_tombstone {
-                index.remove(&key);
-                metadata.tombstone_count += 1;
-            } else {
-                index.insert(key.clone(), offset);
-                bloom_filter.insert(&key);
-                metadata.key_count += 1;
+        let now = time::current_timestamp();
+        let current = match index.get(key.as_bytes()) {
+            Some(offset) if !offset.is_expired(now) => {
+                partition.io_stats.record_read(offset.size as u64);
+                partition.read_value(key.as_bytes(), offset)?
             }
+            _ => None,
+        };
+
+        let current = match current {
+            Some(value) => std::str::from_utf8(&value)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .ok_or_else(|| PlexError::Partition {
+                    id: partition_id,
+                    message: format!("value of '{}' is not an integer", key),
+                })?,
+            None => 0,
+        };
 
+        let total = current.checked_add(delta).ok_or_else(|| PlexError::Partition {
+            id: partition_id,
+            message: format!("adding {} to '{}' overflows", delta, key),
+        })?;
+        let value = total.to_string();
+        self.check_entry_size(key.as_bytes(), value.as_bytes())?;
+
+        self.log(WALCommand::Set {
+            key: key.to_string(),
+            value: value.clone(),
+        })?;
+
+        self.apply_set_locked(partition, &mut index, key.as_bytes(), value.as_bytes(), None)?;
+
+        Ok(total)
+    }
+
+    /// Deletes `key`, returning whether it held a live value. A missing or
+    /// expired key is left alone and nothing is logged for it.
+    pub fn delete(&self, key: &str) -> Result<bool, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
         }
-        Ok(())
+
+        self.delete_logged(key.as_bytes(), || WALCommand::Delete { key: key.to_string() })
     }
 
-    pub fn stats(&self) -> Result<PartitionManagerStats, PlexError> {
-        let mut total_keys = 0;
-        let mut total_size = 0;
-        let mut total_tombstones = 0;
+    /// Like `delete`, for keys that need not be UTF-8.
+    pub fn delete_bytes(&self, key: &[u8]) -> Result<bool, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
 
+        self.delete_logged(key, || WALCommand::DeleteBytes { key: key.to_vec() })
+    }
+
+    /// Checks that `key` is live, logs `command` and tombstones the key, all
+    /// under one hold of the partition's index lock, so of two concurrent
+    /// deletes only one finds the key and logs it.
+    fn delete_logged(&self, key: &[u8], command: impl FnOnce() -> WALCommand) -> Result<bool, PlexError> {
+        let (partition, index) = self.lock_index_for(key)?;
+
+        let now = time::current_timestamp();
+        if index.get(key).is_none_or(|offset| offset.is_expired(now)) {
+            return Ok(false);
+        }
 
+        self.log(command())?;
+        Self::tombstone_in(partition, index, key)?;
+        Ok(true)
+    }
+
+    /// Tombstones every live key in `[start, end)` across all partitions and
+    /// returns how many were deleted. Each delete is logged to the WAL.
+    pub fn delete_range(&self, start: &str, end: &str) -> Result<u64, PlexError> {
+        let mut keys = Vec::new();
+        let now = time::current_timestamp();
+
+        // Expired keys already read as missing, so they are neither counted
+        // nor tombstoned; the expiry sweep drops them.
         for partition in &self.partitions {
-            let metadata = partition.metadata.read().map_err(|_| PlexError::LockError)?;
-            total_keys += metadata.key_count;
-            total_size += metdata.size;
-            total_tombstones += metadata.tombstones_count;
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            keys.extend(
+                index
+                    .iter()
+                    .filter(|(key, offset)| {
+                        key.as_slice() >= start.as_bytes() && key.as_slice() < end.as_bytes() && !offset.is_expired(now)
+                    })
+                    .map(|(key, _)| key.clone()),
+            );
         }
 
-        Ok(PartitionManagerStats {
-            partition_count: self.partitions.len() as u32,
-            total_keys,
-            total_size,
-            total_tombstones,
-        })
+        for key in &keys {
+            self.log(Self::delete_command(key))?;
+            self.apply_delete(key)?;
+        }
+
+        Ok(keys.len() as u64)
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct PartitionManagerStats {
-    pub partition_count: u32,
-    pub total_keys: u64,
-    pub total_size: u64,
-    pub total_tombstones: u64,
-}
+    // Keys written through the string API keep logging as `Delete`, so WALs
+    // stay readable by builds without the byte variants.
+    fn delete_command(key: &[u8]) -> WALCommand {
+        match std::str::from_utf8(key) {
+            Ok(key) => WALCommand::Delete { key: key.to_string() },
+            Err(_) => WALCommand::DeleteBytes { key: key.to_vec() },
+        }
+    }
+
+    pub(crate) fn apply_delete(&self, key: &[u8]) -> Result<(), PlexError> {
+        let partition_id = self.partitioner.partition_for_key(key);
+        let partition = self.partition(partition_id)?;
+
+        let index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        Self::tombstone_in(partition, index, key)
+    }
+
+    /// Writes the partition's memtable to a new segment in key order and
+    /// points the index at the written entries. Buffered keys that have
+    /// since been overwritten on disk, deleted or expired out of the index
+    /// are dropped rather than written.
+    fn flush_memtable_in(partition: &Partition, index: &mut HashMap<Vec<u8>, FileOffset>) -> Result<(), PlexError> {
+        let mut memtable = partition.memtable.write().map_err(|_| PlexError::LockError("memtable".to_string()))?;
+        if memtable.is_empty() {
+            return Ok(());
+        }
+
+        let entries: Vec<(&[u8], &[u8], Option<u64>)> = memtable
+            .iter()
+            .filter_map(|(key, value)| {
+                let offset = index.get(key).filter(|offset| offset.in_memtable())?;
+                Some((key, value, offset.expires_at))
+            })
+            .collect();
+
+        let offsets = {
+            let mut file_manager = partition.file_manager()?;
+            file_manager.start_new_segment()?;
+            file_manager.write_entries_with_expiry(&entries)?
+        };
+
+        let mut size = 0;
+        for ((key, _, _), offset) in entries.iter().zip(offsets) {
+            size += offset.size as u64;
+            index.insert(key.to_vec(), offset);
+        }
+        drop(entries);
+        memtable.clear();
+        drop(memtable);
+
+        partition.io_stats.record_write(size);
+        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+        metadata.size += size;
+        Ok(())
+    }
+
+    fn flush_memtable(partition: &Partition) -> Result<(), PlexError> {
+        let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        Self::flush_memtable_in(partition, &mut index)
+    }
+
+    /// Tombstones every key in the partition whose TTL has passed and
+    /// returns how many there were. Keys are locked one at a time, and one
+    /// rewritten since the scan is left alone.
+    pub(crate) fn sweep_expired_in(partition: &Partition) -> Result<u64, PlexError> {
+        let now = time::current_timestamp();
+        let expired: Vec<Vec<u8>> = {
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            index
+                .iter()
+                .filter(|(_, offset)| offset.is_expired(now))
+                .map(|(key, _)| key.clone())
+                .collect()
+        };
+
+        let mut swept = 0;
+        for key in expired {
+            let index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            if !index.get(&key).is_some_and(|offset| offset.is_expired(now)) {
+                continue;
+            }
+
+            Self::tombstone_in(partition, index, &key)?;
+            swept += 1;
+        }
+
+        Ok(swept)
+    }
+
+    /// Tombstones expired keys in every partition; see `sweep_expired_in`.
+    pub fn sweep_expired(&self) -> Result<u64, PlexError> {
+        let mut swept = 0;
+        for partition in &self.partitions {
+            swept += Self::sweep_expired_in(partition)?;
+        }
+        Ok(swept)
+    }
 
+    /// Writes a tombstone for `key` and drops it from the index, which the
+    /// caller passes in already write-locked.
+    fn tombstone_in(
+        partition: &Partition,
+        mut index: RwLockWriteGuard<'_, HashMap<Vec<u8>, FileOffset>>,
+        key: &[u8],
+    ) -> Result<(), PlexError> {
+        let tombstone = partition.file_manager()?.write_tombstone(key)?;
+        partition.io_stats.record_write(tombstone.size as u64);
+
+        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+        if index.remove(key).is_some() {
+            bloom_filter.remove(&KeyBytes(key));
+            metadata.key_count = metadata.key_count.saturating_sub(1);
+        }
+        partition.forget_cached(key);
+        metadata.tombstone_count += 1;
+        metadata.oldest_tombstone.get_or_insert(tombstone.timestamp);
+        metadata.size += tombstone.size as u64;
+
+        Ok(())
+    }
+
+    /// Deletes every key: each partition's segments, value logs and persisted
+    /// index go, and its index, bloom filter and metadata start over. A
+    /// `Truncate` marker is logged and synced first, so replaying the WAL
+    /// after a crash part way through finishes the clear instead of bringing
+    /// the data back.
+    pub fn clear(&mut self) -> Result<(), PlexError> {
+        self.log(WALCommand::Truncate)?;
+        self.wal.sync()?;
+        self.apply_clear()
+    }
+
+    fn apply_clear(&mut self) -> Result<(), PlexError> {
+        for partition in &self.partitions {
+            let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            partition.memtable.write().map_err(|_| PlexError::LockError("memtable".to_string()))?.clear();
+            partition.file_manager()?.clear()?;
+
+            let persisted_index = Self::partition_dir(&self.data_dir, partition.id).join(PERSISTED_INDEX_FILE);
+            if persisted_index.exists() {
+                std::fs::remove_file(persisted_index)?;
+            }
+
+            let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+            let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+            index.clear();
+            *bloom_filter = CountingBloomFilter::with_hash_family(
+                self.config.bloom_filter_size,
+                self.config.bloom_filter_fp_rate,
+                self.config.hash_family,
+            )?;
+            metadata.generation += 1;
+            metadata.size = 0;
+            metadata.key_count = 0;
+            metadata.tombstone_count = 0;
+            metadata.oldest_tombstone = None;
+
+            if let Some(cache) = &partition.value_cache {
+                cache.clear();
+            }
+        }
+
+        self.stream_sequences.clear();
+        Ok(())
+    }
+
+    /// Starts a transaction whose writes stay buffered until it commits.
+    pub fn begin(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    pub(crate) fn wal(&self) -> &WAL {
+        &self.wal
+    }
+
+    /// Re-applies recovered WAL entries in order. Writes logged inside a
+    /// transaction are only applied once its commit marker is seen, so a
+    /// transaction cut short by a crash leaves no trace. Batches work the
+    /// same way, and are also dropped if their end marker's count or
+    /// checksum does not match what was read.
+    pub fn apply_wal_entries(&mut self, entries: &[WALEntry]) -> Result<(), PlexError> {
+        let mut open_txn: Option<(u64, Vec<&WALEntry>)> = None;
+        let mut open_batch: Option<(u32, Vec<&WALEntry>)> = None;
+
+        for entry in entries {
+            if let Some((_, buffered)) = open_batch.as_mut()
+                && !matches!(entry.command, WALCommand::BatchStart { .. } | WALCommand::BatchEnd { .. })
+            {
+                buffered.push(entry);
+                continue;
+            }
+
+            match &entry.command {
+                WALCommand::BatchStart { count } => {
+                    if let Some((_, dropped)) = open_batch.replace((*count, Vec::new())) {
+                        warn!("Dropping {} WAL entries of a batch that never ended", dropped.len());
+                    }
+                }
+                WALCommand::BatchEnd { checksum } => {
+                    let Some((count, buffered)) = open_batch.take() else {
+                        continue;
+                    };
+                    let whole = buffered.len() == count as usize
+                        && wal::batch_checksum(buffered.iter().map(|entry| &entry.command))? == *checksum;
+                    if !whole {
+                        warn!("Dropping a WAL batch of {} entries whose checksum does not match", buffered.len());
+                        continue;
+                    }
+                    for buffered_entry in buffered {
+                        self.apply_wal_entry(buffered_entry)?;
+                    }
+                }
+                WALCommand::TxnBegin => {
+                    open_txn = Some((entry.sequence_number, Vec::new()));
+                }
+                WALCommand::TxnCommit { begin_sequence } => {
+                    if let Some((sequence, buffered)) = open_txn.take()
+                        && sequence == *begin_sequence
+                    {
+                        for buffered_entry in buffered {
+                            self.apply_wal_entry(buffered_entry)?;
+                        }
+                    }
+                }
+                _ => match open_txn.as_mut() {
+                    Some((_, buffered)) => buffered.push(entry),
+                    None => self.apply_wal_entry(entry)?,
+                },
+            }
+        }
+
+        if let Some((_, dropped)) = open_batch {
+            warn!("Dropping {} WAL entries of a batch cut off before its end marker", dropped.len());
+        }
+        Ok(())
+    }
+
+    /// Re-applies a command recovered from the WAL without logging it again.
+    pub fn apply_wal_entry(&mut self, entry: &WALEntry) -> Result<(), PlexError> {
+        match &entry.command {
+            WALCommand::Set { key, value } => self.apply_set(key.as_bytes(), value.as_bytes(), None),
+            WALCommand::SetEx { key, value, ttl_secs } => {
+                self.apply_set(key.as_bytes(), value.as_bytes(), Some(entry.timestamp + ttl_secs))
+            }
+            WALCommand::Delete { key } => self.apply_delete(key.as_bytes()),
+            WALCommand::SetBytes { key, value } => self.apply_set(key, value, None),
+            WALCommand::SetAt { key, value, expires_at, timestamp } => {
+                let partition = self.partition(self.partitioner.partition_for_key(key))?;
+                let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+                self.apply_set_at_locked(partition, &mut index, key, value, *expires_at, *timestamp)
+            }
+            WALCommand::DeleteBytes { key } => self.apply_delete(key),
+            WALCommand::RebalanceTo { partition_count } => {
+                self.apply_rebalance(*partition_count).map(|_| ())
+            }
+            WALCommand::Truncate => self.apply_clear(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn config(&self) -> &PartitionConfig {
+        &self.config
+    }
+
+    /// Adds a partition when the partitioner reports a skewed size
+    /// distribution, then moves every key the partitioner now routes
+    /// elsewhere. Only the hash-ring partitionings are supported: the new
+    /// partition takes over about 1/(n+1) of the keys, where the modulo
+    /// partitioner would move about n/(n+1) of them. Under weighted
+    /// partitioning the new partition weighs 1.
+    ///
+    /// The target partition count is logged to the WAL first, so a
+    /// rebalance interrupted by a crash is finished during replay. Replay
+    /// scans every index again, but only keys still in the wrong partition
+    /// are moved, so keys moved before the crash are not copied twice.
+    pub fn rebalance(&mut self) -> Result<RebalanceReport, PlexError> {
+        if self.config.partitioning == Partitioning::Modulo {
+            return Err(PlexError::Config(
+                "rebalance needs consistent-hash or weighted partitioning; modulo partitioning would move almost every key. \
+                 Partitioning is fixed when a store is created, so export the keys and import them into a store created with `Partitioning::ConsistentHash`".to_string(),
+            ));
+        }
+
+        if !self.partitioner.rebalance_needed(&self.partitions) {
+            return Ok(RebalanceReport::default());
+        }
+
+        let partition_count = self.partitions.len() as u32 + 1;
+        self.log(WALCommand::RebalanceTo { partition_count })?;
+        self.wal.sync()?;
+
+        self.apply_rebalance(partition_count)
+    }
+
+    fn apply_rebalance(&mut self, partition_count: u32) -> Result<RebalanceReport, PlexError> {
+        let mut report = RebalanceReport::default();
+
+        // Keys are moved by reading them back from disk.
+        for partition in &self.partitions {
+            Self::flush_memtable(partition)?;
+        }
+
+        while (self.partitions.len() as u32) < partition_count {
+            let partition_id = self.partitions.len() as u32;
+            let partition = Self::create_partition(partition_id, &self.data_dir, &self.config)?;
+            self.partitions.push(partition);
+            self.partitioner.add_partition(partition_id);
+            report.new_partitions.push(partition_id);
+        }
+        self.config.partition_count = partition_count;
+
+        for source_id in 0..self.partitions.len() {
+            let misplaced: Vec<(Vec<u8>, FileOffset)> = {
+                let index = self.partitions[source_id].index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+                index
+                    .iter()
+                    .filter(|(key, _)| self.partitioner.partition_for_key(key) as usize != source_id)
+                    .map(|(key, offset)| (key.clone(), offset.clone()))
+                    .collect()
+            };
+
+            for (key, offset) in misplaced {
+                self.move_key(source_id, &key, &offset)?;
+                report.keys_moved += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    // The value is written to the target before the source is tombstoned, so
+    // a crash in between leaves a duplicate rather than losing the key.
+    fn move_key(&mut self, source_id: usize, key: &[u8], offset: &FileOffset) -> Result<(), PlexError> {
+        let target_id = self.partitioner.partition_for_key(key) as usize;
+
+        let Some(value) = self.partitions[source_id].file_manager()?.read_value(offset)? else {
+            return Ok(());
+        };
+
+        {
+            let target = &mut self.partitions[target_id];
+            let new_offset = target.file_manager()?.write_entry_with_expiry(key, &value, offset.expires_at)?;
+            let size = new_offset.size as u64;
+
+            let mut index = target.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            let mut bloom_filter = target.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+            let mut metadata = target.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+            if index.insert(key.to_vec(), new_offset).is_none() {
+                bloom_filter.insert(&KeyBytes(key));
+                metadata.key_count += 1;
+            }
+            metadata.size += size;
+        }
+
+        let source = &mut self.partitions[source_id];
+        let tombstone = source.file_manager()?.write_tombstone(key)?;
+
+        let mut index = source.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        let mut bloom_filter = source.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        let mut metadata = source.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+        if index.remove(key).is_some() {
+            bloom_filter.remove(&KeyBytes(key));
+            metadata.key_count = metadata.key_count.saturating_sub(1);
+        }
+        metadata.tombstone_count += 1;
+        metadata.oldest_tombstone.get_or_insert(tombstone.timestamp);
+
+        Ok(())
+    }
+
+    fn should_compact_partition(&self, partition_id: u32) -> bool {
+        self.partitions
+            .get(partition_id as usize)
+            .is_some_and(|partition| Self::partition_needs_compaction(partition, &self.config))
+    }
+
+    pub(crate) fn partition_needs_compaction(partition: &Partition, config: &PartitionConfig) -> bool {
+        let Ok(metadata) = partition.metadata.read() else {
+            return false;
+        };
+
+        let total_entries = metadata.key_count + metadata.tombstone_count;
+        if total_entries > 0 {
+            let tombstone_ratio = metadata.tombstone_count as f64 / total_entries as f64;
+            if tombstone_ratio > config.compaction_threshold {
+                return true;
+            }
+        }
+
+        if let (Some(ttl), Some(oldest)) = (config.tombstone_ttl, metadata.oldest_tombstone)
+            && time::current_timestamp().saturating_sub(oldest) >= ttl.as_secs()
+        {
+            return true;
+        }
+
+        metadata.size > config.max_partition_size
+    }
+
+    fn compact_partition(&self, partition_id: u32) -> Result<CompactionReport, PlexError> {
+        Self::compact_partition_in(self.partition(partition_id)?, &self.config)
+    }
+
+    /// Handles on every partition, for work that runs off the manager, such
+    /// as background compaction. They go stale once the partitions are
+    /// replaced by a rebalance or restore.
+    pub(crate) fn partition_handles(&self) -> Vec<Partition> {
+        self.partitions.clone()
+    }
+
+    /// Compacts a partition with `config.compaction_strategy`. `Full`
+    /// rewrites the live entries into fresh segments, drops the old ones,
+    /// and swaps in an index and bloom filter built from the rewritten
+    /// entries; `SizeTiered` merges one tier of segments, if any has enough
+    /// of them. The index stays write-locked throughout, so writers to this
+    /// partition wait instead of landing in a segment being deleted.
+    pub(crate) fn compact_partition_in(
+        partition: &Partition,
+        config: &PartitionConfig,
+    ) -> Result<CompactionReport, PlexError> {
+        match config.compaction_strategy {
+            CompactionStrategy::Full => {
+                let mut report = CompactionReport {
+                    partition_id: partition.id,
+                    ..Default::default()
+                };
+                Self::rewrite_partition_in(partition, config, |file_manager, index| {
+                    report.size_before = file_manager.segment_sizes().iter().map(|(_, length)| length).sum();
+                    let headers = file_manager.entry_headers()?;
+                    report.tombstones_dropped = headers.iter().filter(|(_, _, header)| header.is_tombstone()).count() as u64;
+                    (report.superseded_versions, report.superseded_bytes) = Self::superseded_entries(index, headers);
+
+                    if config.compression == CompressionAlgorithm::Zstd && config.encryption_key.is_none() {
+                        Self::retrain_dictionary(partition.id, file_manager, index, config)?;
+                    }
+                    let compacted = file_manager.compact(index)?;
+                    report.size_after = file_manager.segment_sizes().iter().map(|(_, length)| length).sum();
+                    Ok(compacted)
+                })?;
+
+                Ok(report)
+            }
+            CompactionStrategy::SizeTiered => Self::merge_size_tier_in(partition),
+        }
+    }
+
+    /// Merges the segments picked by `compaction::size_tier` and repoints
+    /// the index at the copied entries. Keys are neither added nor removed,
+    /// so the bloom filter and key count stay as they are.
+    fn merge_size_tier_in(partition: &Partition) -> Result<CompactionReport, PlexError> {
+        let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        Self::flush_memtable_in(partition, &mut index)?;
+        let mut file_manager = partition.file_manager()?;
+
+        let size_before = file_manager.segment_sizes().iter().map(|(_, length)| length).sum();
+        let mut report = CompactionReport {
+            partition_id: partition.id,
+            size_before,
+            size_after: size_before,
+            ..Default::default()
+        };
+        let Some(tier) = compaction::size_tier(&file_manager.sealed_segment_sizes()) else {
+            return Ok(report);
+        };
+
+        let tier_headers = file_manager
+            .entry_headers()?
+            .into_iter()
+            .filter(|(file_id, _, _)| tier.contains(file_id));
+        (report.superseded_versions, report.superseded_bytes) = Self::superseded_entries(&index, tier_headers);
+
+        let (moved, tombstones_dropped) = file_manager.compact_segments(&tier, &index)?;
+        let size = file_manager.segment_sizes().iter().map(|(_, length)| length).sum();
+        drop(file_manager);
+
+        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+        index.extend(moved);
+        metadata.generation += 1;
+        metadata.size = size;
+        metadata.tombstone_count = metadata.tombstone_count.saturating_sub(tombstones_dropped);
+        if metadata.tombstone_count == 0 {
+            metadata.oldest_tombstone = None;
+        }
+        metadata.last_compaction = time::current_timestamp();
+        info!("Merged segments {:?} of partition {}", tier, partition.id);
+
+        report.size_after = size;
+        report.tombstones_dropped = tombstones_dropped;
+        Ok(report)
+    }
+
+    /// Counts and sizes the values among `headers` that the index no longer
+    /// points at: older versions of a key, overwritten or deleted since.
+    /// The index holds the version with the latest entry timestamp, so these
+    /// are what a compaction drops as duplicates.
+    fn superseded_entries(
+        index: &HashMap<Vec<u8>, FileOffset>,
+        headers: impl IntoIterator<Item = (u32, u64, EntryHeader)>,
+    ) -> (u64, u64) {
+        let current: HashSet<(u32, u64)> = index.values().map(|offset| (offset.file_id, offset.offset)).collect();
+
+        headers
+            .into_iter()
+            .filter(|(file_id, offset, header)| !header.is_tombstone() && !current.contains(&(*file_id, *offset)))
+            .fold((0, 0), |(count, bytes), (_, _, header)| (count + 1, bytes + header.entry_size()))
+    }
+
+    /// Rebuilds every partition's segments from the entries that pass their
+    /// CRC, then its index and bloom filter, and returns how many entries
+    /// were dropped.
+    pub fn repair(&self) -> Result<u64, PlexError> {
+        let mut dropped = 0;
+        for partition in &self.partitions {
+            Self::rewrite_partition_in(partition, &self.config, |file_manager, _| {
+                let (repaired, partition_dropped) = file_manager.repair()?;
+                if partition_dropped > 0 {
+                    warn!("Repair dropped {} entries from partition {}", partition_dropped, partition.id);
+                }
+                dropped += partition_dropped;
+                Ok(repaired)
+            })?;
+        }
+        Ok(dropped)
+    }
+
+    /// Runs `rewrite`, which replaces the partition's segments and returns
+    /// the offset of every live key in the new ones, under the index and
+    /// file manager locks, then installs the resulting index and bloom
+    /// filter.
+    fn rewrite_partition_in(
+        partition: &Partition,
+        config: &PartitionConfig,
+        rewrite: impl FnOnce(&mut FileManager, &HashMap<Vec<u8>, FileOffset>) -> Result<HashMap<Vec<u8>, FileOffset>, PlexError>,
+    ) -> Result<(), PlexError> {
+        let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        Self::flush_memtable_in(partition, &mut index)?;
+        let mut file_manager = partition.file_manager()?;
+
+        let compacted = rewrite(&mut file_manager, &index)?;
+        let size = file_manager.segment_sizes().iter().map(|(_, length)| length).sum();
+
+        let mut new_bloom_filter = CountingBloomFilter::with_hash_family(
+            config.bloom_filter_size,
+            config.bloom_filter_fp_rate,
+            config.hash_family,
+        )?;
+        for key in compacted.keys() {
+            new_bloom_filter.insert(&KeyBytes(key));
+        }
+
+        drop(file_manager);
+
+        // Index, bloom filter and metadata are swapped while all three are
+        // locked, so a concurrent lookup or `stats` sees the partition either
+        // before the rewrite or after it, never in between.
+        let key_count = compacted.len() as u64;
+        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+        *index = compacted;
+        *bloom_filter = new_bloom_filter;
+        metadata.generation += 1;
+        metadata.key_count = key_count;
+        metadata.size = size;
+        metadata.tombstone_count = 0;
+        metadata.oldest_tombstone = None;
+        metadata.last_compaction = time::current_timestamp();
+
+        Ok(())
+    }
+
+    pub fn compact_all(&self) -> Result<Vec<CompactionReport>, PlexError> {
+        let mut reports = Vec::with_capacity(self.partitions.len());
+        for partition_id in 0..self.partitions.len() as u32 {
+            reports.push(self.compact_partition(partition_id)?);
+        }
+        Ok(reports)
+    }
+
+    /// Like `compact_all`, but compacts up to `max_parallel` partitions at a
+    /// time on scoped threads. Each compaction only locks its own partition.
+    /// A failure does not stop the other partitions; the first error is
+    /// returned once every partition has been tried. Reports are in
+    /// partition id order.
+    pub fn compact_all_parallel(&self, max_parallel: usize) -> Result<Vec<CompactionReport>, PlexError> {
+        let next = AtomicUsize::new(0);
+        let first_error = Mutex::new(None);
+        let reports = Mutex::new(Vec::with_capacity(self.partitions.len()));
+        let workers = max_parallel.clamp(1, self.partitions.len().max(1));
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while let Some(partition) = self.partitions.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        match Self::compact_partition_in(partition, &self.config) {
+                            Ok(report) => {
+                                if let Ok(mut reports) = reports.lock() {
+                                    reports.push(report);
+                                }
+                            }
+                            Err(e) => {
+                                warn!("Compaction of partition {} failed: {}", partition.id, e);
+                                if let Ok(mut first_error) = first_error.lock() {
+                                    first_error.get_or_insert(e);
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        match first_error.into_inner() {
+            Ok(Some(e)) => Err(e),
+            Ok(None) => {
+                let mut reports = reports.into_inner().map_err(|_| PlexError::LockError("compaction reports".to_string()))?;
+                reports.sort_by_key(|report| report.partition_id);
+                Ok(reports)
+            }
+            Err(_) => Err(PlexError::LockError("compaction error".to_string())),
+        }
+    }
+
+    /// Compacts only the partitions whose tombstone ratio is above
+    /// `compaction_threshold`, whose oldest tombstone is older than
+    /// `tombstone_ttl`, or whose size is above `max_partition_size`, and
+    /// returns their ids.
+    pub fn compact_if_needed(&self) -> Result<Vec<u32>, PlexError> {
+        let mut compacted = Vec::new();
+
+        for partition_id in 0..self.partitions.len() as u32 {
+            if self.should_compact_partition(partition_id) {
+                self.compact_partition(partition_id)?;
+                compacted.push(partition_id);
+            }
+        }
+
+        Ok(compacted)
+    }
+
+    /// Works out what compacting a partition would reclaim without rewriting
+    /// anything. Only entry headers are read: an entry is live if the index
+    /// still points at it and it has not expired, and everything else,
+    /// tombstones included, would be dropped.
+    pub fn estimate_compaction(&self, partition_id: u32) -> Result<CompactionEstimate, PlexError> {
+        let partition = self.partition(partition_id)?;
+
+        let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        let headers = partition.file_manager()?.entry_headers()?;
+
+        let now = time::current_timestamp();
+        let live: HashSet<(u32, u64)> = index
+            .values()
+            .filter(|offset| !offset.is_expired(now))
+            .map(|offset| (offset.file_id, offset.offset))
+            .collect();
+        drop(index);
+
+        let mut estimate = CompactionEstimate {
+            partition_id,
+            ..Default::default()
+        };
+        for (file_id, offset, header) in headers {
+            let entry_size = header.entry_size();
+            estimate.disk_size += entry_size;
+            if header.is_tombstone() {
+                estimate.tombstone_count += 1;
+            } else if live.contains(&(file_id, offset)) {
+                estimate.live_size += entry_size;
+            }
+        }
+        estimate.reclaimable_bytes = estimate.disk_size - estimate.live_size;
+
+        Ok(estimate)
+    }
+
+    /// Writes out every partition's memtable, then syncs its data files,
+    /// whatever their durability mode. A partition that fails does not stop
+    /// the rest from being synced; the first error is returned.
+    pub fn flush(&self) -> Result<(), PlexError> {
+        let mut first_error = None;
+        for partition in &self.partitions {
+            let flushed = Self::flush_memtable(partition)
+                .and_then(|()| partition.file_manager())
+                .and_then(|mut file_manager| file_manager.flush());
+            if let Err(e) = flushed {
+                warn!("Failed to sync partition {}: {}", partition.id, e);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Trains a compression dictionary on up to `DICTIONARY_SAMPLE_SIZE` of
+    /// the values `index` points at and installs it, so the compaction that
+    /// follows rewrites every entry with it. Too few values to train on is
+    /// expected for small partitions, which keep their previous dictionary.
+    fn retrain_dictionary(
+        partition_id: u32,
+        file_manager: &mut FileManager,
+        index: &HashMap<Vec<u8>, FileOffset>,
+        config: &PartitionConfig,
+    ) -> Result<(), PlexError> {
+        let mut values = Vec::new();
+        for offset in index.values().take(DICTIONARY_SAMPLE_SIZE) {
+            if let Some(value) = file_manager.read_value(offset)? {
+                values.push(value);
+            }
+        }
+        let samples: Vec<&[u8]> = values.iter().map(Vec::as_slice).collect();
+
+        let level = config.compression_level.unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        let mut compressor = DictionaryCompressor::new(Vec::new(), level);
+        match compressor.train_dictionary(&samples) {
+            Ok(()) => file_manager.install_dictionary(compressor.dictionary().to_vec(), level),
+            Err(e) => {
+                debug!("Partition {} keeps its dictionary: {}", partition_id, e);
+                Ok(())
+            }
+        }
+    }
+
+    /// The dictionary trained for a partition during its last compaction,
+    /// which its new entries are compressed with.
+    pub fn current_dictionary(&self, partition_id: u32) -> Result<Option<Vec<u8>>, PlexError> {
+        let partition = self.partition(partition_id)?;
+
+        Ok(partition.file_manager()?.dictionary().map(<[u8]>::to_vec))
+    }
+
+    /// Writes a point-in-time copy of every partition into `dest`. Each
+    /// partition's index lock is held only while its segment sizes, index and
+    /// bloom filter are captured; anything appended after that is left out of
+    /// the copied segments.
+    pub fn snapshot(&self, dest: &Path) -> Result<SnapshotManifest, PlexError> {
+        std::fs::create_dir_all(dest)?;
+
+        let wal_sequence = self.wal.get_lastest_sequence();
+        let mut partitions = Vec::with_capacity(self.partitions.len());
+
+        for partition in &self.partitions {
+            let (segments, index, bloom_filter) = {
+                let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+                Self::flush_memtable_in(partition, &mut index)?;
+                let bloom_filter = partition.bloom_filter.read().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+                (partition.file_manager()?.segment_sizes(), index.clone(), bloom_filter.clone())
+            };
+
+            let partition_dest = Self::partition_dir(dest, partition.id);
+            partition.file_manager()?.copy_segments(&segments, &partition_dest)?;
+            let index_bytes = Self::seal_if_encrypted(&self.config, bincode::serialize(&index)?, &Self::snapshot_index_aad(partition.id))?;
+            std::fs::write(partition_dest.join(SNAPSHOT_INDEX_FILE), index_bytes)?;
+            bloom_filter.save_to_file(partition_dest.join(SNAPSHOT_BLOOM_FILE))?;
+
+            partitions.push(PartitionSnapshot {
+                id: partition.id,
+                segments,
+                key_count: index.len() as u64,
+            });
+        }
+
+        let manifest = SnapshotManifest {
+            created_at: time::current_timestamp(),
+            wal_sequence,
+            partitions,
+        };
+        std::fs::write(dest.join(SNAPSHOT_MANIFEST_FILE), bincode::serialize(&manifest)?)?;
+
+        Ok(manifest)
+    }
+
+    fn snapshot_index_aad(partition_id: u32) -> String {
+        format!("snapshot {}/{}", partition_id, SNAPSHOT_INDEX_FILE)
+    }
+
+    /// Replaces every partition with the contents of a snapshot taken by
+    /// `snapshot`. The WAL is checkpointed afterwards so writes logged after
+    /// the snapshot are not replayed on top of it.
+    pub fn restore_from_snapshot(&mut self, src: &Path) -> Result<(), PlexError> {
+        let manifest: SnapshotManifest =
+            bincode::deserialize(&std::fs::read(src.join(SNAPSHOT_MANIFEST_FILE))?)?;
+
+        for partition in &self.partitions {
+            let partition_dir = Self::partition_dir(&self.data_dir, partition.id);
+            if partition_dir.exists() {
+                std::fs::remove_dir_all(partition_dir)?;
+            }
+        }
+
+        let mut partitions = Vec::with_capacity(manifest.partitions.len());
+
+        for snapshot in &manifest.partitions {
+            let snapshot_dir = Self::partition_dir(src, snapshot.id);
+            let partition_dir = Self::partition_dir(&self.data_dir, snapshot.id);
+            std::fs::create_dir_all(&partition_dir)?;
+
+            for &(file_id, _) in &snapshot.segments {
+                let file_name = segment_file_name(file_id);
+                std::fs::copy(snapshot_dir.join(&file_name), partition_dir.join(&file_name))?;
+            }
+
+            for entry in std::fs::read_dir(&snapshot_dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                if file_name.starts_with(VALUE_LOG_PREFIX) || is_dictionary_file(&file_name) {
+                    std::fs::copy(entry.path(), partition_dir.join(entry.file_name()))?;
+                }
+            }
+
+            let index_bytes = std::fs::read(snapshot_dir.join(SNAPSHOT_INDEX_FILE))?;
+            let index: HashMap<Vec<u8>, FileOffset> = bincode::deserialize(&Self::unseal_if_encrypted(
+                &self.config,
+                index_bytes,
+                &Self::snapshot_index_aad(snapshot.id),
+            )?)?;
+            let bloom_filter = CountingBloomFilter::load_from_file(snapshot_dir.join(SNAPSHOT_BLOOM_FILE))?;
+
+            partitions.push(self.restored_partition(snapshot, index, bloom_filter)?);
+        }
+
+        self.install_restored_partitions(partitions)
+    }
+
+    /// Opens a partition whose files have just been put in place by a
+    /// restore, with the index and bloom filter that came with them.
+    fn restored_partition(
+        &self,
+        snapshot: &PartitionSnapshot,
+        index: HashMap<Vec<u8>, FileOffset>,
+        bloom_filter: CountingBloomFilter,
+    ) -> Result<Partition, PlexError> {
+        let mut partition = Self::create_partition(snapshot.id, &self.data_dir, &self.config)?;
+
+        {
+            let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+            metadata.key_count = index.len() as u64;
+            metadata.size = snapshot.segments.iter().map(|(_, length)| length).sum();
+        }
+        partition.index = Arc::new(RwLock::new(index));
+        partition.bloom_filter = Arc::new(RwLock::new(bloom_filter));
+
+        Ok(partition)
+    }
+
+    // The WAL is checkpointed so writes logged before the restore are not
+    // replayed on top of it.
+    fn install_restored_partitions(&mut self, partitions: Vec<Partition>) -> Result<(), PlexError> {
+        let partition_count = partitions.len() as u32;
+        self.partitions = partitions;
+        self.partitioner =
+            self.config
+                .partitioning
+                .build(partition_count, self.config.hash_family, &self.config.partition_weights)?;
+        self.config.partition_count = partition_count;
+        self.stream_sequences.clear();
+
+        self.wal.truncate_to_sequence(self.wal.get_lastest_sequence())
+    }
+
+    /// Writes a backup of every partition to `out` as a single archive, so
+    /// it can be piped somewhere without staging it on local disk.
+    ///
+    /// Each partition's segment sizes, index and bloom filter are captured
+    /// under its index lock, and its files are opened then too; streaming
+    /// happens after every lock is released. Writes made meanwhile land past
+    /// the captured segment sizes and are left out, so the archive is
+    /// consistent as of the manifest's watermark.
+    pub fn backup_stream<W: Write>(&self, out: W) -> Result<SnapshotManifest, PlexError> {
+        let mut out = BufWriter::new(out);
+
+        let wal_sequence = self.wal.get_lastest_sequence();
+        let mut captured = Vec::with_capacity(self.partitions.len());
+        let mut partitions = Vec::with_capacity(self.partitions.len());
+
+        for partition in &self.partitions {
+            let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            Self::flush_memtable_in(partition, &mut index)?;
+            let file_manager = partition.file_manager()?;
+            let bloom_filter = partition.bloom_filter.read().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+
+            let segments = file_manager.segment_sizes();
+            let files = file_manager.open_backup_files(&segments)?;
+
+            partitions.push(PartitionSnapshot {
+                id: partition.id,
+                segments,
+                key_count: index.len() as u64,
+            });
+            captured.push((partition.id, index.clone(), bloom_filter.clone(), files));
+        }
+
+        let manifest = SnapshotManifest {
+            created_at: time::current_timestamp(),
+            wal_sequence,
+            partitions,
+        };
+
+        bincode::serialize_into(&mut out, &(BACKUP_MAGIC, BACKUP_VERSION)).map_err(PlexError::Serialize)?;
+        bincode::serialize_into(&mut out, &BackupRecord::Manifest(manifest.clone())).map_err(PlexError::Serialize)?;
+
+        for (partition_id, index, bloom_filter, files) in captured {
+            let record = match &self.config.encryption_key {
+                Some(key) => BackupRecord::SealedIndex {
+                    partition_id,
+                    sealed: seal(
+                        &AesGcmEncryptor::new(key),
+                        &bincode::serialize(&(index, bloom_filter))?,
+                        Self::backup_index_aad(partition_id).as_bytes(),
+                    )?,
+                },
+                None => BackupRecord::Index { partition_id, index, bloom_filter },
+            };
+            bincode::serialize_into(&mut out, &record).map_err(PlexError::Serialize)?;
+
+            for (name, file, length) in files {
+                let record = BackupRecord::File { partition_id, name, length };
+                bincode::serialize_into(&mut out, &record).map_err(PlexError::Serialize)?;
+
+                let copied = std::io::copy(&mut file.take(length), &mut out)?;
+                if copied != length {
+                    return Err(PlexError::IO(std::io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!("partition {} file shrank while being backed up", partition_id),
+                    )));
+                }
+            }
+        }
+
+        bincode::serialize_into(&mut out, &BackupRecord::End).map_err(PlexError::Serialize)?;
+        out.flush()?;
+
+        Ok(manifest)
+    }
+
+    fn backup_index_aad(partition_id: u32) -> String {
+        format!("backup {}", partition_id)
+    }
+
+    /// Replaces every partition with the contents of an archive written by
+    /// `backup_stream`. The archive is unpacked into a staging directory
+    /// first, so a truncated or corrupt stream leaves the current data alone.
+    pub fn restore_stream<R: Read>(&mut self, input: R) -> Result<SnapshotManifest, PlexError> {
+        let mut input = BufReader::new(input);
+
+        let (magic, version): ([u8; 4], u32) = bincode::deserialize_from(&mut input)?;
+        if magic != BACKUP_MAGIC || version != BACKUP_VERSION {
+            return Err(PlexError::Config("Not a plexdb backup archive".to_string()));
+        }
+        let BackupRecord::Manifest(manifest) = bincode::deserialize_from(&mut input)? else {
+            return Err(PlexError::Config("Backup archive does not start with a manifest".to_string()));
+        };
+
+        let staging_dir = self.data_dir.join(RESTORE_STAGING_DIR);
+        if staging_dir.exists() {
+            std::fs::remove_dir_all(&staging_dir)?;
+        }
+
+        let mut indexes = HashMap::new();
+        loop {
+            match bincode::deserialize_from(&mut input)? {
+                BackupRecord::Index { partition_id, index, bloom_filter } => {
+                    std::fs::create_dir_all(Self::partition_dir(&staging_dir, partition_id))?;
+                    indexes.insert(partition_id, (index, bloom_filter));
+                }
+                BackupRecord::SealedIndex { partition_id, sealed } => {
+                    let key = self.config.encryption_key.as_ref().ok_or_else(|| {
+                        PlexError::Config("Backup archive is encrypted but no encryption key is configured".to_string())
+                    })?;
+                    let bytes = unseal(&AesGcmEncryptor::new(key), &sealed, Self::backup_index_aad(partition_id).as_bytes())?;
+                    std::fs::create_dir_all(Self::partition_dir(&staging_dir, partition_id))?;
+                    indexes.insert(partition_id, bincode::deserialize(&bytes)?);
+                }
+                BackupRecord::File { partition_id, name, length } => {
+                    // Names come from the archive, so only a bare file name is
+                    // accepted to keep writes inside the partition directory.
+                    if Path::new(&name).file_name() != Some(std::ffi::OsStr::new(&name)) {
+                        return Err(PlexError::Config(format!("Invalid file name in backup archive: {}", name)));
+                    }
+
+                    let mut file = File::create(Self::partition_dir(&staging_dir, partition_id).join(&name))?;
+                    let copied = std::io::copy(&mut (&mut input).take(length), &mut file)?;
+                    if copied != length {
+                        return Err(PlexError::IO(std::io::Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "Backup archive ended in the middle of a file",
+                        )));
+                    }
+                    file.sync_all()?;
+                }
+                BackupRecord::End => break,
+                BackupRecord::Manifest(_) => {
+                    return Err(PlexError::Config("Backup archive has more than one manifest".to_string()));
+                }
+            }
+        }
+
+        for partition in &self.partitions {
+            let partition_dir = Self::partition_dir(&self.data_dir, partition.id);
+            if partition_dir.exists() {
+                std::fs::remove_dir_all(partition_dir)?;
+            }
+        }
+
+        let mut partitions = Vec::with_capacity(manifest.partitions.len());
+        for snapshot in &manifest.partitions {
+            let (index, bloom_filter) = indexes.remove(&snapshot.id).ok_or_else(|| PlexError::Partition {
+                id: snapshot.id,
+                message: "Missing from backup archive".to_string(),
+            })?;
+
+            std::fs::rename(
+                Self::partition_dir(&staging_dir, snapshot.id),
+                Self::partition_dir(&self.data_dir, snapshot.id),
+            )?;
+            partitions.push(self.restored_partition(snapshot, index, bloom_filter)?);
+        }
+        std::fs::remove_dir_all(&staging_dir)?;
+
+        self.install_restored_partitions(partitions)?;
+        Ok(manifest)
+    }
+
+    /// Lazily yields every live `(key, value)` pair, partition by partition.
+    ///
+    /// When the iterator reaches a partition it copies that partition's keys
+    /// under a short read lock, so no lock is held while values are read from
+    /// disk. Each key is looked up again just before its value is read; keys
+    /// deleted or expired in the meantime are skipped, and keys added after
+    /// the copy are not seen. Pairs stored through `set_bytes` whose key or
+    /// value is not UTF-8 are skipped.
+    pub fn iter_keys(&self) -> KeyIter<'_> {
+        KeyIter {
+            partitions: self.partitions.iter(),
+            current: None,
+            now: time::current_timestamp(),
+        }
+    }
+
+    /// Opens a cursor over every live key followed by every write logged
+    /// after sequence `seq`; see `ChangeCursor`. `u64::MAX` tails from the
+    /// moment the cursor opens. Writes older than the WAL's retention are
+    /// gone, and starting before them is an error on the first read.
+    pub fn cursor_from(&self, seq: u64) -> ChangeCursor<'_> {
+        ChangeCursor::new(self, seq)
+    }
+
+    /// Writes every live key as one `{"key":...,"value":...}` object per line
+    /// and returns how many were written. Deleted and expired keys are skipped,
+    /// as are pairs that are not UTF-8. With an encryption key each object is
+    /// sealed with it and written as a line of hex instead.
+    pub fn export_ndjson<W: Write>(&self, out: W) -> Result<u64, PlexError> {
+        let mut out = BufWriter::new(out);
+        let encryptor = self.config.encryption_key.as_ref().map(AesGcmEncryptor::new);
+        let mut count = 0;
+
+        for entry in self.iter_keys() {
+            let (key, value) = entry?;
+            let line = serde_json::to_vec(&NdjsonRecord { key, value }).map_err(|e| PlexError::IO(e.into()))?;
+            match &encryptor {
+                Some(encryptor) => out.write_all(to_hex(&seal(encryptor, &line, EXPORT_AAD)?).as_bytes())?,
+                None => out.write_all(&line)?,
+            }
+            out.write_all(b"\n")?;
+            count += 1;
+        }
+
+        out.flush()?;
+        Ok(count)
+    }
+
+    /// Sets every pair from an NDJSON export, in batches of
+    /// `IMPORT_BATCH_SIZE`. Returns how many keys were imported. Sealed
+    /// lines of an encrypted export need the same encryption key; plain
+    /// lines are read either way.
+    pub fn import_ndjson<R: Read>(&self, input: R) -> Result<u64, PlexError> {
+        let encryptor = self.config.encryption_key.as_ref().map(AesGcmEncryptor::new);
+        let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        let mut count = 0;
+
+        for (line_number, line) in BufReader::new(input).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |message: String| {
+                PlexError::IO(std::io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_number + 1, message)))
+            };
+
+            let record: NdjsonRecord = match &encryptor {
+                Some(encryptor) if !line.starts_with('{') => {
+                    let sealed = from_hex(line).ok_or_else(|| invalid("not a JSON object or sealed hex".to_string()))?;
+                    serde_json::from_slice(&unseal(encryptor, &sealed, EXPORT_AAD)?).map_err(|e| invalid(e.to_string()))?
+                }
+                _ => serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?,
+            };
+            batch.push((record.key, record.value));
+
+            if batch.len() == IMPORT_BATCH_SIZE {
+                self.set_batch(&batch)?;
+                count += batch.len() as u64;
+                batch.clear();
+            }
+        }
+
+        if !batch.is_empty() {
+            self.set_batch(&batch)?;
+            count += batch.len() as u64;
+        }
+
+        Ok(count)
+    }
+
+    /// Sets every live key of the store whose partitions are in `other_dir`
+    /// into this one. On a key both hold, the entry with the later timestamp
+    /// wins; ties keep this store's value. Merged entries keep their
+    /// timestamp and expiry time, so merging the result into a third store
+    /// still compares them by when they were written. Keys already expired
+    /// are skipped.
+    ///
+    /// `other_dir` is opened read-only, with the config saved in it or else
+    /// this store's, so it may still be in use. Writes still only in its WAL
+    /// are not seen.
+    pub fn merge_from(&mut self, other_dir: &Path) -> Result<MergeReport, PlexError> {
+        let saved_config = PartitionConfig::load(other_dir)?;
+        self.merge_partitions_from(other_dir, saved_config)
+    }
+
+    /// Sets `key` with an entry stamped `timestamp`, logging the timestamp
+    /// so replay writes the same entry.
+    fn set_at(&self, key: &[u8], value: &[u8], expires_at: Option<u64>, timestamp: u64) -> Result<(), PlexError> {
+        self.check_entry_size(key, value)?;
+
+        let (partition, mut index) = self.lock_index_for(key)?;
+        self.log(WALCommand::SetAt {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            expires_at,
+            timestamp,
+        })?;
+
+        self.apply_set_at_locked(partition, &mut index, key, value, expires_at, timestamp)
+    }
+
+    /// `merge_from` for partitions whose config is saved somewhere other
+    /// than next to them, as in an engine's data directory.
+    pub(crate) fn merge_partitions_from(
+        &mut self,
+        other_dir: &Path,
+        saved_config: Option<PartitionConfig>,
+    ) -> Result<MergeReport, PlexError> {
+        let mut other_config = saved_config.unwrap_or_else(|| self.config.clone());
+        other_config.read_only = true;
+        other_config.memtable_size = None;
+        other_config.block_cache_blocks = 0;
+        other_config.value_cache_entries = 0;
+        other_config.encryption_key = self.config.encryption_key.clone();
+
+        let other_dir = other_dir.to_path_buf();
+        let mut report = MergeReport::default();
+
+        for id in Self::partition_ids_on_disk(&other_dir)? {
+            let partition = Self::create_partition(id, &other_dir, &other_config)?;
+            if !Self::load_persisted_index(&partition, &Self::partition_dir(&other_dir, id), &other_config)? {
+                let (entries, _) = partition.file_manager()?.read_all_entries_quarantining()?;
+                Self::apply_scanned_entries(&partition, entries)?;
+            }
+
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            let file_manager = partition.file_manager()?;
+            let now = time::current_timestamp();
+
+            for (key, offset) in index.iter() {
+                if offset.is_expired(now) {
+                    report.skipped += 1;
+                    continue;
+                }
+
+                let existing = self.live_offset(key)?;
+                if existing.as_ref().is_some_and(|(_, ours)| ours.timestamp >= offset.timestamp) {
+                    report.skipped += 1;
+                    continue;
+                }
+
+                let Some(value) = file_manager.read_value(offset)? else {
+                    continue;
+                };
+                self.set_at(key, &value, offset.expires_at, offset.timestamp)?;
+
+                if existing.is_some() {
+                    report.overwritten += 1;
+                } else {
+                    report.inserted += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Rebuilds every partition's index from its persisted index plus the log
+    /// tail written after it, or by scanning every segment when there is no
+    /// usable persisted index.
+    pub fn load_from_disk(&mut self) -> Result<(), PlexError> {
+        for partition in &self.partitions {
+            let partition_dir = Self::partition_dir(&self.data_dir, partition.id);
+            if Self::load_persisted_index(partition, &partition_dir, &self.config)? {
+                info!("Loaded persisted index for partition {}", partition.id);
+            } else {
+                Self::load_partition(partition)?;
+            }
+            self.rebuild_bloom_filter_if_degraded(partition)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the partition's bloom filter, with room for twice its current
+    /// key count, when a just-finished lookup window saw more than twice the
+    /// target false positive rate. `observed_rate` is `None` mid-window.
+    fn retune_bloom_filter(&self, partition: &Partition, observed_rate: Option<f64>) -> Result<(), PlexError> {
+        let Some(observed_rate) = observed_rate else {
+            return Ok(());
+        };
+        if observed_rate <= self.config.bloom_filter_fp_rate * 2.0 {
+            return Ok(());
+        }
+
+        let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        let expected = (index.len() * 2).max(self.config.bloom_filter_size);
+        bloom_filter.rebuild_from_keys(index.keys().map(|key| KeyBytes(key)), expected)?;
+        partition.io_stats.reset_bloom_window();
+        info!(
+            "Rebuilt bloom filter for partition {} after observing a {:.4} false positive rate",
+            partition.id, observed_rate
+        );
+        Ok(())
+    }
+
+    /// Rebuilds the partition's bloom filter from its index once it has
+    /// taken in more keys than it was sized for.
+    fn rebuild_bloom_filter_if_degraded(&self, partition: &Partition) -> Result<(), PlexError> {
+        let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        if bloom_filter.should_resize() {
+            self.rebuild_bloom_filter(partition.id, &index, &mut bloom_filter)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces `bloom_filter` with one holding every key in `index`. The
+    /// new filter has room for twice the current key count so it does not
+    /// degrade again right away. Callers hold both locks.
+    fn rebuild_bloom_filter(
+        &self,
+        partition_id: u32,
+        index: &HashMap<Vec<u8>, FileOffset>,
+        bloom_filter: &mut CountingBloomFilter,
+    ) -> Result<(), PlexError> {
+        let expected = (index.len() * 2).max(self.config.bloom_filter_size);
+        bloom_filter.rebuild_from_keys(index.keys().map(|key| KeyBytes(key)), expected)?;
+        info!("Rebuilt bloom filter for partition {} from {} keys", partition_id, index.len());
+        Ok(())
+    }
+
+    /// Writes each partition's index, metadata and bloom filter to
+    /// `index.bin`, along with the segment sizes they reflect, so the next
+    /// startup only has to scan what was appended after.
+    pub fn persist_indexes(&self) -> Result<(), PlexError> {
+        for partition in &self.partitions {
+            let partition_dir = Self::partition_dir(&self.data_dir, partition.id);
+
+            let persisted = {
+                let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+                Self::flush_memtable_in(partition, &mut index)?;
+                let watermark = partition.file_manager()?.segment_sizes();
+                let bloom_filter = partition.bloom_filter.read().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+                let metadata = partition.metadata.read().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+                PersistedIndex {
+                    watermark,
+                    metadata: metadata.clone(),
+                    index: index.clone(),
+                    bloom_filter: bloom_filter.clone(),
+                }
+            };
+            let bytes = bincode::serialize(&persisted).map_err(PlexError::Serialize)?;
+            let bytes = Self::seal_if_encrypted(&self.config, bytes, &Self::persisted_index_aad(partition.id))?;
+
+            let tmp_path = partition_dir.join(format!("{}.tmp", PERSISTED_INDEX_FILE));
+            std::fs::write(&tmp_path, bytes)?;
+            std::fs::rename(tmp_path, partition_dir.join(PERSISTED_INDEX_FILE))?;
+        }
+        Ok(())
+    }
+
+    /// `bytes` sealed with `config`'s encryption key for the file `aad`
+    /// names, or as they are when there is no key.
+    fn seal_if_encrypted(config: &PartitionConfig, bytes: Vec<u8>, aad: &str) -> Result<Vec<u8>, PlexError> {
+        match &config.encryption_key {
+            Some(key) => seal(&AesGcmEncryptor::new(key), &bytes, aad.as_bytes()),
+            None => Ok(bytes),
+        }
+    }
+
+    /// Reverses `seal_if_encrypted`.
+    fn unseal_if_encrypted(config: &PartitionConfig, bytes: Vec<u8>, aad: &str) -> Result<Vec<u8>, PlexError> {
+        match &config.encryption_key {
+            Some(key) => unseal(&AesGcmEncryptor::new(key), &bytes, aad.as_bytes()),
+            None => Ok(bytes),
+        }
+    }
+
+    fn persisted_index_aad(partition_id: u32) -> String {
+        format!("{}/{}", partition_id, PERSISTED_INDEX_FILE)
+    }
+
+    /// Loads `index.bin` into `partition` and applies the log tail written
+    /// after its watermark. Returns `false` if the file is missing, cannot
+    /// be read with `config`'s encryption key, or its watermark no longer
+    /// matches the segments on disk.
+    fn load_persisted_index(partition: &Partition, partition_dir: &Path, config: &PartitionConfig) -> Result<bool, PlexError> {
+        let index_path = partition_dir.join(PERSISTED_INDEX_FILE);
+        if !index_path.exists() {
+            return Ok(false);
+        }
+
+        let bytes = Self::unseal_if_encrypted(config, std::fs::read(&index_path)?, &Self::persisted_index_aad(partition.id));
+        let persisted: PersistedIndex = match bytes.and_then(|bytes| Ok(bincode::deserialize(&bytes)?)) {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                warn!("Ignoring unreadable {:?}, rebuilding from segments: {}", index_path, e);
+                return Ok(false);
+            }
+        };
+        // A damaged tail is left to the full scan, which cuts it off.
+        let tail = match partition.file_manager()?.read_entries_since(&persisted.watermark) {
+            Ok(Some(tail)) => tail,
+            Ok(None) | Err(PlexError::CorruptData(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        *partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))? = persisted.index;
+        *partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))? = persisted.bloom_filter;
+        *partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))? = persisted.metadata;
+
+        Self::apply_scanned_entries(partition, tail)?;
+        Ok(true)
+    }
+
+    fn load_partition(partition: &Partition) -> Result<(), PlexError> {
+        let (entries, quarantined) = partition.file_manager()?.read_all_entries_quarantining()?;
+        if !quarantined.is_empty() {
+            warn!("Partition {} quarantined corrupt segments {:?}", partition.id, quarantined);
+        }
+        Self::apply_scanned_entries(partition, entries)
+    }
+
+    /// Copies of every segment found corrupt while loading, by partition,
+    /// kept for inspection. The segments themselves were cut back to the
+    /// entries before the damage.
+    pub fn quarantined_segments(&self) -> Result<Vec<(u32, PathBuf)>, PlexError> {
+        let mut segments = Vec::new();
+        for partition in &self.partitions {
+            for path in partition.file_manager()?.quarantined_segments()? {
+                segments.push((partition.id, path));
+            }
+        }
+        Ok(segments)
+    }
+
+    /// Number of quarantined segments across partitions whose file manager
+    /// can be locked. Unlike `quarantined_segments` it never fails, so a
+    /// poisoned partition does not hide the others' counts.
+    pub fn quarantined_segment_count(&self) -> usize {
+        self.partitions
+            .iter()
+            .filter_map(|partition| partition.file_manager().ok()?.quarantined_segments().ok())
+            .map(|segments| segments.len())
+            .sum()
+    }
+
+    /// Ids of the partitions with a poisoned lock.
+    pub fn poisoned_partitions(&self) -> Vec<u32> {
+        self.partitions
+            .iter()
+            .filter(|partition| partition.is_poisoned())
+            .map(|partition| partition.id)
+            .collect()
+    }
+
+    /// Poisons the index lock of `partition_id` the way a panicking writer
+    /// would, for tests of what callers see afterwards.
+    #[cfg(test)]
+    pub(crate) fn poison_partition(&self, partition_id: u32) {
+        let index = self.partitions[partition_id as usize].index.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = index.write().unwrap();
+            panic!("poisoning partition {}", partition_id);
+        })
+        .join();
+    }
+
+    fn apply_scanned_entries(
+        partition: &Partition,
+        entries: Vec<ScannedEntry>,
+    ) -> Result<(), PlexError> {
+        let mut index = partition.index.write().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+        let mut bloom_filter = partition.bloom_filter.write().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+        let mut metadata = partition.metadata.write().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+
+        for (key, offset, is_tombstone) in entries {
+            metadata.size += offset.size as u64;
+            if is_tombstone {
+                if index.remove(&key).is_some() {
+                    bloom_filter.remove(&KeyBytes(&key));
+                    metadata.key_count = metadata.key_count.saturating_sub(1);
+                }
+                metadata.tombstone_count += 1;
+                metadata.oldest_tombstone = Some(
+                    metadata.oldest_tombstone.map_or(offset.timestamp, |oldest| oldest.min(offset.timestamp)),
+                );
+            } else if index.insert(key.clone(), offset).is_none() {
+                bloom_filter.insert(&KeyBytes(&key));
+                metadata.key_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of live keys across all partitions, read from the cached
+    /// per-partition counts rather than by scanning the indexes.
+    pub fn len(&self) -> Result<u64, PlexError> {
+        let mut total = 0;
+        for partition in &self.partitions {
+            let metadata = partition.metadata.read().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+            total += metadata.key_count;
+        }
+        Ok(total)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, PlexError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Number of live keys starting with `prefix`, counted from the indexes
+    /// without reading any value. Keys are spread across partitions by
+    /// hash, so every partition is scanned.
+    pub fn count_prefix(&self, prefix: &str) -> Result<u64, PlexError> {
+        let now = time::current_timestamp();
+        let mut count = 0;
+        for partition in &self.partitions {
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            count += index
+                .iter()
+                .filter(|(key, offset)| key.starts_with(prefix.as_bytes()) && !offset.is_expired(now))
+                .count() as u64;
+        }
+        Ok(count)
+    }
+
+    /// Like `count_prefix`, but stops at the first live match.
+    pub fn contains_prefix(&self, prefix: &str) -> Result<bool, PlexError> {
+        let now = time::current_timestamp();
+        for partition in &self.partitions {
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            if index
+                .iter()
+                .any(|(key, offset)| key.starts_with(prefix.as_bytes()) && !offset.is_expired(now))
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Up to `limit` live keys in sorted order, starting after `after`,
+    /// and the token for the next page, which is the last key returned.
+    /// The token is `None` once no keys are left.
+    ///
+    /// Keys are spread across partitions by hash, so each partition's
+    /// smallest `limit + 1` keys past the token are picked from its index
+    /// with a bounded heap and the sorted runs are merged. Keys that are not UTF-8 are
+    /// skipped, as in `iter_keys`.
+    pub fn list_keys(&self, after: Option<&str>, limit: usize) -> Result<(Vec<String>, Option<String>), PlexError> {
+        if limit == 0 {
+            return Err(PlexError::Config("limit must be at least 1".to_string()));
+        }
+
+        let now = time::current_timestamp();
+        let mut runs = Vec::with_capacity(self.partitions.len());
+        for partition in &self.partitions {
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            let candidates = index
+                .iter()
+                .filter(|(_, offset)| !offset.is_expired(now))
+                .filter_map(|(key, _)| std::str::from_utf8(key).ok())
+                .filter(|key| after.is_none_or(|after| *key > after));
+
+            // A max-heap of the smallest keys seen so far, so only `limit + 1`
+            // keys are held however large the partition is.
+            let mut smallest: BinaryHeap<&str> = BinaryHeap::with_capacity(limit + 2);
+            for key in candidates {
+                if smallest.len() <= limit {
+                    smallest.push(key);
+                } else if smallest.peek().is_some_and(|largest| key < *largest) {
+                    smallest.pop();
+                    smallest.push(key);
+                }
+            }
+            runs.push(smallest.into_sorted_vec().into_iter().map(str::to_string).collect::<Vec<_>>().into_iter());
+        }
+
+        let mut heap: BinaryHeap<Reverse<(String, usize)>> = runs
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(run, keys)| Some(Reverse((keys.next()?, run))))
+            .collect();
+
+        let mut page = Vec::with_capacity(limit);
+        while let Some(Reverse((key, run))) = heap.pop() {
+            if page.len() == limit {
+                // A key past the page is left, so there is another page.
+                let token = page.last().cloned();
+                return Ok((page, token));
+            }
+            page.push(key);
+            if let Some(next) = runs[run].next() {
+                heap.push(Reverse((next, run)));
+            }
+        }
+
+        Ok((page, None))
+    }
+
+    pub fn stats(&self) -> Result<PartitionManagerStats, PlexError> {
+        let mut total_keys = 0;
+        let mut total_size = 0;
+        let mut total_tombstones = 0;
+        let mut io = Vec::with_capacity(self.partitions.len());
+
+        for partition in &self.partitions {
+            let metadata = partition.metadata.read().map_err(|_| PlexError::LockError("partition metadata".to_string()))?;
+            total_keys += metadata.key_count;
+            total_size += metadata.size;
+            total_tombstones += metadata.tombstone_count;
+            io.push(partition.io_stats.snapshot(partition.id));
+        }
+
+        Ok(PartitionManagerStats {
+            partition_count: self.partitions.len() as u32,
+            total_keys,
+            total_size,
+            total_tombstones,
+            io,
+        })
+    }
+
+    /// Checks every partition for an index and bloom filter that disagree,
+    /// entries that fail their CRC, and index entries pointing past the end
+    /// of their segment. Anomalies are reported rather than returned as
+    /// errors, so one bad partition does not hide problems in the others.
+    pub fn verify(&self) -> Result<VerifyReport, PlexError> {
+        let mut report = VerifyReport::default();
+
+        for partition in &self.partitions {
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            let file_manager = partition.file_manager()?;
+            let bloom_filter = partition.bloom_filter.read().map_err(|_| PlexError::LockError("bloom filter".to_string()))?;
+
+            let segments = file_manager.segment_sizes();
+            let files = file_manager.open_segments(&segments)?;
+            let segment_sizes: HashMap<u32, u64> = segments.into_iter().collect();
+            let mut partition_report = PartitionVerifyReport {
+                id: partition.id,
+                ..Default::default()
+            };
+
+            for (key, offset) in index.iter() {
+                if !bloom_filter.contains(&KeyBytes(key)) {
+                    partition_report.bloom_misses.push(String::from_utf8_lossy(key).into_owned());
+                }
+
+                let end = offset.offset + offset.size as u64;
+                if !offset.in_memtable() && segment_sizes.get(&offset.file_id).is_none_or(|&size| end > size) {
+                    partition_report.dangling_offsets.push(String::from_utf8_lossy(key).into_owned());
+                }
+            }
+
+            // Segments are re-read with the partition unlocked, up to the
+            // sizes captured above, so writers are not held up by the scan.
+            drop((index, file_manager, bloom_filter));
+            partition_report.crc_failures = FileManager::crc_failures(files)?;
+            report.partitions.push(partition_report);
+        }
+
+        Ok(report)
+    }
+
+    /// Bloom filter stats per partition. Uses `try_read` so it never waits on
+    /// a writer; a partition whose filter is locked is reported as `None`.
+    pub fn bloom_filter_stats(&self) -> Vec<(u32, Option<BloomFilterStats>)> {
+        self.partitions
+            .iter()
+            .map(|partition| {
+                let stats = partition.bloom_filter.try_read().ok().map(|filter| BloomFilterStats {
+                    observed_false_positive_rate: partition.io_stats.observed_false_positive_rate(),
+                    ..filter.stats()
+                });
+                (partition.id, stats)
+            })
+            .collect()
+    }
+}
+
+/// Iterator returned by `PartitionManager::iter_keys`.
+pub struct KeyIter<'a> {
+    partitions: std::slice::Iter<'a, Partition>,
+    current: Option<(&'a Partition, std::vec::IntoIter<Vec<u8>>)>,
+    now: u64,
+}
+
+impl KeyIter<'_> {
+    fn read_live(partition: &Partition, key: &[u8], now: u64) -> Result<Option<Vec<u8>>, PlexError> {
+        let offset = {
+            let index = partition.index.read().map_err(|_| PlexError::LockError("partition index".to_string()))?;
+            match index.get(key) {
+                // Read under the index lock, before a flush can move it.
+                Some(offset) if offset.in_memtable() && !offset.is_expired(now) => {
+                    return partition.read_value(key, offset);
+                }
+                Some(offset) if !offset.is_expired(now) => offset.clone(),
+                _ => return Ok(None),
+            }
+        };
+
+        partition.file_manager()?.read_value(&offset)
+    }
+}
+
+impl Iterator for KeyIter<'_> {
+    type Item = Result<(String, String), PlexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((partition, keys)) = self.current.as_mut() {
+                let partition = *partition;
+
+                // Pairs written through the byte API that are not UTF-8 have
+                // no string form, so they are skipped.
+                for key in keys.by_ref() {
+                    match Self::read_live(partition, &key, self.now) {
+                        Ok(Some(value)) => match (String::from_utf8(key), String::from_utf8(value)) {
+                            (Ok(key), Ok(value)) => return Some(Ok((key, value))),
+                            _ => continue,
+                        },
+                        Ok(None) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            }
+
+            let partition = self.partitions.next()?;
+            let keys: Vec<Vec<u8>> = match partition.index.read() {
+                Ok(index) => index.keys().cloned().collect(),
+                Err(_) => return Some(Err(PlexError::LockError("partition index".to_string()))),
+            };
+            self.current = Some((partition, keys.into_iter()));
+        }
+    }
+}
+
+/// What `PartitionManager::estimate_compaction` expects compacting a
+/// partition to do. Sizes are in bytes and include entry headers.
+#[derive(Debug, Clone, Default)]
+pub struct CompactionEstimate {
+    pub partition_id: u32,
+    pub disk_size: u64,
+    pub live_size: u64,
+    pub tombstone_count: u64,
+    pub reclaimable_bytes: u64,
+}
+
+/// What compacting one partition did. Sizes are in bytes and include
+/// entry headers.
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    pub partition_id: u32,
+    pub size_before: u64,
+    pub size_after: u64,
+    /// Older versions of keys that were overwritten or deleted, dropped
+    /// because a later entry superseded them.
+    pub superseded_versions: u64,
+    pub superseded_bytes: u64,
+    pub tombstones_dropped: u64,
+}
+
+/// Anomalies `PartitionManager::verify` found in one partition.
+#[derive(Debug, Clone, Default)]
+pub struct PartitionVerifyReport {
+    pub id: u32,
+    /// Indexed keys the bloom filter reports as absent.
+    pub bloom_misses: Vec<String>,
+    /// `(segment id, offset)` of every entry that failed its CRC check.
+    pub crc_failures: Vec<(u32, u64)>,
+    /// Indexed keys whose entry lies past the end of its segment.
+    pub dangling_offsets: Vec<String>,
+}
+
+impl PartitionVerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.bloom_misses.is_empty() && self.crc_failures.is_empty() && self.dangling_offsets.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub partitions: Vec<PartitionVerifyReport>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.partitions.iter().all(PartitionVerifyReport::is_clean)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionManagerStats {
+    pub partition_count: u32,
+    pub total_keys: u64,
+    pub total_size: u64,
+    pub total_tombstones: u64,
+    /// I/O counters per partition, in partition id order.
+    pub io: Vec<PartitionIoSnapshot>,
+}
+
+impl PartitionManagerStats {
+    /// Share of value cache lookups across all partitions that were hits,
+    /// or `None` if the cache has not been consulted.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let hits: u64 = self.io.iter().map(|io| io.cache_hits).sum();
+        let misses: u64 = self.io.iter().map(|io| io.cache_misses).sum();
+        (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64)
+    }
+}
+
+/// A point-in-time copy of a partition's `PartitionIoStats`.
+#[derive(Debug, Clone, Default)]
+pub struct PartitionIoSnapshot {
+    pub id: u32,
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub bloom_false_positives: u64,
+    /// `get`s served from the value cache, and those it had to read from
+    /// disk. Both stay at 0 while the cache is disabled or unreachable.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::cursor::ChangeEvent;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Instant;
+    use std::time::Duration;
+    use crate::storage::file_manager::DICTIONARY_FILE;
+    use crate::storage::wal::WALConfig;
+    use tempfile::TempDir;
+
+    fn open_manager(dir: &Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let mut manager =
+            PartitionManager::new(dir.join("partitions"), PartitionConfig::default(), wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    #[test]
+    fn expired_key_reads_as_missing() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set_with_ttl("session", "abc", 0).unwrap();
+
+        assert_eq!(manager.get("session").unwrap(), None);
+        assert_eq!(manager.stats().unwrap().total_keys, 0);
+    }
+
+    #[test]
+    fn unexpired_key_is_readable() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set_with_ttl("session", "abc", 3600).unwrap();
+
+        assert_eq!(manager.get("session").unwrap().as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn plain_set_clears_an_earlier_ttl() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set_with_ttl("session", "abc", 0).unwrap();
+        manager.set("session", "def").unwrap();
+
+        assert_eq!(manager.get("session").unwrap().as_deref(), Some("def"));
+    }
+
+    #[test]
+    fn set_batch_writes_every_pair() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        let pairs: Vec<(String, String)> = (0..50)
+            .map(|i| (format!("key{}", i), format!("value{}", i)))
+            .collect();
+        manager.set_batch(&pairs).unwrap();
+
+        for (key, value) in &pairs {
+            assert_eq!(manager.get(key).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(manager.stats().unwrap().total_keys, 50);
+    }
+
+    #[test]
+    fn set_batch_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+        let pairs = vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+        ];
+        {
+            let manager = open_manager(dir.path());
+            manager.set_batch(&pairs).unwrap();
+        }
+
+        let manager = open_manager(dir.path());
+        assert_eq!(manager.get("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(manager.get("b").unwrap().as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn set_batch_with_an_empty_key_writes_nothing() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        let pairs = vec![
+            ("a".to_string(), "1".to_string()),
+            (String::new(), "2".to_string()),
+        ];
+
+        assert!(matches!(manager.set_batch(&pairs), Err(PlexError::KeyIsEmpty)));
+        assert_eq!(manager.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn exists_follows_sets_and_deletes() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        assert!(!manager.exists("key").unwrap());
+
+        manager.set("key", "value").unwrap();
+        assert!(manager.exists("key").unwrap());
+
+        manager.delete("key").unwrap();
+        assert!(!manager.exists("key").unwrap());
+    }
+
+    #[test]
+    fn exists_is_false_for_expired_keys() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set_with_ttl("key", "value", 0).unwrap();
+
+        assert!(!manager.exists("key").unwrap());
+    }
+
+    #[test]
+    fn stats_count_keys_and_tombstones() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set("a", "1").unwrap();
+        manager.set("b", "2").unwrap();
+        manager.set("a", "3").unwrap();
+        manager.delete("b").unwrap();
+
+        let stats = manager.stats().unwrap();
+        assert_eq!(stats.partition_count, DEFAULT_PARTITION_COUNT);
+        assert_eq!(stats.total_keys, 1);
+        assert_eq!(stats.total_tombstones, 1);
+    }
+
+    #[test]
+    fn bloom_filter_stats_cover_every_partition() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("a", "1").unwrap();
+
+        let stats = manager.bloom_filter_stats();
+        assert_eq!(stats.len(), DEFAULT_PARTITION_COUNT as usize);
+
+        let inserted: u64 = stats
+            .iter()
+            .map(|(_, stats)| stats.as_ref().unwrap().inserted_elements)
+            .sum();
+        assert_eq!(inserted, 1);
+    }
+
+    #[test]
+    fn delete_clears_the_key_from_the_bloom_filter() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set("key", "value").unwrap();
+        manager.delete("key").unwrap();
+
+        let partition_id = manager.partitioner.partition_for_key(b"key");
+        let bloom_filter = manager.partitions[partition_id as usize].bloom_filter.read().unwrap();
+        assert!(!bloom_filter.contains(&"key"));
+    }
+
+    fn assignments(partitioner: &dyn Partitioner, keys: &[String]) -> Vec<u32> {
+        keys.iter().map(|key| partitioner.partition_for_key(key.as_bytes())).collect()
+    }
+
+    #[test]
+    fn adding_a_ring_partition_remaps_few_keys() {
+        let keys: Vec<String> = (0..10_000).map(|i| format!("key{}", i)).collect();
+        let mut partitioner = ConsistentHashPartitioner::new(16, DEFAULT_VIRTUAL_NODES);
+        let before = assignments(&partitioner, &keys);
+
+        partitioner.add_partition(16);
+        let after = assignments(&partitioner, &keys);
+
+        let moved = before.iter().zip(&after).filter(|(a, b)| a != b).count();
+        assert!(moved < keys.len() / 10, "{} of {} keys moved", moved, keys.len());
+        assert!(before.iter().zip(&after).all(|(a, b)| a == b || *b == 16));
+    }
+
+    #[test]
+    fn removing_a_ring_partition_only_remaps_its_keys() {
+        let keys: Vec<String> = (0..10_000).map(|i| format!("key{}", i)).collect();
+        let mut partitioner = ConsistentHashPartitioner::new(16, DEFAULT_VIRTUAL_NODES);
+        let before = assignments(&partitioner, &keys);
+
+        partitioner.remove_partition(3);
+        let after = assignments(&partitioner, &keys);
+
+        assert!(after.iter().all(|&id| id != 3));
+        assert!(before.iter().zip(&after).all(|(a, b)| a == b || *a == 3));
+    }
+
+    #[test]
+    fn consistent_hash_partitioning_is_used_when_configured() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partitioning: Partitioning::ConsistentHash { virtual_nodes: DEFAULT_VIRTUAL_NODES },
+            ..PartitionConfig::default()
+        };
+        let manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+
+        let ring = ConsistentHashPartitioner::new(DEFAULT_PARTITION_COUNT, DEFAULT_VIRTUAL_NODES);
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            assert_eq!(manager.partitioner.partition_for_key(key.as_bytes()), ring.partition_for_key(key.as_bytes()));
+        }
+
+        manager.set("key", "value").unwrap();
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("value"));
+    }
+
+    fn open_ring_manager(dir: &Path, partition_count: u32) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count,
+            partitioning: Partitioning::ConsistentHash { virtual_nodes: DEFAULT_VIRTUAL_NODES },
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    /// Fills partition 0 with large values so it is well over three times
+    /// the average size.
+    fn skew_towards_first_partition(manager: &mut PartitionManager) -> Vec<String> {
+        let big_value = "x".repeat(4096);
+        let mut keys = Vec::new();
+        for i in 0..400 {
+            let key = format!("key{}", i);
+            let value = if manager.partitioner.partition_for_key(key.as_bytes()) == 0 { big_value.as_str() } else { "v" };
+            manager.set(&key, value).unwrap();
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn rebalance_moves_keys_into_a_new_partition() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_ring_manager(dir.path(), 4);
+        let keys = skew_towards_first_partition(&mut manager);
+
+        let report = manager.rebalance().unwrap();
+
+        assert_eq!(report.new_partitions, vec![4]);
+        assert!(report.keys_moved > 0);
+        assert!(report.keys_moved < keys.len() as u64 / 2, "moved {}", report.keys_moved);
+        for key in &keys {
+            assert!(manager.get(key).unwrap().is_some(), "{} lost", key);
+        }
+    }
+
+    #[test]
+    fn rebalanced_partitions_are_found_on_reopen() {
+        let dir = TempDir::new().unwrap();
+        let keys = {
+            let mut manager = open_ring_manager(dir.path(), 4);
+            let keys = skew_towards_first_partition(&mut manager);
+            manager.rebalance().unwrap();
+            keys
+        };
+
+        let manager = open_ring_manager(dir.path(), 4);
+        assert_eq!(manager.stats().unwrap().partition_count, 5);
+        for key in &keys {
+            assert!(manager.get(key).unwrap().is_some(), "{} lost", key);
+        }
+    }
+
+    #[test]
+    fn replaying_a_finished_rebalance_moves_nothing() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_ring_manager(dir.path(), 4);
+        skew_towards_first_partition(&mut manager);
+        manager.rebalance().unwrap();
+
+        let report = manager.apply_rebalance(5).unwrap();
+
+        assert!(report.new_partitions.is_empty());
+        assert_eq!(report.keys_moved, 0);
+    }
+
+    #[test]
+    fn rebalance_is_refused_with_modulo_partitioning() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_manager(dir.path());
+
+        assert!(matches!(manager.rebalance(), Err(PlexError::Config(message)) if message.contains("export the keys")));
+    }
+
+    #[test]
+    fn compare_and_swap_only_writes_on_a_match() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("key", "old").unwrap();
+
+        assert!(!manager.compare_and_swap("key", Some("other"), "new").unwrap());
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("old"));
+
+        assert!(manager.compare_and_swap("key", Some("old"), "new").unwrap());
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn compare_and_swap_with_none_creates_missing_keys_only() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        assert!(manager.compare_and_swap("key", None, "first").unwrap());
+        assert!(!manager.compare_and_swap("key", None, "second").unwrap());
+
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("first"));
+        assert_eq!(manager.stats().unwrap().total_keys, 1);
+    }
+
+    #[test]
+    fn compare_and_swap_treats_expired_keys_as_missing() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set_with_ttl("key", "old", 0).unwrap();
+
+        assert!(!manager.compare_and_swap("key", Some("old"), "new").unwrap());
+        assert!(manager.compare_and_swap("key", None, "new").unwrap());
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn delete_range_removes_only_keys_in_range() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for key in ["user:1", "user:2", "user:3", "users", "admin:1"] {
+            manager.set(key, "value").unwrap();
+        }
+
+        let deleted = manager.delete_range("user:", "user;").unwrap();
+
+        assert_eq!(deleted, 3);
+        for key in ["user:1", "user:2", "user:3"] {
+            assert_eq!(manager.get(key).unwrap(), None);
+        }
+        assert!(manager.exists("users").unwrap());
+        assert!(manager.exists("admin:1").unwrap());
+        assert_eq!(manager.stats().unwrap().total_tombstones, 3);
+    }
+
+    #[test]
+    fn delete_range_does_not_count_expired_keys() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("user:1", "value").unwrap();
+        manager.set_with_ttl("user:2", "value", 0).unwrap();
+
+        assert_eq!(manager.delete_range("user:", "user;").unwrap(), 1);
+        assert_eq!(manager.get("user:1").unwrap(), None);
+        assert_eq!(manager.get("user:2").unwrap(), None);
+    }
+
+    #[test]
+    fn delete_range_is_replayed_from_the_wal() {
+        let dir = TempDir::new().unwrap();
+        {
+            let manager = open_manager(dir.path());
+            manager.set("a1", "value").unwrap();
+            manager.set("a2", "value").unwrap();
+            manager.delete_range("a", "b").unwrap();
+        }
+        std::fs::remove_dir_all(dir.path().join("partitions")).unwrap();
+
+        let wal = WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap();
+        let mut manager = open_manager(dir.path());
+        for entry in wal.replay().unwrap() {
+            manager.apply_wal_entry(&entry).unwrap();
+        }
+
+        assert_eq!(manager.get("a1").unwrap(), None);
+        assert_eq!(manager.get("a2").unwrap(), None);
+    }
+
+    #[test]
+    fn compaction_trains_and_persists_a_dictionary() {
+        let dir = TempDir::new().unwrap();
+        let config = PartitionConfig {
+            partition_count: 1,
+            compression: CompressionAlgorithm::Zstd,
+            ..PartitionConfig::default()
+        };
+        {
+            let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+            let manager =
+                PartitionManager::new(dir.path().join("partitions"), config.clone(), wal).unwrap();
+            for i in 0..500 {
+                let value = format!(r#"{{"id":{},"name":"user{}","tags":["a","b"]}}"#, i, i);
+                manager.set(&format!("key{}", i), &value).unwrap();
+            }
+            assert_eq!(manager.current_dictionary(0).unwrap(), None);
+
+            manager.compact_all().unwrap();
+            assert!(manager.current_dictionary(0).unwrap().is_some());
+        }
+
+        assert!(dir.path().join("partitions/partition_000").join(DICTIONARY_FILE).exists());
+
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        assert!(manager.current_dictionary(0).unwrap().is_some());
+        manager.set("key500", r#"{"id":500,"name":"user500","tags":["a","b"]}"#).unwrap();
+        assert_eq!(
+            manager.get("key7").unwrap().as_deref(),
+            Some(r#"{"id":7,"name":"user7","tags":["a","b"]}"#)
+        );
+        assert_eq!(
+            manager.get("key500").unwrap().as_deref(),
+            Some(r#"{"id":500,"name":"user500","tags":["a","b"]}"#)
+        );
+    }
+
+    #[test]
+    fn dictionaries_are_only_trained_for_zstd_partitions() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..2000 {
+            let value = format!(r#"{{"id":{},"name":"user{}","tags":["a","b"]}}"#, i, i);
+            manager.set(&format!("key{}", i), &value).unwrap();
+        }
+
+        manager.compact_all().unwrap();
+
+        for partition_id in 0..DEFAULT_PARTITION_COUNT {
+            assert_eq!(manager.current_dictionary(partition_id).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn compacting_a_tiny_partition_skips_the_dictionary() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_zstd_manager(dir.path());
+        manager.set("key", "value").unwrap();
+
+        manager.compact_all().unwrap();
+
+        assert_eq!(manager.current_dictionary(0).unwrap(), None);
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("value"));
+    }
+
+    fn open_zstd_manager(dir: &Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            compression: CompressionAlgorithm::Zstd,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    #[test]
+    fn snapshots_carry_the_dictionary_their_entries_need() {
+        let dir = TempDir::new().unwrap();
+        let source = open_zstd_manager(&dir.path().join("source"));
+        for i in 0..500 {
+            let value = format!(r#"{{"id":{},"name":"user{}","tags":["a","b"]}}"#, i, i);
+            source.set(&format!("key{}", i), &value).unwrap();
+        }
+        source.compact_all().unwrap();
+        source.snapshot(&dir.path().join("snapshot")).unwrap();
+
+        let mut restored = open_zstd_manager(&dir.path().join("restored"));
+        restored.restore_from_snapshot(&dir.path().join("snapshot")).unwrap();
+
+        assert_eq!(restored.current_dictionary(0).unwrap(), source.current_dictionary(0).unwrap());
+        assert_eq!(
+            restored.get("key42").unwrap().as_deref(),
+            Some(r#"{"id":42,"name":"user42","tags":["a","b"]}"#)
+        );
+    }
+
+    #[test]
+    fn restore_brings_back_only_pre_snapshot_keys() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_manager(dir.path());
+
+        for i in 0..20 {
+            manager.set(&format!("before{}", i), &format!("value{}", i)).unwrap();
+        }
+        manager.set("overwritten", "old").unwrap();
+
+        let snapshot_dir = dir.path().join("snapshot");
+        let manifest = manager.snapshot(&snapshot_dir).unwrap();
+        assert_eq!(manifest.partitions.iter().map(|p| p.key_count).sum::<u64>(), 21);
+
+        for i in 0..20 {
+            manager.set(&format!("after{}", i), "later").unwrap();
+        }
+        manager.set("overwritten", "new").unwrap();
+        manager.delete("before0").unwrap();
+
+        manager.restore_from_snapshot(&snapshot_dir).unwrap();
+
+        for i in 0..20 {
+            assert_eq!(
+                manager.get(&format!("before{}", i)).unwrap(),
+                Some(format!("value{}", i))
+            );
+            assert_eq!(manager.get(&format!("after{}", i)).unwrap(), None);
+        }
+        assert_eq!(manager.get("overwritten").unwrap().as_deref(), Some("old"));
+        assert_eq!(manager.stats().unwrap().total_keys, 21);
+    }
+
+    #[test]
+    fn restored_partitions_survive_reopen() {
+        let dir = TempDir::new().unwrap();
+        let snapshot_dir = dir.path().join("snapshot");
+        {
+            let mut manager = open_manager(dir.path());
+            manager.set("kept", "yes").unwrap();
+            manager.snapshot(&snapshot_dir).unwrap();
+            manager.set("dropped", "no").unwrap();
+            manager.restore_from_snapshot(&snapshot_dir).unwrap();
+        }
+
+        let manager = open_manager(dir.path());
+        assert_eq!(manager.get("kept").unwrap().as_deref(), Some("yes"));
+        assert_eq!(manager.get("dropped").unwrap(), None);
+    }
+
+    #[test]
+    fn ndjson_export_round_trips_through_import() {
+        let source_dir = TempDir::new().unwrap();
+        let source = open_manager(source_dir.path());
+        for i in 0..30 {
+            source.set(&format!("key{}", i), &format!("value \"{}\"\n", i)).unwrap();
+        }
+        source.delete("key0").unwrap();
+        source.set_with_ttl("expired", "gone", 0).unwrap();
+
+        let dump = source_dir.path().join("dump.ndjson");
+        let exported = source.export_ndjson(std::fs::File::create(&dump).unwrap()).unwrap();
+        assert_eq!(exported, 29);
+        assert!(!std::fs::read_to_string(&dump).unwrap().contains("\"key0\""));
+
+        let target_dir = TempDir::new().unwrap();
+        let target = open_manager(target_dir.path());
+        let imported = target.import_ndjson(std::fs::File::open(&dump).unwrap()).unwrap();
+        assert_eq!(imported, 29);
+
+        assert_eq!(target.get("key0").unwrap(), None);
+        assert_eq!(target.get("expired").unwrap(), None);
+        for i in 1..30 {
+            let key = format!("key{}", i);
+            assert_eq!(target.get(&key).unwrap(), source.get(&key).unwrap());
+        }
+    }
+
+    #[test]
+    fn import_reports_the_bad_line() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        let input = "{\"key\":\"a\",\"value\":\"1\"}\nnot json\n";
+        let err = manager.import_ndjson(input.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn iter_keys_yields_every_live_entry() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..300 {
+            manager.set(&format!("key{}", i), &format!("value{}", i)).unwrap();
+        }
+        manager.delete("key7").unwrap();
+
+        let mut entries: Vec<(String, String)> =
+            manager.iter_keys().collect::<Result<_, _>>().unwrap();
+        entries.sort();
+
+        assert_eq!(entries.len(), 299);
+        assert!(entries.iter().all(|(key, value)| key != "key7" && value[5..] == key[3..]));
+    }
+
+    #[test]
+    fn iter_keys_skips_keys_deleted_before_their_turn() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..300 {
+            manager.set(&format!("key{}", i), "value").unwrap();
+        }
+
+        let mut iter = manager.iter_keys();
+        let (first, _) = iter.next().unwrap().unwrap();
+
+        // Drop every other key from the indexes behind the iterator's back.
+        let mut removed = 0;
+        for partition in &manager.partitions {
+            let mut index = partition.index.write().unwrap();
+            let doomed: Vec<Vec<u8>> = index
+                .keys()
+                .filter(|key| {
+                    let number: u32 = std::str::from_utf8(&key[3..]).unwrap().parse().unwrap();
+                    key.as_slice() != first.as_bytes() && number.is_multiple_of(2)
+                })
+                .cloned()
+                .collect();
+            for key in doomed {
+                index.remove(&key);
+                removed += 1;
+            }
+        }
+
+        let rest: Vec<(String, String)> = iter.collect::<Result<_, _>>().unwrap();
+        assert!(rest.iter().all(|(key, _)| key[3..].parse::<u32>().unwrap() % 2 == 1));
+        assert_eq!(rest.len(), 299 - removed);
+    }
+
+    #[test]
+    fn stats_track_inserts_overwrites_and_deletes() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        for i in 0..10 {
+            manager.set(&format!("key{}", i), "value").unwrap();
+        }
+        let after_inserts = manager.stats().unwrap();
+        assert_eq!(after_inserts.total_keys, 10);
+        assert_eq!(after_inserts.total_tombstones, 0);
+        assert!(after_inserts.total_size > 0);
+
+        for i in 0..5 {
+            manager.set(&format!("key{}", i), "changed").unwrap();
+        }
+        let after_overwrites = manager.stats().unwrap();
+        assert_eq!(after_overwrites.total_keys, 10);
+        assert_eq!(after_overwrites.total_tombstones, 0);
+        assert!(after_overwrites.total_size > after_inserts.total_size);
+
+        for i in 0..3 {
+            manager.delete(&format!("key{}", i)).unwrap();
+        }
+        let after_deletes = manager.stats().unwrap();
+        assert_eq!(after_deletes.total_keys, 7);
+        assert_eq!(after_deletes.total_tombstones, 3);
+        assert!(after_deletes.total_size > after_overwrites.total_size);
+    }
+
+    #[test]
+    fn concurrent_writers_lose_no_writes() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let manager = &manager;
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        manager.set(&format!("w{}-{}", writer, i), &format!("{}", i)).unwrap();
+                    }
+                });
+            }
+        });
+
+        for writer in 0..8 {
+            for i in 0..100 {
+                assert_eq!(
+                    manager.get(&format!("w{}-{}", writer, i)).unwrap(),
+                    Some(format!("{}", i))
+                );
+            }
+        }
+        assert_eq!(manager.stats().unwrap().total_keys, 800);
+    }
+
+    #[test]
+    fn writes_to_other_partitions_proceed_while_one_is_busy() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        let key_in = |partition_id: u32, i: usize| {
+            (0..)
+                .map(|n| format!("p{}-{}-{}", partition_id, i, n))
+                .find(|key| manager.partitioner.partition_for_key(key.as_bytes()) == partition_id)
+                .unwrap()
+        };
+        let (done, finished) = std::sync::mpsc::channel();
+
+        std::thread::scope(|scope| {
+            // Held inside the scope so a failed assertion releases it before
+            // the scope joins the writer waiting on it.
+            let busy = manager.partitions[0].index.write().unwrap();
+
+            for partition_id in 1..DEFAULT_PARTITION_COUNT {
+                let keys: Vec<String> = (0..50).map(|i| key_in(partition_id, i)).collect();
+                let (manager, done) = (&manager, done.clone());
+                scope.spawn(move || {
+                    for key in &keys {
+                        manager.set(key, "value").unwrap();
+                    }
+                    done.send(partition_id).unwrap();
+                });
+            }
+            let (manager, blocked_key) = (&manager, key_in(0, 0));
+            let blocked = scope.spawn(move || manager.set(&blocked_key, "value"));
+
+            for _ in 1..DEFAULT_PARTITION_COUNT {
+                finished.recv_timeout(Duration::from_secs(30)).expect("a writer was held up by partition 0");
+            }
+            assert!(!blocked.is_finished());
+
+            drop(busy);
+            blocked.join().unwrap().unwrap();
+        });
+
+        assert_eq!(manager.stats().unwrap().total_keys, (DEFAULT_PARTITION_COUNT as u64 - 1) * 50 + 1);
+    }
+
+    #[test]
+    fn concurrent_deletes_of_one_key_report_it_deleted_once() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        for round in 0..20 {
+            manager.set("shared", &round.to_string()).unwrap();
+            let deleted = AtomicUsize::new(0);
+
+            std::thread::scope(|scope| {
+                for _ in 0..8 {
+                    scope.spawn(|| {
+                        if manager.delete("shared").unwrap() {
+                            deleted.fetch_add(1, Ordering::Relaxed);
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(deleted.load(Ordering::Relaxed), 1);
+        }
+        assert_eq!(manager.stats().unwrap().total_tombstones, 20);
+    }
+
+    #[test]
+    fn concurrent_writers_to_one_key_count_it_once() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let manager = &manager;
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        manager.set("shared", &format!("{}-{}", writer, i)).unwrap();
+                        if i % 10 == 0 {
+                            let _ = manager.delete("shared");
+                        }
+                    }
+                });
+            }
+        });
+
+        let live = manager.get("shared").unwrap().is_some() as u64;
+        assert_eq!(manager.stats().unwrap().total_keys, live);
+
+        let partition_id = manager.partitioner.partition_for_key(b"shared");
+        let bloom_filter = manager.partitions[partition_id as usize].bloom_filter.read().unwrap();
+        assert_eq!(bloom_filter.contains(&"shared"), live == 1);
+    }
+
+    #[test]
+    fn concurrent_merges_add_up() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        std::thread::scope(|scope| {
+            for _ in 0..16 {
+                let manager = &manager;
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        manager.merge("counter", 1).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(manager.get("counter").unwrap().as_deref(), Some("400"));
+        assert_eq!(manager.stats().unwrap().total_keys, 1);
+    }
+
+    #[test]
+    fn merge_rejects_non_integer_values_and_overflow() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        manager.set("name", "plexdb").unwrap();
+        assert!(manager.merge("name", 1).is_err());
+
+        manager.set("big", &i64::MAX.to_string()).unwrap();
+        assert!(manager.merge("big", 1).is_err());
+        assert_eq!(manager.merge("big", -1).unwrap(), i64::MAX - 1);
+    }
+
+    #[test]
+    fn merge_logs_the_total_so_replay_is_idempotent() {
+        let dir = TempDir::new().unwrap();
+        {
+            let manager = open_manager(dir.path());
+            assert_eq!(manager.merge("counter", 5).unwrap(), 5);
+            assert_eq!(manager.merge("counter", -2).unwrap(), 3);
+        }
+
+        let wal = WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap();
+        let entries = wal.replay().unwrap();
+        let mut manager = open_manager(dir.path());
+        manager.apply_wal_entries(&entries).unwrap();
+        manager.apply_wal_entries(&entries).unwrap();
+
+        assert_eq!(manager.get("counter").unwrap().as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn hash_family_is_taken_from_config() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            hash_family: HashFamily::Fnv1a,
+            ..PartitionConfig::default()
+        };
+        let manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+
+        let modulo = HashPartitioner::with_hasher(DEFAULT_PARTITION_COUNT, HashFamily::Fnv1a);
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            assert_eq!(manager.partitioner.partition_for_key(key.as_bytes()), modulo.partition_for_key(key.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn ring_placement_depends_only_on_the_hash_family() {
+        let a = ConsistentHashPartitioner::with_hasher(8, DEFAULT_VIRTUAL_NODES, HashFamily::Fnv1a);
+        let b = ConsistentHashPartitioner::with_hasher(8, DEFAULT_VIRTUAL_NODES, HashFamily::Fnv1a);
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            assert_eq!(a.partition_for_key(key.as_bytes()), b.partition_for_key(key.as_bytes()));
+        }
+    }
+
+    fn open_with_count(dir: &Path, partition_count: u32) -> Result<PartitionManager, PlexError> {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count,
+            ..PartitionConfig::default()
+        };
+        PartitionManager::new(dir.join("partitions"), config, wal)
+    }
+
+    #[test]
+    fn fewer_configured_partitions_than_on_disk_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        open_with_count(dir.path(), 8).unwrap().set("key", "value").unwrap();
+
+        let err = open_with_count(dir.path(), 4).err().unwrap();
+        assert!(matches!(err, PlexError::Config(_)), "{}", err);
+    }
+
+    #[test]
+    fn more_configured_partitions_than_on_disk_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        open_with_count(dir.path(), 4).unwrap();
+
+        let err = open_with_count(dir.path(), 8).err().unwrap();
+        assert!(matches!(err, PlexError::Config(_)), "{}", err);
+    }
+
+    #[test]
+    fn a_missing_partition_directory_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        open_with_count(dir.path(), 4).unwrap();
+        std::fs::remove_dir_all(dir.path().join("partitions").join("partition_001")).unwrap();
+
+        let err = open_with_count(dir.path(), 4).err().unwrap();
+        assert!(matches!(err, PlexError::Partition { id: 1, .. }), "{}", err);
+    }
+
+    #[test]
+    fn unknown_partition_ids_are_errors_not_panics() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        assert!(manager.current_dictionary(DEFAULT_PARTITION_COUNT).is_err());
+        assert!(matches!(
+            manager.partition(DEFAULT_PARTITION_COUNT),
+            Err(PlexError::Partition { .. })
+        ));
+    }
+
+    #[test]
+    fn verify_reports_a_corrupted_value() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("key", "some value").unwrap();
+        manager.flush().unwrap();
+        assert!(manager.verify().unwrap().is_clean());
+
+        let partition_id = manager.partitioner.partition_for_key(b"key");
+        let partition_dir = PartitionManager::partition_dir(&dir.path().join("partitions"), partition_id);
+        let segment = std::fs::read_dir(&partition_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "log"))
+            .unwrap();
+        let mut bytes = std::fs::read(&segment).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&segment, bytes).unwrap();
+
+        let report = manager.verify().unwrap();
+        assert!(!report.is_clean());
+        for partition in &report.partitions {
+            assert_eq!(partition.crc_failures.len(), usize::from(partition.id == partition_id));
+            assert!(partition.bloom_misses.is_empty());
+            assert!(partition.dangling_offsets.is_empty());
+        }
+    }
+
+    fn segment_bytes(dir: &Path) -> u64 {
+        let mut total = 0;
+        for partition in std::fs::read_dir(dir.join("partitions")).unwrap() {
+            for entry in std::fs::read_dir(partition.unwrap().path()).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "log") {
+                    total += std::fs::metadata(path).unwrap().len();
+                }
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn compaction_shrinks_segments_and_keeps_live_keys() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..200 {
+            manager.set(&format!("key-{}", i), &format!("value-{}", i)).unwrap();
+        }
+        for i in (0..200).step_by(2) {
+            manager.delete(&format!("key-{}", i)).unwrap();
+        }
+        manager.flush().unwrap();
+        let before = segment_bytes(dir.path());
+
+        manager.compact_all().unwrap();
+
+        assert!(segment_bytes(dir.path()) < before);
+        for i in 0..200 {
+            let expected = (i % 2 == 1).then(|| format!("value-{}", i));
+            assert_eq!(manager.get(&format!("key-{}", i)).unwrap(), expected);
+        }
+        assert!(manager.verify().unwrap().is_clean());
+    }
+
+    #[test]
+    fn compact_if_needed_skips_partitions_under_threshold() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("key", "value").unwrap();
+
+        assert!(manager.compact_if_needed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn len_tracks_inserts_overwrites_and_deletes() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        assert!(manager.is_empty().unwrap());
+
+        manager.set("a", "1").unwrap();
+        manager.set("b", "2").unwrap();
+        assert_eq!(manager.len().unwrap(), 2);
+
+        manager.set("a", "3").unwrap();
+        assert_eq!(manager.len().unwrap(), 2);
+
+        manager.delete("b").unwrap();
+        assert_eq!(manager.len().unwrap(), 1);
+        assert!(!manager.is_empty().unwrap());
+    }
+
+    fn generations(manager: &PartitionManager) -> Vec<u64> {
+        manager
+            .partitions
+            .iter()
+            .map(|partition| partition.metadata.read().unwrap().generation)
+            .collect()
+    }
+
+    #[test]
+    fn startup_loads_the_persisted_index_and_the_log_tail() {
+        let dir = TempDir::new().unwrap();
+        {
+            let manager = open_manager(dir.path());
+            manager.set("before", "1").unwrap();
+            manager.compact_all().unwrap();
+            manager.persist_indexes().unwrap();
+            manager.set("after", "2").unwrap();
+            manager.delete("before").unwrap();
+            manager.flush().unwrap();
+        }
+
+        let manager = open_manager(dir.path());
+
+        // A scan starts every partition at generation 0; only the persisted
+        // metadata remembers the compaction.
+        assert!(generations(&manager).iter().all(|&generation| generation == 1));
+        assert_eq!(manager.get("before").unwrap(), None);
+        assert_eq!(manager.get("after").unwrap().as_deref(), Some("2"));
+        assert_eq!(manager.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn startup_scans_everything_when_the_watermark_is_stale() {
+        let dir = TempDir::new().unwrap();
+        {
+            let manager = open_manager(dir.path());
+            manager.set("a", "1").unwrap();
+            manager.set("b", "2").unwrap();
+            manager.persist_indexes().unwrap();
+            manager.delete("a").unwrap();
+            manager.compact_all().unwrap();
+        }
+
+        let manager = open_manager(dir.path());
+
+        assert!(generations(&manager).iter().all(|&generation| generation == 0));
+        assert_eq!(manager.get("a").unwrap(), None);
+        assert_eq!(manager.get("b").unwrap().as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn io_stats_count_reads_writes_and_bloom_false_positives() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("a", "1").unwrap();
+        manager.set_batch(&[("b".to_string(), "2".to_string()), ("c".to_string(), "3".to_string())]).unwrap();
+        manager.delete("c").unwrap();
+
+        // Leave "ghost" in the bloom filter without an index entry, as a
+        // false positive would.
+        let ghost_partition = manager.partitioner.partition_for_key(b"ghost");
+        manager.partitions[ghost_partition as usize].bloom_filter.write().unwrap().insert(&"ghost");
+
+        assert_eq!(manager.get("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(manager.get("b").unwrap().as_deref(), Some("2"));
+        assert_eq!(manager.get("ghost").unwrap(), None);
+        assert!(!manager.exists("ghost").unwrap());
+
+        let io = manager.stats().unwrap().io;
+        assert_eq!(io.iter().map(|stats| stats.writes).sum::<u64>(), 4);
+        assert_eq!(io.iter().map(|stats| stats.reads).sum::<u64>(), 2);
+        assert!(io.iter().map(|stats| stats.bytes_written).sum::<u64>() > 0);
+        for stats in &io {
+            let expected = if stats.id == ghost_partition { 2 } else { 0 };
+            assert_eq!(stats.bloom_false_positives, expected, "partition {}", stats.id);
+        }
+    }
+
+    #[test]
+    fn delete_reports_an_existing_key_and_tombstones_it() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("key", "value").unwrap();
+
+        assert!(manager.delete("key").unwrap());
+
+        assert_eq!(manager.get("key").unwrap(), None);
+        let stats = manager.stats().unwrap();
+        assert_eq!((stats.total_keys, stats.total_tombstones), (0, 1));
+    }
+
+    #[test]
+    fn delete_of_a_missing_key_writes_nothing() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        let sequence = manager.wal.get_lastest_sequence();
+
+        assert!(!manager.delete("missing").unwrap());
+
+        assert_eq!(manager.stats().unwrap().total_tombstones, 0);
+        assert_eq!(manager.wal.get_lastest_sequence(), sequence);
+    }
+
+    #[test]
+    fn an_overfilled_bloom_filter_is_rebuilt_from_the_index() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            bloom_filter_size: 16,
+            ..PartitionConfig::default()
+        };
+        let manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+
+        for i in 0..500 {
+            manager.set(&format!("key{}", i), "value").unwrap();
+        }
+        let pairs: Vec<(String, String)> = (500..3000).map(|i| (format!("key{}", i), "value".to_string())).collect();
+        manager.set_batch(&pairs).unwrap();
+
+        let bloom_filter = manager.partitions[0].bloom_filter.read().unwrap();
+        assert!(!bloom_filter.should_resize());
+        assert!((0..3000).all(|i| bloom_filter.contains(&format!("key{}", i))));
+    }
+
+    #[test]
+    fn compaction_estimate_matches_what_compaction_reclaims() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig { partition_count: 1, ..PartitionConfig::default() };
+        let manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        for i in 0..100 {
+            manager.set(&format!("key{:03}", i), &"v".repeat(100)).unwrap();
+        }
+        for i in 0..25 {
+            manager.delete(&format!("key{:03}", i)).unwrap();
+        }
+
+        let estimate = manager.estimate_compaction(0).unwrap();
+        let disk_size = |manager: &PartitionManager| -> u64 {
+            manager.partitions[0].file_manager().unwrap().segment_sizes().iter().map(|(_, size)| size).sum()
+        };
+
+        assert_eq!(estimate.disk_size, disk_size(&manager));
+        assert_eq!(estimate.tombstone_count, 25);
+        assert_eq!(estimate.reclaimable_bytes, estimate.disk_size - estimate.live_size);
+
+        manager.compact_all().unwrap();
+        let compacted = disk_size(&manager) as f64;
+        let live = estimate.live_size as f64;
+        assert!((compacted - live).abs() <= live * 0.05, "estimated {} live bytes, compacted to {}", live, compacted);
+    }
+
+    fn open_encrypted_manager(dir: &Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            compression: CompressionAlgorithm::Zstd,
+            encryption_key: Some(EncryptionKey::from_passphrase("passphrase", b"salt")),
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    #[test]
+    fn encrypted_backups_and_snapshots_hold_no_plaintext_keys() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_encrypted_manager(&dir.path().join("source"));
+        for i in 0..500 {
+            manager.set(&format!("secret{}", i), "value").unwrap();
+        }
+        manager.compact_all().unwrap();
+        assert!((0..manager.config.partition_count).all(|id| manager.current_dictionary(id).unwrap().is_none()));
+
+        let contains_secret = |bytes: &[u8]| bytes.windows(b"secret".len()).any(|window| window == b"secret");
+        let mut archive = Vec::new();
+        manager.backup_stream(&mut archive).unwrap();
+        assert!(!contains_secret(&archive));
+
+        let snapshot_dir = dir.path().join("snapshot");
+        manager.snapshot(&snapshot_dir).unwrap();
+        for id in 0..manager.config.partition_count {
+            let index = std::fs::read(PartitionManager::partition_dir(&snapshot_dir, id).join(SNAPSHOT_INDEX_FILE)).unwrap();
+            assert!(!contains_secret(&index));
+        }
+
+        let mut restored = open_encrypted_manager(&dir.path().join("from_backup"));
+        restored.restore_stream(archive.as_slice()).unwrap();
+        assert_eq!(restored.get("secret42").unwrap().as_deref(), Some("value"));
+
+        let mut restored = open_encrypted_manager(&dir.path().join("from_snapshot"));
+        restored.restore_from_snapshot(&snapshot_dir).unwrap();
+        assert_eq!(restored.get("secret42").unwrap().as_deref(), Some("value"));
+
+        let mut plain = open_manager(&dir.path().join("plain"));
+        assert!(matches!(plain.restore_stream(archive.as_slice()), Err(PlexError::Config(_))));
+    }
+
+    #[test]
+    fn backup_stream_round_trips_through_an_in_memory_buffer() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        for i in 0..50 {
+            manager.set(&format!("key{}", i), &format!("value{}", i)).unwrap();
+        }
+        manager.delete("key0").unwrap();
+
+        let mut archive = Vec::new();
+        let manifest = manager.backup_stream(&mut archive).unwrap();
+        assert_eq!(manifest.partitions.iter().map(|p| p.key_count).sum::<u64>(), 49);
+
+        manager.set("key1", "changed").unwrap();
+        manager.set("later", "value").unwrap();
+
+        let restore_dir = TempDir::new().unwrap();
+        let mut restored = open_manager(restore_dir.path());
+        restored.set("stale", "value").unwrap();
+        restored.restore_stream(archive.as_slice()).unwrap();
+
+        assert_eq!(restored.get("key0").unwrap(), None);
+        for i in 1..50 {
+            assert_eq!(restored.get(&format!("key{}", i)).unwrap(), Some(format!("value{}", i)));
+        }
+        assert_eq!(restored.get("later").unwrap(), None);
+        assert_eq!(restored.get("stale").unwrap(), None);
+        assert_eq!(restored.stats().unwrap().total_keys, 49);
+        assert!(!restore_dir.path().join("partitions").join(RESTORE_STAGING_DIR).exists());
+    }
+
+    #[test]
+    fn restore_stream_rejects_a_truncated_archive_and_keeps_current_data() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_manager(dir.path());
+        manager.set("key", "value").unwrap();
+
+        let mut archive = Vec::new();
+        manager.backup_stream(&mut archive).unwrap();
+        archive.truncate(archive.len() - 8);
+
+        assert!(manager.restore_stream(archive.as_slice()).is_err());
+        assert!(manager.restore_stream(&b"not a backup"[..]).is_err());
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn old_tombstones_trigger_compaction_below_the_ratio_threshold() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            tombstone_ttl: Some(Duration::from_secs(60)),
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        for i in 0..20 {
+            manager.set(&format!("key{}", i), "value").unwrap();
+        }
+        manager.delete("key0").unwrap();
+
+        // A fresh tombstone far below the ratio threshold triggers nothing.
+        assert!(manager.compact_if_needed().unwrap().is_empty());
+
+        manager.partitions[0].metadata.write().unwrap().oldest_tombstone =
+            Some(time::current_timestamp() - 120);
+        assert_eq!(manager.compact_if_needed().unwrap(), vec![0]);
+
+        let metadata = manager.partitions[0].metadata.read().unwrap().clone();
+        assert_eq!(metadata.tombstone_count, 0);
+        assert_eq!(metadata.oldest_tombstone, None);
+        assert_eq!(manager.get("key0").unwrap(), None);
+        assert_eq!(manager.get("key1").unwrap().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn bulk_load_makes_every_pair_queryable_without_touching_the_wal() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_manager(dir.path());
+
+        let pairs = (0..100_000).map(|i| (format!("key{:06}", i), format!("value{}", i)));
+        assert_eq!(manager.bulk_load(pairs).unwrap(), 100_000);
+
+        for i in (0..100_000).step_by(997) {
+            assert_eq!(manager.get(&format!("key{:06}", i)).unwrap(), Some(format!("value{}", i)));
+        }
+        assert_eq!(manager.get("key099999").unwrap().as_deref(), Some("value99999"));
+        assert_eq!(manager.stats().unwrap().total_keys, 100_000);
+        assert!(manager.wal.replay().unwrap().is_empty());
+        assert_eq!(manager.wal.get_lastest_sequence(), 0);
+
+        drop(manager);
+        let reopened = open_manager(dir.path());
+        assert_eq!(reopened.get("key000000").unwrap().as_deref(), Some("value0"));
+        assert_eq!(reopened.stats().unwrap().total_keys, 100_000);
+    }
+
+    #[test]
+    fn compression_can_be_turned_on_but_not_switched() {
+        let dir = TempDir::new().unwrap();
+        let lz4 = PartitionConfig {
+            compression: CompressionAlgorithm::Lz4,
+            ..PartitionConfig::default()
+        };
+        {
+            let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+            let mut manager = PartitionManager::new(dir.path().join("partitions"), lz4.clone(), wal).unwrap();
+            manager.load_from_disk().unwrap();
+            manager.set("key", &"value ".repeat(100)).unwrap();
+        }
+
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), lz4.clone(), wal).unwrap();
+        manager.load_from_disk().unwrap();
+        assert_eq!(manager.get("key").unwrap(), Some("value ".repeat(100)));
+
+        assert!(lz4.check_compatible(&PartitionConfig::default()).is_ok());
+        let zstd = PartitionConfig {
+            compression: CompressionAlgorithm::Zstd,
+            ..PartitionConfig::default()
+        };
+        assert!(matches!(zstd.check_compatible(&lz4), Err(PlexError::Config(_))));
+    }
+
+    #[test]
+    fn multi_get_keeps_input_order_across_present_absent_and_deleted_keys() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        for i in 0..100 {
+            manager.set(&format!("key{}", i), &format!("value{}", i)).unwrap();
+        }
+        manager.delete("key7").unwrap();
+        manager.delete("key42").unwrap();
+        manager.set("key3", "rewritten").unwrap();
+
+        let keys = ["key99", "missing", "key7", "key3", "key0", "key42", "key99", "absent"];
+        let values = manager.multi_get(&keys).unwrap();
+
+        assert_eq!(
+            values,
+            vec![
+                Some("value99".to_string()),
+                None,
+                None,
+                Some("rewritten".to_string()),
+                Some("value0".to_string()),
+                None,
+                Some("value99".to_string()),
+                None,
+            ]
+        );
+        for (key, value) in keys.iter().zip(&values) {
+            assert_eq!(&manager.get(key).unwrap(), value);
+        }
+
+        assert!(manager.multi_get(&[]).unwrap().is_empty());
+        assert!(matches!(manager.multi_get(&["key1", ""]), Err(PlexError::KeyIsEmpty)));
+    }
+
+    #[test]
+    fn weighted_partitioner_gives_a_double_weight_partition_twice_the_keys() {
+        let partitioner = WeightedPartitioner::new(vec![1, 1, 2], DEFAULT_VIRTUAL_NODES).unwrap();
+
+        let mut counts = [0u32; 3];
+        for i in 0..100_000 {
+            counts[partitioner.partition_for_key(format!("key{}", i).as_bytes()) as usize] += 1;
+        }
+
+        for light in &counts[..2] {
+            let ratio = counts[2] as f64 / *light as f64;
+            assert!((1.6..2.4).contains(&ratio), "counts {:?}", counts);
+        }
+        assert!(WeightedPartitioner::new(vec![1, 0], DEFAULT_VIRTUAL_NODES).is_err());
+    }
+
+    #[test]
+    fn weighted_partitioning_is_used_when_configured() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 3,
+            partitioning: Partitioning::Weighted { virtual_nodes: DEFAULT_VIRTUAL_NODES },
+            partition_weights: vec![1, 1, 2],
+            ..PartitionConfig::default()
+        };
+        config.validate().unwrap();
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config.clone(), wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        let expected = WeightedPartitioner::new(vec![1, 1, 2], DEFAULT_VIRTUAL_NODES).unwrap();
+        for i in 0..1000 {
+            let key = format!("key{}", i);
+            assert_eq!(manager.partitioner.partition_for_key(key.as_bytes()), expected.partition_for_key(key.as_bytes()));
+            manager.set(&key, "value").unwrap();
+        }
+
+        // Partition 2 holds about half the data, which is its fair share.
+        assert!(!manager.partitioner.rebalance_needed(&manager.partitions));
+
+        let unweighted = PartitionConfig {
+            partition_weights: Vec::new(),
+            ..config.clone()
+        };
+        assert!(matches!(unweighted.check_compatible(&config), Err(PlexError::Config(_))));
+        let zero_weight = PartitionConfig {
+            partition_weights: vec![1, 0, 1],
+            ..config
+        };
+        assert!(matches!(zero_weight.validate(), Err(PlexError::Config(_))));
+    }
+
+    #[test]
+    fn weighted_skew_is_measured_against_each_partitions_share() {
+        let dir = TempDir::new().unwrap();
+        let config = PartitionConfig::default();
+        let partitions: Vec<Partition> = (0..4)
+            .map(|id| PartitionManager::create_partition(id, dir.path(), &config).unwrap())
+            .collect();
+        let set_sizes = |sizes: [u64; 4]| {
+            for (partition, size) in partitions.iter().zip(sizes) {
+                partition.metadata.write().unwrap().size = size;
+            }
+        };
+        let weights = [1, 1, 1, 12];
+
+        set_sizes([10, 10, 10, 300]);
+        assert!(partition_sizes_skewed(&partitions));
+        assert!(!partition_sizes_skewed_by_weight(&partitions, &weights));
+
+        set_sizes([300, 10, 10, 10]);
+        assert!(partition_sizes_skewed_by_weight(&partitions, &weights));
+    }
+
+    fn open_single_partition(dir: &Path) -> PartitionManager {
+        let config = PartitionConfig {
+            partition_count: 1,
+            ..PartitionConfig::default()
+        };
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    // Reopening from the segments alone, so the scan has to deal with them.
+    fn reopen_from_segments(dir: &Path) -> PartitionManager {
+        let partition_dir = PartitionManager::partition_dir(&dir.join("partitions"), 0);
+        std::fs::remove_dir_all(dir.join("wal")).unwrap();
+        let _ = std::fs::remove_file(partition_dir.join(PERSISTED_INDEX_FILE));
+        open_single_partition(dir)
+    }
+
+    fn entry_size(manager: &PartitionManager, key: &str) -> u64 {
+        manager.partitions[0].index.read().unwrap()[key.as_bytes()].size as u64
+    }
+
+    #[test]
+    fn damaged_header_in_an_older_segment_is_quarantined_keeping_the_entries_before_it() {
+        let dir = TempDir::new().unwrap();
+        let partition_dir = PartitionManager::partition_dir(&dir.path().join("partitions"), 0);
+        let first_size = {
+            let manager = open_single_partition(dir.path());
+            manager.set("first", "one").unwrap();
+            manager.set("damaged", "lost").unwrap();
+            manager.partitions[0].file_manager().unwrap().start_new_segment().unwrap();
+            manager.set("second", "two").unwrap();
+            entry_size(&manager, "first")
+        };
+
+        // A data length running far past the end of the segment cannot be
+        // skipped, so the rest of the first segment has to go.
+        let corrupt_path = partition_dir.join(segment_file_name(0));
+        let mut bytes = std::fs::read(&corrupt_path).unwrap();
+        let at = first_size as usize;
+        bytes[at..at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&corrupt_path, &bytes).unwrap();
+
+        let manager = reopen_from_segments(dir.path());
+
+        let quarantine_path = partition_dir.join("quarantine").join(segment_file_name(0));
+        assert_eq!(manager.quarantined_segments().unwrap(), vec![(0, quarantine_path.clone())]);
+        assert_eq!(std::fs::read(quarantine_path).unwrap(), bytes);
+        assert_eq!(std::fs::metadata(&corrupt_path).unwrap().len(), first_size);
+        assert_eq!(manager.get("first").unwrap().as_deref(), Some("one"));
+        assert_eq!(manager.get("damaged").unwrap(), None);
+        assert_eq!(manager.get("second").unwrap().as_deref(), Some("two"));
+    }
+
+    #[test]
+    fn torn_entry_at_the_end_of_the_newest_segment_is_cut_off() {
+        let dir = TempDir::new().unwrap();
+        let segment_path = PartitionManager::partition_dir(&dir.path().join("partitions"), 0).join(segment_file_name(0));
+        let first_size = {
+            let manager = open_single_partition(dir.path());
+            manager.set("first", "one").unwrap();
+            manager.set("torn", "lost").unwrap();
+            entry_size(&manager, "first")
+        };
+        let length = std::fs::metadata(&segment_path).unwrap().len();
+        File::options().write(true).open(&segment_path).unwrap().set_len(length - 3).unwrap();
+
+        {
+            let manager = reopen_from_segments(dir.path());
+            assert!(manager.quarantined_segments().unwrap().is_empty());
+            assert_eq!(std::fs::metadata(&segment_path).unwrap().len(), first_size);
+            assert_eq!(manager.get("first").unwrap().as_deref(), Some("one"));
+            assert_eq!(manager.get("torn").unwrap(), None);
+            manager.set("after", "appended").unwrap();
+        }
+
+        // The next entry was appended right after the last whole one.
+        let manager = reopen_from_segments(dir.path());
+        assert_eq!(manager.get("first").unwrap().as_deref(), Some("one"));
+        assert_eq!(manager.get("after").unwrap().as_deref(), Some("appended"));
+    }
+
+    #[test]
+    fn observed_false_positives_trigger_a_bloom_filter_resize() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        for i in 0..2000 {
+            manager.set(&format!("key{}", i), "value").unwrap();
+        }
+
+        // Swap in a filter far too small for the keys, so most absent keys get
+        // through it.
+        {
+            let partition = &manager.partitions[0];
+            let index = partition.index.read().unwrap();
+            let mut tiny = CountingBloomFilter::new(16, 0.01).unwrap();
+            for key in index.keys() {
+                tiny.insert(key);
+            }
+            *partition.bloom_filter.write().unwrap() = tiny;
+        }
+
+        let mut lookup = 0;
+        let mut absent_lookups = |count: u64| {
+            for _ in 0..count {
+                assert_eq!(manager.get(&format!("absent{}", lookup)).unwrap(), None);
+                lookup += 1;
+            }
+        };
+
+        absent_lookups(BLOOM_FP_WINDOW - 1);
+        let stats = manager.bloom_filter_stats()[0].1.clone().unwrap();
+        assert_eq!(stats.observed_false_positive_rate, None);
+        assert!(manager.partitions[0].bloom_filter.read().unwrap().stats().current_false_positive_rate > 0.5);
+
+        // The last lookup of the window sees the rate cross the threshold and
+        // rebuilds the filter for the current key count.
+        absent_lookups(1);
+        let stats = manager.bloom_filter_stats()[0].1.clone().unwrap();
+        assert_eq!(stats.observed_false_positive_rate, None);
+        assert!(stats.is_healthy());
+
+        absent_lookups(BLOOM_FP_WINDOW);
+        let stats = manager.bloom_filter_stats()[0].1.clone().unwrap();
+        let observed = stats.observed_false_positive_rate.unwrap();
+        assert!(observed <= stats.target_false_positive_rate * 2.0, "observed {}", observed);
+        assert!(stats.is_healthy());
+        assert_eq!(manager.get("key7").unwrap().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn event_streams_number_and_replay_independently() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut manager = open_manager(dir.path());
+            for i in 1..=5 {
+                assert_eq!(manager.append_event("orders", &format!("order{}", i)).unwrap(), i);
+                if i % 2 == 0 {
+                    assert_eq!(manager.append_event("payments", &format!("payment{}", i / 2)).unwrap(), i / 2);
+                }
+            }
+
+            let orders: Vec<String> = manager.read_stream("orders", 0, 100).unwrap().into_iter().map(|(_, p)| p).collect();
+            assert_eq!(orders, ["order1", "order2", "order3", "order4", "order5"]);
+            assert_eq!(
+                manager.read_stream("payments", 1, 100).unwrap(),
+                vec![(1, "payment1".to_string()), (2, "payment2".to_string())]
+            );
+            assert_eq!(
+                manager.read_stream("orders", 2, 2).unwrap(),
+                vec![(2, "order2".to_string()), (3, "order3".to_string())]
+            );
+            assert!(manager.read_stream("orders", 6, 10).unwrap().is_empty());
+            assert!(manager.read_stream("unknown", 1, 10).unwrap().is_empty());
+            assert!(manager.read_stream("orders", 1, 0).unwrap().is_empty());
+        }
+
+        // Sequences pick up where they left off after a reopen.
+        let mut manager = open_manager(dir.path());
+        assert_eq!(manager.append_event("orders", "order6").unwrap(), 6);
+        assert_eq!(manager.append_event("payments", "payment3").unwrap(), 3);
+        assert_eq!(manager.read_stream("orders", 5, 10).unwrap().len(), 2);
+        assert!(matches!(manager.append_event("", "payload"), Err(PlexError::KeyIsEmpty)));
+    }
+
+    #[test]
+    fn set_and_get_returns_the_replaced_value() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+
+        assert_eq!(manager.set_and_get("state", "first").unwrap(), None);
+        assert_eq!(manager.set_and_get("state", "second").unwrap().as_deref(), Some("first"));
+        assert_eq!(manager.get("state").unwrap().as_deref(), Some("second"));
+        assert_eq!(manager.stats().unwrap().total_keys, 1);
+
+        manager.set_with_ttl("expiring", "old", 0).unwrap();
+        assert_eq!(manager.set_and_get("expiring", "new").unwrap(), None);
+        assert_eq!(manager.get("expiring").unwrap().as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected_before_the_wal() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            max_key_size: 8,
+            max_value_size: 16,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        manager.set("12345678", &"v".repeat(15)).unwrap();
+        manager.set("key", &"v".repeat(16)).unwrap();
+        let sequence = manager.wal.get_lastest_sequence();
+        let disk_size = manager.stats().unwrap().total_size;
+
+        assert!(matches!(
+            manager.set("key", &"v".repeat(17)),
+            Err(PlexError::ValueTooLarge { size: 17, limit: 16 })
+        ));
+        assert!(matches!(
+            manager.set("123456789", "value"),
+            Err(PlexError::KeyTooLarge { size: 9, limit: 8 })
+        ));
+        assert!(matches!(manager.set_with_ttl("123456789", "value", 60), Err(PlexError::KeyTooLarge { .. })));
+        assert!(matches!(manager.set_and_get("key", &"v".repeat(17)), Err(PlexError::ValueTooLarge { .. })));
+        assert!(matches!(manager.merge("123456789", 1), Err(PlexError::KeyTooLarge { .. })));
+
+        assert_eq!(manager.wal.get_lastest_sequence(), sequence);
+        assert_eq!(manager.stats().unwrap().total_size, disk_size);
+        assert_eq!(manager.get("key").unwrap(), Some("v".repeat(16)));
+        assert_eq!(manager.get("123456789").unwrap(), None);
+    }
+
+    #[test]
+    fn configs_saved_without_size_limits_get_the_defaults() {
+        let config: PartitionConfig = serde_json::from_str(
+            r#"{"partition_count": 4, "max_partition_size": 1024, "bloom_filter_size": 100,
+                "bloom_filter_fp_rate": 0.01, "compaction_threshold": 0.7}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.max_key_size, DEFAULT_MAX_KEY_SIZE);
+        assert_eq!(config.max_value_size, DEFAULT_MAX_VALUE_SIZE);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn a_zero_durability_interval_is_rejected() {
+        let config = PartitionConfig {
+            durability: Durability::Interval(Duration::ZERO),
+            ..PartitionConfig::default()
+        };
+
+        assert!(matches!(config.validate(), Err(PlexError::Config(message)) if message.contains("durability")));
+    }
+
+    #[test]
+    fn repair_drops_an_entry_failing_its_crc_and_keeps_the_rest() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..10 {
+            manager.set(&format!("good{}", i), "value").unwrap();
+        }
+        manager.set("bad", "value").unwrap();
+        manager.flush().unwrap();
+
+        // "bad" was the last entry appended to its partition's only segment.
+        let partition_id = manager.partitioner.partition_for_key(b"bad");
+        let partition_dir = PartitionManager::partition_dir(&dir.path().join("partitions"), partition_id);
+        let segment = std::fs::read_dir(&partition_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "log"))
+            .unwrap();
+        let mut bytes = std::fs::read(&segment).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&segment, bytes).unwrap();
+
+        assert_eq!(manager.repair().unwrap(), 1);
+
+        assert_eq!(manager.get("bad").unwrap(), None);
+        for i in 0..10 {
+            assert_eq!(manager.get(&format!("good{}", i)).unwrap().as_deref(), Some("value"));
+        }
+        assert_eq!(manager.stats().unwrap().total_keys, 10);
+        assert!(manager.verify().unwrap().is_clean());
+    }
+
+    fn fill_for_compaction(manager: &PartitionManager) {
+        let value = "x".repeat(512);
+        for round in 0..3 {
+            for i in 0..800 {
+                manager.set(&format!("key-{}", i), &format!("{}{}", round, value)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_compaction_matches_sequential() {
+        let sequential_dir = TempDir::new().unwrap();
+        let sequential = open_manager(sequential_dir.path());
+        fill_for_compaction(&sequential);
+        let started = Instant::now();
+        sequential.compact_all().unwrap();
+        let sequential_time = started.elapsed();
+
+        let parallel_dir = TempDir::new().unwrap();
+        let parallel = open_manager(parallel_dir.path());
+        fill_for_compaction(&parallel);
+        assert_eq!(parallel.partitions.len(), 16);
+        let started = Instant::now();
+        parallel.compact_all_parallel(4).unwrap();
+        let parallel_time = started.elapsed();
+
+        for i in (0..800).step_by(37) {
+            let key = format!("key-{}", i);
+            assert_eq!(parallel.get(&key).unwrap(), sequential.get(&key).unwrap());
+        }
+        assert_eq!(parallel.stats().unwrap().total_size, sequential.stats().unwrap().total_size);
+
+        // Only a rough check, and only where there are cores to spread over.
+        if std::thread::available_parallelism().map_or(1, |n| n.get()) >= 4 {
+            assert!(
+                parallel_time < sequential_time,
+                "parallel took {:?}, sequential {:?}",
+                parallel_time,
+                sequential_time
+            );
+        }
+    }
+
+    #[test]
+    fn stats_never_see_a_half_compacted_partition() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        fill_for_compaction(&manager);
+
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    let stats = manager.stats().unwrap();
+                    assert_eq!(stats.total_keys, 800);
+                }
+            });
+            manager.compact_all_parallel(4).unwrap();
+            done.store(true, Ordering::Relaxed);
+            reader.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn get_range_reads_a_slice_and_clamps_past_the_end() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("blob", "0123456789").unwrap();
+
+        assert_eq!(manager.get_range("blob", 3, 4).unwrap().as_deref(), Some("3456"));
+        assert_eq!(manager.get_range("blob", 8, 100).unwrap().as_deref(), Some("89"));
+        assert_eq!(manager.get_range("blob", 50, 5).unwrap().as_deref(), Some(""));
+        assert_eq!(manager.get_range("missing", 0, 5).unwrap(), None);
+
+        manager.delete("blob").unwrap();
+        assert_eq!(manager.get_range("blob", 0, 5).unwrap(), None);
+    }
+
+    #[test]
+    fn get_range_rejects_a_split_character() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("word", "héllo").unwrap();
+
+        assert_eq!(manager.get_range("word", 1, 2).unwrap().as_deref(), Some("é"));
+        assert!(matches!(manager.get_range("word", 1, 1), Err(PlexError::NotUtf8)));
+    }
+
+    #[test]
+    fn get_range_decompresses_compressed_values() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            compression: CompressionAlgorithm::Zstd,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        let value = "abcdefghij".repeat(100);
+        manager.set("blob", &value).unwrap();
+
+        assert_eq!(manager.get_range("blob", 995, 10).unwrap().as_deref(), Some(&value[995..]));
+        assert_eq!(manager.get_range("blob", 12, 3).unwrap().as_deref(), Some("cde"));
+    }
+
+    fn open_with_memtable(dir: &Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            memtable_size: Some(1024),
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    fn buffered_in_memtable(manager: &PartitionManager, key: &str) -> bool {
+        let index = manager.partitions[0].index.read().unwrap();
+        index[key.as_bytes()].in_memtable()
+    }
+
+    #[test]
+    fn memtable_serves_reads_then_flushes_to_disk() {
+        let dir = TempDir::new().unwrap();
+        let value = "v".repeat(100);
+        let manager = open_with_memtable(dir.path());
+
+        manager.set("first", &value).unwrap();
+        assert!(buffered_in_memtable(&manager, "first"));
+        assert_eq!(manager.get("first").unwrap().as_deref(), Some(value.as_str()));
+        let on_disk: u64 = manager.partitions[0].file_manager().unwrap().segment_sizes().iter().map(|(_, size)| size).sum();
+        assert_eq!(on_disk, 0);
+
+        for i in 0..10 {
+            manager.set(&format!("key-{}", i), &value).unwrap();
+        }
+        assert!(!buffered_in_memtable(&manager, "first"));
+
+        manager.set("last", "tail").unwrap();
+        assert!(buffered_in_memtable(&manager, "last"));
+        assert_eq!(manager.get("last").unwrap().as_deref(), Some("tail"));
+        for key in ["first", "key-0", "key-9"] {
+            assert_eq!(manager.get(key).unwrap().as_deref(), Some(value.as_str()));
+        }
+        assert_eq!(manager.stats().unwrap().total_keys, 12);
+    }
+
+    #[test]
+    fn count_prefix_only_counts_live_matches() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..20 {
+            manager.set(&format!("user:{}", i), "u").unwrap();
+            manager.set(&format!("order:{}", i), "o").unwrap();
+        }
+        manager.delete("user:3").unwrap();
+        manager.set_with_ttl("user:expired", "u", 0).unwrap();
+
+        assert_eq!(manager.count_prefix("user:").unwrap(), 19);
+        assert_eq!(manager.count_prefix("order:1").unwrap(), 11);
+        assert_eq!(manager.count_prefix("").unwrap(), 39);
+        assert_eq!(manager.count_prefix("nothing").unwrap(), 0);
+
+        assert!(manager.contains_prefix("order:").unwrap());
+        assert!(!manager.contains_prefix("user:expired").unwrap());
+    }
+
+    fn open_with_retry(dir: &Path, lock_retry: RetryPolicy) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            lock_retry,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    fn poison_index(manager: &PartitionManager) {
+        let index = manager.partitions[0].index.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = index.write().unwrap();
+            panic!("poisoning the index");
+        })
+        .join();
+    }
+
+    #[test]
+    fn reads_and_writes_succeed_once_a_contended_lock_is_released() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_with_retry(dir.path(), RetryPolicy {
+            max_attempts: 6,
+            base_delay: Duration::from_millis(10),
+        });
+        manager.set("key", "before").unwrap();
+
+        let held = manager.partitions[0].index.write().unwrap();
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| manager.set("key", "after"));
+            let reader = scope.spawn(|| manager.get("other"));
+            std::thread::sleep(Duration::from_millis(30));
+            drop(held);
+
+            writer.join().unwrap().unwrap();
+            assert_eq!(reader.join().unwrap().unwrap(), None);
+        });
+        assert_eq!(manager.get("key").unwrap().as_deref(), Some("after"));
+    }
+
+    #[test]
+    fn a_permanently_poisoned_lock_fails_after_the_retries() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_with_retry(dir.path(), RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        });
+        manager.set("key", "value").unwrap();
+        poison_index(&manager);
+
+        assert!(matches!(manager.set("key", "other"), Err(PlexError::LockError(_))));
+        assert!(matches!(manager.compare_and_swap("key", Some("value"), "other"), Err(PlexError::LockError(_))));
+        assert!(matches!(manager.delete("key"), Err(PlexError::LockError(_))));
+        assert!(matches!(manager.get("key"), Err(PlexError::LockError(_))));
+    }
+
+    fn write_segment(manager: &PartitionManager, prefix: &str, keys: usize, value_len: usize) {
+        let value = "s".repeat(value_len);
+        for i in 0..keys {
+            manager.set(&format!("{}-{}", prefix, i), &value).unwrap();
+        }
+        manager.partitions[0].file_manager().unwrap().start_new_segment().unwrap();
+    }
+
+    fn segment_ids(manager: &PartitionManager) -> Vec<u32> {
+        manager.partitions[0].file_manager().unwrap().sealed_segment_sizes().iter().map(|&(id, _)| id).collect()
+    }
+
+    #[test]
+    fn size_tiered_compaction_leaves_large_segments_until_they_have_peers() {
+        let dir = TempDir::new().unwrap();
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            partition_count: 1,
+            compaction_strategy: CompactionStrategy::SizeTiered,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.path().join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+
+        write_segment(&manager, "big0", 40, 1024);
+        let big = segment_ids(&manager);
+        for tier in 0..3 {
+            write_segment(&manager, &format!("small{}", tier), 4, 64);
+        }
+        manager.compact_all().unwrap();
+        assert_eq!(segment_ids(&manager).len(), 4, "three small segments are not a tier");
+
+        manager.set("small0-0", "rewritten").unwrap();
+        manager.delete("small1-0").unwrap();
+        write_segment(&manager, "small3", 4, 64);
+        manager.compact_all().unwrap();
+        let after = segment_ids(&manager);
+        assert!(after.starts_with(&big), "the large segment was rewritten: {:?}", after);
+        assert_eq!(after.len(), 2);
+
+        assert_eq!(manager.get("small0-0").unwrap().as_deref(), Some("rewritten"));
+        assert_eq!(manager.get("small1-0").unwrap(), None);
+        assert_eq!(manager.get("small2-3").unwrap().as_deref(), Some("s".repeat(64).as_str()));
+        assert_eq!(manager.get("big0-7").unwrap().as_deref(), Some("s".repeat(1024).as_str()));
+
+        for tier in 1..4 {
+            write_segment(&manager, &format!("big{}", tier), 40, 1024);
+        }
+        manager.compact_all().unwrap();
+        assert!(!segment_ids(&manager).contains(&big[0]));
+        assert_eq!(manager.get("big0-7").unwrap().as_deref(), Some("s".repeat(1024).as_str()));
+        assert_eq!(manager.len().unwrap(), 4 * 40 + 4 * 4 - 1);
+    }
+
+    #[test]
+    fn clear_drops_every_segment_and_resets_the_partitions() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_manager(dir.path());
+        for i in 0..50 {
+            manager.set(&format!("key-{}", i), "value").unwrap();
+        }
+        manager.delete("key-0").unwrap();
+
+        manager.clear().unwrap();
+
+        assert_eq!(manager.len().unwrap(), 0);
+        let stats = manager.stats().unwrap();
+        assert_eq!((stats.total_size, stats.total_tombstones), (0, 0));
+        assert!(!manager.exists("key-1").unwrap());
+        for partition in &manager.partitions {
+            assert_eq!(partition.file_manager().unwrap().segment_sizes().iter().map(|(_, size)| size).sum::<u64>(), 0);
+        }
+
+        let mut cursor = manager.cursor_from(0);
+        let last = cursor.by_ref().map(Result::unwrap).last();
+        assert!(matches!(last, Some(ChangeEvent::Cleared { .. })));
+    }
+
+    fn open_with_value_cache(dir: &Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let config = PartitionConfig {
+            value_cache_entries: 16,
+            ..PartitionConfig::default()
+        };
+        let mut manager = PartitionManager::new(dir.join("partitions"), config, wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    fn disk_reads(manager: &PartitionManager) -> u64 {
+        manager.stats().unwrap().io.iter().map(|io| io.reads).sum()
+    }
+
+    #[test]
+    fn repeated_gets_are_served_from_the_value_cache() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_with_value_cache(dir.path());
+        manager.set("hot", "value").unwrap();
+
+        assert_eq!(manager.get("hot").unwrap().as_deref(), Some("value"));
+        let reads = disk_reads(&manager);
+        for _ in 0..10 {
+            assert_eq!(manager.get("hot").unwrap().as_deref(), Some("value"));
+        }
+        assert_eq!(disk_reads(&manager), reads);
+
+        let stats = manager.stats().unwrap();
+        let hits: u64 = stats.io.iter().map(|io| io.cache_hits).sum();
+        assert_eq!(hits, 10);
+        assert_eq!(stats.cache_hit_rate(), Some(10.0 / 11.0));
+
+        manager.set("hot", "updated").unwrap();
+        assert_eq!(manager.get("hot").unwrap().as_deref(), Some("updated"));
+        assert_eq!(disk_reads(&manager), reads + 1);
+        manager.delete("hot").unwrap();
+        assert_eq!(manager.get("hot").unwrap(), None);
+    }
+
+    fn cache_lookups(manager: &PartitionManager) -> (u64, u64) {
+        let io = manager.stats().unwrap().io;
+        (io.iter().map(|io| io.cache_hits).sum(), io.iter().map(|io| io.cache_misses).sum())
+    }
+
+    #[test]
+    fn get_no_cache_leaves_the_value_cache_alone() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_with_value_cache(dir.path());
+        manager.set("key", "value").unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(manager.get_no_cache("key").unwrap().as_deref(), Some("value"));
+        }
+        assert_eq!(cache_lookups(&manager), (0, 0));
+        assert_eq!(disk_reads(&manager), 3);
+
+        manager.get("key").unwrap();
+        manager.get("key").unwrap();
+        assert_eq!(cache_lookups(&manager), (1, 1));
+    }
+
+    #[test]
+    fn get_if_only_returns_values_the_predicate_accepts() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("count", "42").unwrap();
+
+        assert_eq!(manager.get_if("count", |value| value.parse::<u32>().is_ok()).unwrap().as_deref(), Some("42"));
+        assert_eq!(manager.get_if("count", |value| value.is_empty()).unwrap(), None);
+        assert_eq!(manager.get_if("missing", |_| true).unwrap(), None);
+    }
+
+    #[test]
+    fn merge_from_keeps_the_newer_value_of_a_shared_key() {
+        let ours_dir = TempDir::new().unwrap();
+        let theirs_dir = TempDir::new().unwrap();
+        let mut ours = open_manager(ours_dir.path());
+        let theirs = open_manager(theirs_dir.path());
+
+        theirs.set("older", "theirs").unwrap();
+        ours.set("shared", "ours").unwrap();
+        ours.set("only-ours", "ours").unwrap();
+        // Timestamps have one-second resolution.
+        std::thread::sleep(Duration::from_millis(1100));
+        ours.set("older", "ours").unwrap();
+        theirs.set("shared", "theirs").unwrap();
+        theirs.set("only-theirs", "theirs").unwrap();
+        theirs.set_with_ttl("expired", "theirs", 0).unwrap();
+        drop(theirs);
+
+        let report = ours.merge_from(&theirs_dir.path().join("partitions")).unwrap();
+
+        assert_eq!((report.inserted, report.overwritten, report.skipped), (1, 1, 1));
+        assert_eq!(ours.get("shared").unwrap().as_deref(), Some("theirs"));
+        assert_eq!(ours.get("older").unwrap().as_deref(), Some("ours"));
+        assert_eq!(ours.get("only-theirs").unwrap().as_deref(), Some("theirs"));
+        assert_eq!(ours.get("only-ours").unwrap().as_deref(), Some("ours"));
+        assert_eq!(ours.get("expired").unwrap(), None);
+    }
+
+    #[test]
+    fn merged_entries_keep_their_timestamps_into_a_third_store() {
+        let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+        let first = open_manager(dirs[0].path());
+        let second = open_manager(dirs[1].path());
+        let mut third = open_manager(dirs[2].path());
+
+        first.set("shared", "first").unwrap();
+        // Timestamps have one-second resolution.
+        std::thread::sleep(Duration::from_millis(1100));
+        second.set("shared", "second").unwrap();
+        std::thread::sleep(Duration::from_millis(1100));
+        drop((first, second));
+
+        third.merge_from(&dirs[0].path().join("partitions")).unwrap();
+        let report = third.merge_from(&dirs[1].path().join("partitions")).unwrap();
+
+        assert_eq!(report.overwritten, 1);
+        assert_eq!(third.get("shared").unwrap().as_deref(), Some("second"));
+    }
+
+    fn stored_versions(manager: &PartitionManager) -> usize {
+        manager
+            .partitions
+            .iter()
+            .map(|partition| partition.file_manager().unwrap().entry_headers().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn compaction_keeps_only_the_latest_version_of_an_overwritten_key() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for i in 0..100 {
+            manager.set("hot", &format!("version-{}", i)).unwrap();
+        }
+        manager.flush().unwrap();
+        assert_eq!(stored_versions(&manager), 100);
+
+        let reports = manager.compact_all().unwrap();
+
+        assert_eq!(stored_versions(&manager), 1);
+        assert_eq!(manager.get("hot").unwrap(), Some("version-99".to_string()));
+        let superseded: u64 = reports.iter().map(|report| report.superseded_versions).sum();
+        assert_eq!(superseded, 99);
+        assert!(reports.iter().map(|report| report.superseded_bytes).sum::<u64>() > 0);
+    }
+
+    #[test]
+    fn compaction_reports_the_tombstones_it_drops() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        manager.set("gone", "value").unwrap();
+        manager.delete("gone").unwrap();
+        manager.flush().unwrap();
+
+        let reports = manager.compact_all().unwrap();
+
+        assert_eq!(reports.len(), manager.partitions.len());
+        assert_eq!(reports.iter().map(|report| report.tombstones_dropped).sum::<u64>(), 1);
+        assert_eq!(reports.iter().map(|report| report.superseded_versions).sum::<u64>(), 1);
+        assert!(reports.iter().all(|report| report.size_after <= report.size_before));
+    }
+
+    #[test]
+    fn paging_through_list_keys_visits_every_key_once_in_order() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        let mut expected: Vec<String> = (0..250).map(|i| format!("key-{:04}", i)).collect();
+        for key in &expected {
+            manager.set(key, "value").unwrap();
+        }
+        manager.delete("key-0100").unwrap();
+        expected.retain(|key| key != "key-0100");
+
+        let mut listed = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let (page, next) = manager.list_keys(token.as_deref(), 32).unwrap();
+            assert!(page.len() <= 32);
+            listed.extend(page);
+            match next {
+                Some(next) => token = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn list_keys_ends_without_a_token_and_rejects_a_zero_limit() {
+        let dir = TempDir::new().unwrap();
+        let manager = open_manager(dir.path());
+        for key in ["a", "b", "c"] {
+            manager.set(key, "value").unwrap();
+        }
+
+        assert_eq!(manager.list_keys(None, 3).unwrap(), (vec!["a".to_string(), "b".to_string(), "c".to_string()], None));
+        assert_eq!(manager.list_keys(Some("a"), 1).unwrap(), (vec!["b".to_string()], Some("b".to_string())));
+        assert_eq!(manager.list_keys(Some("c"), 5).unwrap(), (Vec::new(), None));
+        assert!(matches!(manager.list_keys(None, 0), Err(PlexError::Config(_))));
+    }
+}
@@ -1,20 +1,115 @@
-use crate::cli::Command;
+use async_trait::async_trait;
+use crate::cache::bloom_filter::BloomFilterStats;
+use crate::cli::PlexConfig;
+use crate::engine::compaction::CompactionScheduler;
+use crate::engine::cursor::ChangeCursor;
+use crate::engine::expiry::ExpirySweeper;
+use crate::engine::partition_manager::{
+    CompactionEstimate, CompactionReport, MergeReport, PartitionConfig, PartitionManager, PartitionManagerStats, RebalanceReport,
+    SnapshotManifest, VerifyReport,
+};
+use crate::engine::transaction::Transaction;
 use crate::error::PlexError;
-use crate::storage_engine::StorageEngine;
-use serde::Serialize;
+use crate::storage::storage_engine::{AsyncStorageEngine, StorageEngine};
+use crate::storage::wal::{WALCommand, WALConfig, WALEntry, WAL};
+use crate::utils::compression::CompressionAlgorithm;
+use crate::utils::encryption::{check_key, AesGcmEncryptor, ENCRYPTION_CHECK_FILE};
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions, rename};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Semaphore;
+use tokio::task::JoinError;
 
-#[derive(Serialize, Debug)]
 pub struct PlexEngine {
-    index: HashMap<String, u64>,
+    partition_manager: PartitionManager,
+    wal: Arc<WAL>,
+    data_dir: PathBuf,
+    read_only: bool,
+    compaction_scheduler: Option<CompactionScheduler>,
+    expiry_sweeper: Option<ExpirySweeper>,
+    namespaces: HashMap<String, Arc<PartitionManager>>,
+}
+
+/// What `PlexEngine::health` found when it was called.
+#[derive(Debug, Clone)]
+pub struct HealthStatus {
+    pub read_only: bool,
+    pub wal_writable: bool,
+    /// Partitions with a poisoned lock. Every access to them fails until
+    /// the store is reopened.
+    pub poisoned_partitions: Vec<u32>,
+    pub quarantined_segments: usize,
+    /// Partitions whose bloom filter is past its false positive budget, as
+    /// judged by `BloomFilterStats::is_healthy`.
+    pub unhealthy_bloom_filters: Vec<u32>,
+}
+
+impl HealthStatus {
+    /// Whether the store can serve requests: no partition is poisoned and,
+    /// unless it was opened read-only, writes can reach the WAL.
+    pub fn is_ready(&self) -> bool {
+        self.poisoned_partitions.is_empty() && (self.read_only || self.wal_writable)
+    }
+
+    /// Ready, with no quarantined segments and every bloom filter healthy.
+    pub fn is_healthy(&self) -> bool {
+        self.is_ready() && self.quarantined_segments == 0 && self.unhealthy_bloom_filters.is_empty()
+    }
+}
 
-    #[serde(skip_serializing, skip_deserializing)]
-    data_file: File,
+/// Keyspaces opened with `PlexEngine::open_namespace` live in directories
+/// named with this prefix, next to the default keyspace's `partitions/`.
+const NAMESPACE_DIR_PREFIX: &str = "ns_";
 
-    path: PathBuf,
+/// A keyspace separate from the default one and from every other
+/// namespace, sharing the engine's WAL. The same key can hold a different
+/// value in each.
+#[derive(Clone)]
+pub struct NamespaceHandle {
+    manager: Arc<PartitionManager>,
+    read_only: bool,
+}
+
+impl NamespaceHandle {
+    pub fn name(&self) -> &str {
+        self.manager.namespace().unwrap_or_default()
+    }
+
+    fn check_writable(&self) -> Result<(), PlexError> {
+        if self.read_only {
+            return Err(PlexError::Config("store is read-only".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl StorageEngine for NamespaceHandle {
+    fn get(&self, key: &str) -> Result<Option<String>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.manager.get(key)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError> {
+        self.check_writable()?;
+        if key.is_empty() || value.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.manager.set(key, value)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, PlexError> {
+        self.check_writable()?;
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.manager.delete(key)
+    }
 }
 
 impl StorageEngine for PlexEngine {
@@ -23,197 +118,1412 @@ impl StorageEngine for PlexEngine {
             return Err(PlexError::KeyIsEmpty);
         }
 
-        let Some(&offset) = self.index.get(key) else {
-            return Ok(None);
+        self.partition_manager.get(key)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError> {
+        self.check_writable()?;
+        if key.is_empty() || value.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.set(key, value)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, PlexError> {
+        self.check_writable()?;
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.delete(key)
+    }
+}
+
+// Every call runs on Tokio's blocking pool, since writes end in an fsync.
+// Nothing bounds how many run at once; `AsyncPlexEngine` does.
+#[async_trait]
+impl AsyncStorageEngine for Arc<RwLock<PlexEngine>> {
+    async fn get(&self, key: &str) -> Result<Option<String>, PlexError> {
+        let engine = Arc::clone(self);
+        let key = key.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let engine = engine.read().map_err(|_| PlexError::LockError("engine".to_string()))?;
+            StorageEngine::get(&*engine, &key)
+        })
+        .await
+        .map_err(join_error)?
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), PlexError> {
+        let engine = Arc::clone(self);
+        let key = key.to_string();
+        let value = value.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut engine = engine.write().map_err(|_| PlexError::LockError("engine".to_string()))?;
+            StorageEngine::set(&mut *engine, &key, &value)
+        })
+        .await
+        .map_err(join_error)?
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, PlexError> {
+        let engine = Arc::clone(self);
+        let key = key.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut engine = engine.write().map_err(|_| PlexError::LockError("engine".to_string()))?;
+            StorageEngine::delete(&mut *engine, &key)
+        })
+        .await
+        .map_err(join_error)?
+    }
+}
+
+/// Limits for `AsyncPlexEngine`.
+#[derive(Debug, Clone)]
+pub struct AsyncEngineConfig {
+    /// Most blocking operations allowed on Tokio's blocking pool at once.
+    /// Further calls wait for a permit instead of spawning.
+    pub max_inflight: usize,
+}
+
+impl Default for AsyncEngineConfig {
+    fn default() -> Self {
+        Self { max_inflight: 64 }
+    }
+}
+
+/// An engine shared across tasks, like `Arc<RwLock<PlexEngine>>`, but with
+/// a bound on how many calls can occupy blocking threads at a time.
+#[derive(Clone)]
+pub struct AsyncPlexEngine {
+    engine: Arc<RwLock<PlexEngine>>,
+    inflight: Arc<Semaphore>,
+    max_inflight: usize,
+}
+
+impl AsyncPlexEngine {
+    pub fn new(engine: PlexEngine, config: AsyncEngineConfig) -> Self {
+        Self::from_shared(Arc::new(RwLock::new(engine)), config)
+    }
+
+    pub fn from_shared(engine: Arc<RwLock<PlexEngine>>, config: AsyncEngineConfig) -> Self {
+        // A zero limit would make every call wait forever.
+        let max_inflight = config.max_inflight.max(1);
+        Self {
+            engine,
+            inflight: Arc::new(Semaphore::new(max_inflight)),
+            max_inflight,
+        }
+    }
+
+    pub fn max_inflight(&self) -> usize {
+        self.max_inflight
+    }
+
+    pub fn engine(&self) -> &Arc<RwLock<PlexEngine>> {
+        &self.engine
+    }
+
+    /// Waits for a permit, then runs `op` on the blocking pool. The permit
+    /// moves into the blocking task so it is held until `op` returns.
+    async fn run_blocking<T, F>(&self, op: F) -> Result<T, PlexError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&RwLock<PlexEngine>) -> Result<T, PlexError> + Send + 'static,
+    {
+        let permit = Arc::clone(&self.inflight)
+            .acquire_owned()
+            .await
+            .map_err(|_| PlexError::LockError("inflight semaphore".to_string()))?;
+        let engine = Arc::clone(&self.engine);
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            op(&engine)
+        })
+        .await
+        .map_err(join_error)?
+    }
+}
+
+#[async_trait]
+impl AsyncStorageEngine for AsyncPlexEngine {
+    async fn get(&self, key: &str) -> Result<Option<String>, PlexError> {
+        let key = key.to_string();
+
+        self.run_blocking(move |engine| {
+            let engine = engine.read().map_err(|_| PlexError::LockError("engine".to_string()))?;
+            StorageEngine::get(&*engine, &key)
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), PlexError> {
+        let key = key.to_string();
+        let value = value.to_string();
+
+        self.run_blocking(move |engine| {
+            let mut engine = engine.write().map_err(|_| PlexError::LockError("engine".to_string()))?;
+            StorageEngine::set(&mut *engine, &key, &value)
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, PlexError> {
+        let key = key.to_string();
+
+        self.run_blocking(move |engine| {
+            let mut engine = engine.write().map_err(|_| PlexError::LockError("engine".to_string()))?;
+            StorageEngine::delete(&mut *engine, &key)
+        })
+        .await
+    }
+}
+
+fn join_error(err: JoinError) -> PlexError {
+    PlexError::IO(std::io::Error::other(err))
+}
+
+/// Opens a `PlexEngine` from settings given one at a time. Anything not
+/// set keeps its default; only the data directory is required.
+#[derive(Debug, Clone, Default)]
+pub struct PlexEngineBuilder {
+    data_dir: Option<PathBuf>,
+    config: PlexConfig,
+}
+
+impl PlexEngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    pub fn partitions(mut self, partition_count: u32) -> Self {
+        self.config.partition.partition_count = partition_count;
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionAlgorithm) -> Self {
+        self.config.partition.compression = compression;
+        self
+    }
+
+    pub fn wal_config(mut self, wal_config: WALConfig) -> Self {
+        self.config.wal = wal_config;
+        self
+    }
+
+    /// Blocks of segment data each partition caches for reads; see
+    /// `PartitionConfig::block_cache_blocks`.
+    pub fn cache_capacity(mut self, blocks: usize) -> Self {
+        self.config.partition.block_cache_blocks = blocks;
+        self
+    }
+
+    /// Values each partition caches for `get`; see
+    /// `PartitionConfig::value_cache_entries`.
+    pub fn value_cache_capacity(mut self, entries: usize) -> Self {
+        self.config.partition.value_cache_entries = entries;
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.config.partition.read_only = read_only;
+        self
+    }
+
+    /// Opens the engine as `PlexEngine::with_plex_config` would.
+    pub fn build(self) -> Result<PlexEngine, PlexError> {
+        let data_dir = self
+            .data_dir
+            .ok_or_else(|| PlexError::Config("a data directory is required".to_string()))?;
+        PlexEngine::with_plex_config(data_dir, self.config)
+    }
+}
+
+impl PlexEngine {
+    pub fn builder() -> PlexEngineBuilder {
+        PlexEngineBuilder::new()
+    }
+
+    pub fn new(data_dir: PathBuf) -> Result<Self, PlexError> {
+        Self::with_config(data_dir, PartitionConfig::default())
+    }
+
+    pub fn config(&self) -> &PartitionConfig {
+        self.partition_manager.config()
+    }
+
+    /// Opens the store in `data_dir` with `config`, which must agree with
+    /// the `config.json` saved there on partition count, partitioning and
+    /// hash family.
+    /// The config is saved back on every writable open.
+    ///
+    /// With `config.read_only` set, data files are opened without write
+    /// access, no WAL files are created, and every write fails with a
+    /// `Config` error. WAL recovery is skipped too, so writes that were
+    /// logged but never checkpointed by a writer are not visible.
+    pub fn with_config(data_dir: PathBuf, config: PartitionConfig) -> Result<Self, PlexError> {
+        Self::with_plex_config(
+            data_dir,
+            PlexConfig {
+                partition: config,
+                wal: WALConfig::default(),
+            },
+        )
+    }
+
+    /// Like `with_config`, but also takes the WAL settings, as loaded by
+    /// `PlexConfig::from_file`.
+    pub fn with_plex_config(data_dir: PathBuf, config: PlexConfig) -> Result<Self, PlexError> {
+        config.validate()?;
+        let PlexConfig { partition: config, wal: wal_config } = config;
+        if let Some(saved) = PartitionConfig::load(&data_dir)? {
+            config.check_compatible(&saved)?;
+        }
+
+        let read_only = config.read_only;
+        let wal_config = WALConfig {
+            read_only,
+            ..wal_config
         };
+        let check_path = data_dir.join(ENCRYPTION_CHECK_FILE);
+        match &config.encryption_key {
+            Some(key) => {
+                if !read_only {
+                    std::fs::create_dir_all(&data_dir)?;
+                }
+                check_key(&check_path, key, !read_only)?;
+            }
+            None if check_path.exists() => {
+                return Err(PlexError::Encryption(
+                    "Store is encrypted but no encryption key is configured".to_string(),
+                ));
+            }
+            None => {}
+        }
 
-        let mut reader = BufReader::new(&self.data_file);
+        let mut wal = WAL::new(data_dir.join("wal"), wal_config)?;
+        if let Some(key) = &config.encryption_key {
+            wal.set_encryptor(Some(Box::new(AesGcmEncryptor::new(key))));
+        }
+        let wal = Arc::new(wal);
 
-        reader.seek(SeekFrom::Start(offset))?;
+        let mut partition_manager = PartitionManager::new(
+            data_dir.join("partitions"),
+            config,
+            wal.clone(),
+        )?;
+        partition_manager.load_from_disk()?;
 
-        let mut length_bytes = [0u8; 8];
+        let mut engine = PlexEngine {
+            partition_manager,
+            wal,
+            data_dir,
+            read_only,
+            compaction_scheduler: None,
+            expiry_sweeper: None,
+            namespaces: HashMap::new(),
+        };
+        engine.load_namespaces()?;
+        if !read_only {
+            engine.recover()?;
+            engine.save_config()?;
+            engine.start_background_tasks();
+        }
 
-        let _ = reader.read_exact(&mut length_bytes);
+        Ok(engine)
+    }
 
-        let length = u64::from_le_bytes(length_bytes) as usize;
+    /// Starts background compaction if the config asks for it. The
+    /// scheduler holds on to the current partitions, so anything that
+    /// replaces them stops it first and calls this again afterwards.
+    fn start_background_tasks(&mut self) {
+        let config = self.partition_manager.config();
+        self.compaction_scheduler = config
+            .compaction_schedule
+            .map(|schedule| CompactionScheduler::spawn(&self.partition_manager, schedule));
+        self.expiry_sweeper = config
+            .expiry_sweep_interval
+            .map(|interval| ExpirySweeper::spawn(&self.partition_manager, interval));
+    }
 
-        let mut command_bytes = vec![0u8; length];
-        let _ = reader.read_exact(&mut command_bytes);
+    /// Stops the compaction scheduler and expiry sweeper, waiting for any
+    /// pass in progress to finish.
+    fn stop_background_tasks(&mut self) {
+        self.compaction_scheduler = None;
+        self.expiry_sweeper = None;
+    }
 
-        let command: Command = bincode::deserialize(&command_bytes)?;
+    fn save_config(&self) -> Result<(), PlexError> {
+        self.partition_manager.config().save(&self.data_dir)
+    }
 
-        match command {
-            Command::Set { key: k, value: v} if k == key => Ok(Some(v)),
-            Command::Set { key: _, value: _} | Command::Delete { key: _} => {
-                eprintln!(
-                    "Index points to mismatched or deleted command at offset {} for key '{}'",
-                    offset, key
-                );
-                Ok(None)
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), PlexError> {
+        if self.read_only {
+            return Err(PlexError::Config("store is read-only".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Re-applies WAL entries that never made it into the partitions, then
+    /// checkpoints the WAL so they are not replayed again on the next start.
+    /// Entries tagged with a namespace are replayed into that namespace,
+    /// opening it if needed.
+    fn recover(&mut self) -> Result<(), PlexError> {
+        let mut entries = Vec::new();
+        let mut namespaced: HashMap<String, Vec<WALEntry>> = HashMap::new();
+        for entry in self.wal.replay()? {
+            match entry.command {
+                WALCommand::Namespaced { namespace, command } => {
+                    namespaced.entry(namespace).or_default().push(WALEntry {
+                        command: *command,
+                        ..entry
+                    });
+                }
+                _ => entries.push(entry),
+            }
+        }
+
+        self.partition_manager.apply_wal_entries(&entries)?;
+        for (namespace, entries) in namespaced {
+            self.namespace_manager(&namespace)?;
+            let manager = self
+                .namespaces
+                .get_mut(&namespace)
+                .and_then(Arc::get_mut)
+                .ok_or_else(|| PlexError::Recovery(format!("namespace '{}' is already in use", namespace)))?;
+            manager.apply_wal_entries(&entries)?;
+        }
+
+        self.checkpoint()
+    }
+
+    /// Opens the keyspace `name`, creating it under `ns_<name>/` in the data
+    /// directory if it does not exist yet. Its writes share the engine's WAL,
+    /// tagged with the name, so they are recovered with everything else.
+    /// Background compaction and expiry sweeps only cover the default
+    /// keyspace.
+    pub fn open_namespace(&mut self, name: &str) -> Result<NamespaceHandle, PlexError> {
+        Ok(NamespaceHandle {
+            manager: self.namespace_manager(name)?,
+            read_only: self.read_only,
+        })
+    }
+
+    fn namespace_manager(&mut self, name: &str) -> Result<Arc<PartitionManager>, PlexError> {
+        if let Some(manager) = self.namespaces.get(name) {
+            return Ok(Arc::clone(manager));
+        }
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(PlexError::Config(format!(
+                "namespace '{}' must be non-empty and use only letters, digits, '_' and '-'",
+                name
+            )));
+        }
+
+        // A namespace keeps the partition layout it was created with, even
+        // if the default keyspace is rebalanced later.
+        let namespace_dir = self.data_dir.join(format!("{}{}", NAMESPACE_DIR_PREFIX, name));
+        let mut config = match PartitionConfig::load(&namespace_dir)? {
+            Some(saved) => saved,
+            None => self.partition_manager.config().clone(),
+        };
+        config.read_only = self.read_only;
+        config.encryption_key = self.partition_manager.config().encryption_key.clone();
+
+        let mut manager = PartitionManager::for_namespace(namespace_dir.clone(), config, self.wal.clone(), name)?;
+        manager.load_from_disk()?;
+        if !self.read_only {
+            manager.config().save(&namespace_dir)?;
+        }
+
+        let manager = Arc::new(manager);
+        self.namespaces.insert(name.to_string(), Arc::clone(&manager));
+        Ok(manager)
+    }
+
+    /// Opens every namespace found in the data directory, so WAL replay and
+    /// checkpoints cover them.
+    fn load_namespaces(&mut self) -> Result<(), PlexError> {
+        if !self.data_dir.exists() {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(&self.data_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
             }
 
-            Command::Get { key: _ } | Command::Compact => todo!(),
+            let file_name = entry.file_name();
+            if let Some(name) = file_name.to_str().and_then(|name| name.strip_prefix(NAMESPACE_DIR_PREFIX)) {
+                self.namespace_manager(name)?;
+            }
+        }
+        Ok(())
+    }
 
+    /// Hands every buffered WAL entry to the OS. Partition data files are
+    /// written unbuffered, so once this returns every write so far survives
+    /// the process exiting, though not a power loss; see `sync`.
+    pub fn flush(&self) -> Result<(), PlexError> {
+        if self.read_only {
+            return Ok(());
         }
+
+        self.wal.flush()
     }
 
+    /// Syncs the WAL and then the data files of every partition, namespaces
+    /// included, so every write so far survives a power loss and is found on
+    /// reopen without replaying the WAL. Every partition is attempted even
+    /// if one fails; the first error is returned.
+    pub fn sync(&self) -> Result<(), PlexError> {
+        if self.read_only {
+            return Ok(());
+        }
 
-    fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError> {
+        let mut first_error = self.wal.sync().err();
+        for manager in std::iter::once(&self.partition_manager).chain(self.namespaces.values().map(Arc::as_ref)) {
+            if let Err(e) = manager.flush() {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Marks everything logged so far as durable in the partitions and drops
+    /// the WAL files that no longer hold anything to replay.
+    pub fn checkpoint(&self) -> Result<(), PlexError> {
+        self.check_writable()?;
+        let sequence = self.wal.get_lastest_sequence();
+        // Data files may lag the WAL under a relaxed durability mode, so they
+        // have to be synced before the log that covers them is dropped.
+        self.partition_manager.flush()?;
+        for manager in self.namespaces.values() {
+            manager.flush()?;
+        }
+        self.wal.truncate_to_sequence(sequence)?;
+        self.wal.truncate(sequence)?;
+        Ok(())
+    }
+
+    /// Stops the engine cleanly: syncs the WAL and every partition, persists
+    /// the indexes and bloom filters so the next startup skips the segment
+    /// scan, then checkpoints the WAL.
+    pub fn shutdown(mut self) -> Result<(), PlexError> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        // Let a running compaction or sweep finish before the indexes are
+        // persisted.
+        self.stop_background_tasks();
+
+        self.wal.sync()?;
+        self.partition_manager.flush()?;
+        self.partition_manager.persist_indexes()?;
+        for manager in self.namespaces.values() {
+            manager.flush()?;
+            manager.persist_indexes()?;
+        }
+        self.checkpoint()
+    }
+
+    pub fn delete_range(&mut self, start: &str, end: &str) -> Result<u64, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.delete_range(start, end)
+    }
+
+    pub fn multi_get(&self, keys: &[&str]) -> Result<Vec<Option<String>>, PlexError> {
+        self.partition_manager.multi_get(keys)
+    }
+
+    pub fn exists(&self, key: &str) -> Result<bool, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.exists(key)
+    }
+
+    /// Like `get`, without touching the value cache.
+    pub fn get_no_cache(&self, key: &str) -> Result<Option<String>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.get_no_cache(key)
+    }
+
+    pub fn get_if(&self, key: &str, pred: impl Fn(&str) -> bool) -> Result<Option<String>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.get_if(key, pred)
+    }
+
+    pub fn get_range(&self, key: &str, start: usize, len: usize) -> Result<Option<String>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.get_range(key, start, len)
+    }
+
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>, PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.partition_manager.get_bytes(key)
+    }
+
+    pub fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<(), PlexError> {
+        self.check_writable()?;
         if key.is_empty() || value.is_empty() {
             return Err(PlexError::KeyIsEmpty);
         }
 
-        let command = Command::Set { key: key.to_string(), value: value.to_string()};
-        let serialized = bincode::serialize(&command)?;
+        self.partition_manager.set_bytes(key, value)
+    }
 
-        let length = serialized.len() as u64;
-        let length_bytes = length.to_le_bytes();
+    pub fn delete_bytes(&mut self, key: &[u8]) -> Result<bool, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.delete_bytes(key)
+    }
 
-        let offset = self.data_file.seek(SeekFrom::End(0))?;
+    pub fn set_and_get(&mut self, key: &str, value: &str) -> Result<Option<String>, PlexError> {
+        self.check_writable()?;
+        if key.is_empty() || value.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
 
-        self.data_file.write_all(&length_bytes)?;
-        self.data_file.write_all(&serialized)?;
-        self.data_file.flush()?;
+        self.partition_manager.set_and_get(key, value)
+    }
 
-        self.index.insert(key.to_string(), offset);
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, PlexError> {
+        self.check_writable()?;
+        if key.is_empty() || new.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
 
-        Ok(())
+        self.partition_manager.compare_and_swap(key, expected, new)
     }
 
-    fn delete(&mut self, key: &str) -> Result<(), PlexError> {
+    pub fn merge(&mut self, key: &str, delta: i64) -> Result<i64, PlexError> {
+        self.check_writable()?;
         if key.is_empty() {
             return Err(PlexError::KeyIsEmpty);
         }
 
-        if self.index.contains_key(key) {
-            let command = Command::Delete {key: key.to_string()};
+        self.partition_manager.merge(key, delta)
+    }
 
-            let serialized = bincode::serialize(&command)?;
-            let length = serialized.len() as u64;
-            let length_bytes = length.to_le_bytes();
+    pub fn set_with_ttl(&mut self, key: &str, value: &str, ttl_secs: u64) -> Result<(), PlexError> {
+        self.check_writable()?;
+        if key.is_empty() || value.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
 
-            self.data_file.seek(SeekFrom::End(0))?;
-            self.data_file.write_all(&length_bytes)?;
-            self.data_file.write_all(&serialized)?;
+        self.partition_manager.set_with_ttl(key, value, ttl_secs)
+    }
 
-            self.data_file.flush()?;
-            self.index.remove(key);
+    pub fn set_batch(&mut self, pairs: &[(String, String)]) -> Result<(), PlexError> {
+        self.check_writable()?;
+        self.partition_manager.set_batch(pairs)
+    }
 
-            return Ok(());
+    pub fn len(&self) -> Result<u64, PlexError> {
+        self.partition_manager.len()
+    }
+
+    pub fn is_empty(&self) -> Result<bool, PlexError> {
+        self.partition_manager.is_empty()
+    }
+
+    pub fn stats(&self) -> Result<PartitionManagerStats, PlexError> {
+        self.partition_manager.stats()
+    }
+
+    pub fn bloom_filter_stats(&self) -> Vec<(u32, Option<BloomFilterStats>)> {
+        self.partition_manager.bloom_filter_stats()
+    }
+
+    /// Liveness and readiness of the default keyspace and the WAL. Bloom
+    /// filters that are locked at the time are left out of the check.
+    pub fn health(&self) -> HealthStatus {
+        let unhealthy_bloom_filters = self
+            .partition_manager
+            .bloom_filter_stats()
+            .into_iter()
+            .filter(|(_, stats)| stats.as_ref().is_some_and(|stats| !stats.is_healthy()))
+            .map(|(partition_id, _)| partition_id)
+            .collect();
+
+        HealthStatus {
+            read_only: self.read_only,
+            wal_writable: self.wal.is_writable(),
+            poisoned_partitions: self.partition_manager.poisoned_partitions(),
+            quarantined_segments: self.partition_manager.quarantined_segment_count(),
+            unhealthy_bloom_filters,
         }
-        Err(PlexError::KeyNotFound)
     }
+
+    pub fn count_prefix(&self, prefix: &str) -> Result<u64, PlexError> {
+        self.partition_manager.count_prefix(prefix)
+    }
+
+    pub fn contains_prefix(&self, prefix: &str) -> Result<bool, PlexError> {
+        self.partition_manager.contains_prefix(prefix)
+    }
+
+    /// One page of keys in sorted order; see `PartitionManager::list_keys`.
+    pub fn list_keys(&self, after: Option<&str>, limit: usize) -> Result<(Vec<String>, Option<String>), PlexError> {
+        self.partition_manager.list_keys(after, limit)
+    }
+
+    pub fn verify(&self) -> Result<VerifyReport, PlexError> {
+        self.partition_manager.verify()
+    }
+
+    /// Rewrites every segment without the entries that fail their CRC and
+    /// rebuilds the indexes and bloom filters from what is left. Returns how
+    /// many entries were dropped.
+    pub fn repair(&mut self) -> Result<u64, PlexError> {
+        self.check_writable()?;
+        self.stop_background_tasks();
+        let dropped = self.partition_manager.repair();
+        self.start_background_tasks();
+        dropped
+    }
+
+    /// Deletes every key in the default keyspace; namespaces are left alone.
+    pub fn clear(&mut self) -> Result<(), PlexError> {
+        self.check_writable()?;
+        self.stop_background_tasks();
+        let cleared = self.partition_manager.clear();
+        self.start_background_tasks();
+        cleared
+    }
+
+    /// Merges the default keyspace of the store in `other_dir` into this
+    /// one; see `PartitionManager::merge_from`.
+    pub fn merge_from(&mut self, other_dir: &Path) -> Result<MergeReport, PlexError> {
+        self.check_writable()?;
+        let saved_config = PartitionConfig::load(other_dir)?;
+        self.partition_manager.merge_partitions_from(&other_dir.join("partitions"), saved_config)
+    }
+
+    pub fn rebalance(&mut self) -> Result<RebalanceReport, PlexError> {
+        self.check_writable()?;
+        self.stop_background_tasks();
+        let report = self.partition_manager.rebalance();
+        self.start_background_tasks();
+        let report = report?;
+        // The partition count changed, and the next open has to agree.
+        self.save_config()?;
+        Ok(report)
+    }
+
+    pub fn compact(&mut self) -> Result<Vec<CompactionReport>, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.compact_all()
+    }
+
+    /// Compacts every partition, up to `max_parallel` at a time.
+    pub fn compact_parallel(&mut self, max_parallel: usize) -> Result<Vec<CompactionReport>, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.compact_all_parallel(max_parallel)
+    }
+
+    pub fn compact_if_needed(&mut self) -> Result<Vec<u32>, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.compact_if_needed()
+    }
+
+    /// Tombstones every expired key now, rather than waiting for the
+    /// sweeper, and returns how many there were.
+    pub fn sweep_expired(&mut self) -> Result<u64, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.sweep_expired()
+    }
+
+    pub fn estimate_compaction(&self, partition_id: u32) -> Result<CompactionEstimate, PlexError> {
+        self.partition_manager.estimate_compaction(partition_id)
+    }
+
+    pub fn begin(&mut self) -> Transaction<'_> {
+        self.partition_manager.begin()
+    }
+
+    pub fn cursor_from(&self, seq: u64) -> ChangeCursor<'_> {
+        self.partition_manager.cursor_from(seq)
+    }
+
+    pub fn export_ndjson<W: Write>(&self, out: W) -> Result<u64, PlexError> {
+        self.partition_manager.export_ndjson(out)
+    }
+
+    pub fn import_ndjson<R: Read>(&mut self, input: R) -> Result<u64, PlexError> {
+        self.check_writable()?;
+        self.partition_manager.import_ndjson(input)
+    }
+
+    pub fn snapshot(&self, dest: &Path) -> Result<SnapshotManifest, PlexError> {
+        self.partition_manager.snapshot(dest)
+    }
+
+    pub fn restore_from_snapshot(&mut self, src: &Path) -> Result<(), PlexError> {
+        self.check_writable()?;
+        self.stop_background_tasks();
+        let restored = self.partition_manager.restore_from_snapshot(src);
+        self.start_background_tasks();
+        restored?;
+        // A snapshot may have a different partition count.
+        self.save_config()
+    }
+
+    pub fn backup_stream<W: Write>(&self, out: W) -> Result<SnapshotManifest, PlexError> {
+        self.partition_manager.backup_stream(out)
+    }
+
+    pub fn restore_stream<R: Read>(&mut self, input: R) -> Result<SnapshotManifest, PlexError> {
+        self.check_writable()?;
+        self.stop_background_tasks();
+        let manifest = self.partition_manager.restore_stream(input);
+        self.start_background_tasks();
+        let manifest = manifest?;
+        // The backup may have a different partition count.
+        self.save_config()?;
+        Ok(manifest)
+    }
+
 }
 
-impl PlexEngine {
-    pub fn new(path: PathBuf) -> Result<Self, PlexError> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::engine::partition_manager::DEFAULT_PARTITION_COUNT;
+    use tempfile::TempDir;
 
-        let mut engine = PlexEngine {
-            index: HashMap::new(),
-            data_file: file,
-            path,
-        };
-        engine.load()?;
+    #[test]
+    fn replays_wal_after_data_files_are_lost() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("kept", "value").unwrap();
+            engine.set("gone", "value").unwrap();
+            engine.delete("gone").unwrap();
+        }
 
-        Ok(engine)
+        std::fs::remove_dir_all(dir.path().join("partitions")).unwrap();
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.get("kept").unwrap().as_deref(), Some("value"));
+        assert_eq!(engine.get("gone").unwrap(), None);
+    }
+
+    #[test]
+    fn bulk_loaded_values_are_not_overwritten_by_replaying_earlier_sets() {
+        let dir = TempDir::new().unwrap();
+        let pairs = || (0..10).map(|i| (format!("key{}", i), "loaded".to_string()));
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("key3", "logged").unwrap();
+            assert!(matches!(engine.partition_manager.bulk_load(pairs()), Err(PlexError::WAL(_))));
+
+            engine.checkpoint().unwrap();
+            assert_eq!(engine.partition_manager.bulk_load(pairs()).unwrap(), 10);
+        }
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.get("key3").unwrap().as_deref(), Some("loaded"));
     }
 
-    pub fn load(&mut self) -> Result<(), PlexError> {
-        let mut offset = 0u64;
-        let mut reader = BufReader::new(&self.data_file);
-        reader.seek(SeekFrom::Start(0))?;
+    #[test]
+    fn encrypted_store_keeps_keys_off_disk_and_refuses_a_wrong_key() {
+        use crate::utils::encryption::EncryptionKey;
 
-        loop {
-            let mut length_bytes = [0u8; 8];
+        let dir = TempDir::new().unwrap();
+        let open = |passphrase: Option<&str>| {
+            let config = PartitionConfig {
+                encryption_key: passphrase.map(|p| EncryptionKey::from_passphrase(p, b"salt")),
+                ..PartitionConfig::default()
+            };
+            PlexEngine::with_config(dir.path().to_path_buf(), config)
+        };
+        let mut export = Vec::new();
+        {
+            let mut engine = open(Some("right")).unwrap();
+            engine.set("secret-key", "secret-value").unwrap();
+            engine.partition_manager.persist_indexes().unwrap();
+            engine.export_ndjson(&mut export).unwrap();
+        }
 
-            match reader.read_exact(&mut length_bytes) {
-                Ok(()) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(PlexError::IO(e)),
+        let mut plaintext = Vec::new();
+        for entry in walkdir(dir.path()) {
+            let bytes = std::fs::read(&entry).unwrap();
+            if bytes.windows(b"secret-".len()).any(|window| window == b"secret-") {
+                plaintext.push(entry);
             }
+        }
+        assert!(plaintext.is_empty(), "plaintext found in {:?}", plaintext);
+        assert!(!String::from_utf8_lossy(&export).contains("secret-"));
 
-            let length = u64::from_le_bytes(length_bytes) as usize;
-            let mut command_bytes = vec![0u8; length];
+        assert!(matches!(open(Some("wrong")), Err(PlexError::Encryption(_))));
+        assert!(matches!(open(None), Err(PlexError::Encryption(_))));
 
-            reader.read_exact(&mut command_bytes).map_err(PlexError::IO)?;
+        let mut engine = open(Some("right")).unwrap();
+        assert_eq!(engine.get("secret-key").unwrap().as_deref(), Some("secret-value"));
+        engine.delete("secret-key").unwrap();
+        assert_eq!(engine.import_ndjson(export.as_slice()).unwrap(), 1);
+        assert_eq!(engine.get("secret-key").unwrap().as_deref(), Some("secret-value"));
+    }
 
-            let command: Command =
-                bincode::deserialize(&command_bytes).map_err(|_| PlexError::CorruptData(offset))?;
+    fn walkdir(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(walkdir(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
 
-            match command {
-                Command::Set { key: k, value: _} => {
-                    self.index.insert(k, offset);
-                }
+    #[test]
+    fn checkpoint_stops_entries_from_being_replayed() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("key", "value").unwrap();
+            engine.checkpoint().unwrap();
+        }
+
+        std::fs::remove_dir_all(dir.path().join("partitions")).unwrap();
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.get("key").unwrap(), None);
+    }
 
-                Command::Delete { key: k} => {
-                    self.index.remove(&k);
+    #[test]
+    fn set_get_delete_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+
+        engine.set("key", "value").unwrap();
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("value"));
+
+        assert!(engine.delete("key").unwrap());
+        assert_eq!(engine.get("key").unwrap(), None);
+        assert!(!engine.delete("key").unwrap());
+    }
+
+    #[test]
+    fn values_survive_compaction_and_reopen() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("a", "1").unwrap();
+            engine.set("a", "2").unwrap();
+            engine.set("b", "3").unwrap();
+            engine.delete("b").unwrap();
+            engine.compact().unwrap();
+            engine.checkpoint().unwrap();
+
+            assert_eq!(engine.get("a").unwrap().as_deref(), Some("2"));
+            assert_eq!(engine.get("b").unwrap(), None);
+        }
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.get("a").unwrap().as_deref(), Some("2"));
+        assert_eq!(engine.get("b").unwrap(), None);
+        assert_eq!(engine.stats().unwrap().total_tombstones, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_async_writes_are_all_kept() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(RwLock::new(PlexEngine::new(dir.path().to_path_buf()).unwrap()));
+
+        let mut tasks = Vec::new();
+        for task in 0..8 {
+            let engine = Arc::clone(&engine);
+            tasks.push(tokio::spawn(async move {
+                for i in 0..25 {
+                    let key = format!("task{}-key{}", task, i);
+                    engine.set(&key, &format!("value{}", i)).await.unwrap();
+                    assert_eq!(
+                        AsyncStorageEngine::get(&engine, &key).await.unwrap(),
+                        Some(format!("value{}", i))
+                    );
                 }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
 
-                Command::Get { key: _} | Command::Compact => todo!(),
+        for task in 0..8 {
+            for i in 0..25 {
+                let key = format!("task{}-key{}", task, i);
+                assert_eq!(
+                    AsyncStorageEngine::get(&engine, &key).await.unwrap(),
+                    Some(format!("value{}", i))
+                );
             }
+        }
+    }
+
+    #[tokio::test]
+    async fn async_delete_removes_the_key() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(RwLock::new(PlexEngine::new(dir.path().to_path_buf()).unwrap()));
+
+        engine.set("key", "value").await.unwrap();
+        AsyncStorageEngine::delete(&engine, "key").await.unwrap();
+
+        assert_eq!(AsyncStorageEngine::get(&engine, "key").await.unwrap(), None);
+    }
+
+    fn persisted_index_count(dir: &Path) -> usize {
+        std::fs::read_dir(dir.join("partitions"))
+            .unwrap()
+            .filter(|partition| partition.as_ref().unwrap().path().join("index.bin").exists())
+            .count()
+    }
 
-            offset += 8 + length as u64;
+    #[test]
+    fn reopening_after_shutdown_loads_the_persisted_indexes() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("a", "1").unwrap();
+            engine.set("b", "2").unwrap();
+            engine.delete("b").unwrap();
+            engine.shutdown().unwrap();
         }
+        assert_eq!(persisted_index_count(dir.path()), DEFAULT_PARTITION_COUNT as usize);
 
-        Ok(())
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+
+        assert_eq!(engine.get("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(engine.get("b").unwrap(), None);
+        assert_eq!(engine.stats().unwrap().total_keys, 1);
     }
 
-    pub fn compact(&mut self) -> Result<(), PlexError> {
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn inflight_calls_stay_under_the_limit() {
+        let dir = TempDir::new().unwrap();
+        let engine = AsyncPlexEngine::new(
+            PlexEngine::new(dir.path().to_path_buf()).unwrap(),
+            AsyncEngineConfig { max_inflight: 8 },
+        );
+        let current = Arc::new(AtomicUsize::new(0));
+        let observed_max = Arc::new(AtomicUsize::new(0));
 
-        let compact_path = self.path.with_extension("compacting");
-        let mut compact_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .truncate(true)
-            .open(&compact_path)?;
+        let mut tasks = Vec::new();
+        for i in 0..1000 {
+            let engine = engine.clone();
+            let current = Arc::clone(&current);
+            let observed_max = Arc::clone(&observed_max);
+            tasks.push(tokio::spawn(async move {
+                engine
+                    .run_blocking(move |engine| {
+                        let running = current.fetch_add(1, Ordering::SeqCst) + 1;
+                        observed_max.fetch_max(running, Ordering::SeqCst);
+                        let result = engine.write().unwrap().set(&format!("key-{}", i), &i.to_string());
+                        current.fetch_sub(1, Ordering::SeqCst);
+                        result
+                    })
+                    .await
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
 
-        let mut new_index = HashMap::new();
+        assert!(observed_max.load(Ordering::SeqCst) <= engine.max_inflight());
+        for i in [0, 499, 999] {
+            let value = AsyncStorageEngine::get(&engine, &format!("key-{}", i)).await.unwrap();
+            assert_eq!(value, Some(i.to_string()));
+        }
+    }
 
+    #[test]
+    fn a_zero_limit_still_allows_one_call() {
+        let dir = TempDir::new().unwrap();
+        let engine = AsyncPlexEngine::new(
+            PlexEngine::new(dir.path().to_path_buf()).unwrap(),
+            AsyncEngineConfig { max_inflight: 0 },
+        );
 
-        for (key, &offset) in &self.index {
-            let mut reader = BufReader::new(&self.data_file);
-            reader.seek(SeekFrom::Start(offset))?;
+        assert_eq!(engine.max_inflight(), 1);
+    }
 
-            let mut length_bytes = [0u8; 8];
-            let _ = reader.read_exact(&mut length_bytes)?;
-            let length = u64::from_le_bytes(length_bytes) as usize;
+    fn read_only_config() -> PartitionConfig {
+        PartitionConfig {
+            read_only: true,
+            ..PartitionConfig::default()
+        }
+    }
 
-            let mut command_bytes = vec![0u8; length];
-            let _ = reader.read_exact(&mut command_bytes)?;
+    fn dir_listing(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.sort();
+        paths
+    }
 
+    #[test]
+    fn read_only_store_serves_reads_and_rejects_writes() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("key", "value").unwrap();
+            engine.checkpoint().unwrap();
+        }
+        let wal_files = dir_listing(&dir.path().join("wal"));
+
+        let mut engine = PlexEngine::with_config(dir.path().to_path_buf(), read_only_config()).unwrap();
 
-            let _: Command = bincode::deserialize(&command_bytes)?;
+        assert!(engine.is_read_only());
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("value"));
+        assert!(matches!(engine.set("key", "other"), Err(PlexError::Config(_))));
+        assert!(matches!(engine.delete("key"), Err(PlexError::Config(_))));
+        assert!(matches!(engine.compact(), Err(PlexError::Config(_))));
+        assert_eq!(dir_listing(&dir.path().join("wal")), wal_files);
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("value"));
+    }
 
+    #[test]
+    fn read_only_open_creates_no_wal_directory() {
+        let dir = TempDir::new().unwrap();
+        drop(PlexEngine::new(dir.path().to_path_buf()).unwrap());
+        std::fs::remove_dir_all(dir.path().join("wal")).unwrap();
 
-            let new_offset = compact_file.seek(SeekFrom::End(0))?;
-            let mut writer = BufWriter::new(&compact_file);
-            /*let new_offset = compact_file.seek(SeekFrom::End(0))?;*/
-            writer.write_all(&length_bytes)?;
-            writer.write_all(&command_bytes)?;
-            writer.flush()?;
+        PlexEngine::with_config(dir.path().to_path_buf(), read_only_config()).unwrap();
 
-            new_index.insert(key.clone(), new_offset);
+        assert!(!dir.path().join("wal").exists());
+    }
+
+    #[test]
+    fn reopening_with_a_different_partition_count_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let config = PartitionConfig {
+            partition_count: 4,
+            ..PartitionConfig::default()
+        };
+        {
+            let mut engine = PlexEngine::with_config(dir.path().to_path_buf(), config.clone()).unwrap();
+            engine.set("key", "value").unwrap();
+            engine.shutdown().unwrap();
         }
 
-        compact_file.flush()?;
-        drop(compact_file);
+        let saved = PartitionConfig::load(dir.path()).unwrap().unwrap();
+        assert_eq!(saved.partition_count, 4);
+
+        let mismatched = PartitionConfig {
+            partition_count: 8,
+            ..PartitionConfig::default()
+        };
+        assert!(matches!(
+            PlexEngine::with_config(dir.path().to_path_buf(), mismatched),
+            Err(PlexError::Config(_))
+        ));
+
+        let engine = PlexEngine::with_config(dir.path().to_path_buf(), config).unwrap();
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("value"));
+    }
 
-        rename(&compact_path, &self.path)?;
+    #[test]
+    fn invalid_config_is_rejected_before_opening() {
+        let dir = TempDir::new().unwrap();
+        let no_partitions = PartitionConfig {
+            partition_count: 0,
+            ..PartitionConfig::default()
+        };
+        let bad_fp_rate = PartitionConfig {
+            bloom_filter_fp_rate: 1.0,
+            ..PartitionConfig::default()
+        };
 
-        self.data_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&self.path)?;
+        assert!(matches!(
+            PlexEngine::with_config(dir.path().to_path_buf(), no_partitions),
+            Err(PlexError::Config(_))
+        ));
+        assert!(matches!(
+            PlexEngine::with_config(dir.path().to_path_buf(), bad_fp_rate),
+            Err(PlexError::Config(_))
+        ));
+        assert!(!dir.path().join("config.json").exists());
+    }
 
-        self.index = new_index;
+    #[test]
+    fn namespaces_keep_separate_values_and_recover_from_the_shared_wal() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            let mut alpha = engine.open_namespace("alpha").unwrap();
+            let mut beta = engine.open_namespace("beta").unwrap();
+            engine.set("key", "default").unwrap();
+            alpha.set("key", "from alpha").unwrap();
+            beta.set("key", "from beta").unwrap();
 
-        Ok(())
+            assert_eq!(alpha.get("key").unwrap().as_deref(), Some("from alpha"));
+            assert_eq!(beta.get("key").unwrap().as_deref(), Some("from beta"));
+            assert_eq!(engine.get("key").unwrap().as_deref(), Some("default"));
+        }
+
+        // Only the WAL is left to bring the namespaces back.
+        std::fs::remove_dir_all(dir.path().join("ns_alpha")).unwrap();
+        std::fs::remove_dir_all(dir.path().join("ns_beta")).unwrap();
+
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        let alpha = engine.open_namespace("alpha").unwrap();
+        let beta = engine.open_namespace("beta").unwrap();
+        assert_eq!(alpha.get("key").unwrap().as_deref(), Some("from alpha"));
+        assert_eq!(beta.get("key").unwrap().as_deref(), Some("from beta"));
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn namespace_names_are_checked() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+
+        assert!(matches!(engine.open_namespace("../escape"), Err(PlexError::Config(_))));
+        assert!(matches!(engine.open_namespace(""), Err(PlexError::Config(_))));
     }
 
+    #[test]
+    fn non_utf8_bytes_round_trip_and_replay() {
+        let dir = TempDir::new().unwrap();
+        let key = [0xff, 0x00, 0xfe, b'k'];
+        let value = [0xc3, 0x28, 0x00, 0xa0, 0xa1];
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set_bytes(&key, &value).unwrap();
+            engine.set_bytes(b"text", &value).unwrap();
+
+            assert_eq!(engine.get_bytes(&key).unwrap().as_deref(), Some(value.as_slice()));
+            assert!(matches!(engine.get("text"), Err(PlexError::NotUtf8)));
+        }
+
+        // Only the WAL is left to bring the pairs back.
+        std::fs::remove_dir_all(dir.path().join("partitions")).unwrap();
+
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.get_bytes(&key).unwrap().as_deref(), Some(value.as_slice()));
+        assert!(engine.delete_bytes(&key).unwrap());
+        assert_eq!(engine.get_bytes(&key).unwrap(), None);
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &target);
+            } else {
+                std::fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn synced_writes_are_on_disk_without_the_wal() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        for i in 0..50 {
+            engine.set(&format!("key{}", i), &format!("value{}", i)).unwrap();
+        }
+        engine.sync().unwrap();
+
+        // Copy the files while the engine is still open, as a crash would leave
+        // them, and drop the WAL so nothing can be replayed.
+        let copy = TempDir::new().unwrap();
+        copy_dir(dir.path(), copy.path());
+        std::fs::remove_dir_all(copy.path().join("wal")).unwrap();
+
+        let reopened = PlexEngine::new(copy.path().to_path_buf()).unwrap();
+        for i in 0..50 {
+            assert_eq!(reopened.get(&format!("key{}", i)).unwrap(), Some(format!("value{}", i)));
+        }
+        drop(engine);
+    }
+
+    #[test]
+    fn unflushed_memtable_writes_are_replayed_from_the_wal() {
+        let dir = TempDir::new().unwrap();
+        let config = PartitionConfig {
+            memtable_size: Some(64 * 1024),
+            ..PartitionConfig::default()
+        };
+        {
+            let mut engine = PlexEngine::with_config(dir.path().to_path_buf(), config.clone()).unwrap();
+            for i in 0..20 {
+                engine.set(&format!("key-{}", i), "buffered").unwrap();
+            }
+        }
+
+        let engine = PlexEngine::with_config(dir.path().to_path_buf(), config).unwrap();
+        for i in 0..20 {
+            assert_eq!(engine.get(&format!("key-{}", i)).unwrap().as_deref(), Some("buffered"));
+        }
+    }
+
+    #[test]
+    fn builder_with_defaults_matches_new() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = PlexEngine::builder().data_dir(dir.path()).build().unwrap();
+
+        assert_eq!(engine.config().partition_count, PartitionConfig::default().partition_count);
+        assert_eq!(engine.config().compression, CompressionAlgorithm::None);
+        assert!(!engine.is_read_only());
+        engine.set("key", "value").unwrap();
+        assert_eq!(engine.get("key").unwrap().as_deref(), Some("value"));
+
+        assert!(matches!(PlexEngine::builder().build(), Err(PlexError::Config(_))));
+    }
+
+    #[test]
+    fn builder_honors_every_setting() {
+        let dir = TempDir::new().unwrap();
+        let wal_config = WALConfig {
+            max_entries_per_file: 2,
+            ..WALConfig::default()
+        };
+        let build = |read_only| {
+            PlexEngine::builder()
+                .data_dir(dir.path())
+                .partitions(4)
+                .compression(CompressionAlgorithm::Lz4)
+                .wal_config(wal_config.clone())
+                .cache_capacity(64)
+                .read_only(read_only)
+                .build()
+                .unwrap()
+        };
+
+        let mut engine = build(false);
+        assert_eq!(engine.config().partition_count, 4);
+        assert_eq!(engine.stats().unwrap().partition_count, 4);
+        assert_eq!(engine.config().compression, CompressionAlgorithm::Lz4);
+        assert_eq!(engine.config().block_cache_blocks, 64);
+        for i in 0..6 {
+            engine.set(&format!("key-{}", i), "value").unwrap();
+        }
+        engine.shutdown().unwrap();
+        assert!(std::fs::read_dir(dir.path().join("wal")).unwrap().count() >= 3);
+
+        let mut engine = build(true);
+        assert!(engine.is_read_only());
+        assert_eq!(engine.get("key-5").unwrap().as_deref(), Some("value"));
+        assert!(matches!(engine.set("key", "value"), Err(PlexError::Config(_))));
+    }
+
+    #[test]
+    fn cleared_store_stays_empty_after_reopening() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            for i in 0..50 {
+                engine.set(&format!("key-{}", i), "value").unwrap();
+            }
+            engine.flush().unwrap();
+
+            engine.clear().unwrap();
+            assert_eq!(engine.len().unwrap(), 0);
+            assert_eq!(engine.get("key-1").unwrap(), None);
+
+            engine.set("after", "clear").unwrap();
+        }
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.len().unwrap(), 1);
+        assert_eq!(engine.get("key-1").unwrap(), None);
+        assert_eq!(engine.get("after").unwrap().as_deref(), Some("clear"));
+    }
+
+    #[test]
+    fn a_batch_cut_off_before_its_end_marker_is_not_replayed() {
+        let dir = TempDir::new().unwrap();
+        let batch = |prefix: &str| -> Vec<(String, String)> {
+            (0..5).map(|i| (format!("{}-{}", prefix, i), "value".to_string())).collect()
+        };
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set_batch(&batch("first")).unwrap();
+            engine.set_batch(&batch("second")).unwrap();
+        }
+
+        // Cut the last WAL entry, the second batch's end marker, in half.
+        let wal_file = std::fs::read_dir(dir.path().join("wal"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .max()
+            .unwrap();
+        let len = std::fs::metadata(&wal_file).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&wal_file).unwrap().set_len(len - 4).unwrap();
+        std::fs::remove_dir_all(dir.path().join("partitions")).unwrap();
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        for i in 0..5 {
+            assert_eq!(engine.get(&format!("first-{}", i)).unwrap().as_deref(), Some("value"));
+            assert_eq!(engine.get(&format!("second-{}", i)).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn a_fresh_engine_is_healthy() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        engine.set("key", "value").unwrap();
+
+        let health = engine.health();
+        assert!(health.is_ready());
+        assert!(health.is_healthy());
+        assert!(health.wal_writable);
+        assert!(health.poisoned_partitions.is_empty());
+        assert_eq!(health.quarantined_segments, 0);
+    }
+
+    #[test]
+    fn a_poisoned_partition_makes_the_engine_unready() {
+        let dir = TempDir::new().unwrap();
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+
+        engine.partition_manager.poison_partition(1);
+
+        let health = engine.health();
+        assert!(!health.is_ready());
+        assert!(!health.is_healthy());
+        assert_eq!(health.poisoned_partitions, vec![1]);
+        assert!(health.wal_writable);
+    }
 }
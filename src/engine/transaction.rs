@@ -0,0 +1,166 @@
+use crate::engine::partition_manager::PartitionManager;
+use crate::error::PlexError;
+use crate::storage::wal::WALCommand;
+use std::collections::BTreeMap;
+
+/// A set of writes applied all-or-nothing, possibly across partitions.
+///
+/// Mutations are buffered in memory. `commit` logs them to the WAL between a
+/// `TxnBegin` and a `TxnCommit` marker before touching any partition, so
+/// recovery either replays the whole transaction or none of it. Dropping the
+/// transaction or calling `rollback` discards the buffer.
+pub struct Transaction<'a> {
+    manager: &'a mut PartitionManager,
+    writes: BTreeMap<String, Option<String>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(manager: &'a mut PartitionManager) -> Self {
+        Self {
+            manager,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError> {
+        if key.is_empty() || value.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+        self.manager.check_entry_size(key.as_bytes(), value.as_bytes())?;
+
+        self.writes.insert(key.to_string(), Some(value.to_string()));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<(), PlexError> {
+        if key.is_empty() {
+            return Err(PlexError::KeyIsEmpty);
+        }
+
+        self.writes.insert(key.to_string(), None);
+        Ok(())
+    }
+
+    /// Reads through the transaction's own writes before the store.
+    pub fn get(&self, key: &str) -> Result<Option<String>, PlexError> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.manager.get(key),
+        }
+    }
+
+    pub fn commit(self) -> Result<(), PlexError> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+
+        let begin_sequence = self.manager.log(WALCommand::TxnBegin)?;
+
+        for (key, value) in &self.writes {
+            let command = match value {
+                Some(value) => WALCommand::Set { key: key.clone(), value: value.clone() },
+                None => WALCommand::Delete { key: key.clone() },
+            };
+            self.manager.log(command)?;
+        }
+
+        self.manager.log(WALCommand::TxnCommit { begin_sequence })?;
+        self.manager.wal().sync()?;
+
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => self.manager.apply_set(key.as_bytes(), value.as_bytes(), None)?,
+                None => self.manager.apply_delete(key.as_bytes())?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::partition_manager::{
+        HashPartitioner, PartitionConfig, Partitioner, DEFAULT_PARTITION_COUNT,
+    };
+    use crate::engine::plex_engine::PlexEngine;
+    use crate::storage::storage_engine::StorageEngine;
+    use crate::storage::wal::{WALConfig, WAL};
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn open_manager(dir: &Path) -> PartitionManager {
+        let wal = Arc::new(WAL::new(dir.join("wal"), WALConfig::default()).unwrap());
+        let mut manager =
+            PartitionManager::new(dir.join("partitions"), PartitionConfig::default(), wal).unwrap();
+        manager.load_from_disk().unwrap();
+        manager
+    }
+
+    #[test]
+    fn commit_applies_writes_across_partitions() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+            engine.set("stale", "value").unwrap();
+
+            let mut txn = engine.begin();
+            txn.set("a", "1").unwrap();
+            txn.set("b", "2").unwrap();
+            txn.set("c", "3").unwrap();
+            txn.delete("stale").unwrap();
+            assert_eq!(txn.get("a").unwrap().as_deref(), Some("1"));
+            assert_eq!(txn.get("stale").unwrap(), None);
+            txn.commit().unwrap();
+
+            assert_eq!(engine.get("b").unwrap().as_deref(), Some("2"));
+        }
+
+        let partitioner = HashPartitioner::new(DEFAULT_PARTITION_COUNT);
+        let mut partitions: Vec<u32> =
+            ["a", "b", "c"].iter().map(|key| partitioner.partition_for_key(key.as_bytes())).collect();
+        partitions.dedup();
+        assert!(partitions.len() >= 2);
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(engine.get("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(engine.get("b").unwrap().as_deref(), Some("2"));
+        assert_eq!(engine.get("c").unwrap().as_deref(), Some("3"));
+        assert_eq!(engine.get("stale").unwrap(), None);
+    }
+
+    #[test]
+    fn rollback_persists_nothing() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open_manager(dir.path());
+
+        let mut txn = manager.begin();
+        txn.set("a", "1").unwrap();
+        txn.rollback();
+
+        assert_eq!(manager.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn transaction_cut_short_by_a_crash_is_not_recovered() {
+        let dir = TempDir::new().unwrap();
+        {
+            let manager = open_manager(dir.path());
+            let wal = manager.wal();
+            wal.append(WALCommand::TxnBegin).unwrap();
+            for key in ["a", "b", "c"] {
+                wal.append(WALCommand::Set { key: key.to_string(), value: "1".to_string() }).unwrap();
+            }
+            wal.sync().unwrap();
+        }
+
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        for key in ["a", "b", "c"] {
+            assert_eq!(engine.get(key).unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::cache::sync_lru_cache::SyncLruCache;
+use crate::engine::partition_manager::FileOffset;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A cached value and the offset it was read from.
+type CachedValue = (FileOffset, Vec<u8>);
+
+/// Values of recently read keys for one partition, so hot keys are served
+/// without going back to the file manager. Each value is cached with the
+/// offset it was read from and only served while the index still points
+/// there, so a read racing a write can never leave a stale value behind.
+///
+/// `get` is synchronous, so the cache sits behind a plain mutex. A poisoned
+/// cache misses every lookup and drops every insert.
+pub struct ValueCache {
+    cache: Mutex<SyncLruCache<Vec<u8>, CachedValue>>,
+    capacity: usize,
+}
+
+impl fmt::Debug for ValueCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueCache").field("capacity", &self.capacity).finish_non_exhaustive()
+    }
+}
+
+impl ValueCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: Mutex::new(SyncLruCache::new(capacity)),
+            capacity,
+        }
+    }
+
+    /// The value of `key` if it was cached from `offset`.
+    pub fn get(&self, key: &[u8], offset: &FileOffset) -> Option<Vec<u8>> {
+        let mut cache = self.cache.lock().ok()?;
+        let (cached_offset, value) = cache.get(&key.to_vec())?;
+        (cached_offset == offset).then(|| value.clone())
+    }
+
+    pub fn insert(&self, key: &[u8], offset: &FileOffset, value: &[u8]) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(key.to_vec(), (offset.clone(), value.to_vec()));
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.remove(&key.to_vec());
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear();
+        }
+    }
+}
@@ -1,4 +1,3 @@
-use bincode;
 use std::{fmt, io};
 
 #[derive(Debug)]
@@ -9,6 +8,20 @@ pub enum PlexError {
     /// The key provided was empty.
     KeyIsEmpty,
 
+    /// The key is longer than the configured maximum, in bytes.
+    KeyTooLarge {
+        size: usize, limit: usize
+    },
+
+    /// The value is longer than the configured maximum, in bytes.
+    ValueTooLarge {
+        size: usize, limit: usize
+    },
+
+    /// A key or value stored as raw bytes was read through the string API
+    /// but is not valid UTF-8.
+    NotUtf8,
+
     /// An I/O error occurred
     IO(io::Error),
 
@@ -22,19 +35,19 @@ pub enum PlexError {
     CorruptData(u64),
 
     /// Lock aquisition failed
-    LockError,
+    LockError(String),
 
     /// A configuration error occurred
-    Config,
+    Config(String),
 
     /// Compaction process failed
-    CompactionFailed,
+    CompactionFailed(String),
 
     /// A Write Ahead Log error occurred
-    WAL,
+    WAL(String),
 
     /// Recovery failed
-    Recovery,
+    Recovery(String),
 
     /// Failed to partition correctly
     Partition {
@@ -42,7 +55,13 @@ pub enum PlexError {
     },
 
     /// A bloom filter error occurred
-    BloomFilter,
+    BloomFilter(String),
+
+    /// Compressing or decompressing data failed
+    Compression(String),
+
+    /// Encrypting or decrypting data failed
+    Encryption(String),
 
     /// A mismatch in checking sum
     CheckSumMisMatch {
@@ -52,6 +71,12 @@ pub enum PlexError {
     /// An invalid file format error
     InvalidFormat,
 
+    /// The file was written by a newer version of the format than this
+    /// build can read.
+    UnsupportedVersion {
+        found: u32, supported: u32
+    },
+
     /// Operation taking too long
     TimeOut {
         operation: String,
@@ -66,26 +91,38 @@ impl fmt::Display for PlexError {
         match self {
             PlexError::KeyIsEmpty => write!(f, "The key is empty"),
             PlexError::KeyNotFound => write!(f, "Key not found"),
+            PlexError::KeyTooLarge { size, limit } => {
+                write!(f, "Key is {} bytes, over the limit of {}", size, limit)
+            }
+            PlexError::ValueTooLarge { size, limit } => {
+                write!(f, "Value is {} bytes, over the limit of {}", size, limit)
+            }
+            PlexError::NotUtf8 => write!(f, "Stored bytes are not valid UTF-8"),
             PlexError::IO(err) => write!(f, "I/O error: {}", err),
             PlexError::Deserialize(err) => write!(f, "Deserialization error: {}", err),
             PlexError::Serialize(err) => write!(f, "Serialization error: {}", err),
             PlexError::CorruptData(offset) => {
                 write!(f, "Corrupt data detected at file offset {}", offset)
             }
-            PlexError::LockError(resource) => write!(f, "Lock acquisition failed: {}" resource),
+            PlexError::LockError(resource) => write!(f, "Lock acquisition failed: {}", resource),
             PlexError::Config(err) => write!(f, "Configuration error: {}", err),
             PlexError::CompactionFailed(err) => write!(f, "Compaction failed: {}", err),
             PlexError::WAL(err) => write!(f, "WAL error: {}", err),
             PlexError::Recovery(err) => write!(f, "Recovery error: {}", err),
-            PlexError::Partition(id, message) => {
-                write!(f, "Partition error: {} {}" id, message)
+            PlexError::Partition { id, message } => {
+                write!(f, "Partition error: {} {}", id, message)
             },
-            PlexError::BloomFilter(err) => write!(f, "Bloom filter error: {}" err),
-            PlexError::CheckSumMisMatch(expected, actual) => {
-            write!(f, "Checksummismatch: expected {} actual {}", expected, actual)
+            PlexError::BloomFilter(err) => write!(f, "Bloom filter error: {}", err),
+            PlexError::Compression(err) => write!(f, "Compression error: {}", err),
+            PlexError::Encryption(err) => write!(f, "Encryption error: {}", err),
+            PlexError::CheckSumMisMatch { expected, actual } => {
+            write!(f, "Checksum mismatch: expected {} actual {}", expected, actual)
             },
-            PlexError::InvalidFormat => write!("Invalid file format"),
-            PlexError::TimeOut(operation, timeout_ms) => write!(f, "Timeout: {} took too long {}", operation, timeout_ms),
+            PlexError::InvalidFormat => write!(f, "Invalid file format"),
+            PlexError::UnsupportedVersion { found, supported } => {
+                write!(f, "Unsupported format version {}, this build reads up to {}; upgrade to open it", found, supported)
+            }
+            PlexError::TimeOut { operation, timeout_ms } => write!(f, "Timeout: {} took too long {}", operation, timeout_ms),
         }
     }
 }
@@ -95,18 +132,18 @@ impl std::error::Error for PlexError {
         match self {
             PlexError::IO(err) => Some(err),
             PlexError::Deserialize(err) => Some(err),
+            PlexError::Serialize(err) => Some(err),
             _ => None,
         }
     }
 }
 
+// bincode uses one error type for both directions, so `?` on a bincode call
+// reports a deserialization error. Serialization paths that care map to
+// `PlexError::Serialize` explicitly.
 impl From<bincode::Error> for PlexError {
     fn from(err: bincode::Error) -> PlexError {
-        PlexError::Deserialize(err.to_string())
-    }
-
-    fn from(err: bincode::Error) -> PlexError {
-        PlexError::Serialize(err.to_string())
+        PlexError::Deserialize(err)
     }
 }
 
@@ -132,13 +169,102 @@ pub enum ErrorSeverity {
 impl PlexError {
     pub fn severity(&self) -> ErrorSeverity {
         match self {
-
-
+            PlexError::KeyNotFound
+            | PlexError::KeyIsEmpty
+            | PlexError::KeyTooLarge { .. }
+            | PlexError::ValueTooLarge { .. }
+            | PlexError::NotUtf8
+            | PlexError::LockError(_)
+            | PlexError::TimeOut { .. } => ErrorSeverity::Low,
+
+            PlexError::Config(_)
+            | PlexError::CompactionFailed(_)
+            | PlexError::BloomFilter(_)
+            | PlexError::Compression(_)
+            | PlexError::Encryption(_)
+            | PlexError::Partition { .. } => ErrorSeverity::Medium,
+
+            PlexError::Deserialize(_)
+            | PlexError::Serialize(_)
+            | PlexError::CorruptData(_)
+            | PlexError::CheckSumMisMatch { .. }
+            | PlexError::InvalidFormat
+            | PlexError::UnsupportedVersion { .. } => ErrorSeverity::High,
+
+            PlexError::IO(_)
+            | PlexError::WAL(_)
+            | PlexError::Recovery(_) => ErrorSeverity::Critical,
         }
 
     }
 
     pub fn is_recoverable(&self) -> bool {
-        matches!(self.severity(), ErrorSeverity::Low | ErrorSeverity::Medium | ErrorSeverity::High || ErrorSeverity::Critical)
+        matches!(self.severity(), ErrorSeverity::Low | ErrorSeverity::Medium)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_payloads_render_in_messages() {
+        assert_eq!(
+            PlexError::LockError("partition index".to_string()).to_string(),
+            "Lock acquisition failed: partition index"
+        );
+        assert_eq!(PlexError::Config("bad".to_string()).to_string(), "Configuration error: bad");
+        assert_eq!(
+            PlexError::CompactionFailed("disk full".to_string()).to_string(),
+            "Compaction failed: disk full"
+        );
+        assert_eq!(PlexError::WAL("torn".to_string()).to_string(), "WAL error: torn");
+        assert_eq!(PlexError::Recovery("gap".to_string()).to_string(), "Recovery error: gap");
+        assert_eq!(
+            PlexError::BloomFilter("size".to_string()).to_string(),
+            "Bloom filter error: size"
+        );
+        assert_eq!(
+            PlexError::Compression("zstd".to_string()).to_string(),
+            "Compression error: zstd"
+        );
+    }
+
+    #[test]
+    fn struct_variants_render_their_fields() {
+        assert_eq!(
+            PlexError::Partition { id: 3, message: "full".to_string() }.to_string(),
+            "Partition error: 3 full"
+        );
+        assert_eq!(
+            PlexError::CheckSumMisMatch { expected: 1, actual: 2 }.to_string(),
+            "Checksum mismatch: expected 1 actual 2"
+        );
+        assert_eq!(
+            PlexError::TimeOut { operation: "sync".to_string(), timeout_ms: 50 }.to_string(),
+            "Timeout: sync took too long 50"
+        );
+        assert_eq!(PlexError::CorruptData(42).to_string(), "Corrupt data detected at file offset 42");
+        assert_eq!(PlexError::KeyNotFound.to_string(), "Key not found");
+        assert_eq!(PlexError::KeyIsEmpty.to_string(), "The key is empty");
+        assert_eq!(PlexError::InvalidFormat.to_string(), "Invalid file format");
+    }
+
+    #[test]
+    fn wrapped_errors_keep_their_source() {
+        let io = PlexError::from(io::Error::other("boom"));
+        assert_eq!(io.to_string(), "I/O error: boom");
+        assert!(std::error::Error::source(&io).is_some());
+
+        let bincode_error = bincode::deserialize::<u64>(&[]).unwrap_err();
+        assert!(matches!(PlexError::from(bincode_error), PlexError::Deserialize(_)));
+    }
+
+    #[test]
+    fn only_low_and_medium_severity_errors_are_recoverable() {
+        assert!(PlexError::LockError("x".to_string()).is_recoverable());
+        assert!(PlexError::Config("x".to_string()).is_recoverable());
+        assert!(!PlexError::InvalidFormat.is_recoverable());
+        assert!(!PlexError::WAL("x".to_string()).is_recoverable());
     }
 }
@@ -1,6 +1,13 @@
+pub mod cache;
 pub mod cli;
 pub mod engine;
 pub mod error;
+pub mod net;
+pub mod storage;
+pub mod utils;
 
 pub use cli::Command;
+pub use engine::memory_engine::MemoryEngine;
+pub use engine::plex_engine::{AsyncEngineConfig, AsyncPlexEngine, NamespaceHandle, PlexEngine, PlexEngineBuilder};
 pub use error::PlexError;
+pub use storage::storage_engine::{AsyncStorageEngine, StorageEngine};
@@ -1,16 +1,76 @@
 use plexdb::PlexError;
 use plexdb::StorageEngine;
+use plexdb::engine::partition_manager::{PartitionConfig, Partitioning};
 use plexdb::engine::plex_engine::PlexEngine;
-use plexdb::cli::{CliArgs, Command};
+use plexdb::utils::compression::CompressionAlgorithm;
+use plexdb::utils::encryption::{load_or_create_salt, EncryptionKey, ENCRYPTION_SALT_FILE};
+use plexdb::cli::{CliArgs, Command, PlexConfig};
 use clap::Parser;
 use anyhow::bail;
+use std::time::Duration;
+
+/// Holds the passphrase for encryption at rest. Read from the environment
+/// rather than a flag so it stays out of the process list.
+const ENCRYPTION_PASSPHRASE_ENV: &str = "PLEXDB_ENCRYPTION_PASSPHRASE";
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     let args = CliArgs::parse();
-    let path = args.data_dir.join("./data.log");
-    let mut store = PlexEngine::new(path)?;
+    // Settings not given on the command line come from the config file if
+    // there is one, and otherwise from the data directory, so reopening
+    // never silently changes the partition layout.
+    let mut plex_config = match &args.config {
+        Some(path) => PlexConfig::from_file(path)?,
+        None => PlexConfig {
+            partition: PartitionConfig::load(&args.data_dir)?.unwrap_or_default(),
+            ..PlexConfig::default()
+        },
+    };
+    let config = &mut plex_config.partition;
+    config.read_only = args.read_only;
+    if let Some(partitions) = args.partitions {
+        config.partition_count = partitions;
+    }
+    if let Some(fp_rate) = args.bloom_fp_rate {
+        config.bloom_filter_fp_rate = fp_rate;
+    }
+    if let Some(max_partition_size) = args.max_partition_size {
+        config.max_partition_size = max_partition_size;
+    }
+    if let Some(ttl_secs) = args.tombstone_ttl_secs {
+        config.tombstone_ttl = Some(Duration::from_secs(ttl_secs));
+    }
+    if args.compaction_interval_secs.is_some() || args.max_concurrent_compactions.is_some() {
+        let mut schedule = config.compaction_schedule.unwrap_or_default();
+        if let Some(interval_secs) = args.compaction_interval_secs {
+            schedule.interval = Duration::from_secs(interval_secs);
+        }
+        if let Some(max_concurrent) = args.max_concurrent_compactions {
+            schedule.max_concurrent = max_concurrent;
+        }
+        config.compaction_schedule = Some(schedule);
+    }
+    if args.no_verify_crc {
+        config.verify_crc = false;
+    }
+    if let Some(interval_secs) = args.expiry_sweep_interval_secs {
+        config.expiry_sweep_interval = Some(Duration::from_secs(interval_secs));
+    }
+    if let Some(compression) = args.compression {
+        config.compression = compression.parse::<CompressionAlgorithm>()?;
+        // A level saved for the old algorithm may not apply to the new one.
+        config.compression_level = None;
+    }
+    if let Some(level) = args.compression_level {
+        config.compression_level = Some(level);
+    }
+    if let Ok(passphrase) = std::env::var(ENCRYPTION_PASSPHRASE_ENV) {
+        let salt = load_or_create_salt(&args.data_dir.join(ENCRYPTION_SALT_FILE))?;
+        config.encryption_key = Some(EncryptionKey::from_passphrase(&passphrase, &salt));
+    }
+
+    let mut store = PlexEngine::with_plex_config(args.data_dir, plex_config)?;
 
     match args.command {
         Command::Set { key, value} => {
@@ -18,6 +78,30 @@ fn main() -> anyhow::Result<()> {
             println!("Set '{}' = '{}'", key, value);
         }
 
+        Command::SetEx { key, value, ttl_secs } => {
+            store.set_with_ttl(&key, &value, ttl_secs)?;
+            println!("Set '{}' = '{}' (expires in {}s)", key, value, ttl_secs);
+        }
+
+        Command::BatchSet { path } => {
+            let contents = std::fs::read_to_string(&path)?;
+            let mut pairs = Vec::new();
+
+            for (line_number, line) in contents.lines().enumerate() {
+                if line.is_empty() {
+                    continue;
+                }
+
+                match line.split_once('\t') {
+                    Some((key, value)) => pairs.push((key.to_string(), value.to_string())),
+                    None => bail!("Line {} is not a tab-separated key/value pair", line_number + 1),
+                }
+            }
+
+            store.set_batch(&pairs)?;
+            println!("Set {} keys", pairs.len());
+        }
+
         Command::Get { key } => {
             match store.get(&key)? {
                 Some(val) => println!("{}", val),
@@ -25,17 +109,171 @@ fn main() -> anyhow::Result<()> {
             }
         }
 
+        Command::MGet => {
+            let keys = std::io::stdin()
+                .lines()
+                .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .collect::<Result<Vec<String>, _>>()?;
+            let keys: Vec<&str> = keys.iter().map(|key| key.trim()).collect();
+
+            for (key, value) in keys.iter().zip(store.multi_get(&keys)?) {
+                match value {
+                    Some(value) => println!("{} = {}", key, value),
+                    None => println!("{} (not found)", key),
+                }
+            }
+        }
+
         Command::Delete { key } => {
-            store.delete(&key)?;
-            println!("Deleted '{}'", key);
-        
+            if store.delete(&key)? {
+                println!("Deleted '{}'", key);
+            } else {
+                println!("Key not present '{}'", key);
+            }
+        }
+
+        Command::DeleteRange { start, end } => {
+            let deleted = store.delete_range(&start, &end)?;
+            println!("Deleted {} keys in ['{}', '{}')", deleted, start, end);
+        }
+
+        Command::Exists { key } => {
+            let exists = store.exists(&key)?;
+            println!("{}", exists);
+
+            if !exists {
+                std::process::exit(1);
+            }
         }
 
-        Command::Compact => {
-            store.compact()?;
-            println!("Compaction complete.");
+        Command::Cas { key, expected, value } => {
+            if store.compare_and_swap(&key, expected.as_deref(), &value)? {
+                println!("Set '{}' = '{}'", key, value);
+            } else {
+                println!("'{}' did not match the expected value; unchanged", key);
+                std::process::exit(1);
+            }
+        }
+
+        Command::Swap { key, value } => {
+            match store.set_and_get(&key, &value)? {
+                Some(previous) => println!("{}", previous),
+                None => println!("'{}' was not set", key),
+            }
+        }
+
+        Command::Incr { key, by } => {
+            let total = store.merge(&key, by)?;
+            println!("{}", total);
+        }
+
+        Command::Compact { parallel } => {
+            let reports = store.compact_parallel(parallel)?;
+            let superseded: u64 = reports.iter().map(|report| report.superseded_versions).sum();
+            let reclaimed: u64 = reports.iter().map(|report| report.superseded_bytes).sum();
+            println!("Compaction complete; dropped {} superseded versions ({} bytes).", superseded, reclaimed);
+        }
+
+        Command::Repair => {
+            let dropped = store.repair()?;
+            println!("Repair complete; dropped {} corrupt entries.", dropped);
+        }
+
+        Command::Compactif => {
+            let compacted = store.compact_if_needed()?;
+            if compacted.is_empty() {
+                println!("No partition needs compaction.");
+            } else {
+                println!("Compacted partitions {:?}", compacted);
+            }
+        }
+
+        Command::CompactEstimate => {
+            for partition_id in 0..store.stats()?.partition_count {
+                let estimate = store.estimate_compaction(partition_id)?;
+                println!(
+                    "partition {:<4} size {:>12}  live {:>12}  tombstones {:>8}  reclaimable {:>12}",
+                    estimate.partition_id,
+                    estimate.disk_size,
+                    estimate.live_size,
+                    estimate.tombstone_count,
+                    estimate.reclaimable_bytes,
+                );
+            }
+        }
+
+        Command::Count => {
+            println!("{}", store.len()?);
+        }
+
+        Command::Stats => {
+            let stats = store.stats()?;
+            println!("{:<12} {}", "partitions", stats.partition_count);
+            println!("{:<12} {}", "keys", stats.total_keys);
+            println!("{:<12} {} bytes", "size", stats.total_size);
+            println!("{:<12} {}", "tombstones", stats.total_tombstones);
+            if let Some(rate) = stats.cache_hit_rate() {
+                println!("{:<12} {:.1}%", "cache hits", rate * 100.0);
+            }
+            println!();
+
+            println!("{:>9}  {:>12}  {:>12}  {:>12}  {:>7}", "partition", "current fp", "observed fp", "target fp", "healthy");
+            for (id, bloom_stats) in store.bloom_filter_stats() {
+                match bloom_stats {
+                    Some(bloom_stats) => println!(
+                        "{:>9}  {:>12.6}  {:>12}  {:>12.6}  {:>7}",
+                        id,
+                        bloom_stats.current_false_positive_rate,
+                        bloom_stats
+                            .observed_false_positive_rate
+                            .map_or_else(|| "-".to_string(), |rate| format!("{:.6}", rate)),
+                        bloom_stats.target_false_positive_rate,
+                        if bloom_stats.is_healthy() { "yes" } else { "no" },
+                    ),
+                    None => println!("{:>9}  {:>12}", id, "busy"),
+                }
+            }
+        }
+
+        Command::Rebalance => {
+            if store.config().partitioning == Partitioning::Modulo {
+                bail!(
+                    "Rebalance needs consistent-hash or weighted partitioning, and this store uses modulo, \
+                     which cannot change in place. To switch, export the keys, then import them into a new \
+                     --data-dir opened with a --config file that sets, under [partition], \
+                     `partitioning = {{ ConsistentHash = {{ virtual_nodes = 128 }} }}`"
+                );
+            }
+            let report = store.rebalance()?;
+            println!(
+                "Moved {} keys into {} new partition(s)",
+                report.keys_moved,
+                report.new_partitions.len()
+            );
+        }
+
+        Command::Clear { yes } => {
+            if !yes {
+                bail!("Clear deletes every key; pass --yes to confirm");
+            }
+            store.clear()?;
+            println!("Cleared the store.");
+        }
+
+        Command::Export { path } => {
+            let file = std::fs::File::create(&path)?;
+            let count = store.export_ndjson(file)?;
+            println!("Exported {} keys to {}", count, path.display());
+        }
+
+        Command::Import { path } => {
+            let file = std::fs::File::open(&path)?;
+            let count = store.import_ndjson(file)?;
+            println!("Imported {} keys from {}", count, path.display());
         }
     }
 
+    store.shutdown()?;
+
     Ok(())
 }
@@ -0,0 +1,211 @@
+use crate::engine::plex_engine::PlexEngine;
+use crate::error::PlexError;
+use crate::storage::storage_engine::StorageEngine;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+pub type SharedEngine = Arc<Mutex<PlexEngine>>;
+
+/// Routes:
+/// - `GET /kv/:key` returns the value, or 404 if the key is missing
+/// - `PUT /kv/:key` stores the request body as the value
+/// - `DELETE /kv/:key` removes the key
+/// - `GET /stats` returns partition statistics as plain text
+/// - `GET /healthz` returns the health status as plain text, with 503 if
+///   the store is not ready
+pub fn router(engine: SharedEngine) -> Router {
+    Router::new()
+        .route("/kv/:key", get(get_key).put(put_key).delete(delete_key))
+        .route("/stats", get(stats))
+        .route("/healthz", get(healthz))
+        .with_state(engine)
+}
+
+pub async fn serve(engine: SharedEngine, addr: SocketAddr) -> Result<(), PlexError> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(engine)).await?;
+    Ok(())
+}
+
+async fn get_key(State(engine): State<SharedEngine>, Path(key): Path<String>) -> Response {
+    match with_engine(engine, move |engine| engine.get(&key)).await {
+        Ok(Some(value)) => (StatusCode::OK, value).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn put_key(
+    State(engine): State<SharedEngine>,
+    Path(key): Path<String>,
+    value: String,
+) -> Response {
+    match with_engine(engine, move |engine| engine.set(&key, &value)).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn delete_key(State(engine): State<SharedEngine>, Path(key): Path<String>) -> Response {
+    match with_engine(engine, move |engine| engine.delete(&key)).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn stats(State(engine): State<SharedEngine>) -> Response {
+    match with_engine(engine, |engine| engine.stats()).await {
+        Ok(stats) => {
+            let body = format!(
+                "partitions: {}\nkeys: {}\nsize: {}\ntombstones: {}\n",
+                stats.partition_count, stats.total_keys, stats.total_size, stats.total_tombstones,
+            );
+            (StatusCode::OK, body).into_response()
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+async fn healthz(State(engine): State<SharedEngine>) -> Response {
+    match with_engine(engine, |engine| Ok(engine.health())).await {
+        Ok(health) => {
+            let status = if health.is_ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            let body = format!(
+                "ready: {}\nhealthy: {}\nwal writable: {}\npoisoned partitions: {:?}\nquarantined segments: {}\nunhealthy bloom filters: {:?}\n",
+                health.is_ready(),
+                health.is_healthy(),
+                health.wal_writable,
+                health.poisoned_partitions,
+                health.quarantined_segments,
+                health.unhealthy_bloom_filters,
+            );
+            (status, body).into_response()
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+// Engine calls block on file IO, so they run on Tokio's blocking pool.
+async fn with_engine<T, F>(engine: SharedEngine, op: F) -> Result<T, PlexError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut PlexEngine) -> Result<T, PlexError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut engine = engine.lock().map_err(|_| PlexError::LockError("engine".to_string()))?;
+        op(&mut engine)
+    })
+    .await
+    .map_err(|e| PlexError::IO(std::io::Error::other(e)))?
+}
+
+fn error_response(err: PlexError) -> Response {
+    let status = match err {
+        PlexError::KeyNotFound => StatusCode::NOT_FOUND,
+        PlexError::KeyIsEmpty => StatusCode::BAD_REQUEST,
+        PlexError::KeyTooLarge { .. } | PlexError::ValueTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        PlexError::NotUtf8 => StatusCode::UNPROCESSABLE_ENTITY,
+        PlexError::LockError(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    (status, err.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    fn test_router(dir: &TempDir) -> Router {
+        let engine = PlexEngine::new(dir.path().to_path_buf()).unwrap();
+        router(Arc::new(Mutex::new(engine)))
+    }
+
+    #[tokio::test]
+    async fn put_then_get_round_trips_a_value() {
+        let dir = TempDir::new().unwrap();
+        let app = test_router(&dir);
+
+        let (status, _) = send(&app, "PUT", "/kv/greeting", "hello").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send(&app, "GET", "/kv/greeting", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hello");
+    }
+
+    #[tokio::test]
+    async fn missing_and_deleted_keys_are_not_found() {
+        let dir = TempDir::new().unwrap();
+        let app = test_router(&dir);
+
+        let (status, _) = send(&app, "GET", "/kv/missing", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        send(&app, "PUT", "/kv/key", "value").await;
+        let (status, _) = send(&app, "DELETE", "/kv/key", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _) = send(&app, "GET", "/kv/key", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn stats_reports_key_count() {
+        let dir = TempDir::new().unwrap();
+        let app = test_router(&dir);
+
+        send(&app, "PUT", "/kv/a", "1").await;
+        send(&app, "PUT", "/kv/b", "2").await;
+
+        let (status, body) = send(&app, "GET", "/stats", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("keys: 2"), "{}", body);
+    }
+
+    #[test]
+    fn errors_map_to_status_codes() {
+        assert_eq!(error_response(PlexError::KeyNotFound).status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            error_response(PlexError::LockError("engine".to_string())).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            error_response(PlexError::InvalidFormat).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_a_ready_store() {
+        let dir = TempDir::new().unwrap();
+        let app = test_router(&dir);
+
+        let (status, body) = send(&app, "GET", "/healthz", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("ready: true"), "{}", body);
+        assert!(body.contains("poisoned partitions: []"), "{}", body);
+    }
+}
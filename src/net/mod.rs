@@ -0,0 +1,4 @@
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "resp")]
+pub mod resp;
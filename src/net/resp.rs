@@ -0,0 +1,255 @@
+use crate::engine::partition_manager::PartitionManager;
+use crate::error::PlexError;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+pub type SharedPartitions = Arc<Mutex<PartitionManager>>;
+
+/// Longest bulk string a client may send, Redis' default
+/// `proto-max-bulk-len`. Lengths are checked before anything is allocated.
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+/// Most arguments a client may send in one command.
+const MAX_ARGUMENTS: usize = 1024 * 1024;
+/// Arguments are allocated for as they arrive past this many, so a large
+/// announced count costs nothing until the arguments are actually sent.
+const PREALLOCATED_ARGUMENTS: usize = 16;
+
+/// Accepts RESP2 clients on `addr` and serves `PING`, `GET`, `SET` and `DEL`
+/// from the partition manager, so tools like `redis-cli` can talk to plexdb.
+pub async fn serve(manager: SharedPartitions, addr: SocketAddr) -> Result<(), PlexError> {
+    let listener = TcpListener::bind(addr).await?;
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let manager = Arc::clone(&manager);
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(socket, manager).await {
+                warn!("RESP connection from {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(socket: TcpStream, manager: SharedPartitions) -> Result<(), PlexError> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let args = match read_command(&mut reader).await {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                writer.write_all(b"-ERR Protocol error\r\n").await?;
+                return Err(e);
+            }
+        };
+
+        debug!("RESP command: {:?}", args.first());
+
+        let manager = Arc::clone(&manager);
+        let reply = tokio::task::spawn_blocking(move || match manager.lock() {
+            Ok(mut manager) => execute(&mut manager, &args),
+            Err(_) => error_reply(&PlexError::LockError("partition manager".to_string())),
+        })
+        .await
+        .map_err(|e| PlexError::IO(std::io::Error::other(e)))?;
+
+        writer.write_all(&reply).await?;
+    }
+}
+
+/// Reads one command sent as a RESP array of bulk strings. Returns `None`
+/// when the client closes the connection between commands.
+async fn read_command<R>(reader: &mut R) -> Result<Option<Vec<String>>, PlexError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let Some(header) = read_line(reader).await? else {
+        return Ok(None);
+    };
+
+    let count = parse_length(&header, '*', MAX_ARGUMENTS)?;
+    let mut args = Vec::with_capacity(count.min(PREALLOCATED_ARGUMENTS));
+
+    for _ in 0..count {
+        let line = read_line(reader).await?.ok_or(PlexError::InvalidFormat)?;
+        let length = parse_length(&line, '$', MAX_BULK_LENGTH)?;
+
+        let mut data = vec![0u8; length.checked_add(2).ok_or(PlexError::InvalidFormat)?];
+        reader.read_exact(&mut data).await?;
+        if !data.ends_with(b"\r\n") {
+            return Err(PlexError::InvalidFormat);
+        }
+        data.truncate(length);
+
+        args.push(String::from_utf8(data).map_err(|_| PlexError::InvalidFormat)?);
+    }
+
+    Ok(Some(args))
+}
+
+async fn read_line<R>(reader: &mut R) -> Result<Option<String>, PlexError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+
+    match line.strip_suffix("\r\n") {
+        Some(line) => Ok(Some(line.to_string())),
+        None => Err(PlexError::InvalidFormat),
+    }
+}
+
+/// Parses a `*<count>` or `$<length>` line, rejecting values above `max`.
+fn parse_length(line: &str, prefix: char, max: usize) -> Result<usize, PlexError> {
+    line.strip_prefix(prefix)
+        .and_then(|length| length.parse().ok())
+        .filter(|&length| length <= max)
+        .ok_or(PlexError::InvalidFormat)
+}
+
+fn execute(manager: &mut PartitionManager, args: &[String]) -> Vec<u8> {
+    let Some(name) = args.first().map(|name| name.to_ascii_uppercase()) else {
+        return b"-ERR empty command\r\n".to_vec();
+    };
+
+    match (name.as_str(), args.len()) {
+        ("PING", 1) => simple_reply("PONG"),
+        ("PING", 2) => bulk_reply(Some(&args[1])),
+        ("GET", 2) => match manager.get(&args[1]) {
+            Ok(value) => bulk_reply(value.as_deref()),
+            Err(e) => error_reply(&e),
+        },
+        ("SET", 3) => match manager.set(&args[1], &args[2]) {
+            Ok(()) => simple_reply("OK"),
+            Err(e) => error_reply(&e),
+        },
+        ("DEL", count) if count > 1 => {
+            let mut deleted = 0;
+            for key in &args[1..] {
+                match manager.delete(key) {
+                    Ok(true) => deleted += 1,
+                    Ok(false) => {}
+                    Err(e) => return error_reply(&e),
+                }
+            }
+            format!(":{}\r\n", deleted).into_bytes()
+        }
+        ("PING" | "GET" | "SET" | "DEL", _) => {
+            format!("-ERR wrong number of arguments for '{}' command\r\n", name.to_lowercase())
+                .into_bytes()
+        }
+        _ => format!("-ERR unknown command '{}'\r\n", args[0]).into_bytes(),
+    }
+}
+
+fn simple_reply(message: &str) -> Vec<u8> {
+    format!("+{}\r\n", message).into_bytes()
+}
+
+fn bulk_reply(value: Option<&str>) -> Vec<u8> {
+    match value {
+        Some(value) => format!("${}\r\n{}\r\n", value.len(), value).into_bytes(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn error_reply(err: &PlexError) -> Vec<u8> {
+    // RESP errors are a single line.
+    format!("-ERR {}\r\n", err.to_string().replace(['\r', '\n'], " ")).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::partition_manager::PartitionConfig;
+    use crate::storage::wal::{WALConfig, WAL};
+    use tempfile::TempDir;
+
+    async fn start_server(dir: &TempDir) -> (TcpStream, tokio::task::JoinHandle<Result<(), PlexError>>) {
+        let wal = Arc::new(WAL::new(dir.path().join("wal"), WALConfig::default()).unwrap());
+        let mut manager =
+            PartitionManager::new(dir.path().join("partitions"), PartitionConfig::default(), wal)
+                .unwrap();
+        manager.load_from_disk().unwrap();
+        let manager = Arc::new(Mutex::new(manager));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            handle_connection(socket, manager).await
+        });
+
+        (TcpStream::connect(addr).await.unwrap(), server)
+    }
+
+    async fn connect(dir: &TempDir) -> TcpStream {
+        start_server(dir).await.0
+    }
+
+    async fn round_trip(client: &mut TcpStream, request: &[u8], expected: &[u8]) {
+        client.write_all(request).await.unwrap();
+        let mut reply = vec![0u8; expected.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&reply), String::from_utf8_lossy(expected));
+    }
+
+    #[tokio::test]
+    async fn set_then_get_speaks_resp() {
+        let dir = TempDir::new().unwrap();
+        let mut client = connect(&dir).await;
+
+        round_trip(&mut client, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n", b"+OK\r\n").await;
+        round_trip(&mut client, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n", b"$5\r\nvalue\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn missing_key_is_a_null_bulk_string() {
+        let dir = TempDir::new().unwrap();
+        let mut client = connect(&dir).await;
+
+        round_trip(&mut client, b"*2\r\n$3\r\nGET\r\n$7\r\nmissing\r\n", b"$-1\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn ping_and_del_reply_like_redis() {
+        let dir = TempDir::new().unwrap();
+        let mut client = connect(&dir).await;
+
+        round_trip(&mut client, b"*1\r\n$4\r\nping\r\n", b"+PONG\r\n").await;
+        round_trip(&mut client, b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n", b"+OK\r\n").await;
+        round_trip(&mut client, b"*2\r\n$3\r\nDEL\r\n$1\r\na\r\n", b":1\r\n").await;
+        round_trip(&mut client, b"*2\r\n$3\r\nGET\r\n$1\r\na\r\n", b"$-1\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_are_errors() {
+        let dir = TempDir::new().unwrap();
+        let mut client = connect(&dir).await;
+
+        round_trip(&mut client, b"*1\r\n$4\r\nINCR\r\n", b"-ERR unknown command 'INCR'\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn oversized_lengths_are_refused_before_allocating() {
+        for request in [
+            b"*2\r\n$3\r\nGET\r\n$1099511627776\r\n".as_slice(),
+            b"*2\r\n$3\r\nGET\r\n$18446744073709551615\r\n".as_slice(),
+            b"*4294967296\r\n".as_slice(),
+        ] {
+            let dir = TempDir::new().unwrap();
+            let (mut client, server) = start_server(&dir).await;
+
+            round_trip(&mut client, request, b"-ERR Protocol error\r\n").await;
+            assert!(matches!(server.await.unwrap(), Err(PlexError::InvalidFormat)));
+        }
+    }
+}
@@ -1,19 +1,77 @@
+use crate::cache::block_cache::{Block, SyncBlockCache};
+use crate::cache::bloom_filter::BloomFilter;
 use crate::error::PlexError;
 use crate::engine::partition_manager::FileOffset;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::fs::{create_dir_all, read_dir};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use crc32fast::Hasher;
+use crate::utils::compression::{frame_dictionary_id, Compressor, DictionaryCompressor};
+use crate::utils::encryption::{seal, unseal, Encryptor};
+use crate::utils::hash::{HashFamily, KeyBytes};
 use crate::utils::time;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, warn};
+
+const HEADER_SIZE: usize = 24;
+const TOMBSTONE_FLAG: u32 = 0x8000_0000;
+const COMPRESSED_FLAG: u32 = 0x4000_0000;
+/// The entry was encrypted after any compression, bound to its partition,
+/// segment and offset. Its nonce follows the fixed header, ahead of the
+/// ciphertext, and is counted in `data_length` and the CRC like the rest of
+/// the entry.
+const ENCRYPTED_FLAG: u32 = 0x2000_0000;
+/// The entry was compressed with one of the partition's trained zstd
+/// dictionaries rather than its compressor. The frame names the dictionary.
+const DICTIONARY_FLAG: u32 = 0x1000_0000;
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+pub const VALUE_LOG_PREFIX: &str = "vlog_";
+const VALUE_CRC_SIZE: usize = 4;
+const SEGMENT_PREFIX: &str = "data_";
+const SEGMENT_SUFFIX: &str = ".log";
+const QUARANTINE_DIR: &str = "quarantine";
+/// Appended to a segment's file name for the file holding its bloom filter.
+const SEGMENT_FILTER_SUFFIX: &str = ".bloom";
+/// The dictionary new entries are compressed with.
+pub const DICTIONARY_FILE: &str = "dict.bin";
+/// Dictionaries replaced by a newer one are kept as `dict.<id>.bin` while
+/// entries compressed with them may remain.
+const DICTIONARY_PREFIX: &str = "dict.";
+const DICTIONARY_SUFFIX: &str = ".bin";
+/// Blocks are cached under `segment id << SEGMENT_ADDRESS_BITS | offset`,
+/// which leaves each segment 1 TiB of address space.
+const SEGMENT_ADDRESS_BITS: u32 = 40;
+
+/// A key, where its entry was read from, and whether it is a tombstone.
+pub type ScannedEntry = (Vec<u8>, FileOffset, bool);
+
+/// File name of segment `file_id`, e.g. `data_000042.log`.
+pub fn segment_file_name(file_id: u32) -> String {
+    format!("{}{:06}{}", SEGMENT_PREFIX, file_id, SEGMENT_SUFFIX)
+}
 
-const HEADER_SIZE: usize = 20;
-const TOMBSTONE_FLAG: u32 = 0x800000000;
+/// Whether `file_name` holds one of a partition's compression dictionaries.
+pub fn is_dictionary_file(file_name: &str) -> bool {
+    file_name.starts_with(DICTIONARY_PREFIX) && file_name.ends_with(DICTIONARY_SUFFIX)
+}
 
+/// Inverse of `segment_file_name`; `None` for anything that is not a segment.
+pub fn parse_segment_id(file_name: &str) -> Option<u32> {
+    file_name
+        .strip_prefix(SEGMENT_PREFIX)?
+        .strip_suffix(SEGMENT_SUFFIX)?
+        .parse()
+        .ok()
+}
 
-#[derive(Debug, Clone, Serilize, Deserialize)];
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntryHeader {
     pub data_length: u64,
     pub crc: u32,
@@ -22,252 +80,2240 @@ pub struct EntryHeader {
 
 }
 
+impl EntryHeader {
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[0..8].copy_from_slice(&self.data_length.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.flags & TOMBSTONE_FLAG != 0
+    }
+
+    /// Size of the whole entry on disk, header included.
+    pub fn entry_size(&self) -> u64 {
+        HEADER_SIZE as u64 + self.data_length
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Self {
+        Self {
+            data_length: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            crc: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+            flags: u32::from_le_bytes(bytes[20..24].try_into().unwrap()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
-    pub key: String,
-    pub value: Option<String>,
+    // Bincode writes `Vec<u8>` exactly like `String`, so entries written
+    // before keys and values became bytes still decode.
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
     pub timestamp: u64,
+    pub expires_at: Option<u64>,
+    /// Set instead of `value` when the value was moved to the value log.
+    pub value_pointer: Option<ValuePointer>,
+}
+
+/// Location of a value stored in a value log file rather than inline.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ValuePointer {
+    pub file_id: u32,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// When the active segment is fsync'd. Every mode but `Sync` acknowledges
+/// writes that a crash can still drop from the data files; they are
+/// recovered from the WAL as long as the WAL itself is synced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Durability {
+    /// fsync after every write.
+    #[default]
+    Sync,
+    /// Never fsync on the write path; only `flush` and rotation sync.
+    Async,
+    /// fsync once every `n` writes.
+    EveryN(usize),
+    /// fsync from a background thread once per interval.
+    Interval(Duration),
+}
+
+/// Background thread that fsyncs the active segment on a fixed interval.
+/// It holds its own handle to the segment, swapped out on rotation.
+struct Flusher {
+    file: Arc<Mutex<Option<File>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Flusher {
+    fn spawn(interval: Duration, file: Option<File>) -> Self {
+        let file = Arc::new(Mutex::new(file));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let file = Arc::clone(&file);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    thread::park_timeout(interval);
+
+                    if let Ok(file) = file.lock()
+                        && let Some(file) = file.as_ref()
+                        && let Err(e) = file.sync_all()
+                    {
+                        warn!("Background fsync failed: {}", e);
+                    }
+                }
+            })
+        };
+
+        Self {
+            file,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    fn set_file(&self, file: Option<File>) -> Result<(), PlexError> {
+        let mut current = self.file.lock().map_err(|_| PlexError::LockError("flusher file".to_string()))?;
+        *current = file;
+        Ok(())
+    }
+}
 
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+/// What a scan of one segment found.
+#[derive(Default)]
+struct SegmentScan {
+    entries: Vec<ScannedEntry>,
+    crc_failures: u64,
+    /// Offset of a header that is cut short or whose length runs past the
+    /// end of the segment. Nothing after it can be read.
+    damaged_at: Option<u64>,
 }
 
-#[derive(Debug)]
 pub struct FileManager {
     data_dir: PathBuf,
     active_file: Option<File>,
     active_file_id: u32,
     file_offsets: HashMap<u32, u64>,
+    compressor: Option<Box<dyn Compressor>>,
+    max_file_size: u64,
+    durability: Durability,
+    unsynced_writes: usize,
+    flusher: Option<Flusher>,
+    value_log_threshold: Option<usize>,
+    value_log: Option<File>,
+    value_log_id: u32,
+    value_log_size: u64,
+    read_only: bool,
+    block_cache: Option<Arc<SyncBlockCache>>,
+    verify_crc: bool,
+    encryptor: Option<Box<dyn Encryptor>>,
+    /// Partition the entries are bound to when encrypted.
+    partition_id: u32,
+    /// Bloom filter of each sealed segment's keys and the false positive
+    /// rate they are built for, when per-segment filters are on.
+    segment_filters: Option<(HashMap<u32, BloomFilter>, f64)>,
+    /// Every dictionary an entry on disk may have been compressed with, by
+    /// zstd dictionary ID, and the ID of the one new entries use.
+    dictionaries: HashMap<u32, DictionaryCompressor>,
+    dictionary_id: Option<u32>,
+    /// How many times the data files have been synced since opening.
+    syncs: u64,
+}
 
+impl fmt::Debug for FileManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileManager")
+            .field("data_dir", &self.data_dir)
+            .field("active_file_id", &self.active_file_id)
+            .field("compressed", &self.compressor.is_some())
+            .field("max_file_size", &self.max_file_size)
+            .field("durability", &self.durability)
+            .field("value_log_threshold", &self.value_log_threshold)
+            .field("read_only", &self.read_only)
+            .field("block_cache", &self.block_cache.is_some())
+            .field("verify_crc", &self.verify_crc)
+            .field("encrypted", &self.encryptor.is_some())
+            .field("segment_filters", &self.segment_filters.is_some())
+            .field("dictionary_id", &self.dictionary_id)
+            .finish_non_exhaustive()
+    }
 }
 
 impl FileManager {
     pub fn new(data_dir: PathBuf) -> Result<Self, PlexError> {
-        create_dir_all(&data_dir)?;
+        Self::open(data_dir, None, false)
+    }
+
+    /// Opens a file manager that compresses every entry it writes with
+    /// `compressor`. Entries written without compression stay readable.
+    pub fn with_compressor(data_dir: PathBuf, compressor: Box<dyn Compressor>) -> Result<Self, PlexError> {
+        Self::open(data_dir, Some(compressor), false)
+    }
+
+    /// Opens existing segments for reading only. No file is created or
+    /// opened for writing, and every write or compaction returns an error,
+    /// so several processes can share the directory.
+    pub fn open_read_only(data_dir: PathBuf, compressor: Option<Box<dyn Compressor>>) -> Result<Self, PlexError> {
+        Self::open(data_dir, compressor, true)
+    }
+
+    fn open(data_dir: PathBuf, compressor: Option<Box<dyn Compressor>>, read_only: bool) -> Result<Self, PlexError> {
+        if !read_only {
+            create_dir_all(&data_dir)?;
+        }
 
         let mut manager = Self {
             data_dir,
             active_file: None,
             active_file_id: 0,
             file_offsets: HashMap::new(),
+            compressor,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            durability: Durability::Sync,
+            unsynced_writes: 0,
+            flusher: None,
+            value_log_threshold: None,
+            value_log: None,
+            value_log_id: 0,
+            value_log_size: 0,
+            read_only,
+            block_cache: None,
+            verify_crc: true,
+            encryptor: None,
+            partition_id: 0,
+            segment_filters: None,
+            dictionaries: HashMap::new(),
+            dictionary_id: None,
+            syncs: 0,
         };
 
         manager.initialize_active_file()?;
         Ok(manager)
     }
 
-    fn initialize_active_file(&mut self) -> Result<(), PlexError> {
-        let mut max_file_id = 0;
+    /// Serves entry reads from `block_cache`, loading whole blocks on a miss.
+    /// Blocks are keyed by segment id and offset only, so a cache must not be
+    /// shared with another file manager.
+    pub fn set_block_cache(&mut self, block_cache: Option<Arc<SyncBlockCache>>) {
+        self.block_cache = block_cache;
+    }
 
-        if let Ok(entries) = read_dir(&self.data_dir) {
-            for entry in entries.flatten() {
-                if let Some(file_name) = entry.file_name().to_str() {
-                    if file_name.starts_with("data") && file_name.ends_with(".log") {
-                        if let Ok(id) = file_name[5..file_name.len()-4].parse::<u32>() {
-                            max_file_id = max_file_id.max(id);
-                        }
-                    }
-                }
+    /// Whether reads of single entries check their CRC. Turning it off saves
+    /// a checksum per read on trusted storage. Segment scans, such as
+    /// recovery and `repair`, always check.
+    pub fn set_verify_crc(&mut self, verify_crc: bool) {
+        self.verify_crc = verify_crc;
+    }
+
+    /// Loads the dictionaries saved in the directory. New entries are
+    /// compressed at `level` with the one in `DICTIONARY_FILE`, if the file
+    /// manager has a compressor; the retired ones are only read with.
+    pub fn load_dictionaries(&mut self, level: i32) -> Result<(), PlexError> {
+        for path in self.dictionary_files()? {
+            let dictionary = DictionaryCompressor::new(std::fs::read(&path)?, level);
+            let id = dictionary.id().ok_or(PlexError::InvalidFormat)?;
+            if path.file_name() == Some(std::ffi::OsStr::new(DICTIONARY_FILE)) {
+                self.dictionary_id = Some(id);
             }
+            self.dictionaries.insert(id, dictionary);
         }
+        Ok(())
+    }
 
-        self.active_file_id = max_file_id;
-        let file_path = self.data_dir.join(format!("data_{:06}.log", self.active_file_id));
+    /// Makes `dictionary` the one new entries are compressed with and saves
+    /// it as `DICTIONARY_FILE`. The previous one is kept, under its ID, until
+    /// `compact` has rewritten every entry compressed with it.
+    pub fn install_dictionary(&mut self, dictionary: Vec<u8>, level: i32) -> Result<(), PlexError> {
+        self.check_writable()?;
+        let dictionary = DictionaryCompressor::new(dictionary, level);
+        let id = dictionary.id().ok_or(PlexError::InvalidFormat)?;
+
+        if let Some(previous) = self.dictionary_id.filter(|&previous| previous != id) {
+            let retired = self.data_dir.join(format!("{}{}{}", DICTIONARY_PREFIX, previous, DICTIONARY_SUFFIX));
+            std::fs::write(retired, self.dictionaries[&previous].dictionary())?;
+        }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(file_path)?;
+        let tmp_path = self.data_dir.join(format!("{}.tmp", DICTIONARY_FILE));
+        std::fs::write(&tmp_path, dictionary.dictionary())?;
+        std::fs::rename(tmp_path, self.data_dir.join(DICTIONARY_FILE))?;
 
+        self.dictionaries.insert(id, dictionary);
+        self.dictionary_id = Some(id);
+        Ok(())
+    }
 
-        let current_size = file.metadata()?.len();
-        self.file_offsets.insert(self.active_file_id, current_size);
+    /// The dictionary new entries are compressed with.
+    pub fn dictionary(&self) -> Option<&[u8]> {
+        self.dictionary_id.map(|id| self.dictionaries[&id].dictionary())
+    }
 
-        self.active_file = Some(file);
+    /// Drops every dictionary but the current one, once no entry can still
+    /// be compressed with them.
+    fn drop_retired_dictionaries(&mut self) -> Result<(), PlexError> {
+        for path in self.dictionary_files()? {
+            if path.file_name() != Some(std::ffi::OsStr::new(DICTIONARY_FILE)) {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let current = self.dictionary_id;
+        self.dictionaries.retain(|&id, _| Some(id) == current);
         Ok(())
     }
 
-    pub fn write_entry(&mut self, key: &str, value: &str) -> Result<FileOffset, PlexError> {
-        let entry = LogEntry {
-            key: key.to_string(),
-            value: Some(value.to_string()),
-            timestamp: time::current_timestamp(), 
+    fn dictionary_files(&self) -> Result<Vec<PathBuf>, PlexError> {
+        let mut paths = Vec::new();
+        let Ok(entries) = read_dir(&self.data_dir) else {
+            return Ok(paths);
         };
 
-        self.write_log_entry(&entry, false)
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name.to_str().is_some_and(is_dictionary_file) {
+                paths.push(entry.path());
+            }
+        }
+        Ok(paths)
     }
 
-    pub fn write_tombstone(&mut self, key: &str) -> Result<FileOffset, PlexError> {
-        let entry = LogEntry {
-            key: key.to_string(),
-            value: None,
-            timestamp: time::current_timestamp(),
+    /// Encrypts every entry written from now on with `encryptor`, which is
+    /// also needed to read them back. Each entry is bound to `partition_id`
+    /// and to the segment and offset it is written at, so it cannot be read
+    /// back from anywhere else. Entries written without encryption stay
+    /// readable. While it is set, values stay inline rather than going to
+    /// the value log, which is never encrypted; compaction moves values
+    /// already there back inline.
+    pub fn set_encryptor(&mut self, partition_id: u32, encryptor: Option<Box<dyn Encryptor>>) {
+        self.partition_id = partition_id;
+        self.encryptor = encryptor;
+    }
+
+    /// Associated data an encrypted entry is sealed with.
+    fn entry_aad(&self, file_id: u32, offset: u64) -> [u8; 16] {
+        let mut aad = [0u8; 16];
+        aad[..4].copy_from_slice(&self.partition_id.to_le_bytes());
+        aad[4..8].copy_from_slice(&file_id.to_le_bytes());
+        aad[8..].copy_from_slice(&offset.to_le_bytes());
+        aad
+    }
+
+    /// Keeps a bloom filter of the keys of every sealed segment, each saved
+    /// next to its segment, at `fp_rate` false positives. Filters missing
+    /// from disk are rebuilt by scanning their segment. `None` drops them.
+    /// The active segment never has one, so it may hold any key.
+    pub fn set_segment_filters(&mut self, fp_rate: Option<f64>) -> Result<(), PlexError> {
+        let Some(fp_rate) = fp_rate else {
+            self.segment_filters = None;
+            return Ok(());
         };
 
-        self.write_log_entry(&entry, true)
+        self.segment_filters = Some((HashMap::new(), fp_rate));
+        for (file_id, _) in self.sealed_segment_sizes() {
+            match BloomFilter::load_from_file(self.segment_filter_path(file_id)) {
+                Ok(filter) => {
+                    if let Some((filters, _)) = self.segment_filters.as_mut() {
+                        filters.insert(file_id, filter);
+                    }
+                }
+                Err(_) => self.build_segment_filter(file_id)?,
+            }
+        }
+        Ok(())
     }
 
-    fn write_log_entry(&mut self, entry: &LogEntry, is_tombstone: bool) -> Result<FileOffset, PlexError> {
-        let serialized = bincode::serialize(entry)?;
+    /// False if segment `file_id` certainly holds no entry for `key`, live
+    /// or tombstone. Always true without a filter for the segment.
+    pub fn segment_may_contain(&self, file_id: u32, key: &[u8]) -> bool {
+        self.segment_filters
+            .as_ref()
+            .and_then(|(filters, _)| filters.get(&file_id))
+            .is_none_or(|filter| filter.contains(&KeyBytes(key)))
+    }
 
-        let mut hasher = Hasher::new();
-        hasher.update(&serialized);
-        let crc = hasher.finalize();
+    /// Scans segment `file_id` into a new filter and saves it. A segment
+    /// that fails to scan gets none, so it is never ruled out.
+    fn build_segment_filter(&mut self, file_id: u32) -> Result<(), PlexError> {
+        let Some(fp_rate) = self.segment_filters.as_ref().map(|&(_, fp_rate)| fp_rate) else {
+            return Ok(());
+        };
+
+        let entries = match self.read_file_entries(file_id, 0) {
+            Ok(entries) => entries,
+            Err(PlexError::CorruptData(offset)) => {
+                warn!("Not building a bloom filter for segment {} with damage at {}", file_id, offset);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
 
+        let mut filter = BloomFilter::with_hash_family(entries.len().max(1), fp_rate, HashFamily::default())?;
+        for (key, _, _) in &entries {
+            filter.insert(&KeyBytes(key));
+        }
+        if !self.read_only {
+            filter.save_to_file(self.segment_filter_path(file_id))?;
+        }
 
-        let flags = if is_tombstone { TOMBSTONE_FLAG } else { 0 };
+        if let Some((filters, _)) = self.segment_filters.as_mut() {
+            filters.insert(file_id, filter);
+        }
+        Ok(())
+    }
 
-        let header = EntryHeader {
-            data_length: serialized.len() as u64,
-            crc,
-            timestamp: entry.timestamp,
-            flags,
+    /// Forgets the filter of a segment that is going away, and its file.
+    fn drop_segment_filter(&mut self, file_id: u32) -> Result<(), PlexError> {
+        if let Some((filters, _)) = self.segment_filters.as_mut() {
+            filters.remove(&file_id);
+        }
+
+        let path = self.segment_filter_path(file_id);
+        if !self.read_only && path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn segment_filter_path(&self, file_id: u32) -> PathBuf {
+        self.data_dir.join(format!("{}{}", segment_file_name(file_id), SEGMENT_FILTER_SUFFIX))
+    }
+
+    /// Segments are rotated once the active file grows past this size.
+    pub fn set_max_file_size(&mut self, max_file_size: u64) {
+        self.max_file_size = max_file_size;
+    }
+
+    /// Switches the fsync policy. Anything written so far is synced first,
+    /// so the new mode's durability window starts empty.
+    pub fn set_durability(&mut self, durability: Durability) -> Result<(), PlexError> {
+        self.flush()?;
+        self.flusher = match durability {
+            Durability::Interval(interval) => {
+                let file = self.active_file.as_ref().map(File::try_clone).transpose()?;
+                Some(Flusher::spawn(interval, file))
+            }
+            _ => None,
         };
+        self.durability = durability;
+        Ok(())
+    }
 
-        let file = self.active_file.as_mut().ok_or(PlexError::IO(
-                Error::new(ErrorKind::NotFound, "No active file")
-        ))?;
+    /// Values longer than `threshold` bytes are written to a separate value
+    /// log and the segment keeps only a pointer to them, so compaction never
+    /// copies large values. `None` keeps every value inline.
+    pub fn set_value_log_threshold(&mut self, threshold: Option<usize>) -> Result<(), PlexError> {
+        self.value_log_threshold = threshold;
 
-        let current_offset = *self.file_offsets.get(&self.active_file_id).unwrap_or(&0);
+        if threshold.is_some() && self.value_log.is_none() && !self.read_only {
+            let latest_id = self.value_log_ids()?.into_iter().max().unwrap_or(0);
+            self.open_value_log(latest_id)?;
+        }
+        Ok(())
+    }
 
-        file.write_all(&header.data_length.to_le_bytes())?;
-        file.write_all(&header.crc.to_le_bytes())?;
-        file.write_all(&header.timestamp.to_le_bytes())?;
-        file.write_all(&header.flags.to_le_bytes())?;
+    /// Ids of every value log file on disk, in no particular order.
+    pub fn value_log_ids(&self) -> Result<Vec<u32>, PlexError> {
+        let mut ids = Vec::new();
+
+        for entry in read_dir(&self.data_dir)? {
+            let entry = entry?;
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(VALUE_LOG_PREFIX))
+                .and_then(|name| name.strip_suffix(".log"))
+                .and_then(|id| id.parse::<u32>().ok())
+            {
+                ids.push(id);
+            }
+        }
 
+        Ok(ids)
+    }
 
-        file.write_all(serialized)?;
-        file.sync_all()?;
+    fn segment_path(&self, file_id: u32) -> PathBuf {
+        self.data_dir.join(segment_file_name(file_id))
+    }
 
+    fn value_log_path(&self, file_id: u32) -> PathBuf {
+        self.data_dir.join(format!("{}{:06}.log", VALUE_LOG_PREFIX, file_id))
+    }
 
-        let new_offset = current_offset + HEADER_SIZE as u64 + serialized.len() as u64;
-        self.file_offsets.insert(self.active_file_id, new_offset);
+    fn open_value_log(&mut self, file_id: u32) -> Result<(), PlexError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.value_log_path(file_id))?;
 
-        Ok(FileOffset {
-            partition_id: 0,
-            file_id: self.active_file_id,
-            offset: current_offset,
-            size: (HEADER_SIZE + serialized.len()) as u32,
-            timestamp: entry.timestamp,
-        })
+        self.value_log_size = file.metadata()?.len();
+        self.value_log_id = file_id;
+        self.value_log = Some(file);
+        Ok(())
     }
 
-    pub fn read_value(&self, offset: &FileOffest) -> Result<Option<String>, PlexError> {
-        let file_path = self.data_dir.join(format!("data_{:06}.log", offset.file_id));
-        let file = File::open(file_path)?;
-        let mut reader = BufReader::new(file);
+    /// Appends `value` to the value log as a CRC followed by the raw bytes.
+    fn append_value(&mut self, value: &[u8]) -> Result<ValuePointer, PlexError> {
+        if self.value_log.is_none() {
+            self.open_value_log(self.value_log_id)?;
+        } else if self.value_log_size >= self.max_file_size {
+            if let Some(file) = self.value_log.as_ref() {
+                file.sync_all()?;
+            }
+            self.open_value_log(self.value_log_id + 1)?;
+        }
 
-        reader.seek(SeekFrom::Start(offset.offset))?;
+        let mut hasher = Hasher::new();
+        hasher.update(value);
+        let crc = hasher.finalize();
 
-        let mut header_bytes = [0u8, HEADER_SIZE];
-        reader.read_exact(&mut header_bytes)?;
+        let offset = self.value_log_size;
+        if let Some(file) = self.value_log.as_mut() {
+            file.write_all(&crc.to_le_bytes())?;
+            file.write_all(value)?;
+        }
+        self.value_log_size += (VALUE_CRC_SIZE + value.len()) as u64;
 
+        Ok(ValuePointer {
+            file_id: self.value_log_id,
+            offset,
+            len: value.len() as u64,
+        })
+    }
 
-        let data_length = u64::from_le_bytes(header_bytes[0..8].try_into().unwrap()) as usize;
-        let stored_crc = u32::from_le_bytes(header_bytes[8..12].try_into().unwrap());
-        let _timestamp = u64::from_le_bytes(header_bytes[12..16].try_into().unwrap());
-        let flags = u32::from_le_bytes(header_bytes[16..20].try_into().unwrap());
+    fn read_value_log(&self, pointer: &ValuePointer) -> Result<Vec<u8>, PlexError> {
+        let mut file = File::open(self.value_log_path(pointer.file_id))?;
+        file.seek(SeekFrom::Start(pointer.offset))?;
 
-        let mut data = vec![0u8; data_length];
-        reader.read_exact(&mut data);
+        let mut crc_bytes = [0u8; VALUE_CRC_SIZE];
+        file.read_exact(&mut crc_bytes)?;
+        let mut data = vec![0u8; pointer.len as usize];
+        file.read_exact(&mut data)?;
 
         let mut hasher = Hasher::new();
         hasher.update(&data);
-        let calculated_crc = hasher.finalize();
+        if hasher.finalize() != u32::from_le_bytes(crc_bytes) {
+            return Err(PlexError::CorruptData(pointer.offset));
+        }
 
-        if calculated_crc != stored_crc {
-            return Err(PlexError::CorruptData(offset.offset));
+        Ok(data)
+    }
+
+    /// Ids of every segment on disk, oldest first.
+    pub fn segment_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.file_offsets.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Current size of every segment, oldest first.
+    pub fn segment_sizes(&self) -> Vec<(u32, u64)> {
+        self.segment_ids()
+            .into_iter()
+            .map(|id| (id, self.file_offsets[&id]))
+            .collect()
+    }
+
+    /// Like `segment_sizes`, leaving out the active segment, which is still
+    /// being appended to.
+    pub fn sealed_segment_sizes(&self) -> Vec<(u32, u64)> {
+        self.segment_sizes()
+            .into_iter()
+            .filter(|&(id, _)| id != self.active_file_id)
+            .collect()
+    }
+
+    /// Copies the first `length` bytes of each `(segment id, length)` pair
+    /// into `dest`, leaving out anything appended after those lengths were
+    /// taken. Value logs are copied whole: values are written before the
+    /// entries pointing at them, so they cover every copied pointer.
+    pub fn copy_segments(&self, segments: &[(u32, u64)], dest: &Path) -> Result<(), PlexError> {
+        create_dir_all(dest)?;
+
+        for &(file_id, length) in segments {
+            let file_name = segment_file_name(file_id);
+            let mut source = File::open(self.data_dir.join(&file_name))?.take(length);
+            let mut target = File::create(dest.join(&file_name))?;
+
+            std::io::copy(&mut source, &mut target)?;
+            target.sync_all()?;
         }
 
-        if flags & TOMBSTONE_FLAG != 0 {
-            return Ok(None);
+        for source in self.value_log_ids()?.into_iter().map(|file_id| self.value_log_path(file_id)).chain(self.dictionary_files()?) {
+            let target = dest.join(source.file_name().unwrap_or_default());
+            std::fs::copy(&source, &target)?;
         }
 
-        let entry: LogEntry = bincode::deserialize(&data)?;
-        Ok(entry.value)
+        Ok(())
     }
 
-    pub fn read_all_entries(&self) -> Result<Vec<(String, FileOffset, bool)>, PlexError> {
-        let mut entries = Vec::new();
+    /// Opens every file a backup of `segments` needs: each segment, to be
+    /// read up to its recorded length, and every value log, up to its
+    /// current length. Holding the handles keeps the data readable even if
+    /// compaction deletes the files before they are streamed.
+    pub fn open_backup_files(&self, segments: &[(u32, u64)]) -> Result<Vec<(String, File, u64)>, PlexError> {
+        let mut files = Vec::with_capacity(segments.len());
+
+        for &(file_id, length) in segments {
+            let file_name = segment_file_name(file_id);
+            let file = File::open(self.data_dir.join(&file_name))?;
+            files.push((file_name, file, length));
+        }
 
-        if let Ok(dir_entries) = std::fs::read_dir(&self.data_dir) {
-            for entry in dir_entries.flatten() {
-                if let Some(file_name) = entry.file_name.to_str() {
-                    if file_name.starts_with("data") && file_name.ends_with(".log") {
-                        if let Ok(file_id) = file_name[5..file_name.len()-4].parse::<u32>() {
-                            let file_entries = self.read_file_entries(file_id)?;
-                            entries.extend(file_entries);
-                        }
-                    }
+        for path in self.value_log_ids()?.into_iter().map(|file_id| self.value_log_path(file_id)).chain(self.dictionary_files()?) {
+            let file = File::open(&path)?;
+            let length = file.metadata()?.len();
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            files.push((file_name, file, length));
+        }
+
+        Ok(files)
+    }
+
+    fn initialize_active_file(&mut self) -> Result<(), PlexError> {
+        let mut max_file_id = 0;
+
+        if let Ok(entries) = read_dir(&self.data_dir) {
+            for entry in entries.flatten() {
+                if let Some(id) = entry.file_name().to_str().and_then(parse_segment_id) {
+                    max_file_id = max_file_id.max(id);
+                    self.file_offsets.insert(id, entry.metadata()?.len());
                 }
             }
         }
 
-        entries.sort_by_key(|(_, offset, _)| offset.timestamp);
-        Ok(entries)
+        if self.read_only {
+            self.active_file_id = max_file_id;
+            return Ok(());
+        }
+        self.open_active_file(max_file_id)
     }
 
-    fn read_file_entries(&self, file_id: u32) -> Result<Vec<(String, FileOffset, bool)>, PlexError> {
-        let file_path = self.data_dir.join(format!("data{:06}.log", file_id));
-        let file = File::open(file_path)?;
-        let mut reader = BufReader::new(file);
-        let mut entries = Vec::new(),
-        let mut offset = 0u64;
+    fn open_active_file(&mut self, file_id: u32) -> Result<(), PlexError> {
+        self.active_file_id = file_id;
+        let file_path = self.segment_path(self.active_file_id);
 
-        loop {
-            let start_offset = offset;
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            
+            .append(true)
+            .open(file_path)?;
 
 
-            let mut header_bytes = [0u8; HEADER_SIZE];
-            match reader.read_exact(&mut header_bytes) {
-                Ok(()) => {}
-                Err(e) if e.kind() = ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(PlexError::IO(e)),
-            }
+        let current_size = file.metadata()?.len();
+        self.file_offsets.insert(self.active_file_id, current_size);
 
-            let data_length = u64::from_le_bytes(header_bytes[0..8].try_into().unwrap()) as usize;
-            let stored_crc = u32::from_le_bytes(header_bytes[8..12].try_into().unwrap());
-            let timestamp = u64::from_le_bytes(header_bytes[12..16].try_into().unwrap());
-            let flags = u32::from_le_bytes(header_bytes[16..20].try_into().unwrap());
+        if let Some(flusher) = &self.flusher {
+            flusher.set_file(Some(file.try_clone()?))?;
+        }
+        self.active_file = Some(file);
+        Ok(())
+    }
 
-            let mut data = vec![0u8; data_length];
-            reader.read_exact(&mut data)?;
+    pub fn write_entry(&mut self, key: &[u8], value: &[u8]) -> Result<FileOffset, PlexError> {
+        self.write_entry_with_expiry(key, value, None)
+    }
 
-            let mut hasher = Hasher::new();
-            hasher.update(&data);
-            let calculated_crc = hasher.finalize();
+    pub fn write_entry_with_expiry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<FileOffset, PlexError> {
+        self.write_entry_at(key, value, expires_at, time::current_timestamp())
+    }
 
-            if calculated_crc != stored_crc {
-                eprintln!("CRC mismatch at offset {}: expected {}, got {}",
-                    start_offset, stored_crc, calculated_crc);
-                offset += HEADER_SIZE as u64 + data_length as u64;
-                continue;
-            }
+    /// Like `write_entry_with_expiry`, stamping the entry with `timestamp`
+    /// instead of the current time.
+    pub fn write_entry_at(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+        timestamp: u64,
+    ) -> Result<FileOffset, PlexError> {
+        let entry = LogEntry {
+            key: key.to_vec(),
+            value: Some(value.to_vec()),
+            timestamp,
+            expires_at,
+            value_pointer: None,
+        };
 
-            let entry: LogEntry = bincode::deserialize(&data)?;
-            let is_tombstone = flags & TOMBSTONE_FLAG != 0;
+        self.write_log_entry(&entry, false)
+    }
 
-            let file_offset = FileOffset {
-                partition_id: 0,
-                file_id,
-                offset: start_offfset,
-                size: (HEADER_SIZE + data_length) as u32,
-                timestamp,
+    pub fn write_tombstone(&mut self, key: &[u8]) -> Result<FileOffset, PlexError> {
+        let entry = LogEntry {
+            key: key.to_vec(),
+            value: None,
+            timestamp: time::current_timestamp(),
+            expires_at: None,
+            value_pointer: None,
+        };
+
+        self.write_log_entry(&entry, true)
+    }
+
+    /// Appends every pair to the active file and applies the durability mode
+    /// once at the end, instead of once per entry.
+    pub fn write_entries(&mut self, pairs: &[(&[u8], &[u8])]) -> Result<Vec<FileOffset>, PlexError> {
+        let entries: Vec<_> = pairs.iter().map(|&(key, value)| (key, value, None)).collect();
+        self.write_entries_with_expiry(&entries)
+    }
+
+    pub fn write_entries_with_expiry(&mut self, entries: &[(&[u8], &[u8], Option<u64>)]) -> Result<Vec<FileOffset>, PlexError> {
+        let mut offsets = Vec::with_capacity(entries.len());
+
+        for (key, value, expires_at) in entries {
+            let entry = LogEntry {
+                key: key.to_vec(),
+                value: Some(value.to_vec()),
+                timestamp: time::current_timestamp(),
+                expires_at: *expires_at,
+                value_pointer: None,
             };
 
-            entries.push((entry.key, file_offset, is_tombstone));
-            offset += HEADER_SIZE as u64 + data_length as u64;
+            offsets.push(self.append_log_entry(&entry, false)?);
+            self.rotate_if_full()?;
+        }
+
+        self.sync_after_writes(entries.len())?;
+        Ok(offsets)
+    }
 
+    pub fn sync(&self) -> Result<(), PlexError> {
+        // Values go first so no synced entry points at an unsynced value.
+        if let Some(file) = self.value_log.as_ref() {
+            file.sync_all()?;
         }
+        if let Some(file) = self.active_file.as_ref() {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
 
-        Ok(entries)
+    /// Syncs every write made so far, whatever the durability mode.
+    pub fn flush(&mut self) -> Result<(), PlexError> {
+        self.sync()?;
+        self.syncs += 1;
+        self.unsynced_writes = 0;
+        Ok(())
     }
 
-    pub fn rotate_file(&mut self) -> Result<(), PlexError> {
-        self.active_file_id += 1;
-        self.initialize_active_file()?;
+    /// How many times the write path or `flush` has synced the data files
+    /// since the file manager was opened.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    fn sync_after_writes(&mut self, count: usize) -> Result<(), PlexError> {
+        match self.durability {
+            Durability::Sync => self.flush(),
+            Durability::EveryN(n) => {
+                self.unsynced_writes += count;
+                if self.unsynced_writes >= n {
+                    self.flush()
+                } else {
+                    Ok(())
+                }
+            }
+            Durability::Async | Durability::Interval(_) => Ok(()),
+        }
+    }
 
-        Ok(());
+    fn write_log_entry(&mut self, entry: &LogEntry, is_tombstone: bool) -> Result<FileOffset, PlexError> {
+        let offset = self.append_log_entry(entry, is_tombstone)?;
+        self.sync_after_writes(1)?;
+        self.rotate_if_full()?;
+
+        Ok(offset)
     }
-}
 
+    fn check_writable(&self) -> Result<(), PlexError> {
+        if self.read_only {
+            return Err(PlexError::Config("store is read-only".to_string()));
+        }
+        Ok(())
+    }
+
+    fn rotate_if_full(&mut self) -> Result<(), PlexError> {
+        let active_size = *self.file_offsets.get(&self.active_file_id).unwrap_or(&0);
+
+        if active_size >= self.max_file_size {
+            self.rotate_file()?;
+        }
+        Ok(())
+    }
+
+    fn append_log_entry(&mut self, entry: &LogEntry, is_tombstone: bool) -> Result<FileOffset, PlexError> {
+        self.check_writable()?;
+
+        let (header, payload) = self.encode_log_entry(entry, is_tombstone)?;
+
+        let file = self.active_file.as_mut().ok_or(PlexError::IO(
+                Error::new(ErrorKind::NotFound, "No active file")
+        ))?;
+
+        let current_offset = *self.file_offsets.get(&self.active_file_id).unwrap_or(&0);
+
+        file.write_all(&header.to_bytes())?;
+        file.write_all(&payload)?;
+
+
+        let new_offset = current_offset + HEADER_SIZE as u64 + payload.len() as u64;
+        self.file_offsets.insert(self.active_file_id, new_offset);
+
+        Ok(FileOffset {
+            partition_id: 0,
+            file_id: self.active_file_id,
+            offset: current_offset,
+            size: (HEADER_SIZE + payload.len()) as u32,
+            timestamp: entry.timestamp,
+            expires_at: entry.expires_at,
+        })
+    }
+
+    /// Builds the on-disk header and payload of `entry`, moving a large
+    /// value out to the value log first. The entry is encrypted for the end
+    /// of the active segment, so it has to be appended there.
+    fn encode_log_entry(&mut self, entry: &LogEntry, is_tombstone: bool) -> Result<(EntryHeader, Vec<u8>), PlexError> {
+        let separated;
+        let entry = match (self.value_log_threshold, &entry.value) {
+            (Some(threshold), Some(value)) if value.len() > threshold && self.encryptor.is_none() => {
+                let pointer = self.append_value(value)?;
+                separated = LogEntry {
+                    key: entry.key.clone(),
+                    value: None,
+                    timestamp: entry.timestamp,
+                    expires_at: entry.expires_at,
+                    value_pointer: Some(pointer),
+                };
+                &separated
+            }
+            _ => entry,
+        };
+
+        let serialized = bincode::serialize(entry)?;
+
+        let mut flags = if is_tombstone { TOMBSTONE_FLAG } else { 0 };
+
+        // The CRC covers the bytes as they sit on disk, so corruption is
+        // caught before anything is handed to the decompressor.
+        let dictionary = self.dictionary_id.map(|id| &self.dictionaries[&id]);
+        let payload = match (&self.compressor, dictionary) {
+            (Some(_), Some(dictionary)) => {
+                flags |= COMPRESSED_FLAG | DICTIONARY_FLAG;
+                dictionary.compress(&serialized)?
+            }
+            (Some(compressor), None) => {
+                flags |= COMPRESSED_FLAG;
+                compressor.compress(&serialized)?
+            }
+            (None, _) => serialized,
+        };
+
+        let payload = match &self.encryptor {
+            Some(encryptor) => {
+                flags |= ENCRYPTED_FLAG;
+                let offset = self.file_offsets.get(&self.active_file_id).copied().unwrap_or(0);
+                seal(encryptor.as_ref(), &payload, &self.entry_aad(self.active_file_id, offset))?
+            }
+            None => payload,
+        };
+
+        let mut hasher = Hasher::new();
+        hasher.update(&payload);
+        let crc = hasher.finalize();
+
+        let header = EntryHeader {
+            data_length: payload.len() as u64,
+            crc,
+            timestamp: entry.timestamp,
+            flags,
+        };
+
+        Ok((header, payload))
+    }
+
+    /// Starts a new segment unless the active one is still empty, so the
+    /// writes that follow land in segments of their own.
+    pub fn start_new_segment(&mut self) -> Result<(), PlexError> {
+        self.check_writable()?;
+        if self.file_offsets.get(&self.active_file_id).copied().unwrap_or(0) > 0 {
+            self.rotate_file()?;
+        }
+        Ok(())
+    }
+
+    /// Appends every pair like `write_entries`, but encodes a whole segment's
+    /// worth of entries into memory and writes it with a single call. Nothing
+    /// is synced, whatever the durability mode; callers `flush` when done.
+    pub fn bulk_write(&mut self, pairs: &[(Vec<u8>, Vec<u8>)]) -> Result<Vec<FileOffset>, PlexError> {
+        self.check_writable()?;
+
+        let mut offsets = Vec::with_capacity(pairs.len());
+        let mut buffer = Vec::new();
+
+        for (key, value) in pairs {
+            let entry = LogEntry {
+                key: key.clone(),
+                value: Some(value.clone()),
+                timestamp: time::current_timestamp(),
+                expires_at: None,
+                value_pointer: None,
+            };
+            let (header, payload) = self.encode_log_entry(&entry, false)?;
+
+            let current_offset = *self.file_offsets.get(&self.active_file_id).unwrap_or(&0);
+            buffer.extend_from_slice(&header.to_bytes());
+            buffer.extend_from_slice(&payload);
+
+            let new_offset = current_offset + HEADER_SIZE as u64 + payload.len() as u64;
+            self.file_offsets.insert(self.active_file_id, new_offset);
+
+            offsets.push(FileOffset {
+                partition_id: 0,
+                file_id: self.active_file_id,
+                offset: current_offset,
+                size: (HEADER_SIZE + payload.len()) as u32,
+                timestamp: entry.timestamp,
+                expires_at: None,
+            });
+
+            if new_offset >= self.max_file_size {
+                self.write_buffered(&mut buffer)?;
+                self.rotate_file()?;
+            }
+        }
+
+        self.write_buffered(&mut buffer)?;
+        Ok(offsets)
+    }
+
+    fn write_buffered(&mut self, buffer: &mut Vec<u8>) -> Result<(), PlexError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let file = self.active_file.as_mut().ok_or(PlexError::IO(
+                Error::new(ErrorKind::NotFound, "No active file")
+        ))?;
+        file.write_all(buffer)?;
+        buffer.clear();
+        Ok(())
+    }
+
+    pub fn read_value(&self, offset: &FileOffset) -> Result<Option<Vec<u8>>, PlexError> {
+        match self.read_entry(offset)? {
+            Some(LogEntry { value: Some(value), .. }) => Ok(Some(value)),
+            Some(LogEntry { value_pointer: Some(pointer), .. }) => Ok(Some(self.read_value_log(&pointer)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads `len` bytes of the value at `offset` from byte `start`, clamped
+    /// to the end of the value. An inline value in an entry that is neither
+    /// compressed nor encrypted is read in place, without the rest of the
+    /// entry, so its CRC is not checked. Any other value is read whole and
+    /// then sliced.
+    pub fn read_value_range(&self, offset: &FileOffset, start: usize, len: usize) -> Result<Option<Vec<u8>>, PlexError> {
+        let slice_whole = || -> Result<Option<Vec<u8>>, PlexError> {
+            Ok(self.read_value(offset)?.map(|value| {
+                let start = start.min(value.len());
+                value[start..start + len.min(value.len() - start)].to_vec()
+            }))
+        };
+
+        let mut reader = BufReader::new(File::open(self.segment_path(offset.file_id))?);
+        reader.seek(SeekFrom::Start(offset.offset))?;
+
+        let mut header_bytes = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header_bytes)?;
+        let header = EntryHeader::from_bytes(&header_bytes);
+        if header.is_tombstone() {
+            return Ok(None);
+        }
+        if header.flags & (COMPRESSED_FLAG | ENCRYPTED_FLAG) != 0 {
+            return slice_whole();
+        }
+
+        // Bincode writes the key's length and bytes, then the value's
+        // `Option` tag, length and bytes.
+        let mut length = [0u8; 8];
+        reader.read_exact(&mut length)?;
+        let key_len = u64::from_le_bytes(length);
+        if key_len >= header.data_length {
+            return Err(PlexError::CorruptData(offset.offset));
+        }
+        reader.seek_relative(key_len as i64)?;
+
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        if tag[0] == 0 {
+            // The value lives in the value log.
+            return slice_whole();
+        }
+
+        reader.read_exact(&mut length)?;
+        let value_len = u64::from_le_bytes(length);
+        if key_len.checked_add(17).and_then(|prefix| prefix.checked_add(value_len)).is_none_or(|end| end > header.data_length) {
+            return Err(PlexError::CorruptData(offset.offset));
+        }
+
+        let start = (start as u64).min(value_len);
+        let len = (len as u64).min(value_len - start);
+        reader.seek_relative(start as i64)?;
+
+        let mut data = vec![0u8; len as usize];
+        reader.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Reads the entry as stored, without following a value pointer, so
+    /// compaction can move it without copying the value.
+    fn read_entry(&self, offset: &FileOffset) -> Result<Option<LogEntry>, PlexError> {
+        let (header, data) = match self.read_cached_entry(offset)? {
+            Some(entry) => entry,
+            None => {
+                let file_path = self.segment_path(offset.file_id);
+                let file = File::open(file_path)?;
+                let mut reader = BufReader::new(file);
+
+                reader.seek(SeekFrom::Start(offset.offset))?;
+
+                let mut header_bytes = [0u8; HEADER_SIZE];
+                reader.read_exact(&mut header_bytes)?;
+                let header = EntryHeader::from_bytes(&header_bytes);
+
+                let mut data = vec![0u8; header.data_length as usize];
+                reader.read_exact(&mut data)?;
+                (header, data)
+            }
+        };
+
+        if self.verify_crc && !Self::crc_matches(&header, &data) {
+            return Err(PlexError::CorruptData(offset.offset));
+        }
+
+        if header.flags & TOMBSTONE_FLAG != 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(self.decode_entry(&data, header.flags, offset.file_id, offset.offset)?))
+    }
+
+    /// Reads the entry at `offset` through the block cache. Returns `None`
+    /// when there is no cache to use or the entry straddles two blocks, in
+    /// which case the caller reads it from disk.
+    fn read_cached_entry(&self, offset: &FileOffset) -> Result<Option<(EntryHeader, Vec<u8>)>, PlexError> {
+        let Some(block_cache) = &self.block_cache else {
+            return Ok(None);
+        };
+
+        let address = (offset.file_id as u64) << SEGMENT_ADDRESS_BITS | offset.offset;
+        let size = offset.size as usize;
+        if size < HEADER_SIZE {
+            return Ok(None);
+        }
+        let block_start = block_cache.align_to_block(address);
+        if address + size as u64 > block_start + block_cache.block_size() as u64 {
+            return Ok(None);
+        }
+
+        let bytes = match block_cache.get_data(address, size) {
+            Some(bytes) => Some(bytes),
+            None => {
+                // Missing, or cached while the segment ended inside the block.
+                let block = self.load_block(offset.file_id, block_start, block_cache.block_size())?;
+                let bytes = block
+                    .data
+                    .get((address - block_start) as usize..)
+                    .and_then(|rest| rest.get(..size))
+                    .map(|bytes| bytes.to_vec());
+
+                // Only blocks whose requested entry checks out are cached.
+                if let Some(bytes) = &bytes {
+                    let (header, data) = Self::split_entry(bytes);
+                    if self.verify_crc && !Self::crc_matches(&header, data) {
+                        return Err(PlexError::CorruptData(offset.offset));
+                    }
+                    block_cache.set_block(block);
+                }
+                bytes
+            }
+        };
+
+        Ok(bytes.map(|bytes| {
+            let (header, data) = Self::split_entry(&bytes);
+            (header, data.to_vec())
+        }))
+    }
+
+    /// Reads up to `block_size` bytes of segment `file_id`, starting at the
+    /// segment offset that `block_start` addresses.
+    fn load_block(&self, file_id: u32, block_start: u64, block_size: usize) -> Result<Block, PlexError> {
+        let file_path = self.segment_path(file_id);
+        let mut file = File::open(file_path)?;
+        file.seek(SeekFrom::Start(block_start & ((1 << SEGMENT_ADDRESS_BITS) - 1)))?;
+
+        let mut data = Vec::with_capacity(block_size);
+        file.take(block_size as u64).read_to_end(&mut data)?;
+
+        let mut hasher = Hasher::new();
+        hasher.update(&data);
+
+        Ok(Block {
+            size: data.len(),
+            checksum: hasher.finalize(),
+            data,
+            offset: block_start,
+        })
+    }
+
+    fn split_entry(bytes: &[u8]) -> (EntryHeader, &[u8]) {
+        let (header_bytes, data) = bytes.split_at(HEADER_SIZE);
+        let header = EntryHeader::from_bytes(header_bytes.try_into().unwrap());
+        (header, data)
+    }
+
+    fn crc_matches(header: &EntryHeader, data: &[u8]) -> bool {
+        let mut hasher = Hasher::new();
+        hasher.update(data);
+        hasher.finalize() == header.crc
+    }
+
+    /// Decodes the payload of the entry at `offset` in segment `file_id`.
+    fn decode_entry(&self, data: &[u8], flags: u32, file_id: u32, offset: u64) -> Result<LogEntry, PlexError> {
+        let decrypted;
+        let data = if flags & ENCRYPTED_FLAG != 0 {
+            let encryptor = self.encryptor.as_ref().ok_or_else(|| {
+                PlexError::Config("Entry is encrypted but no encryption key is configured".to_string())
+            })?;
+            decrypted = unseal(encryptor.as_ref(), data, &self.entry_aad(file_id, offset))?;
+            decrypted.as_slice()
+        } else {
+            data
+        };
+
+        if flags & COMPRESSED_FLAG == 0 {
+            return Ok(bincode::deserialize(data)?);
+        }
+
+        if flags & DICTIONARY_FLAG != 0 {
+            let dictionary = frame_dictionary_id(data)
+                .and_then(|id| self.dictionaries.get(&id))
+                .ok_or_else(|| PlexError::Compression("Entry was compressed with a dictionary that is missing".to_string()))?;
+            return Ok(bincode::deserialize(&dictionary.decompress(data)?)?);
+        }
+
+        let compressor = self.compressor.as_ref().ok_or_else(|| {
+            PlexError::Config("Entry is compressed but no compressor is configured".to_string())
+        })?;
+        let decompressed = compressor.decompress(data)?;
+        Ok(bincode::deserialize(&decompressed)?)
+    }
+
+    pub fn read_all_entries(&self) -> Result<Vec<ScannedEntry>, PlexError> {
+        let mut file_ids = Vec::new();
+        if let Ok(dir_entries) = std::fs::read_dir(&self.data_dir) {
+            for entry in dir_entries.flatten() {
+                if let Some(file_id) = entry.file_name().to_str().and_then(parse_segment_id) {
+                    file_ids.push(file_id);
+                }
+            }
+        }
+        file_ids.sort_unstable();
+
+        let mut entries = Vec::new();
+        for file_id in file_ids {
+            entries.extend(self.read_file_entries(file_id, 0)?);
+        }
+
+        // Timestamps only have second resolution, so entries written in the
+        // same second are kept in the order they were appended.
+        entries.sort_by_key(|(_, offset, _)| (offset.timestamp, offset.file_id, offset.offset));
+        Self::mark_expired(&mut entries);
+
+        Ok(entries)
+    }
+
+    /// Like `read_all_entries`, but a damaged header that would fail the scan
+    /// is cut off instead, keeping the entries before it. In the newest
+    /// segment that is an entry torn by a crash mid-append. In an older one
+    /// it is corruption, so the whole segment is first copied to
+    /// `quarantine/` for inspection. Read-only file managers leave the files
+    /// alone and just stop reading at the damage. Also returns the ids of
+    /// the quarantined segments.
+    pub fn read_all_entries_quarantining(&mut self) -> Result<(Vec<ScannedEntry>, Vec<u32>), PlexError> {
+        let mut entries = Vec::new();
+        let mut quarantined = Vec::new();
+        let segment_ids = self.segment_ids();
+        let newest = segment_ids.last().copied();
+
+        for file_id in segment_ids {
+            let scan = self.scan_file_entries(file_id, 0)?;
+            if let Some(offset) = scan.damaged_at {
+                if Some(file_id) == newest {
+                    warn!("Cutting a torn entry off segment {:?} at offset {}", self.segment_path(file_id), offset);
+                } else {
+                    error!("Quarantining segment {:?}, damaged at offset {}", self.segment_path(file_id), offset);
+                    self.quarantine_segment(file_id)?;
+                    quarantined.push(file_id);
+                }
+                self.truncate_segment(file_id, offset)?;
+            }
+            entries.extend(scan.entries);
+        }
+
+        entries.sort_by_key(|(_, offset, _)| (offset.timestamp, offset.file_id, offset.offset));
+        Self::mark_expired(&mut entries);
+
+        Ok((entries, quarantined))
+    }
+
+    /// Copies a segment into `quarantine/`. Read-only file managers skip it.
+    fn quarantine_segment(&self, file_id: u32) -> Result<(), PlexError> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let quarantine_dir = self.data_dir.join(QUARANTINE_DIR);
+        create_dir_all(&quarantine_dir)?;
+        std::fs::copy(self.segment_path(file_id), quarantine_dir.join(segment_file_name(file_id)))?;
+        Ok(())
+    }
+
+    /// Cuts a segment back to `length` bytes, so appends to it land right
+    /// after its last whole entry. Read-only file managers only stop reading
+    /// past `length`.
+    fn truncate_segment(&mut self, file_id: u32, length: u64) -> Result<(), PlexError> {
+        self.file_offsets.insert(file_id, length);
+        if self.read_only {
+            return Ok(());
+        }
+
+        let file = OpenOptions::new().write(true).open(self.segment_path(file_id))?;
+        file.set_len(length)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Paths of every segment copied to `quarantine/`, in file name order.
+    pub fn quarantined_segments(&self) -> Result<Vec<PathBuf>, PlexError> {
+        let quarantine_dir = self.data_dir.join(QUARANTINE_DIR);
+        if !quarantine_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths = Vec::new();
+        for entry in read_dir(&quarantine_dir)? {
+            let entry = entry?;
+            if entry.file_name().to_str().and_then(parse_segment_id).is_some() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Reads every entry appended after `watermark`, a list of segment sizes
+    /// taken from `segment_sizes`, oldest first. Returns `None` when a
+    /// segment the watermark covers is missing or shorter than recorded,
+    /// meaning the segments have been rewritten since it was taken.
+    pub fn read_entries_since(
+        &self,
+        watermark: &[(u32, u64)],
+    ) -> Result<Option<Vec<ScannedEntry>>, PlexError> {
+        let recorded: HashMap<u32, u64> = watermark.iter().copied().collect();
+        let newest_recorded = recorded.keys().max().copied();
+
+        for (file_id, length) in &recorded {
+            if self.file_offsets.get(file_id).is_none_or(|size| size < length) {
+                return Ok(None);
+            }
+        }
+
+        let mut entries = Vec::new();
+        for file_id in self.segment_ids() {
+            let start_offset = match recorded.get(&file_id) {
+                Some(&length) => length,
+                None if newest_recorded.is_some_and(|newest| file_id < newest) => return Ok(None),
+                None => 0,
+            };
+            entries.extend(self.read_file_entries(file_id, start_offset)?);
+        }
+
+        Self::mark_expired(&mut entries);
+        Ok(Some(entries))
+    }
+
+    // Expired entries are reported as tombstones so they shadow any older
+    // version of the key instead of being re-indexed on recovery.
+    fn mark_expired(entries: &mut [ScannedEntry]) {
+        let now = time::current_timestamp();
+        for (_, offset, is_tombstone) in entries.iter_mut() {
+            if offset.is_expired(now) {
+                *is_tombstone = true;
+            }
+        }
+    }
+
+    /// Entries whose CRC does not match are skipped. A length running past
+    /// the end of the segment means the header itself is damaged and the
+    /// next entry cannot be found, so that returns `PlexError::CorruptData`.
+    fn read_file_entries(&self, file_id: u32, start_offset: u64) -> Result<Vec<ScannedEntry>, PlexError> {
+        let scan = self.scan_file_entries(file_id, start_offset)?;
+        match scan.damaged_at {
+            Some(offset) => Err(PlexError::CorruptData(offset)),
+            None => Ok(scan.entries),
+        }
+    }
+
+    /// Reads entries from `start_offset` to the end of the segment, or up to
+    /// the first damaged header, skipping and counting those that fail their
+    /// CRC.
+    fn scan_file_entries(&self, file_id: u32, start_offset: u64) -> Result<SegmentScan, PlexError> {
+        let file_path = self.segment_path(file_id);
+        let file = File::open(file_path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(start_offset))?;
+        let mut scan = SegmentScan::default();
+        let mut offset = start_offset;
+
+        loop {
+            let entry_offset = offset;
+
+
+            let mut header_bytes = [0u8; HEADER_SIZE];
+            match reader.read_exact(&mut header_bytes) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    if entry_offset < file_size {
+                        scan.damaged_at = Some(entry_offset);
+                    }
+                    break;
+                }
+                Err(e) => return Err(PlexError::IO(e)),
+            }
+
+            let header = EntryHeader::from_bytes(&header_bytes);
+            if header.data_length > file_size - entry_offset - HEADER_SIZE as u64 {
+                scan.damaged_at = Some(entry_offset);
+                break;
+            }
+
+            let data_length = header.data_length as usize;
+            let stored_crc = header.crc;
+            let timestamp = header.timestamp;
+            let flags = header.flags;
+
+            let mut data = vec![0u8; data_length];
+            reader.read_exact(&mut data)?;
+
+            let mut hasher = Hasher::new();
+            hasher.update(&data);
+            let calculated_crc = hasher.finalize();
+
+            if calculated_crc != stored_crc {
+                warn!(
+                    "CRC mismatch in segment {} at offset {}: expected {}, got {}; skipping entry",
+                    file_id, entry_offset, stored_crc, calculated_crc
+                );
+                scan.crc_failures += 1;
+                offset += HEADER_SIZE as u64 + data_length as u64;
+                continue;
+            }
+
+            let entry = self.decode_entry(&data, flags, file_id, entry_offset)?;
+            let is_tombstone = flags & TOMBSTONE_FLAG != 0;
+
+            let file_offset = FileOffset {
+                partition_id: 0,
+                file_id,
+                offset: entry_offset,
+                size: (HEADER_SIZE + data_length) as u32,
+                timestamp,
+                expires_at: entry.expires_at,
+            };
+
+            scan.entries.push((entry.key, file_offset, is_tombstone));
+            offset += HEADER_SIZE as u64 + data_length as u64;
+
+        }
+
+        Ok(scan)
+    }
+
+    /// Opens each of `segments` for `crc_failures`, which reads it up to its
+    /// recorded length. Holding the handles keeps the data readable even if
+    /// compaction deletes the files before they are checked.
+    pub fn open_segments(&self, segments: &[(u32, u64)]) -> Result<Vec<(u32, File, u64)>, PlexError> {
+        segments
+            .iter()
+            .map(|&(file_id, length)| Ok((file_id, File::open(self.segment_path(file_id))?, length)))
+            .collect()
+    }
+
+    /// Re-reads segments opened by `open_segments` and returns the
+    /// `(segment id, offset)` of each entry whose CRC does not match, or
+    /// whose length runs past the end of its segment. Needs no access to the
+    /// file manager, so it can run without holding its lock.
+    pub fn crc_failures(segments: Vec<(u32, File, u64)>) -> Result<Vec<(u32, u64)>, PlexError> {
+        let mut failures = Vec::new();
+
+        for (file_id, file, file_size) in segments {
+            let mut reader = BufReader::new(file);
+            let mut offset = 0u64;
+
+            while offset + HEADER_SIZE as u64 <= file_size {
+                let mut header_bytes = [0u8; HEADER_SIZE];
+                reader.read_exact(&mut header_bytes)?;
+                let header = EntryHeader::from_bytes(&header_bytes);
+
+                // A corrupted length would send the scan off into garbage, so
+                // the rest of the segment cannot be checked.
+                let remaining = file_size - offset - HEADER_SIZE as u64;
+                if header.data_length > remaining {
+                    failures.push((file_id, offset));
+                    break;
+                }
+
+                let mut data = vec![0u8; header.data_length as usize];
+                reader.read_exact(&mut data)?;
+
+                let mut hasher = Hasher::new();
+                hasher.update(&data);
+                if hasher.finalize() != header.crc {
+                    failures.push((file_id, offset));
+                }
+
+                offset += HEADER_SIZE as u64 + header.data_length;
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Returns `(segment id, offset, header)` for every entry, reading only
+    /// the headers and seeking over the data. Stops a segment early at a
+    /// header whose length runs past its end.
+    pub fn entry_headers(&self) -> Result<Vec<(u32, u64, EntryHeader)>, PlexError> {
+        let mut headers = Vec::new();
+
+        for (file_id, file_size) in self.segment_sizes() {
+            let file_path = self.segment_path(file_id);
+            let mut reader = BufReader::new(File::open(file_path)?);
+            let mut offset = 0u64;
+
+            while offset + HEADER_SIZE as u64 <= file_size {
+                let mut header_bytes = [0u8; HEADER_SIZE];
+                reader.read_exact(&mut header_bytes)?;
+                let header = EntryHeader::from_bytes(&header_bytes);
+
+                if header.data_length > file_size - offset - HEADER_SIZE as u64 {
+                    break;
+                }
+                reader.seek_relative(header.data_length as i64)?;
+
+                let entry_size = header.entry_size();
+                headers.push((file_id, offset, header));
+                offset += entry_size;
+            }
+        }
+
+        Ok(headers)
+    }
+
+    pub fn rotate_file(&mut self) -> Result<(), PlexError> {
+        self.flush()?;
+        // The segment being sealed is complete, so its filter can be built.
+        self.build_segment_filter(self.active_file_id)?;
+        self.open_active_file(self.active_file_id + 1)
+    }
+
+    /// Deletes every segment and value log and starts a fresh segment. Ids
+    /// keep counting up, so blocks cached for the deleted segments are never
+    /// served for new ones.
+    pub fn clear(&mut self) -> Result<(), PlexError> {
+        self.check_writable()?;
+        let stale_ids = self.segment_ids();
+
+        self.rotate_file()?;
+        for file_id in stale_ids {
+            std::fs::remove_file(self.segment_path(file_id))?;
+            self.file_offsets.remove(&file_id);
+            self.drop_segment_filter(file_id)?;
+        }
+
+        // `append_value` opens a new value log on the next large value.
+        self.value_log = None;
+        for file_id in self.value_log_ids()? {
+            std::fs::remove_file(self.value_log_path(file_id))?;
+        }
+        self.value_log_id += 1;
+        self.value_log_size = 0;
+
+        self.drop_retired_dictionaries()
+    }
+
+    /// Rewrites every entry in `live` into fresh segments and deletes the
+    /// segments they were read from. `live` should be the caller's index, so
+    /// it already points at the latest version of each key and leaves out
+    /// deleted ones. Entries are copied one at a time, so only one value is
+    /// held in memory however large the partition is. Returns the new offset
+    /// of each surviving key so callers can repoint their index. Values in
+    /// the value log stay where they are; only their pointers are rewritten,
+    /// unless an encryptor is set, in which case they are moved back inline.
+    /// A value log file is deleted once no surviving entry points into it,
+    /// but one that still holds a live value is kept whole, so the space of
+    /// its dead values is not reclaimed.
+    pub fn compact(&mut self, live: &HashMap<Vec<u8>, FileOffset>) -> Result<HashMap<Vec<u8>, FileOffset>, PlexError> {
+        self.check_writable()?;
+        let stale_ids = self.segment_ids();
+
+        self.rotate_file()?;
+
+        let now = time::current_timestamp();
+        let mut compacted = HashMap::with_capacity(live.len());
+        let mut live_value_logs = HashSet::new();
+
+        for (key, offset) in live {
+            if offset.is_expired(now) {
+                continue;
+            }
+
+            if let Some(mut entry) = self.read_entry(offset)? {
+                if self.encryptor.is_some()
+                    && let Some(pointer) = entry.value_pointer.take()
+                {
+                    entry.value = Some(self.read_value_log(&pointer)?);
+                }
+                if let Some(pointer) = &entry.value_pointer {
+                    live_value_logs.insert(pointer.file_id);
+                }
+                compacted.insert(key.clone(), self.append_log_entry(&entry, false)?);
+                self.rotate_if_full()?;
+            }
+        }
+        self.sync()?;
+
+        for file_id in stale_ids {
+            std::fs::remove_file(self.segment_path(file_id))?;
+            self.file_offsets.remove(&file_id);
+            self.drop_segment_filter(file_id)?;
+        }
+
+        for file_id in self.value_log_ids()? {
+            let is_active = self.value_log.is_some() && file_id == self.value_log_id;
+            if !is_active && !live_value_logs.contains(&file_id) {
+                std::fs::remove_file(self.value_log_path(file_id))?;
+            }
+        }
+
+        // Every entry left was just rewritten with the current dictionary.
+        self.drop_retired_dictionaries()?;
+
+        Ok(compacted)
+    }
+
+    /// Like `compact`, but only merges the segments in `segment_ids`, leaving
+    /// every other segment alone. An entry is copied if `live` still points
+    /// at it. Of the tombstones, only the latest for each key that is not in
+    /// `live` is kept, since it may still shadow a value in a segment outside
+    /// the merge; with per-segment filters, those whose key no such segment
+    /// can hold are dropped too. Entries keep their timestamps, which is what
+    /// orders them when the segments are scanned. Returns the new offset of
+    /// each copied key and how many tombstones were dropped.
+    pub fn compact_segments(
+        &mut self,
+        segment_ids: &[u32],
+        live: &HashMap<Vec<u8>, FileOffset>,
+    ) -> Result<(HashMap<Vec<u8>, FileOffset>, u64), PlexError> {
+        self.check_writable()?;
+        let others: Vec<u32> = self
+            .segment_ids()
+            .into_iter()
+            .filter(|file_id| !segment_ids.contains(file_id))
+            .collect();
+        self.rotate_file()?;
+
+        let mut moved = HashMap::new();
+        let mut tombstones: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut dropped = 0;
+
+        for &file_id in segment_ids {
+            for (key, offset, is_tombstone) in self.read_file_entries(file_id, 0)? {
+                if is_tombstone {
+                    dropped += 1;
+                    if !live.contains_key(&key) {
+                        let timestamp = tombstones.entry(key).or_insert(offset.timestamp);
+                        *timestamp = (*timestamp).max(offset.timestamp);
+                    }
+                    continue;
+                }
+
+                let is_live = live
+                    .get(&key)
+                    .is_some_and(|current| current.file_id == file_id && current.offset == offset.offset);
+                if !is_live {
+                    continue;
+                }
+
+                if let Some(mut entry) = self.read_entry(&offset)? {
+                    if self.encryptor.is_some()
+                        && let Some(pointer) = entry.value_pointer.take()
+                    {
+                        entry.value = Some(self.read_value_log(&pointer)?);
+                    }
+                    moved.insert(key, self.append_log_entry(&entry, false)?);
+                    self.rotate_if_full()?;
+                }
+            }
+        }
+
+        for (key, timestamp) in tombstones {
+            if !others.iter().any(|&file_id| self.segment_may_contain(file_id, &key)) {
+                continue;
+            }
+            let entry = LogEntry {
+                key,
+                value: None,
+                timestamp,
+                expires_at: None,
+                value_pointer: None,
+            };
+            self.append_log_entry(&entry, true)?;
+            self.rotate_if_full()?;
+            dropped -= 1;
+        }
+        self.sync()?;
+
+        for &file_id in segment_ids {
+            std::fs::remove_file(self.segment_path(file_id))?;
+            self.file_offsets.remove(&file_id);
+            self.drop_segment_filter(file_id)?;
+        }
+
+        Ok((moved, dropped))
+    }
+
+    /// Rebuilds the segments from the entries that pass their CRC. Entries
+    /// that fail are dropped, and so is the rest of a segment after a damaged
+    /// header, counted as one entry since its length is unknown. The latest
+    /// surviving version of each key is then rewritten as in `compact`.
+    /// Returns the new offset of each live key and how many entries were
+    /// dropped. A dropped entry may uncover an older version of its key.
+    pub fn repair(&mut self) -> Result<(HashMap<Vec<u8>, FileOffset>, u64), PlexError> {
+        self.check_writable()?;
+
+        let mut entries = Vec::new();
+        let mut dropped = 0;
+        for file_id in self.segment_ids() {
+            let scan = self.scan_file_entries(file_id, 0)?;
+            dropped += scan.crc_failures;
+            if let Some(offset) = scan.damaged_at {
+                warn!("Damaged header in segment {} at offset {}; dropping the rest of the segment", file_id, offset);
+                dropped += 1;
+            }
+            entries.extend(scan.entries);
+        }
+        entries.sort_by_key(|(_, offset, _)| (offset.timestamp, offset.file_id, offset.offset));
+        Self::mark_expired(&mut entries);
+
+        let mut live = HashMap::new();
+        for (key, offset, is_tombstone) in entries {
+            if is_tombstone {
+                live.remove(&key);
+            } else {
+                live.insert(key, offset);
+            }
+        }
+
+        let repaired = self.compact(&live)?;
+        Ok((repaired, dropped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::encryption::{AesGcmEncryptor, EncryptionKey};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use crate::utils::compression::ZstdCompressor;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn live_index(manager: &FileManager) -> HashMap<Vec<u8>, FileOffset> {
+        let mut index = HashMap::new();
+        for (key, offset, is_tombstone) in manager.read_all_entries().unwrap() {
+            if is_tombstone {
+                index.remove(&key);
+            } else {
+                index.insert(key, offset);
+            }
+        }
+        index
+    }
+
+    #[test]
+    fn compact_keeps_only_the_latest_live_versions() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+
+        manager.write_entry(b"a", b"1").unwrap();
+        manager.write_entry(b"a", b"2").unwrap();
+        manager.write_entry(b"b", b"3").unwrap();
+        manager.write_tombstone(b"b").unwrap();
+        let old_segments = manager.segment_ids();
+
+        let compacted = manager.compact(&live_index(&manager)).unwrap();
+
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(manager.read_value(&compacted[b"a".as_slice()]).unwrap().as_deref(), Some(b"2".as_slice()));
+        assert!(old_segments.iter().all(|id| !manager.segment_ids().contains(id)));
+    }
+
+    #[test]
+    fn reopening_continues_after_the_newest_segment() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+            manager.write_entry(b"a", b"1").unwrap();
+            manager.rotate_file().unwrap();
+            manager.write_entry(b"b", b"2").unwrap();
+        }
+
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        let offset = manager.write_entry(b"c", b"3").unwrap();
+
+        assert_eq!(offset.file_id, 1);
+        assert_eq!(manager.segment_ids(), vec![0, 1]);
+        assert_eq!(manager.read_all_entries().unwrap().len(), 3);
+    }
+
+    fn data_file_size(dir: &Path) -> u64 {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn compressed_entries_shrink_and_read_back() {
+        let plain_dir = TempDir::new().unwrap();
+        let zstd_dir = TempDir::new().unwrap();
+        let value = "abcdefgh".repeat(1024);
+
+        let mut plain = FileManager::new(plain_dir.path().to_path_buf()).unwrap();
+        plain.write_entry(b"key", value.as_bytes()).unwrap();
+
+        let mut zstd = FileManager::with_compressor(
+            zstd_dir.path().to_path_buf(),
+            Box::new(ZstdCompressor::new(3)),
+        )
+        .unwrap();
+        let offset = zstd.write_entry(b"key", value.as_bytes()).unwrap();
+
+        assert!(data_file_size(zstd_dir.path()) < data_file_size(plain_dir.path()) / 4);
+        assert_eq!(zstd.read_value(&offset).unwrap().as_deref(), Some(value.as_bytes()));
+    }
+
+    fn trained_dictionary(field: &str) -> Vec<u8> {
+        let values: Vec<String> = (0..1000)
+            .map(|i| format!(r#"{{"id":{},"{}":"user{}","roles":["reader","writer"]}}"#, i, field, i))
+            .collect();
+        let samples: Vec<&[u8]> = values.iter().map(|value| value.as_bytes()).collect();
+
+        let mut compressor = DictionaryCompressor::new(Vec::new(), 3);
+        compressor.train_dictionary(&samples).unwrap();
+        compressor.dictionary().to_vec()
+    }
+
+    fn dictionary_file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(DICTIONARY_PREFIX))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn entries_stay_readable_after_their_dictionary_is_replaced() {
+        let dir = TempDir::new().unwrap();
+        let open = || {
+            let mut manager =
+                FileManager::with_compressor(dir.path().to_path_buf(), Box::new(ZstdCompressor::new(3))).unwrap();
+            manager.load_dictionaries(3).unwrap();
+            manager
+        };
+        let first_value = br#"{"id":1,"name":"user1","roles":["reader","writer"]}"#;
+        let second_value = br#"{"id":2,"email":"user2","roles":["reader","writer"]}"#;
+
+        {
+            let mut manager = open();
+            manager.install_dictionary(trained_dictionary("name"), 3).unwrap();
+            manager.write_entry(b"first", first_value).unwrap();
+            manager.install_dictionary(trained_dictionary("email"), 3).unwrap();
+            manager.write_entry(b"second", second_value).unwrap();
+        }
+        assert_eq!(dictionary_file_names(dir.path()).len(), 2);
+
+        let mut manager = open();
+        let headers = manager.entry_headers().unwrap();
+        assert!(headers.iter().all(|(_, _, header)| header.flags & DICTIONARY_FLAG != 0));
+
+        let live = live_index(&manager);
+        assert_eq!(manager.read_value(&live[b"first".as_slice()]).unwrap().as_deref(), Some(first_value.as_slice()));
+
+        let compacted = manager.compact(&live).unwrap();
+        assert_eq!(dictionary_file_names(dir.path()), vec![DICTIONARY_FILE.to_string()]);
+        assert_eq!(manager.read_value(&compacted[b"first".as_slice()]).unwrap().as_deref(), Some(first_value.as_slice()));
+        assert_eq!(manager.read_value(&compacted[b"second".as_slice()]).unwrap().as_deref(), Some(second_value.as_slice()));
+    }
+
+    #[test]
+    fn uncompressed_entries_stay_readable_with_a_compressor() {
+        let dir = TempDir::new().unwrap();
+        let offset = {
+            let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+            manager.write_entry(b"key", b"value").unwrap()
+        };
+
+        let manager =
+            FileManager::with_compressor(dir.path().to_path_buf(), Box::new(ZstdCompressor::new(3)))
+                .unwrap();
+
+        assert_eq!(manager.read_value(&offset).unwrap().as_deref(), Some(b"value".as_slice()));
+        assert_eq!(manager.read_all_entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn keys_read_back_across_rotated_segments() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_max_file_size(1024);
+
+        let value = "v".repeat(100);
+        let offsets: Vec<_> = (0..40)
+            .map(|i| (i, manager.write_entry(format!("key{}", i).as_bytes(), value.as_bytes()).unwrap()))
+            .collect();
+
+        assert!(manager.segment_ids().len() >= 3);
+        for (_, offset) in &offsets {
+            assert_eq!(manager.read_value(offset).unwrap().as_deref(), Some(value.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn compact_merges_rotated_segments_and_deletes_them() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_max_file_size(1024);
+
+        let value = "v".repeat(100);
+        for round in 0..3 {
+            for i in 0..10 {
+                manager.write_entry(format!("key{}", i).as_bytes(), format!("{}{}", value, round).as_bytes()).unwrap();
+            }
+        }
+        let stale = manager.segment_ids();
+
+        let compacted = manager.compact(&live_index(&manager)).unwrap();
+
+        assert_eq!(compacted.len(), 10);
+        for id in stale {
+            assert!(!dir.path().join(format!("data_{:06}.log", id)).exists());
+        }
+        for (_, offset) in compacted {
+            assert_eq!(manager.read_value(&offset).unwrap(), Some(format!("{}2", value).into_bytes()));
+        }
+    }
+
+    fn syncs_for_writes(dir: &Path, durability: Durability, count: usize) -> u64 {
+        let mut file_manager = FileManager::new(dir.to_path_buf()).unwrap();
+        file_manager.set_durability(durability).unwrap();
+
+        let before = file_manager.syncs();
+        for i in 0..count {
+            file_manager.write_entry(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        file_manager.syncs() - before
+    }
+
+    #[test]
+    fn batched_fsyncs_sync_once_per_batch() {
+        let sync_dir = TempDir::new().unwrap();
+        let batched_dir = TempDir::new().unwrap();
+        let async_dir = TempDir::new().unwrap();
+
+        assert_eq!(syncs_for_writes(sync_dir.path(), Durability::Sync, 10_000), 10_000);
+        assert_eq!(syncs_for_writes(batched_dir.path(), Durability::EveryN(1000), 10_000), 10);
+        assert_eq!(syncs_for_writes(async_dir.path(), Durability::Async, 10_000), 0);
+    }
+
+    #[test]
+    fn every_write_is_readable_after_flush() {
+        for durability in [
+            Durability::Async,
+            Durability::EveryN(7),
+            Durability::Interval(Duration::from_millis(5)),
+        ] {
+            let dir = TempDir::new().unwrap();
+            let offsets: Vec<FileOffset> = {
+                let mut file_manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+                file_manager.set_durability(durability).unwrap();
+                let offsets = (0..100)
+                    .map(|i| file_manager.write_entry(format!("key{}", i).as_bytes(), format!("value{}", i).as_bytes()).unwrap())
+                    .collect();
+                file_manager.flush().unwrap();
+                offsets
+            };
+
+            let file_manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+            for (i, offset) in offsets.iter().enumerate() {
+                assert_eq!(file_manager.read_value(offset).unwrap(), Some(format!("value{}", i).into_bytes()));
+            }
+            assert_eq!(file_manager.read_all_entries().unwrap().len(), 100);
+        }
+    }
+
+    #[test]
+    fn large_values_go_to_the_value_log() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_value_log_threshold(Some(1024)).unwrap();
+        let big = "x".repeat(1024 * 1024);
+
+        let offset = manager.write_entry(b"big", big.as_bytes()).unwrap();
+        manager.write_entry(b"small", b"inline").unwrap();
+
+        let segment_bytes: u64 = manager.segment_sizes().iter().map(|(_, size)| size).sum();
+        assert!(segment_bytes < 1024);
+        assert_eq!(manager.read_value(&offset).unwrap().as_deref(), Some(big.as_bytes()));
+    }
+
+    #[test]
+    fn compact_deletes_value_logs_nothing_points_into() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_value_log_threshold(Some(16)).unwrap();
+        manager.set_max_file_size(1024);
+        let big = "y".repeat(2048);
+
+        manager.write_entry(b"old", big.as_bytes()).unwrap();
+        manager.write_entry(b"kept", big.as_bytes()).unwrap();
+        manager.write_tombstone(b"old").unwrap();
+        assert_eq!(manager.value_log_ids().unwrap().len(), 2);
+
+        let compacted = manager.compact(&live_index(&manager)).unwrap();
+
+        assert_eq!(manager.value_log_ids().unwrap(), vec![1]);
+        assert_eq!(manager.read_value(&compacted[b"kept".as_slice()]).unwrap().as_deref(), Some(big.as_bytes()));
+    }
+
+    #[test]
+    fn read_entries_since_reads_only_the_tail() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_max_file_size(64 * 1024);
+        for i in 0..5000 {
+            manager.write_entry(format!("key-{}", i).as_bytes(), "v".repeat(64).as_bytes()).unwrap();
+        }
+        let watermark = manager.segment_sizes();
+        for i in 0..10 {
+            manager.write_entry(format!("tail-{}", i).as_bytes(), b"t").unwrap();
+        }
+        manager.flush().unwrap();
+
+        let scanned_bytes = |entries: &[ScannedEntry]| -> u64 {
+            entries.iter().map(|(_, offset, _)| offset.size as u64).sum()
+        };
+        let full = manager.read_all_entries().unwrap();
+        let tail = manager.read_entries_since(&watermark).unwrap().unwrap();
+
+        assert_eq!(tail.len(), 10);
+        assert!(tail.iter().all(|(key, _, _)| key.starts_with(b"tail-")));
+        assert!(scanned_bytes(&tail) * 100 < scanned_bytes(&full));
+    }
+
+    #[test]
+    fn read_entries_since_rejects_a_rewritten_segment() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.write_entry(b"a", b"1").unwrap();
+        let watermark = manager.segment_sizes();
+
+        manager.compact(&live_index(&manager)).unwrap();
+
+        assert!(manager.read_entries_since(&watermark).unwrap().is_none());
+    }
+
+    #[test]
+    fn second_read_in_the_same_block_is_served_from_the_block_cache() {
+        let dir = TempDir::new().unwrap();
+        let mut file_manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        let block_cache = Arc::new(SyncBlockCache::new(16, 4096));
+        file_manager.set_block_cache(Some(block_cache.clone()));
+
+        let first = file_manager.write_entry(b"first", b"one").unwrap();
+        let second = file_manager.write_entry(b"second", b"two").unwrap();
+        file_manager.flush().unwrap();
+
+        assert_eq!(file_manager.read_value(&first).unwrap().as_deref(), Some(b"one".as_slice()));
+        let stats = block_cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 1));
+
+        assert_eq!(file_manager.read_value(&second).unwrap().as_deref(), Some(b"two".as_slice()));
+        let stats = block_cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn reopened_file_manager_reads_back_entries_in_write_order() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut file_manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+            file_manager.set_max_file_size(64);
+            for i in 0..20 {
+                file_manager.write_entry(b"key", format!("value{}", i).as_bytes()).unwrap();
+            }
+            file_manager.write_tombstone(b"gone").unwrap();
+            file_manager.flush().unwrap();
+        }
+        assert!(dir.path().join(segment_file_name(0)).exists());
+        assert_eq!(parse_segment_id(&segment_file_name(42)), Some(42));
+        assert_eq!(parse_segment_id("vlog_000001.log"), None);
+
+        let file_manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        let entries = file_manager.read_all_entries().unwrap();
+        assert_eq!(entries.len(), 21);
+        assert!(file_manager.segment_ids().len() > 1);
+
+        // Every write lands in the same second, so only the segment and offset
+        // tie-break keeps the last write last.
+        let last_value = entries.iter().rfind(|(key, _, _)| key == b"key").unwrap();
+        assert_eq!(file_manager.read_value(&last_value.1).unwrap().as_deref(), Some(b"value19".as_slice()));
+        let (key, _, is_tombstone) = entries.last().unwrap();
+        assert_eq!((key.as_slice(), *is_tombstone), (b"gone".as_slice(), true));
+    }
+
+    struct CountingAllocator;
+
+    thread_local! {
+        static TRACKING: Cell<bool> = const { Cell::new(false) };
+        static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+        static PEAK_BYTES: Cell<isize> = const { Cell::new(0) };
+    }
+
+    impl CountingAllocator {
+        fn record(delta: isize) {
+            let _ = TRACKING.try_with(|tracking| {
+                if tracking.get() {
+                    let live = LIVE_BYTES.get() + delta;
+                    LIVE_BYTES.set(live);
+                    PEAK_BYTES.set(PEAK_BYTES.get().max(live));
+                }
+            });
+        }
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            Self::record(layout.size() as isize);
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            Self::record(-(layout.size() as isize));
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            Self::record(new_size as isize - layout.size() as isize);
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Runs `f` and returns the most bytes it had allocated on this thread at
+    /// any one time.
+    fn peak_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        LIVE_BYTES.set(0);
+        PEAK_BYTES.set(0);
+        TRACKING.set(true);
+        let result = f();
+        TRACKING.set(false);
+        (result, PEAK_BYTES.get().max(0) as usize)
+    }
+
+    #[test]
+    fn compaction_streams_values_instead_of_buffering_them() {
+        const MEMORY_CEILING: usize = 2 * 1024 * 1024;
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_max_file_size(1024 * 1024);
+
+        let value = "z".repeat(64 * 1024);
+        for i in 0..200 {
+            manager.write_entry(format!("key-{}", i).as_bytes(), value.as_bytes()).unwrap();
+        }
+        let live = live_index(&manager);
+
+        let (compacted, peak) = peak_allocation(|| manager.compact(&live));
+        let compacted = compacted.unwrap();
+
+        assert!(200 * value.len() > 4 * MEMORY_CEILING);
+        assert!(peak < MEMORY_CEILING, "compaction peaked at {} bytes", peak);
+        assert_eq!(compacted.len(), 200);
+        assert_eq!(manager.read_value(&compacted[b"key-7".as_slice()]).unwrap().as_deref(), Some(value.as_bytes()));
+    }
+
+    fn encryptor() -> Option<Box<dyn Encryptor>> {
+        Some(Box::new(AesGcmEncryptor::new(&EncryptionKey::from_bytes([3; 32]))))
+    }
+
+    #[test]
+    fn encrypted_entries_do_not_reach_disk_in_plaintext() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_encryptor(0, encryptor());
+
+        let offset = manager.write_entry(b"key", b"plaintext-marker").unwrap();
+        manager.sync().unwrap();
+
+        for file in std::fs::read_dir(dir.path()).unwrap() {
+            let bytes = std::fs::read(file.unwrap().path()).unwrap();
+            assert!(!bytes.windows(16).any(|w| w == b"plaintext-marker"));
+        }
+        assert_eq!(manager.read_value(&offset).unwrap().as_deref(), Some(&b"plaintext-marker"[..]));
+
+        drop(manager);
+        let reopened = FileManager::new(dir.path().to_path_buf()).unwrap();
+        assert!(matches!(reopened.read_value(&offset), Err(PlexError::Config(_))));
+    }
+
+    #[test]
+    fn encrypted_entries_cannot_be_read_back_from_another_offset() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_encryptor(0, encryptor());
+
+        let first = manager.write_entry(b"a", b"1").unwrap();
+        let second = manager.write_entry(b"b", b"2").unwrap();
+        let third = manager.write_entry(b"c", b"3").unwrap();
+        manager.sync().unwrap();
+        assert_eq!(first.size, second.size);
+
+        // Swapping the entries keeps each CRC intact but moves the entries
+        // away from the offsets they were sealed for.
+        let path = manager.segment_path(first.file_id);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let (head, tail) = bytes.split_at_mut(second.offset as usize);
+        head[first.offset as usize..].swap_with_slice(&mut tail[..second.size as usize]);
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(manager.read_value(&first), Err(PlexError::Encryption(_))));
+        assert_eq!(manager.read_value(&third).unwrap().as_deref(), Some(&b"3"[..]));
+
+        let mut other_partition = FileManager::new(dir.path().to_path_buf()).unwrap();
+        other_partition.set_encryptor(1, encryptor());
+        assert!(matches!(other_partition.read_value(&third), Err(PlexError::Encryption(_))));
+    }
+
+    #[test]
+    fn compaction_moves_value_log_values_inline_once_encrypted() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_value_log_threshold(Some(8)).unwrap();
+        manager.write_entry(b"big", &[b'v'; 64]).unwrap();
+
+        manager.set_encryptor(0, encryptor());
+        let live = live_index(&manager);
+        let compacted = manager.compact(&live).unwrap();
+
+        let offset = &compacted[&b"big"[..]];
+        assert!(manager.read_entry(offset).unwrap().unwrap().value_pointer.is_none());
+        assert_eq!(manager.read_value(offset).unwrap(), Some(vec![b'v'; 64]));
+    }
+
+    #[test]
+    fn segment_filters_rule_out_segments_without_the_key() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_segment_filters(Some(0.01)).unwrap();
+
+        manager.write_entry(b"first", b"1").unwrap();
+        manager.start_new_segment().unwrap();
+        let second = manager.write_entry(b"second", b"2").unwrap();
+        manager.start_new_segment().unwrap();
+        let first_id = second.file_id - 1;
+
+        assert!(manager.segment_may_contain(first_id, b"first"));
+        assert!(!manager.segment_may_contain(first_id, b"second"));
+        assert!(manager.segment_may_contain(second.file_id, b"second"));
+        // The active segment has no filter, so nothing is ruled out there.
+        assert!(manager.segment_may_contain(second.file_id + 1, b"anything"));
+
+        // Filters are saved next to their segments and rebuilt when missing.
+        std::fs::remove_file(manager.segment_filter_path(first_id)).unwrap();
+        assert!(manager.segment_filter_path(second.file_id).exists());
+        drop(manager);
+        let mut reopened = FileManager::new(dir.path().to_path_buf()).unwrap();
+        reopened.set_segment_filters(Some(0.01)).unwrap();
+        assert!(reopened.segment_filter_path(first_id).exists());
+        assert!(!reopened.segment_may_contain(first_id, b"second"));
+        assert_eq!(reopened.read_value(&second).unwrap().as_deref(), Some(&b"2"[..]));
+    }
+
+    #[test]
+    fn merging_segments_drops_tombstones_no_other_segment_can_need() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = FileManager::new(dir.path().to_path_buf()).unwrap();
+        manager.set_segment_filters(Some(0.001)).unwrap();
+
+        manager.write_entry(b"old", b"value").unwrap();
+        manager.start_new_segment().unwrap();
+        manager.write_entry(b"short-lived", b"value").unwrap();
+        manager.write_tombstone(b"short-lived").unwrap();
+        manager.write_tombstone(b"old").unwrap();
+        manager.start_new_segment().unwrap();
+
+        let merged = manager.sealed_segment_sizes()[1].0;
+        let live = live_index(&manager);
+        let (_, dropped) = manager.compact_segments(&[merged], &live).unwrap();
+
+        // "old" still has a value in the first segment, so its tombstone stays.
+        assert_eq!(dropped, 1);
+        let remaining: Vec<_> = manager.read_all_entries().unwrap().into_iter().map(|(key, _, _)| key).collect();
+        assert!(remaining.contains(&b"old".to_vec()));
+        assert!(!remaining.contains(&b"short-lived".to_vec()));
+        assert!(!manager.segment_filter_path(merged).exists());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod file_manager;
+pub mod storage_engine;
+pub mod wal;
@@ -1,5 +1,7 @@
 use std::result::Result;
 
+use async_trait::async_trait;
+
 use crate::error::PlexError;
 
 pub trait StorageEngine {
@@ -9,5 +11,17 @@ pub trait StorageEngine {
 
     fn set(&mut self, key: &str, value: &str) -> Result<(), PlexError>;
 
-    fn delete(&mut self, key: &str) -> Result<(), PlexError>;
+    /// Returns whether the key held a value before the delete.
+    fn delete(&mut self, key: &str) -> Result<bool, PlexError>;
+}
+
+/// Async counterpart of `StorageEngine` for callers running on Tokio. All
+/// methods take `&self` so one engine can be driven from many tasks.
+#[async_trait]
+pub trait AsyncStorageEngine {
+    async fn get(&self, key: &str) -> Result<Option<String>, PlexError>;
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), PlexError>;
+
+    async fn delete(&self, key: &str) -> Result<bool, PlexError>;
 }
@@ -1,22 +1,132 @@
 use crate::error::{PlexError, PlexResult};
-use crate::cli::Command;
+use crate::utils::compression::{Compressor, ZstdCompressor};
+use crate::utils::encryption::{seal, unseal, Encryptor};
 use serde::{Serialize, Deserialize};
 use std::fs::{File, OpenOptions, rename};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, BufWriter, Cursor, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use crc32fast::Hasher;
 use tracing::{debug, error, info, warn};
 
-#[derive(Debug, Clone, Serialize, Deserialize)];
-pb struct WALEntry {
+const CHECKPOINT_FILE: &str = "checkpoint";
+const COMPRESSED_WAL_EXTENSION: &str = "log.zst";
+const WAL_COMPRESSION_LEVEL: i32 = 3;
+
+/// What a WAL entry records. `Set`, `Get`, `Delete` and `Compact` keep the
+/// positions they had when the WAL logged CLI commands directly, so their
+/// discriminants still decode; new records go at the end.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WALCommand {
+    Set {
+        key: String,
+        value: String,
+    },
+
+    /// Never logged; holds its slot so later discriminants do not shift.
+    Get {
+        key: String,
+    },
+
+    Delete {
+        key: String,
+    },
+
+    /// Never logged; holds its slot so later discriminants do not shift.
+    Compact,
+
+    SetEx {
+        key: String,
+        value: String,
+        ttl_secs: u64,
+    },
+
+    /// Records the partition count a rebalance is moving to.
+    RebalanceTo {
+        partition_count: u32,
+    },
+
+    /// Opens a batch of `count` writes, which replay applies only once the
+    /// matching `BatchEnd` is read.
+    BatchStart {
+        count: u32,
+    },
+
+    /// Closes a batch; `checksum` covers the batch's commands.
+    BatchEnd {
+        checksum: u32,
+    },
+
+    /// Records that every partition was cleared.
+    Truncate,
+
+    /// Opens a transaction.
+    TxnBegin,
+
+    /// Commits the transaction opened at `begin_sequence`.
+    TxnCommit {
+        begin_sequence: u64,
+    },
+
+    /// A `set_bytes`, whose key and value need not be UTF-8.
+    SetBytes {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+
+    /// A `delete_bytes`.
+    DeleteBytes {
+        key: Vec<u8>,
+    },
+
+    /// A command that belongs to a namespace rather than the default
+    /// keyspace.
+    Namespaced {
+        namespace: String,
+        command: Box<WALCommand>,
+    },
+
+    /// Another command, sealed with the WAL's encryptor and bound to the
+    /// entry's sequence number. Only ever on disk; reads hand back the
+    /// command it holds.
+    Encrypted {
+        sealed: Vec<u8>,
+    },
+
+    /// A set that keeps the timestamp its entry was first written with, as
+    /// entries merged in from another store do.
+    SetAt {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+        timestamp: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WALEntry {
     pub sequence_number: u64,
     pub timestamp: u64,
-    pub command: Command,
+    pub command: WALCommand,
     pub checksum: u32,
 }
 
+/// Counts the bytes read through it, so replay knows where an entry began.
+struct PositionReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct WALHeader {
     magic: [u8; 4],
@@ -25,7 +135,7 @@ struct WALHeader {
     flags: u32,
 }
 
-impl Header {
+impl WALHeader {
     const MAGIC: [u8; 4] = *b"PLEX";
     const VERSION: u32 = 1;
 
@@ -44,16 +154,70 @@ impl Header {
         self.magic == Self::MAGIC && self.version == Self::VERSION
     }
 
+    /// Fails with `InvalidFormat` if this is not a WAL header at all, and
+    /// with `UnsupportedVersion` if a newer build wrote it.
+    fn check(&self) -> PlexResult<()> {
+        if self.magic != Self::MAGIC {
+            return Err(PlexError::InvalidFormat);
+        }
+        if self.version > Self::VERSION {
+            return Err(PlexError::UnsupportedVersion {
+                found: self.version,
+                supported: Self::VERSION,
+            });
+        }
+        if !self.is_valid() {
+            return Err(PlexError::InvalidFormat);
+        }
+        Ok(())
+    }
+
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WALConfig {
     pub max_file_size: u64,
     pub sync_interval: std::time::Duration,
     pub max_entries_per_file: u64,
     pub compress_old_files: bool,
     pub retention_period: std::time::Duration,
+    pub fsync: FsyncMode,
+    pub replay_strictness: ReplayStrictness,
+    /// When set, `append` returns only once its entry is synced. Appends
+    /// arriving while one sync is in flight share the next one, and the
+    /// caller that runs it first waits this long for others to join.
+    pub group_commit_window: Option<std::time::Duration>,
+    /// Reject every append, so no WAL file is ever created or written.
+    #[serde(skip)]
+    pub read_only: bool,
+}
+
+/// What replay does with an entry that is torn or fails its checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayStrictness {
+    /// Cut off an entry torn at the end of the newest file, and fail on any
+    /// other bad entry without modifying a file, so nothing after it is
+    /// ever applied.
+    #[default]
+    Strict,
+    /// Log and skip entries that fail their checksum and keep reading.
+    Lenient,
+}
+
+/// How far `WAL::sync` pushes buffered entries towards the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncMode {
+    /// Hand entries to the OS only; a power loss can drop them.
+    None,
+    /// `fdatasync`: flush file contents and the metadata needed to read them.
+    #[default]
+    Fdatasync,
+    /// `fsync`: flush file contents and all metadata.
+    Fsync,
 }
 
 
@@ -61,51 +225,93 @@ impl Default for WALConfig {
     fn default() -> Self {
         Self {
             max_file_size: 100 * 1024 * 1024,
-            sync_interval: std::time::Duration::from_sec(1),
+            sync_interval: std::time::Duration::from_secs(1),
             max_entries_per_file: 1_000_000,
             compress_old_files: false,
             retention_period: std::time::Duration::from_secs(24 * 60 * 60),
+            fsync: FsyncMode::default(),
+            replay_strictness: ReplayStrictness::default(),
+            group_commit_window: None,
+            read_only: false,
+        }
+    }
+}
+
+impl WALConfig {
+    pub fn validate(&self) -> Result<(), PlexError> {
+        if self.max_file_size == 0 {
+            return Err(PlexError::Config("max_file_size must be greater than 0".to_string()));
+        }
+        if self.max_entries_per_file == 0 {
+            return Err(PlexError::Config("max_entries_per_file must be greater than 0".to_string()));
         }
+        Ok(())
     }
 }
 
 pub struct WAL {
     config: WALConfig,
     wal_dir: PathBuf,
-    current_file:  Arc<Mutex><Option<WALFile>>>,
+    current_file:  Arc<Mutex<Option<WALFile>>>,
     sequence_number: Arc<Mutex<u64>>,
     last_sync: Arc<Mutex<SystemTime>>,
+    group_commit: Mutex<GroupCommit>,
+    group_synced: Condvar,
+    sync_count: AtomicU64,
+    encryptor: Option<Box<dyn Encryptor>>,
+}
+
+/// Progress of group commit: the highest sequence known to be synced, and
+/// whether some appender is currently running a sync for the group.
+#[derive(Default)]
+struct GroupCommit {
+    synced_through: u64,
+    syncing: bool,
 }
 
 struct WALFile {
     file: BufWriter<File>,
-    path: pathBuf,
+    path: PathBuf,
     entry_count: u64,
     file_size: u64,
-    start_sequence: u64,
 }
 
 impl WAL {
     pub fn new(wal_dir: PathBuf, config: WALConfig) -> PlexResult<Self> {
-        std::fs::create_dir_all(&wal_dir).map_err(|e| {
-            PlexError::WAL(format!("Failed to create WAL directory: {}", e))
-        })?;
+        if !config.read_only {
+            std::fs::create_dir_all(&wal_dir).map_err(|e| {
+                PlexError::WAL(format!("Failed to create WAL directory: {}", e))
+            })?;
+        }
 
         let mut wal = Self {
             config,
             wal_dir,
-            current_files: Arc::new(Mutex::new(None)),
+            current_file: Arc::new(Mutex::new(None)),
             sequence_number: Arc::new(Mutex::new(0)),
             last_sync: Arc::new(Mutex::new(SystemTime::now())),
+            group_commit: Mutex::new(GroupCommit::default()),
+            group_synced: Condvar::new(),
+            sync_count: AtomicU64::new(0),
+            encryptor: None,
         };
 
-        wal.initialize()?;
-        Ok(wal);
+        // A read-only WAL may be opened on a store no writer has logged to.
+        if wal.wal_dir.exists() {
+            wal.initialize()?;
+        }
+        Ok(wal)
     }
 
-    fn intialize(&mut self) -> PlexResult<()> {
+    /// Encrypts the command of every entry appended from now on with
+    /// `encryptor`, which is also needed to read them back.
+    pub fn set_encryptor(&mut self, encryptor: Option<Box<dyn Encryptor>>) {
+        self.encryptor = encryptor;
+    }
+
+    fn initialize(&mut self) -> PlexResult<()> {
         let mut lastest_sequence = 0u64;
-        let mut files = std::fs::read_dir(&self.wal_dir)
+        let files = std::fs::read_dir(&self.wal_dir)
             .map_err(|e| PlexError::WAL(format!("Failed to read WAL directory: {}", e)))?;
 
         let mut wal_files = Vec::new();
@@ -113,17 +319,16 @@ impl WAL {
             let entry = entry.map_err(|e| PlexError::WAL(format!("Failed to read directory entry: {}", e)))?;
             let path = entry.path();
 
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("wal") && name.ends_with(".log") {
+            if let Some(name) = path.file_name().and_then(|n| n.to_str())
+                && is_wal_file_name(name) {
                     wal_files.push(path);
                 }
-            }
         }
 
         wal_files.sort();
 
         for file_path in &wal_files {
-            match self.scamn_wal_files(file_path) {
+            match self.scan_wal_file(file_path) {
                 Ok(max_seq) => {
                     lastest_sequence = lastest_sequence.max(max_seq);
                 }
@@ -137,22 +342,58 @@ impl WAL {
         *self.sequence_number.lock().unwrap() = lastest_sequence;
         info!("WAL initialized with sequence number: {}", lastest_sequence);
 
-        Ok(());
-
+        Ok(())
     }
 
-    fn scan_wal_file(&self, file_path: &Path) -> PlexResult<u64> {
+    /// Opens a WAL file for reading, decompressing rotated `.log.zst` files
+    /// in memory so callers see the same header and entry stream either way.
+    fn open_wal_reader(&self, file_path: &Path) -> PlexResult<Box<dyn Read>> {
         let file = File::open(file_path).map_err(|e| {
-            PlexError::WAL(format!("Failed to ope WAL file: {:?}: {}", file_path, e))
-        })?
+            PlexError::WAL(format!("Failed to open WAL file {:?}: {}", file_path, e))
+        })?;
+
+        if !is_compressed_wal_file(file_path) {
+            return Ok(Box::new(BufReader::new(file)));
+        }
 
-        let mut reader = BufReader::new(file));
+        let mut compressed = Vec::new();
+        BufReader::new(file).read_to_end(&mut compressed).map_err(|e| {
+            PlexError::WAL(format!("Failed to read WAL file {:?}: {}", file_path, e))
+        })?;
+        let data = ZstdCompressor::new(WAL_COMPRESSION_LEVEL).decompress(&compressed)?;
+
+        Ok(Box::new(Cursor::new(data)))
+    }
+
+    /// Replaces a closed WAL file with a zstd-compressed `.log.zst` copy.
+    fn compress_file(&self, file_path: &Path) -> PlexResult<()> {
+        let data = std::fs::read(file_path)
+            .map_err(|e| PlexError::WAL(format!("Failed to read WAL file {:?}: {}", file_path, e)))?;
+        let compressed = ZstdCompressor::new(WAL_COMPRESSION_LEVEL).compress(&data)?;
+
+        let compressed_path = file_path.with_extension(COMPRESSED_WAL_EXTENSION);
+        let temp_path = compressed_path.with_extension("zst.tmp");
 
-        let header: WALHeader = bincode::deserialization_from(&mut reader)
+        std::fs::write(&temp_path, compressed)
+            .map_err(|e| PlexError::WAL(format!("Failed to write compressed WAL file: {}", e)))?;
+        rename(&temp_path, &compressed_path)
+            .map_err(|e| PlexError::WAL(format!("Failed to write compressed WAL file: {}", e)))?;
+        std::fs::remove_file(file_path)
+            .map_err(|e| PlexError::WAL(format!("Failed to remove WAL file: {}", e)))?;
+
+        info!("Compressed rotated WAL file: {:?}", compressed_path);
+        Ok(())
+    }
+
+    fn scan_wal_file(&self, file_path: &Path) -> PlexResult<u64> {
+        let mut reader = self.open_wal_reader(file_path)?;
+
+        let header: WALHeader = bincode::deserialize_from(&mut reader)
             .map_err(|e| PlexError::WAL(format!("Failed to read WAL header: {}", e)))?;
         
-        if !header.is_valid() {
-            return Err(PlexError::WAL(format!("Invalid Wal file header in {:?}", file_path)));
+        if let Err(e) = header.check() {
+            error!("Invalid WAL file header in {:?}: {}", file_path, e);
+            return Err(e);
         }
         
         let mut max_sequence = 0u64;
@@ -179,41 +420,124 @@ impl WAL {
         Ok(max_sequence)
     }
 
-    pub fn append(&self, command: Command) -> PlexResult<u64> {
-        let sequence = {
-            let mut seq = self.sequence_number.lock().unwrap();
-            *seq += 1;
-            *seq
+    pub fn append(&self, command: WALCommand) -> PlexResult<u64> {
+        if self.config.read_only {
+            return Err(PlexError::Config("store is read-only".to_string()));
+        }
 
-        };
+        let sequence = self.write_entry(command)?;
 
-        let entry = WALEntry {
-            sequence_number: sequence,
-            timestamp: current_timestamp(),
-            command,
-            checksum: 0,
+        match self.config.group_commit_window {
+            Some(window) => self.wait_durable(sequence, window)?,
+            None => {
+                if self.should_sync()? {
+                    self.sync()?;
+                }
+            }
         }
 
-        self.write_entry(entry)?;
+        Ok(sequence)
+    }
 
+    /// Appends `commands` back to back, with no other appender's entries in
+    /// between, then syncs them as one `append` would. Returns the sequence
+    /// of the last one.
+    pub fn append_batch(&self, commands: Vec<WALCommand>) -> PlexResult<u64> {
+        if self.config.read_only {
+            return Err(PlexError::Config("store is read-only".to_string()));
+        }
 
-        if self.should_sync()? {
-            self.sync()?;
+        let sequence = {
+            let mut current_file = self.current_file.lock().unwrap();
+            let mut sequence = self.get_lastest_sequence();
+            for command in commands {
+                sequence = self.write_entry_locked(&mut current_file, command)?;
+            }
+            sequence
+        };
+
+        match self.config.group_commit_window {
+            Some(window) => self.wait_durable(sequence, window)?,
+            None => {
+                if self.should_sync()? {
+                    self.sync()?;
+                }
+            }
         }
 
         Ok(sequence)
     }
 
-    fn write_entry(&self, mut entry: WALEntry) -> PlexResult<()> {
+    /// Blocks until `sequence` is synced. If no sync is in flight this caller
+    /// runs one for everything appended so far, after waiting `window` for
+    /// other appenders to join; otherwise it waits for the running one and
+    /// checks again.
+    fn wait_durable(&self, sequence: u64, window: std::time::Duration) -> PlexResult<()> {
+        let mut group = self.group_commit.lock().unwrap();
+        loop {
+            if group.synced_through >= sequence {
+                return Ok(());
+            }
+            if !group.syncing {
+                break;
+            }
+            group = self.group_synced.wait(group).unwrap();
+        }
+        group.syncing = true;
+        drop(group);
 
+        if !window.is_zero() {
+            std::thread::sleep(window);
+        }
+        let result = self.sync();
+
+        self.group_commit.lock().unwrap().syncing = false;
+        self.group_synced.notify_all();
+        result
+    }
+
+    // The sequence number is taken under the file lock, so entries land in
+    // sequence order and a sync covers every sequence handed out before it.
+    fn write_entry(&self, command: WALCommand) -> PlexResult<u64> {
+        let mut current_file = self.current_file.lock().unwrap();
+        self.write_entry_locked(&mut current_file, command)
+    }
+
+    fn write_entry_locked(&self, current_file: &mut Option<WALFile>, command: WALCommand) -> PlexResult<u64> {
+        let sequence = {
+            let mut seq = self.sequence_number.lock().unwrap();
+            *seq += 1;
+            *seq
+        };
+
+        let command = match &self.encryptor {
+            Some(encryptor) => WALCommand::Encrypted {
+                sealed: seal(encryptor.as_ref(), &bincode::serialize(&command)?, &sequence.to_le_bytes())?,
+            },
+            None => command,
+        };
+        let mut entry = WALEntry {
+            sequence_number: sequence,
+            timestamp: current_timestamp(),
+            command,
+            checksum: 0,
+        };
         entry.checksum = self.calculate_checksum(&entry)?;
 
         let serialized = bincode::serialize(&entry)
             .map_err(|e| PlexError::WAL(format!("Failed to serialize WAL entry: {}", e)))?;
 
-        let mut current_file = self.current_file.lock().unwrap();
+        if current_file.is_none() ||self.should_rotate_file(current_file)? {
+            if let Some(mut old_file) = current_file.take() {
+                self.sync_file(&mut old_file)?;
+                let old_path = old_file.path.clone();
+                drop(old_file);
+
+                if self.config.compress_old_files {
+                    self.compress_file(&old_path)?;
+                }
+            }
 
-        if current_file.is_none() ||self.should_rotate_file(&current_file)? {
             *current_file = Some(self.create_new_file(entry.sequence_number)?);
         }
 
@@ -227,16 +551,17 @@ impl WAL {
             debug!("Wrote WAL entry with sequence: {}", entry.sequence_number);
         }
 
-        Ok(())
+        Ok(sequence)
     }
 
-    fn should_rotate_file(&sef, current_file: &Options<WALFILE>) -> PlexResult<bool>
-        if let Some(ref file) = current_file {
-            Ok(file.fil_size) >= self.config.max.file_size ||
+    fn should_rotate_file(&self, current_file: &Option<WALFile>) -> PlexResult<bool> {
+        if let Some(file) = current_file {
+            Ok(file.file_size >= self.config.max_file_size ||
                 file.entry_count >= self.config.max_entries_per_file)
         } else {
             Ok(true)
         }
+    }
 
     fn create_new_file(&self, start_sequence: u64) -> PlexResult<WALFile> {
         let timestamp = current_timestamp();
@@ -245,14 +570,14 @@ impl WAL {
 
         let file = OpenOptions::new()
             .create(true)
-            .write(true)
+            
             .append(true)
             .open(&file_path)
             .map_err(|e| PlexError::WAL(format!("Failed to create WAL file: {}", e)))?;
         
         let mut writer = BufWriter::new(file);
 
-        let header = WALHEADER::new();
+        let header = WALHeader::new();
         bincode::serialize_into(&mut writer, &header)
             .map_err(|e| PlexError::WAL(format!("Failed to write WAL header: {}", e)))?;
 
@@ -263,7 +588,6 @@ impl WAL {
             path: file_path,
             entry_count: 0,
             file_size: bincode::serialized_size(&header).unwrap_or(0),
-            start_sequence,
         })
     }
 
@@ -273,40 +597,94 @@ impl WAL {
     }
 
     pub fn sync (&self) -> PlexResult<()> {
-        let mut current_file = self.current_file.lock().unwrap();
+        let synced_through = {
+            let mut current_file = self.current_file.lock().unwrap();
 
-        if let Some(ref mut wal_file) = current_file.as_mut() {
-            wal_file.file.flush()
-                .map_error(|e|PlexError::WAL(format!("Failed to flush WAL file: {}", e)))?;
-        }
+            if let Some(wal_file) = current_file.as_mut() {
+                self.sync_file(wal_file)?;
+            }
+            self.get_lastest_sequence()
+        };
 
         *self.last_sync.lock().unwrap() = SystemTime::now();
-        
+
+        let mut group = self.group_commit.lock().unwrap();
+        group.synced_through = group.synced_through.max(synced_through);
+
         Ok(())
     }
 
+    /// Hands buffered entries to the OS without syncing them, so they
+    /// survive the process exiting but not a power loss.
+    pub fn flush(&self) -> PlexResult<()> {
+        if let Some(wal_file) = self.current_file.lock().unwrap().as_mut() {
+            wal_file.file.flush()
+                .map_err(|e| PlexError::WAL(format!("Failed to flush WAL file: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Whether appends can currently succeed: the WAL is not read-only, no
+    /// writer panicked while holding the active file, and the directory is
+    /// not write-protected.
+    pub fn is_writable(&self) -> bool {
+        !self.config.read_only
+            && !self.current_file.is_poisoned()
+            && std::fs::metadata(&self.wal_dir).is_ok_and(|metadata| !metadata.permissions().readonly())
+    }
+
+    /// Number of times a WAL file has been synced since the WAL was opened.
+    pub fn sync_count(&self) -> u64 {
+        self.sync_count.load(Ordering::Relaxed)
+    }
+
+    /// Flushes the buffered writer, then syncs the file as `config.fsync` asks.
+    fn sync_file(&self, wal_file: &mut WALFile) -> PlexResult<()> {
+        wal_file.file.flush()
+            .map_err(|e| PlexError::WAL(format!("Failed to flush WAL file: {}", e)))?;
+        self.sync_count.fetch_add(1, Ordering::Relaxed);
+
+        let file = wal_file.file.get_ref();
+        match self.config.fsync {
+            FsyncMode::None => Ok(()),
+            FsyncMode::Fdatasync => file.sync_data(),
+            FsyncMode::Fsync => file.sync_all(),
+        }
+        .map_err(|e| PlexError::WAL(format!("Failed to fsync WAL file: {}", e)))
+    }
+
     fn calculate_checksum(&self, entry: &WALEntry) -> PlexResult<u32> {
         let mut hasher = Hasher::new();
 
-        let command_bytes = bincode::serialize(&sentry.command)
-            .map_err(|e| PlexError::WAL(format!("Failed to serialize command for checksum: {}", e)))?
+        let command_bytes = bincode::serialize(&entry.command)
+            .map_err(|e| PlexError::WAL(format!("Failed to serialize command for checksum: {}", e)))?;
 
         hasher.update(&entry.sequence_number.to_le_bytes());
         hasher.update(&entry.timestamp.to_le_bytes());
         hasher.update(&command_bytes);
 
-        Ok(hasher.finalize());
+        Ok(hasher.finalize())
+    }
+
+    /// Returns every entry written after the last durable checkpoint, in
+    /// sequence order, so the caller can re-apply them after a crash.
+    pub fn replay(&self) -> PlexResult<Vec<WALEntry>> {
+        let durable_sequence = self.durable_sequence()?;
+        let entries = self.read_from_sequence(durable_sequence + 1)?;
+
+        info!("Replaying {} WAL entries after sequence {}", entries.len(), durable_sequence);
+        Ok(entries)
     }
 
     pub fn read_from_sequence(&self, start_sequence: u64) -> PlexResult<Vec<WALEntry>> {
         let mut entries = Vec::new();
 
-        let mut wal_files = std::fs::read_dir(&self.waldir)
+        let mut wal_files = std::fs::read_dir(&self.wal_dir)
             .map_err(|e| PlexError::WAL(format!("Failed to read WAL directory: {}", e)))?
             .filter_map(|entry| {
                 let entry = entry.ok()?;
                 let path = entry.path();
-                if path.file_name()?.to_str()?.starts_with("wal_") {
+                if is_wal_file_name(path.file_name()?.to_str()?) {
                     Some(path)
                 } else {
                     None
@@ -316,98 +694,229 @@ impl WAL {
 
         wal_files.sort();
 
-        for file_path in wal_files {
-            let file_entries = self.read_wal_file(&file_path, start_sequence)?;
+        let newest = wal_files.len().saturating_sub(1);
+        for (i, file_path) in wal_files.iter().enumerate() {
+            let file_entries = self.read_wal_file(file_path, start_sequence, i == newest)?;
             entries.extend(file_entries);
         }
 
         entries.sort_by_key(|e| e.sequence_number);
+        // A crash while compressing a rotated file can leave both copies.
+        entries.dedup_by_key(|e| e.sequence_number);
 
-        Ok(entries);
+        Ok(entries)
     }
 
-    fn read_wal_file(&self, file_path: &Path, start_sequence: u64) -> PlexResult<Vec<WALEntry>> {
-        let file = File::open(file_path).map_err|e| {
-            PlexError::WAL(format!("Failed to open WAL file {:?}: {}", file_path, e))
-        })?;
-
-        let mut reader = BufReader::new(file);
+    /// Reads the entries of one WAL file. Only the newest file can end in an
+    /// entry torn by a crash mid-append; in strict mode that tail is cut
+    /// off, and any other damage fails replay without touching the file, so
+    /// no entry past a gap is ever applied.
+    fn read_wal_file(&self, file_path: &Path, start_sequence: u64, is_newest: bool) -> PlexResult<Vec<WALEntry>> {
+        let mut reader = PositionReader {
+            inner: self.open_wal_reader(file_path)?,
+            position: 0,
+        };
         let mut entries = Vec::new();
 
         let header: WALHeader = bincode::deserialize_from(&mut reader)
             .map_err(|e| PlexError::WAL(format!("Failed to read WAL header: {}", e)))?;
 
-        if !header.is_valid() {
-            return Err(PlexError::WAL(format!("Invalid WAL file header in: {:?}", file_path)));
+        if let Err(e) = header.check() {
+            error!("Invalid WAL file header in {:?}: {}", file_path, e);
+            return Err(e);
         }
 
         loop {
-            match::bincode::deserialize_from::<_, WALEntry>(&mut reader) {
+            let entry_start = reader.position;
+
+            match bincode::deserialize_from::<_, WALEntry>(&mut reader) {
                 Ok(entry) => {
                     let expected_checksum = entry.checksum;
-                    let mut entry_for_checksum = entry.clone();
-                    entry_for_checksum.checksum = 0;
-
-                    let calculated = self.calculate_checksum(&entry_for_checksum)?;
+                    let calculated_checksum = self.calculate_checksum(&entry)?;
 
                     if calculated_checksum != expected_checksum {
                         error!("Checksum mismatch in WAL entry {}: expected {}, got {}",
                             entry.sequence_number, expected_checksum, calculated_checksum);
-                        return Err(PlexError::CheckSumMismatch {
+
+                        if self.config.replay_strictness == ReplayStrictness::Lenient {
+                            continue;
+                        }
+                        return Err(PlexError::CheckSumMisMatch {
                             expected: expected_checksum,
                             actual: calculated_checksum,
                         });
                     }
                     if entry.sequence_number >= start_sequence {
-                        entries.push(entry);
+                        entries.push(self.decrypt_entry(entry)?);
                     }
                 }
                 Err(e) => {
-                    if e.to_string().contains("io error") {
+                    // Running out of bytes right at an entry boundary is a
+                    // clean end of file; part way into one, a torn tail.
+                    // Anything else is an unreadable entry.
+                    let at_eof = matches!(&*e, bincode::ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::UnexpectedEof);
+                    if at_eof && reader.position == entry_start {
                         break;
                     }
-                    warn!("Failed to read WAL entry from {:?}: {}", file_path, e);
-                    break;
-                }
 
+                    warn!("Failed to read WAL entry from {:?} at byte {}: {}", file_path, entry_start, e);
+                    if self.config.replay_strictness == ReplayStrictness::Lenient {
+                        break;
+                    }
+                    if at_eof && is_newest {
+                        self.truncate_file_at(file_path, entry_start)?;
+                        break;
+                    }
+                    return Err(PlexError::WAL(format!(
+                        "Unreadable WAL entry in {:?} at byte {}: {}",
+                        file_path, entry_start, e
+                    )));
+                }
             }
         }
 
         Ok(entries)
     }
 
+    /// Replaces an `Encrypted` command with the command it holds.
+    fn decrypt_entry(&self, entry: WALEntry) -> PlexResult<WALEntry> {
+        let WALCommand::Encrypted { sealed } = &entry.command else {
+            return Ok(entry);
+        };
+        let encryptor = self.encryptor.as_ref().ok_or_else(|| {
+            PlexError::Config("WAL entry is encrypted but no encryption key is configured".to_string())
+        })?;
+        let command = bincode::deserialize(&unseal(encryptor.as_ref(), sealed, &entry.sequence_number.to_le_bytes())?)?;
+
+        Ok(WALEntry { command, ..entry })
+    }
+
+    /// Cuts `file_path` off at `length` bytes. Compressed files were closed
+    /// before being compressed, so they are never torn and are left alone.
+    fn truncate_file_at(&self, file_path: &Path, length: u64) -> PlexResult<()> {
+        if is_compressed_wal_file(file_path) {
+            return Ok(());
+        }
+
+        warn!("Truncating WAL file {:?} to {} bytes", file_path, length);
+        OpenOptions::new()
+            .write(true)
+            .open(file_path)
+            .and_then(|file| {
+                file.set_len(length)?;
+                file.sync_all()
+            })
+            .map_err(|e| PlexError::WAL(format!("Failed to truncate WAL file {:?}: {}", file_path, e)))
+    }
+
+    /// Records `sequence` as durable: every entry up to and including it has
+    /// been applied to the data files and does not need to be replayed.
     pub fn truncate_to_sequence(&self, sequence: u64) -> PlexResult<()> {
-        // Mark entries before this sequence as committed
         info!("Truncating WAL to sequence: {}", sequence);
+
+        let checkpoint_path = self.wal_dir.join(CHECKPOINT_FILE);
+        let temp_path = checkpoint_path.with_extension("tmp");
+
+        std::fs::write(&temp_path, sequence.to_le_bytes())
+            .map_err(|e| PlexError::WAL(format!("Failed to write WAL checkpoint: {}", e)))?;
+        rename(&temp_path, &checkpoint_path)
+            .map_err(|e| PlexError::WAL(format!("Failed to write WAL checkpoint: {}", e)))?;
+
         Ok(())
     }
 
+    pub fn durable_sequence(&self) -> PlexResult<u64> {
+        let checkpoint_path = self.wal_dir.join(CHECKPOINT_FILE);
+        if !checkpoint_path.exists() {
+            return Ok(0);
+        }
+
+        let bytes = std::fs::read(&checkpoint_path)
+            .map_err(|e| PlexError::WAL(format!("Failed to read WAL checkpoint: {}", e)))?;
+        let bytes: [u8; 8] = bytes.as_slice().try_into()
+            .map_err(|_| PlexError::WAL(format!("Invalid WAL checkpoint in {:?}", checkpoint_path)))?;
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
     pub fn get_lastest_sequence(&self) -> u64 {
         *self.sequence_number.lock().unwrap()
     }
 
-    pub fn cleanup_old_files(&self, before_timestamp: u64) -> PlexResult<()> {
+    /// Deletes every WAL file whose entries all have sequence numbers at or
+    /// below `up_to_sequence` and that is older than the retention period.
+    /// The active file is never removed. Returns the number of files deleted.
+    pub fn truncate(&self, up_to_sequence: u64) -> PlexResult<usize> {
+        let active_path = self.current_file.lock().unwrap()
+            .as_ref()
+            .map(|wal_file| wal_file.path.clone());
+        let retention_cutoff = current_timestamp()
+            .saturating_sub(self.config.retention_period.as_secs());
+
+        let mut removed = 0;
+
+        for file_path in self.list_wal_files()? {
+            if active_path.as_ref() == Some(&file_path) {
+                continue;
+            }
+
+            let created_at = file_path.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(wal_file_timestamp)
+                .unwrap_or(u64::MAX);
+            if created_at > retention_cutoff {
+                continue;
+            }
+
+            if self.scan_wal_file(&file_path)? <= up_to_sequence {
+                info!("Removing applied WAL file: {:?}", file_path);
+                std::fs::remove_file(&file_path)
+                    .map_err(|e| PlexError::WAL(format!("Failed to remove WAL file: {}", e)))?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn list_wal_files(&self) -> PlexResult<Vec<PathBuf>> {
+        let mut wal_files = Vec::new();
+
         let entries = std::fs::read_dir(&self.wal_dir)
-            .map_err(|e| PlexError::WAL(format!("Failed to read WAL directory entry: {}", e)))?
+            .map_err(|e| PlexError::WAL(format!("Failed to read WAL directory: {}", e)))?;
 
         for entry in entries {
             let entry = entry.map_err(|e| PlexError::WAL(format!("Failed to read directory entry: {}", e)))?;
             let path = entry.path();
 
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("wal_") && name.ends_with(".log") {
+            if let Some(name) = path.file_name().and_then(|n| n.to_str())
+                && is_wal_file_name(name)
+            {
+                wal_files.push(path);
+            }
+        }
 
-                    if let Some(timestamp_str) = name.strip_prefix("wal_").and_then(|s| s.split('_').next()) {
-                        if let Ok(timestamp) = timestamp_str.parse::<u64>() {
-                            if timestamp < before_timestamp {
+        wal_files.sort();
+        Ok(wal_files)
+    }
+
+    pub fn cleanup_old_files(&self, before_timestamp: u64) -> PlexResult<()> {
+        let entries = std::fs::read_dir(&self.wal_dir)
+            .map_err(|e| PlexError::WAL(format!("Failed to read WAL directory entry: {}", e)))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|e| PlexError::WAL(format!("Failed to read directory entry: {}", e)))?;
+            let path = entry.path();
+
+            if let Some(name) = path.file_name().and_then(|n| n.to_str())
+                && is_wal_file_name(name)
+                    && let Some(timestamp_str) = name.strip_prefix("wal_").and_then(|s| s.split('_').next())
+                        && let Ok(timestamp) = timestamp_str.parse::<u64>()
+                            && timestamp < before_timestamp {
                                 info!("Removing old WAL file: {:?}", path);
                                 std::fs::remove_file(&path)
                                     .map_err(|e| PlexError::WAL(format!("Failed to remove old wal file: {}", e)))?;
                             }
-                        }
-                    }
-                }
-            }
         }
         
         Ok(())
@@ -415,9 +924,436 @@ impl WAL {
 
 }
 
+/// Checksum a `BatchEnd` marker carries over the commands of its batch, so
+/// replay can tell the batch arrived whole.
+pub fn batch_checksum<'a>(commands: impl IntoIterator<Item = &'a WALCommand>) -> PlexResult<u32> {
+    let mut hasher = Hasher::new();
+    for command in commands {
+        let command_bytes = bincode::serialize(command)
+            .map_err(|e| PlexError::WAL(format!("Failed to serialize command for checksum: {}", e)))?;
+        hasher.update(&command_bytes);
+    }
+    Ok(hasher.finalize())
+}
+
+fn is_wal_file_name(name: &str) -> bool {
+    name.starts_with("wal_")
+        && (name.ends_with(".log") || name.ends_with(&format!(".{}", COMPRESSED_WAL_EXTENSION)))
+}
+
+fn is_compressed_wal_file(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|name| name.ends_with(&format!(".{}", COMPRESSED_WAL_EXTENSION)))
+}
+
+/// Creation timestamp encoded in a `wal_{timestamp}_{sequence}.log` name.
+fn wal_file_timestamp(name: &str) -> Option<u64> {
+    name.strip_prefix("wal_")?.split('_').next()?.parse().ok()
+}
+
 fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn set_command(i: u64) -> WALCommand {
+        WALCommand::Set { key: format!("key{}", i), value: format!("value{}", i) }
+    }
+
+    fn wal_file_count(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                let name = name.to_str().unwrap();
+                name.starts_with("wal_") && name.ends_with(".log")
+            })
+            .count()
+    }
+
+    #[test]
+    fn truncate_removes_files_below_the_watermark() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            max_entries_per_file: 2,
+            retention_period: Duration::ZERO,
+            ..WALConfig::default()
+        };
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+
+        for i in 0..6 {
+            wal.append(set_command(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        assert_eq!(wal_file_count(dir.path()), 3);
+
+        assert_eq!(wal.truncate(4).unwrap(), 2);
+        assert_eq!(wal_file_count(dir.path()), 1);
+    }
+
+    #[test]
+    fn truncate_keeps_the_active_file() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            retention_period: Duration::ZERO,
+            ..WALConfig::default()
+        };
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+
+        let sequence = wal.append(set_command(0)).unwrap();
+        wal.sync().unwrap();
+
+        assert_eq!(wal.truncate(sequence).unwrap(), 0);
+        assert_eq!(wal_file_count(dir.path()), 1);
+    }
+
+    #[test]
+    fn truncate_keeps_files_inside_the_retention_period() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            max_entries_per_file: 1,
+            ..WALConfig::default()
+        };
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+
+        for i in 0..3 {
+            wal.append(set_command(i)).unwrap();
+        }
+        wal.sync().unwrap();
+
+        assert_eq!(wal.truncate(3).unwrap(), 0);
+        assert_eq!(wal_file_count(dir.path()), 3);
+    }
+
+    fn files_with_suffix(dir: &Path, suffix: &str) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_str().unwrap().ends_with(suffix))
+            .count()
+    }
+
+    #[test]
+    fn rotated_files_are_compressed_and_still_replayed() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            max_entries_per_file: 3,
+            compress_old_files: true,
+            ..WALConfig::default()
+        };
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+
+        for i in 0..7 {
+            wal.append(set_command(i)).unwrap();
+        }
+        wal.sync().unwrap();
+
+        assert_eq!(files_with_suffix(dir.path(), ".log.zst"), 2);
+        assert_eq!(files_with_suffix(dir.path(), ".log"), 1);
+
+        let entries = wal.replay().unwrap();
+        let sequences: Vec<u64> = entries.iter().map(|e| e.sequence_number).collect();
+        assert_eq!(sequences, (1..=7).collect::<Vec<_>>());
+        match &entries[0].command {
+            WALCommand::Set { key, value } => {
+                assert_eq!(key, "key0");
+                assert_eq!(value, "value0");
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn compressed_files_are_scanned_on_reopen() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            max_entries_per_file: 2,
+            compress_old_files: true,
+            ..WALConfig::default()
+        };
+        {
+            let wal = WAL::new(dir.path().to_path_buf(), config.clone()).unwrap();
+            for i in 0..5 {
+                wal.append(set_command(i)).unwrap();
+            }
+            wal.sync().unwrap();
+        }
+
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+        assert_eq!(wal.get_lastest_sequence(), 5);
+    }
+
+    #[test]
+    fn rotated_files_stay_plain_without_compression() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            max_entries_per_file: 2,
+            ..WALConfig::default()
+        };
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+
+        for i in 0..5 {
+            wal.append(set_command(i)).unwrap();
+        }
+
+        assert_eq!(files_with_suffix(dir.path(), ".log.zst"), 0);
+        assert_eq!(files_with_suffix(dir.path(), ".log"), 3);
+    }
+
+    fn wal_bytes(dir: &Path) -> u64 {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn sync_writes_every_entry_to_disk() {
+        for fsync in [FsyncMode::None, FsyncMode::Fdatasync, FsyncMode::Fsync] {
+            let dir = TempDir::new().unwrap();
+            let config = WALConfig { fsync, ..WALConfig::default() };
+            let synced_bytes = {
+                let wal = WAL::new(dir.path().to_path_buf(), config.clone()).unwrap();
+                for i in 0..5 {
+                    wal.append(set_command(i)).unwrap();
+                }
+                wal.sync().unwrap();
+                wal_bytes(dir.path())
+            };
+
+            assert!(synced_bytes > 0);
+            assert_eq!(wal_bytes(dir.path()), synced_bytes, "{:?}", fsync);
+            let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+            assert_eq!(wal.replay().unwrap().len(), 5, "{:?}", fsync);
+        }
+    }
+
+    fn write_and_corrupt(dir: &Path, config: &WALConfig) {
+        {
+            let wal = WAL::new(dir.to_path_buf(), config.clone()).unwrap();
+            wal.append(set_command(1)).unwrap();
+            wal.append(WALCommand::Set { key: "bad".to_string(), value: "corrupt-me".to_string() }).unwrap();
+            wal.append(set_command(3)).unwrap();
+            wal.sync().unwrap();
+        }
+
+        let path = std::fs::read_dir(dir).unwrap().next().unwrap().unwrap().path();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let at = bytes.windows(10).position(|window| window == b"corrupt-me").unwrap();
+        bytes[at] = b'C';
+        std::fs::write(&path, bytes).unwrap();
+    }
+
+    #[test]
+    fn strict_replay_fails_on_a_corrupt_entry_and_leaves_the_file_alone() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig::default();
+        write_and_corrupt(dir.path(), &config);
+        let corrupted = wal_bytes(dir.path());
+
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+        assert!(matches!(wal.replay(), Err(PlexError::CheckSumMisMatch { .. })));
+        assert!(matches!(wal.replay(), Err(PlexError::CheckSumMisMatch { .. })));
+        assert_eq!(wal_bytes(dir.path()), corrupted);
+    }
+
+    fn wal_files(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.sort();
+        paths
+    }
+
+    fn tear_last_entry(path: &Path) {
+        let length = std::fs::metadata(path).unwrap().len();
+        OpenOptions::new().write(true).open(path).unwrap().set_len(length - 3).unwrap();
+    }
+
+    #[test]
+    fn encrypted_commands_do_not_reach_disk_in_plaintext() {
+        use crate::utils::encryption::{AesGcmEncryptor, EncryptionKey};
+        let encryptor = || Some(Box::new(AesGcmEncryptor::new(&EncryptionKey::from_bytes([5; 32]))) as Box<dyn Encryptor>);
+
+        let dir = TempDir::new().unwrap();
+        {
+            let mut wal = WAL::new(dir.path().to_path_buf(), WALConfig::default()).unwrap();
+            wal.set_encryptor(encryptor());
+            wal.append(WALCommand::Set { key: "key".to_string(), value: "plaintext-marker".to_string() }).unwrap();
+            wal.sync().unwrap();
+        }
+        for path in wal_files(dir.path()) {
+            let bytes = std::fs::read(path).unwrap();
+            assert!(!bytes.windows(16).any(|window| window == b"plaintext-marker"));
+        }
+
+        let mut wal = WAL::new(dir.path().to_path_buf(), WALConfig::default()).unwrap();
+        assert!(matches!(wal.replay(), Err(PlexError::Config(_))));
+        wal.set_encryptor(encryptor());
+        let entries = wal.replay().unwrap();
+        assert!(matches!(&entries[0].command, WALCommand::Set { value, .. } if value == "plaintext-marker"));
+    }
+
+    #[test]
+    fn strict_replay_cuts_a_torn_tail_off_the_newest_file() {
+        let dir = TempDir::new().unwrap();
+        {
+            let wal = WAL::new(dir.path().to_path_buf(), WALConfig::default()).unwrap();
+            for i in 0..3 {
+                wal.append(set_command(i)).unwrap();
+            }
+            wal.sync().unwrap();
+        }
+        let path = wal_files(dir.path()).pop().unwrap();
+        tear_last_entry(&path);
+        let torn_length = std::fs::metadata(&path).unwrap().len();
+
+        let wal = WAL::new(dir.path().to_path_buf(), WALConfig::default()).unwrap();
+        assert_eq!(wal.replay().unwrap().len(), 2);
+        assert!(std::fs::metadata(&path).unwrap().len() < torn_length);
+    }
+
+    #[test]
+    fn strict_replay_does_not_skip_past_a_torn_older_file() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig { max_file_size: 1, ..WALConfig::default() };
+        {
+            let wal = WAL::new(dir.path().to_path_buf(), config.clone()).unwrap();
+            for i in 0..3 {
+                wal.append(set_command(i)).unwrap();
+            }
+            wal.sync().unwrap();
+        }
+        let files = wal_files(dir.path());
+        assert!(files.len() > 1);
+        tear_last_entry(&files[0]);
+        let before = wal_bytes(dir.path());
+
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+        assert!(matches!(wal.replay(), Err(PlexError::WAL(_))));
+        assert_eq!(wal_bytes(dir.path()), before);
+    }
+
+    #[test]
+    fn lenient_replay_skips_a_corrupt_entry() {
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            replay_strictness: ReplayStrictness::Lenient,
+            ..WALConfig::default()
+        };
+        write_and_corrupt(dir.path(), &config);
+
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+        let keys: Vec<String> = wal
+            .replay()
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry.command {
+                WALCommand::Set { key, .. } => Some(key),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(keys, vec!["key1", "key3"]);
+    }
+
+    #[test]
+    fn group_commit_shares_syncs_between_concurrent_writers() {
+        const WRITERS: u64 = 16;
+        const PER_WRITER: u64 = 50;
+        let dir = TempDir::new().unwrap();
+        let config = WALConfig {
+            group_commit_window: Some(Duration::from_millis(2)),
+            ..WALConfig::default()
+        };
+        let wal = Arc::new(WAL::new(dir.path().to_path_buf(), config.clone()).unwrap());
+
+        let handles: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let wal = Arc::clone(&wal);
+                std::thread::spawn(move || {
+                    (0..PER_WRITER)
+                        .map(|i| wal.append(set_command(writer * PER_WRITER + i)).unwrap())
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+        let mut sequences: Vec<u64> = handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect();
+
+        // Every append returned only once it was synced, so nothing is left
+        // buffered for the drop to write out.
+        let durable_bytes = wal_bytes(dir.path());
+        let sync_count = wal.sync_count();
+        drop(wal);
+        assert_eq!(wal_bytes(dir.path()), durable_bytes);
+
+        sequences.sort_unstable();
+        assert_eq!(sequences, (1..=WRITERS * PER_WRITER).collect::<Vec<u64>>());
+        assert!(sync_count * 4 < WRITERS * PER_WRITER, "{} syncs", sync_count);
+
+        let wal = WAL::new(dir.path().to_path_buf(), config).unwrap();
+        assert_eq!(wal.replay().unwrap().len() as u64, WRITERS * PER_WRITER);
+    }
+
+    #[test]
+    fn batch_checksum_depends_on_every_command_and_their_order() {
+        let set = |key: &str| WALCommand::Set {
+            key: key.to_string(),
+            value: "v".to_string(),
+        };
+        let checksum = batch_checksum(&[set("a"), set("b")]).unwrap();
+
+        assert_eq!(batch_checksum(&[set("a"), set("b")]).unwrap(), checksum);
+        assert_ne!(batch_checksum(&[set("b"), set("a")]).unwrap(), checksum);
+        assert_ne!(batch_checksum(&[set("a")]).unwrap(), checksum);
+    }
+
+    fn write_header_only(dir: &Path, header: &WALHeader) {
+        let bytes = bincode::serialize(header).unwrap();
+        std::fs::write(dir.join("wal_1700000000_0000000001.log"), bytes).unwrap();
+    }
+
+    #[test]
+    fn a_newer_header_version_is_reported_as_unsupported() {
+        let dir = TempDir::new().unwrap();
+        write_header_only(dir.path(), &WALHeader { version: WALHeader::VERSION + 1, ..WALHeader::new() });
+
+        let wal = WAL::new(dir.path().to_path_buf(), WALConfig::default()).unwrap();
+        match wal.replay() {
+            Err(PlexError::UnsupportedVersion { found, supported }) => {
+                assert_eq!(found, WALHeader::VERSION + 1);
+                assert_eq!(supported, WALHeader::VERSION);
+            }
+            other => panic!("expected UnsupportedVersion, got {:?}", other.map(|e| e.len())),
+        }
+    }
+
+    #[test]
+    fn a_foreign_header_is_reported_as_invalid_format() {
+        let dir = TempDir::new().unwrap();
+        write_header_only(dir.path(), &WALHeader { magic: *b"NOPE", ..WALHeader::new() });
+
+        let wal = WAL::new(dir.path().to_path_buf(), WALConfig::default()).unwrap();
+        assert!(matches!(wal.replay(), Err(PlexError::InvalidFormat)));
+    }
+
+    #[test]
+    fn baseline_commands_keep_their_discriminants() {
+        let set = WALCommand::Set { key: "key".to_string(), value: "value".to_string() };
+        let delete = WALCommand::Delete { key: "key".to_string() };
+        let set_bytes = bincode::serialize(&set).unwrap();
+        let delete_bytes = bincode::serialize(&delete).unwrap();
+
+        assert_eq!(set_bytes[..4], 0u32.to_le_bytes());
+        assert_eq!(delete_bytes[..4], 2u32.to_le_bytes());
+        assert!(matches!(
+            bincode::deserialize::<crate::cli::Command>(&delete_bytes).unwrap(),
+            crate::cli::Command::Delete { key } if key == "key"
+        ));
+    }
+}
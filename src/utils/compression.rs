@@ -1,10 +1,110 @@
 use crate::error::PlexError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io::{Read, Write};
+use std::str::FromStr;
+
+const DEFAULT_LZ4_LEVEL: i32 = 4;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+/// `CompressionAlgorithm::Adaptive` keeps a compressed payload only if it
+/// saves at least 10%.
+const DEFAULT_ADAPTIVE_THRESHOLD: f64 = 0.9;
+
+/// Which compressor segment entries are written with. Entries only record
+/// that they are compressed, not how, so a data directory has to keep
+/// using the algorithm it was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    #[default]
+    None,
+    Lz4,
+    Snappy,
+    Zstd,
+    Adaptive,
+}
+
+impl CompressionAlgorithm {
+    /// Whether a compression level can be given for this algorithm.
+    pub fn supports_level(self) -> bool {
+        matches!(self, CompressionAlgorithm::Lz4 | CompressionAlgorithm::Zstd)
+    }
+
+    pub fn validate_level(self, level: Option<i32>) -> Result<(), PlexError> {
+        let Some(level) = level else {
+            return Ok(());
+        };
+        if !self.supports_level() {
+            return Err(PlexError::Config(format!(
+                "compression level only applies to lz4 and zstd, not {}",
+                self
+            )));
+        }
+        if self == CompressionAlgorithm::Zstd && !zstd::compression_level_range().contains(&level) {
+            return Err(PlexError::Config(format!(
+                "zstd compression level must be in {:?}, got {}",
+                zstd::compression_level_range(),
+                level
+            )));
+        }
+        Ok(())
+    }
+
+    /// Builds the compressor for this algorithm, or `None` for
+    /// `CompressionAlgorithm::None`. `level` falls back to the algorithm's
+    /// default when not given.
+    pub fn compressor(self, level: Option<i32>) -> Result<Option<Box<dyn Compressor>>, PlexError> {
+        self.validate_level(level)?;
+
+        let compressor: Box<dyn Compressor> = match self {
+            CompressionAlgorithm::None => return Ok(None),
+            CompressionAlgorithm::Lz4 => Box::new(Lz4Compressor::new(level.unwrap_or(DEFAULT_LZ4_LEVEL))),
+            CompressionAlgorithm::Snappy => Box::new(SnappyCompressor::new()),
+            CompressionAlgorithm::Zstd => Box::new(ZstdCompressor::new(level.unwrap_or(DEFAULT_ZSTD_LEVEL))),
+            CompressionAlgorithm::Adaptive => Box::new(AdaptiveCompressor::new(DEFAULT_ADAPTIVE_THRESHOLD)),
+        };
+        Ok(Some(compressor))
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = PlexError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Ok(CompressionAlgorithm::None),
+            "lz4" => Ok(CompressionAlgorithm::Lz4),
+            "snappy" => Ok(CompressionAlgorithm::Snappy),
+            "zstd" => Ok(CompressionAlgorithm::Zstd),
+            "adaptive" => Ok(CompressionAlgorithm::Adaptive),
+            _ => Err(PlexError::Config(format!(
+                "unknown compression algorithm '{}', expected one of none, lz4, snappy, zstd, adaptive",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompressionAlgorithm::None => "none",
+            CompressionAlgorithm::Lz4 => "lz4",
+            CompressionAlgorithm::Snappy => "snappy",
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Adaptive => "adaptive",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Upper bound on the size of a trained dictionary.
+pub const MAX_DICTIONARY_SIZE: usize = 16 * 1024;
 
 pub trait Compressor: Send + Sync {
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError>;
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError>;
-    fn compression_ratio(&self, original_size: usize, comprehend_size: usize) -> f64 {
+    fn compression_ratio(&self, original_size: usize, compressed_size: usize) -> f64 {
         if original_size == 0 {
             1.0
         } else {
@@ -21,15 +121,20 @@ impl Lz4Compressor {
     pub fn new(level: i32) -> Self {
         Self { level }
     }
+
+    /// The level this compressor was created with. `lz4_flex` only has one
+    /// speed, so it does not change the output.
+    pub fn level(&self) -> i32 {
+        self.level
+    }
 }
 
 impl Compressor for Lz4Compressor {
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
-        lz4_flex::compress_prepend_size(data)
-            .map_err(|e| PlexError::Compression(format!("LZ4 compression failed: {}", e)))
+        Ok(lz4_flex::compress_prepend_size(data))
     }
 
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8> PlexError> {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
         lz4_flex::decompress_size_prepended(data)
             .map_err(|e| PlexError::Compression(format!("LZ4 decompression failed: {}", e)))
     }
@@ -38,6 +143,12 @@ impl Compressor for Lz4Compressor {
 
 pub struct SnappyCompressor;
 
+impl Default for SnappyCompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SnappyCompressor {
     pub fn new() -> Self {
         Self
@@ -79,7 +190,7 @@ impl Compressor for ZstdCompressor {
     }
 
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
-        zstd::bulk::decompress(data, data.len() * 4)
+        zstd::stream::decode_all(data)
             .map_err(|e| PlexError::Compression(format!("Zstd decompression failed: {}", e)))
     }
 }
@@ -91,12 +202,16 @@ impl Compressor for NoCompressor {
         Ok(data.to_vec())
     }
 
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8, PlexError> {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
         Ok(data.to_vec())
     }
 
 }
 
+/// Algorithm id `AdaptiveCompressor` stores when no compressor beat the
+/// threshold and the payload is kept as-is.
+const UNCOMPRESSED_ALGORITHM: u8 = 255;
+
 pub struct AdaptiveCompressor {
     compressors: Vec<Box<dyn Compressor>>,
     threshold: f64,
@@ -119,20 +234,19 @@ impl Compressor for AdaptiveCompressor {
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
         let mut best_result = data.to_vec();
         let mut best_ratio = 1.0;
-        let mut best_algorithm = 0u8;
+        let mut best_algorithm = UNCOMPRESSED_ALGORITHM;
 
         for (i, compressor) in self.compressors.iter().enumerate() {
             match compressor.compress(data) {
                 Ok(compressed) => {
-                    let ratio = compressor.compression_ratio(data.len(), compressed.len();
-                        if ratio < best_ratio && ratio < self.threshold {
-                            best_result = compressed;
-                            best_ratio = ratio;
-                            best_algorithm = i as u8;
-                        }
+                    let ratio = compressor.compression_ratio(data.len(), compressed.len());
+                    if ratio < best_ratio && ratio < self.threshold {
+                        best_result = compressed;
+                        best_ratio = ratio;
+                        best_algorithm = i as u8;
+                    }
                 }
                 Err(_) => continue,
-            
             }
         }
 
@@ -146,100 +260,81 @@ impl Compressor for AdaptiveCompressor {
             return Err(PlexError::Compression("Empty compressed data".to_string()));
         }
 
-        let algorithm = data[0] as usize;
+        let algorithm = data[0];
         let compressed_data = &data[1..];
 
-        if algorithm < self.compressors.len() {
-            self.compressors[algorithm].decompress(compressed_data)
-        } else {
-            Err(PlexError:::Compression("Unknown algorithm".to_string()))
+        if algorithm == UNCOMPRESSED_ALGORITHM {
+            return NoCompressor.decompress(compressed_data);
+        }
+
+        match self.compressors.get(algorithm as usize) {
+            Some(compressor) => compressor.decompress(compressed_data),
+            None => Err(PlexError::Compression("Unknown algorithm".to_string())),
         }
     }
 }
 
+/// Zstd with a dictionary trained on sample values, which pays off for
+/// small values that share structure, such as JSON documents with the same
+/// fields. Data compressed with one dictionary only decompresses with it.
 pub struct DictionaryCompressor {
     dictionary: Vec<u8>,
-    base_compressor: Box<dyn Compressor>,
+    level: i32,
 }
 
 impl DictionaryCompressor {
-    pub fn new(dictionary: Vec<u8>, base_compressor: Box<dyn Compressor>) -> Self {
-        Self {
-            dictionary,
-            base_compressor,
-        }
+    pub fn new(dictionary: Vec<u8>, level: i32) -> Self {
+        Self { dictionary, level }
     }
 
+    /// Replaces the dictionary with one of at most `MAX_DICTIONARY_SIZE`
+    /// bytes trained on `samples`. Fails if there is too little sample data
+    /// to train on.
     pub fn train_dictionary(&mut self, samples: &[&[u8]]) -> Result<(), PlexError> {
-        let mut dict = Vec::new();
-        let mut freq_map = std::collections::HashMap::new();
-
-        for sample in samples {
-            for window in sample.windows(4) {
-                *freq_map.entry(window.to_vec()).or_insert(0) += 1;
-            }
-        }
-
-        let mut sorted_patterns: Vec<_> = freq_map.into_iter().collect();
-        sorted_patterns.sort_by(|a, b| b.1.cmp(&a.1));
+        self.dictionary = zstd::dict::from_samples(samples, MAX_DICTIONARY_SIZE)
+            .map_err(|e| PlexError::Compression(format!("Dictionary training failed: {}", e)))?;
+        Ok(())
+    }
 
-        for (pattern, _) in sorted_patterns.into_iter().take(1024) {
-            dict.extend_from_slice(&pattern);
-        }
+    pub fn dictionary(&self) -> &[u8] {
+        &self.dictionary
+    }
 
-        self.dictionary = dict;
-        Ok(())
+    /// The ID zstd stored in a trained dictionary, which it also writes into
+    /// every frame compressed with it. `None` for an empty or raw dictionary.
+    pub fn id(&self) -> Option<u32> {
+        zstd::zstd_safe::get_dict_id_from_dict(&self.dictionary).map(u32::from)
     }
 }
 
+/// The ID of the dictionary a zstd frame was compressed with, if any.
+pub fn frame_dictionary_id(frame: &[u8]) -> Option<u32> {
+    zstd::zstd_safe::get_dict_id_from_frame(frame).map(u32::from)
+}
+
 impl Compressor for DictionaryCompressor {
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
-        let mut result = data.to_vec();
-
-        for (i, window) in self.dictionary.windows(4).enumerate() {
-            if i >= 256 { break; }
-
-            let pattern = window;
-            let replacement = &[0xFF, i as u8];
-
-            if let Some(pos) = result.windows(pattern.len()).position(|w| w == pattern ) {
-                result.splice(pos..pos + pattern.len(), replacement.iter().cloned());
-            }
-        }
-
-        self.base_compressor.compress(&result);
+        let mut encoder = zstd::stream::Encoder::with_dictionary(Vec::new(), self.level, &self.dictionary)
+            .map_err(|e| PlexError::Compression(format!("Dictionary compression failed: {}", e)))?;
+        encoder.write_all(data)
+            .map_err(|e| PlexError::Compression(format!("Dictionary compression failed: {}", e)))?;
+        encoder.finish()
+            .map_err(|e| PlexError::Compression(format!("Dictionary compression failed: {}", e)))
     }
 
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
-        let decompressed = self.base_compressor.decompress(data)?;
-
+        let mut decoder = zstd::stream::Decoder::with_dictionary(data, &self.dictionary)
+            .map_err(|e| PlexError::Compression(format!("Dictionary decompression failed: {}", e)))?;
         let mut result = Vec::new();
-        let mut i = 0;
-
-
-        while i < decompressed.len() {
-            if decompressed[i] = 0xFF, && i + 1 < decompressed.len() {
-                let dict_index = decompressed[i + 1] as usize;
-                if dict_index * 4 + 4 <= self.dictionary.len() {
-                    result.extend_from_slice(&self.dictionary[dict_index * 4..(dict_index + 1) * 4]);
-                    i += 2l;
-                } else {
-                    result.push(decompressed[i]);
-                    i += 1;
-                }
-            } else {
-                result.push(decompressed[i]);
-                i += 1;
-            }
-        }
-
+        decoder.read_to_end(&mut result)
+            .map_err(|e| PlexError::Compression(format!("Dictionary decompression failed: {}", e)))?;
         Ok(result)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CompressionStats {
-    pub total_compressd: u64,
+    pub total_compressed: u64,
     pub total_uncompressed: u64,
     pub compression_ratio: f64,
     pub compression_time: std::time::Duration,
@@ -248,7 +343,7 @@ pub struct CompressionStats {
 
 pub struct CompressionWithStats{
     inner: Box<dyn Compressor>,
-    stats: std::sync::Arc<tokio::sync::RwLock<ComprehensionStats>>,
+    stats: std::sync::Arc<tokio::sync::RwLock<CompressionStats>>,
 }
 
 impl  CompressionWithStats {
@@ -266,7 +361,7 @@ impl  CompressionWithStats {
     }
 
     pub async fn stats(&self) -> CompressionStats {
-        self.status.read().await.clone()
+        self.stats.read().await.clone()
     }
 }
 
@@ -279,16 +374,16 @@ impl Compressor for CompressionWithStats {
         if let Ok(ref compressed) = result {
             tokio::task::block_in_place(|| {
                 tokio::runtime::Handle::current().block_on(async {
-                    let mut stats = self.status.write().await;
+                    let mut stats = self.stats.write().await;
                     stats.total_uncompressed += data.len() as u64;
-                    stats.total_compressed += compressed().len() as u64;
+                    stats.total_compressed += compressed.len() as u64;
                     stats.compression_ratio = stats.total_compressed as f64 / stats.total_uncompressed as f64;
                     stats.compression_time += duration;
                 });
             });
         }
 
-        result;
+        result
     }
 
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PlexError> {
@@ -306,3 +401,117 @@ impl Compressor for CompressionWithStats {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_values(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                format!(
+                    r#"{{"id":{},"name":"user{}","email":"user{}@example.com","active":{},"roles":["reader","writer"]}}"#,
+                    i,
+                    i,
+                    i,
+                    i % 2 == 0
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trained_dictionary_shrinks_similar_values() {
+        let values = json_values(1_000);
+        let samples: Vec<&[u8]> = values.iter().map(|value| value.as_bytes()).collect();
+
+        let mut with_dictionary = DictionaryCompressor::new(Vec::new(), 3);
+        with_dictionary.train_dictionary(&samples).unwrap();
+        let without_dictionary = ZstdCompressor::new(3);
+
+        let value = br#"{"id":5000,"name":"user5000","email":"user5000@example.com","active":true,"roles":["reader","writer"]}"#;
+        let compressed = with_dictionary.compress(value).unwrap();
+
+        assert!(compressed.len() < without_dictionary.compress(value).unwrap().len());
+        assert_eq!(with_dictionary.decompress(&compressed).unwrap(), value);
+        assert!(with_dictionary.id().is_some());
+        assert_eq!(frame_dictionary_id(&compressed), with_dictionary.id());
+    }
+
+    #[test]
+    fn training_on_too_little_data_fails() {
+        let mut compressor = DictionaryCompressor::new(Vec::new(), 3);
+
+        assert!(compressor.train_dictionary(&[b"tiny"]).is_err());
+    }
+
+    fn pseudo_random_bytes(len: usize) -> Vec<u8> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adaptive_stores_incompressible_data_raw() {
+        let compressor = AdaptiveCompressor::new(0.9);
+        let data = ZstdCompressor::new(3).compress(&pseudo_random_bytes(4096)).unwrap();
+
+        let compressed = compressor.compress(&data).unwrap();
+
+        assert_eq!(compressed[0], UNCOMPRESSED_ALGORITHM);
+        assert_eq!(compressor.decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn adaptive_picks_a_real_algorithm_for_compressible_data() {
+        let compressor = AdaptiveCompressor::new(0.9);
+        let data = b"plexdb ".repeat(1024);
+
+        let compressed = compressor.compress(&data).unwrap();
+
+        assert_ne!(compressed[0], UNCOMPRESSED_ALGORITHM);
+        assert!(compressed.len() < data.len() / 4);
+        assert_eq!(compressor.decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn every_algorithm_round_trips_a_value() {
+        let value = b"the quick brown fox jumps over the lazy dog ".repeat(20);
+
+        for name in ["none", "lz4", "snappy", "zstd", "adaptive"] {
+            let algorithm: CompressionAlgorithm = name.parse().unwrap();
+            assert_eq!(algorithm.to_string(), name);
+
+            match algorithm.compressor(None).unwrap() {
+                None => assert_eq!(algorithm, CompressionAlgorithm::None),
+                Some(compressor) => {
+                    let compressed = compressor.compress(&value).unwrap();
+                    assert_eq!(compressor.decompress(&compressed).unwrap(), value, "{}", name);
+                }
+            }
+        }
+
+        let zstd = CompressionAlgorithm::Zstd.compressor(Some(19)).unwrap().unwrap();
+        assert_eq!(zstd.decompress(&zstd.compress(&value).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn unknown_algorithm_and_misplaced_levels_are_config_errors() {
+        assert!(matches!("brotli".parse::<CompressionAlgorithm>(), Err(PlexError::Config(_))));
+        assert!(matches!(
+            CompressionAlgorithm::Snappy.compressor(Some(3)),
+            Err(PlexError::Config(_))
+        ));
+        assert!(matches!(
+            CompressionAlgorithm::Zstd.validate_level(Some(1000)),
+            Err(PlexError::Config(_))
+        ));
+        assert!(CompressionAlgorithm::Lz4.validate_level(Some(9)).is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::error::PlexError;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use sha2::Sha256;
+use std::fmt;
+use std::path::Path;
+
+pub const NONCE_SIZE: usize = 12;
+pub const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+/// File in the data directory holding the salt passphrases are stretched
+/// with. Losing it makes the data unreadable, like losing the passphrase.
+pub const ENCRYPTION_SALT_FILE: &str = "encryption.salt";
+/// File in the data directory holding a token sealed with the store's key,
+/// so a wrong key is caught on open rather than on the first read.
+pub const ENCRYPTION_CHECK_FILE: &str = "encryption.check";
+const KEY_CHECK_TOKEN: &[u8] = b"plexdb encryption key check";
+const PBKDF2_ROUNDS: u32 = 600_000;
+
+/// Seals data so it can only be read back with the same key. Every call
+/// to `encrypt` uses a fresh nonce, which the caller stores next to the
+/// ciphertext and passes back to `decrypt`. `aad` is authenticated but not
+/// stored; callers pass where the data lives, so sealed data moved
+/// anywhere else fails to decrypt.
+pub trait Encryptor: Send + Sync {
+    fn encrypt(&self, data: &[u8], aad: &[u8]) -> Result<([u8; NONCE_SIZE], Vec<u8>), PlexError>;
+    fn decrypt(&self, nonce: &[u8; NONCE_SIZE], data: &[u8], aad: &[u8]) -> Result<Vec<u8>, PlexError>;
+}
+
+/// Encrypts `data` into its nonce followed by the ciphertext.
+pub fn seal(encryptor: &dyn Encryptor, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, PlexError> {
+    let (nonce, ciphertext) = encryptor.encrypt(data, aad)?;
+    let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Reverses `seal`.
+pub fn unseal(encryptor: &dyn Encryptor, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, PlexError> {
+    let (nonce, ciphertext) = sealed.split_first_chunk::<NONCE_SIZE>().ok_or(PlexError::InvalidFormat)?;
+    encryptor.decrypt(nonce, ciphertext, aad)
+}
+
+/// Checks `key` against the token sealed at `path`, sealing one there first
+/// if there is none yet and `create` is set. Fails if the token was sealed
+/// with a different key.
+pub fn check_key(path: &Path, key: &EncryptionKey, create: bool) -> Result<(), PlexError> {
+    let encryptor = AesGcmEncryptor::new(key);
+    if path.exists() {
+        return match unseal(&encryptor, &std::fs::read(path)?, KEY_CHECK_TOKEN) {
+            Ok(token) if token == KEY_CHECK_TOKEN => Ok(()),
+            _ => Err(PlexError::Encryption(format!("Wrong encryption key for the store sealed by {:?}", path))),
+        };
+    }
+    if !create {
+        return Ok(());
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, seal(&encryptor, KEY_CHECK_TOKEN, KEY_CHECK_TOKEN)?)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// A 256-bit key for `AesGcmEncryptor`. `Debug` never prints the key.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; KEY_SIZE]);
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> Self {
+        Self(bytes)
+    }
+
+    /// Stretches `passphrase` with PBKDF2-HMAC-SHA256. The same passphrase
+    /// and salt always give the same key.
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Self {
+        let mut key = [0u8; KEY_SIZE];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+        Self(key)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Reads the salt stored at `path`, writing a random one there first if
+/// there is none yet.
+pub fn load_or_create_salt(path: &Path) -> Result<[u8; SALT_SIZE], PlexError> {
+    if path.exists() {
+        return std::fs::read(path)?
+            .try_into()
+            .map_err(|_| PlexError::Encryption(format!("Invalid salt in {:?}", path)));
+    }
+
+    let mut salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, salt)?;
+    std::fs::rename(tmp_path, path)?;
+
+    Ok(salt)
+}
+
+pub struct AesGcmEncryptor {
+    cipher: Aes256Gcm,
+}
+
+impl AesGcmEncryptor {
+    pub fn new(key: &EncryptionKey) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0)),
+        }
+    }
+}
+
+impl Encryptor for AesGcmEncryptor {
+    fn encrypt(&self, data: &[u8], aad: &[u8]) -> Result<([u8; NONCE_SIZE], Vec<u8>), PlexError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .map_err(|e| PlexError::Encryption(format!("AES-GCM encryption failed: {}", e)))?;
+
+        Ok((nonce.into(), ciphertext))
+    }
+
+    fn decrypt(&self, nonce: &[u8; NONCE_SIZE], data: &[u8], aad: &[u8]) -> Result<Vec<u8>, PlexError> {
+        // Authentication fails the same way for a wrong key, tampered data
+        // and data moved from where it was sealed, so the error cannot say
+        // which it was.
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+            .map_err(|_| PlexError::Encryption("AES-GCM decryption failed: wrong key or tampered data".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn decrypting_needs_the_key_it_was_sealed_with() {
+        let encryptor = AesGcmEncryptor::new(&EncryptionKey::from_bytes([7; KEY_SIZE]));
+        let (nonce, ciphertext) = encryptor.encrypt(b"secret value", b"here").unwrap();
+
+        assert_ne!(ciphertext.as_slice(), b"secret value");
+        assert_eq!(encryptor.decrypt(&nonce, &ciphertext, b"here").unwrap(), b"secret value");
+        assert!(matches!(encryptor.decrypt(&nonce, &ciphertext, b"there"), Err(PlexError::Encryption(_))));
+
+        let other = AesGcmEncryptor::new(&EncryptionKey::from_bytes([8; KEY_SIZE]));
+        assert!(matches!(other.decrypt(&nonce, &ciphertext, b"here"), Err(PlexError::Encryption(_))));
+
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        assert!(matches!(encryptor.decrypt(&nonce, &tampered, b"here"), Err(PlexError::Encryption(_))));
+    }
+
+    #[test]
+    fn key_check_fails_fast_on_a_different_key() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(ENCRYPTION_CHECK_FILE);
+        let key = EncryptionKey::from_bytes([7; KEY_SIZE]);
+
+        check_key(&path, &key, false).unwrap();
+        assert!(!path.exists());
+        check_key(&path, &key, true).unwrap();
+        check_key(&path, &key, false).unwrap();
+
+        let other = EncryptionKey::from_bytes([8; KEY_SIZE]);
+        assert!(matches!(check_key(&path, &other, true), Err(PlexError::Encryption(_))));
+    }
+
+    #[test]
+    fn salt_is_created_once_and_then_reused() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(ENCRYPTION_SALT_FILE);
+
+        let salt = load_or_create_salt(&path).unwrap();
+        assert_eq!(load_or_create_salt(&path).unwrap(), salt);
+
+        std::fs::write(&path, b"short").unwrap();
+        assert!(matches!(load_or_create_salt(&path), Err(PlexError::Encryption(_))));
+    }
+}
@@ -0,0 +1,91 @@
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, Hash, Hasher};
+use xxhash_rust::xxh64::Xxh64;
+
+/// A hash function with a fixed output across Rust releases. Anything that
+/// persists hashes, such as bloom filter bits or the key-to-partition
+/// mapping, must use one of these: `DefaultHasher` may change between
+/// toolchains and silently invalidate data written by an older build.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashFamily {
+    #[default]
+    XxHash64,
+    Fnv1a,
+}
+
+impl BuildHasher for HashFamily {
+    type Hasher = StableHasher;
+
+    fn build_hasher(&self) -> StableHasher {
+        match self {
+            HashFamily::XxHash64 => StableHasher::XxHash64(Xxh64::new(0)),
+            HashFamily::Fnv1a => StableHasher::Fnv1a(FnvHasher::default()),
+        }
+    }
+}
+
+pub enum StableHasher {
+    XxHash64(Xxh64),
+    Fnv1a(FnvHasher),
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        match self {
+            StableHasher::XxHash64(hasher) => hasher.finish(),
+            StableHasher::Fnv1a(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            StableHasher::XxHash64(hasher) => hasher.write(bytes),
+            StableHasher::Fnv1a(hasher) => hasher.write(bytes),
+        }
+    }
+}
+
+/// A byte key that hashes exactly like the `str` with the same bytes, so
+/// partition routing and bloom filter bits for keys written as strings stay
+/// valid now that keys are stored as bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyBytes<'a>(pub &'a [u8]);
+
+impl Hash for KeyBytes<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Mirrors `impl Hash for str`.
+        state.write(self.0);
+        state.write_u8(0xff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_pinned_across_builds() {
+        // These values are persisted in bloom filters and decide partition
+        // placement, so they must never change.
+        let mut xx = HashFamily::XxHash64.build_hasher();
+        xx.write(b"plexdb");
+        assert_eq!(xx.finish(), xxhash_rust::xxh64::xxh64(b"plexdb", 0));
+
+        let mut fnv = HashFamily::Fnv1a.build_hasher();
+        fnv.write(b"");
+        assert_eq!(fnv.finish(), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn families_hash_differently() {
+        assert_ne!(HashFamily::XxHash64.hash_one("key"), HashFamily::Fnv1a.hash_one("key"));
+    }
+
+    #[test]
+    fn key_bytes_hash_like_the_matching_str() {
+        for family in [HashFamily::XxHash64, HashFamily::Fnv1a] {
+            assert_eq!(family.hash_one(KeyBytes(b"key")), family.hash_one("key"));
+        }
+    }
+}
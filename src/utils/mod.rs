@@ -0,0 +1,5 @@
+pub mod compression;
+pub mod encryption;
+pub mod hash;
+pub mod retry;
+pub mod time;
@@ -0,0 +1,152 @@
+use crate::error::PlexError;
+use serde::{Deserialize, Serialize};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use tracing::warn;
+
+/// How often to retry an operation that failed with `PlexError::LockError`,
+/// doubling the delay after each attempt. Any other error is returned
+/// straight away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first included. 1 disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Runs `op` until it succeeds, fails with an error other than
+    /// `LockError`, or runs out of attempts, in which case the last error
+    /// is returned. `op` must be safe to run again after a lock error. A
+    /// poisoned lock stays poisoned, so it still fails once the attempts
+    /// run out.
+    pub fn run<T>(&self, mut op: impl FnMut() -> Result<T, PlexError>) -> Result<T, PlexError> {
+        let mut delay = self.base_delay;
+        let mut attempt = 1;
+
+        loop {
+            match op() {
+                Err(PlexError::LockError(lock)) if attempt < self.max_attempts => {
+                    warn!("Failed to lock {} (attempt {} of {}), retrying in {:?}", lock, attempt, self.max_attempts, delay);
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Read-locks `lock`, named `name` in the error. Every attempt but the
+    /// last only tries the lock, so a contended lock is retried with
+    /// backoff; the last attempt waits for it. A poisoned lock fails with
+    /// `LockError` once the attempts run out.
+    pub fn read<'a, T>(&self, lock: &'a RwLock<T>, name: &str) -> Result<RwLockReadGuard<'a, T>, PlexError> {
+        let mut attempt = 0;
+        self.run(|| {
+            attempt += 1;
+            if attempt >= self.max_attempts {
+                lock.read().map_err(|_| PlexError::LockError(name.to_string()))
+            } else {
+                lock.try_read().map_err(|_| PlexError::LockError(name.to_string()))
+            }
+        })
+    }
+
+    /// Like `read`, for a write lock.
+    pub fn write<'a, T>(&self, lock: &'a RwLock<T>, name: &str) -> Result<RwLockWriteGuard<'a, T>, PlexError> {
+        let mut attempt = 0;
+        self.run(|| {
+            attempt += 1;
+            if attempt >= self.max_attempts {
+                lock.write().map_err(|_| PlexError::LockError(name.to_string()))
+            } else {
+                lock.try_write().map_err(|_| PlexError::LockError(name.to_string()))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_errors_are_retried_until_the_budget_runs_out() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(1),
+        };
+
+        let mut calls = 0;
+        let result = policy.run(|| {
+            calls += 1;
+            if calls < 3 { Err(PlexError::LockError("index".to_string())) } else { Ok(calls) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<(), _> = policy.run(|| {
+            calls += 1;
+            Err(PlexError::LockError("index".to_string()))
+        });
+        assert!(matches!(result, Err(PlexError::LockError(_))));
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let mut calls = 0;
+        let result: Result<(), _> = RetryPolicy::default().run(|| {
+            calls += 1;
+            Err(PlexError::KeyIsEmpty)
+        });
+        assert!(matches!(result, Err(PlexError::KeyIsEmpty)));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn a_contended_lock_is_retried_and_a_poisoned_one_fails() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let lock = std::sync::Arc::new(RwLock::new(0));
+
+        let held = lock.read().unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| *policy.write(&lock, "value").unwrap() += 1);
+            std::thread::sleep(Duration::from_millis(20));
+            drop(held);
+        });
+        assert_eq!(*policy.read(&lock, "value").unwrap(), 1);
+
+        let poisoner = std::sync::Arc::clone(&lock);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.write().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join();
+        assert!(matches!(policy.read(&lock, "value"), Err(PlexError::LockError(_))));
+        assert!(matches!(policy.write(&lock, "value"), Err(PlexError::LockError(_))));
+    }
+}